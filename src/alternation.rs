@@ -0,0 +1,184 @@
+use crate::api::ChatMessageRequest;
+
+/// Merge runs of consecutive messages with the same role into one message.
+///
+/// Many providers require user and assistant turns to alternate and reject a
+/// request with two user (or two assistant) messages in a row with a bare 400.
+/// Such runs appear after excluding messages from context, after starting from a
+/// template's reference context, and in group chats where several personas answer
+/// in turn. Merged texts are joined with a blank line; when the merged messages
+/// came from different personas, each part is labelled with its persona's name.
+/// System messages, tool results and replies calling tools are left alone.
+///
+/// Returns the number of messages that were merged into the one before them.
+pub fn normalize(messages: &mut Vec<ChatMessageRequest>) -> usize {
+    let mut merged_count = 0;
+    let mut normalized: Vec<ChatMessageRequest> = Vec::with_capacity(messages.len());
+    // Whether the last normalized message already carries name labels.
+    let mut labelled = false;
+    for message in messages.drain(..) {
+        let Some(previous) = normalized
+            .last_mut()
+            .filter(|previous| previous.role == message.role && !matches!(message.role.as_str(), "system" | "tool"))
+            // A reply calling tools is answered by the results, one message each.
+            .filter(|previous| previous.tool_calls.is_empty() && message.tool_calls.is_empty())
+        else {
+            labelled = false;
+            normalized.push(message);
+            continue;
+        };
+        if !labelled && previous.name != message.name {
+            if let Some(name) = &previous.name {
+                previous.content = format!("{}: {}", name, previous.content);
+            }
+            labelled = true;
+        }
+        let content = match (&message.name, labelled) {
+            (Some(name), true) => format!("{}: {}", name, message.content),
+            _ => message.content,
+        };
+        if labelled {
+            previous.name = None;
+        }
+        match (previous.content.is_empty(), content.is_empty()) {
+            (_, true) => {}
+            (true, false) => previous.content = content,
+            (false, false) => {
+                previous.content.push_str("\n\n");
+                previous.content.push_str(&content);
+            }
+        }
+        previous.images.extend(message.images);
+        merged_count += 1;
+    }
+    *messages = normalized;
+    merged_count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{FunctionCall, ToolCall};
+
+    fn message(role: &str, content: &str) -> ChatMessageRequest {
+        ChatMessageRequest::new(role, content)
+    }
+
+    fn named(role: &str, name: &str, content: &str) -> ChatMessageRequest {
+        let mut message = message(role, content);
+        message.name = Some(name.to_string());
+        message
+    }
+
+    /// Roles and contents of `messages`, for comparing.
+    fn turns(messages: &[ChatMessageRequest]) -> Vec<(&str, &str)> {
+        messages.iter().map(|message| (message.role.as_str(), message.content.as_str())).collect()
+    }
+
+    #[test]
+    fn merges_runs_of_the_same_role() {
+        let mut messages = vec![
+            message("user", "First question"),
+            message("user", "Second question"),
+            message("assistant", "An answer"),
+            message("assistant", ""),
+            message("assistant", "More of it"),
+            message("user", "Thanks"),
+        ];
+        assert_eq!(normalize(&mut messages), 3);
+        assert_eq!(
+            turns(&messages),
+            [
+                ("user", "First question\n\nSecond question"),
+                ("assistant", "An answer\n\nMore of it"),
+                ("user", "Thanks"),
+            ]
+        );
+    }
+
+    #[test]
+    fn alternating_messages_are_untouched() {
+        let mut messages = vec![message("user", "Hi"), message("assistant", "Hello"), message("user", "Bye")];
+        assert_eq!(normalize(&mut messages), 0);
+        assert_eq!(turns(&messages), [("user", "Hi"), ("assistant", "Hello"), ("user", "Bye")]);
+    }
+
+    #[test]
+    fn leading_assistant_messages_stay_first() {
+        // A template's reference context may open with the assistant.
+        let mut messages = vec![
+            message("system", "Be brief."),
+            message("assistant", "Here is the context."),
+            message("assistant", "And some more."),
+            message("user", "Go on"),
+        ];
+        assert_eq!(normalize(&mut messages), 1);
+        assert_eq!(
+            turns(&messages),
+            [
+                ("system", "Be brief."),
+                ("assistant", "Here is the context.\n\nAnd some more."),
+                ("user", "Go on"),
+            ]
+        );
+    }
+
+    #[test]
+    fn system_messages_are_never_merged_or_moved() {
+        let mut messages = vec![
+            message("system", "Be brief."),
+            message("system", "Today is Monday."),
+            message("user", "Hi"),
+            message("system", "Model changed"),
+            message("user", "Still there?"),
+        ];
+        assert_eq!(normalize(&mut messages), 0);
+        assert_eq!(
+            turns(&messages),
+            [
+                ("system", "Be brief."),
+                ("system", "Today is Monday."),
+                ("user", "Hi"),
+                ("system", "Model changed"),
+                ("user", "Still there?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn persona_replies_are_labelled() {
+        let mut messages = vec![
+            message("user", "Opinions?"),
+            named("assistant", "Critic", "Too long."),
+            named("assistant", "Fan", "Just right."),
+            named("assistant", "Fan", "Really."),
+        ];
+        assert_eq!(normalize(&mut messages), 2);
+        assert_eq!(messages[1].content, "Critic: Too long.\n\nFan: Just right.\n\nFan: Really.");
+        assert_eq!(messages[1].name, None);
+
+        // One persona answering twice keeps its name, unlabelled.
+        let mut messages = vec![named("assistant", "Fan", "Yes."), named("assistant", "Fan", "Again.")];
+        normalize(&mut messages);
+        assert_eq!(messages[0].content, "Yes.\n\nAgain.");
+        assert_eq!(messages[0].name.as_deref(), Some("Fan"));
+    }
+
+    #[test]
+    fn tool_calls_and_results_are_left_alone() {
+        let mut call = message("assistant", "");
+        call.tool_calls.push(ToolCall {
+            id: "call_1".to_string(),
+            kind: "function".to_string(),
+            function: FunctionCall::default(),
+        });
+        let mut messages = vec![
+            message("assistant", "Let me check."),
+            call,
+            message("tool", "one"),
+            message("tool", "two"),
+        ];
+        assert_eq!(normalize(&mut messages), 0);
+        assert_eq!(messages.len(), 4);
+    }
+}
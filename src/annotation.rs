@@ -0,0 +1,121 @@
+use crate::api::ChatMessageRequest;
+use crate::config::Template;
+use crate::error::Result;
+use crate::limit::Limit;
+
+/// Characters of a system prompt's first line shown in a change.
+const PROMPT_PREVIEW: usize = 40;
+
+/// The settings a template or a model switch changes mid-conversation, for
+/// showing what changes before it does.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub model: String,
+    pub system_prompt: String,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub limit: Option<Limit>,
+}
+
+impl Settings {
+    /// These settings with what `template` sets; what it leaves unset is kept.
+    pub fn with_template(&self, template: &Template) -> Result<Self> {
+        Ok(Self {
+            model: template.model.clone().unwrap_or_else(|| self.model.clone()),
+            system_prompt: template.system_prompt.clone().unwrap_or_else(|| self.system_prompt.clone()),
+            temperature: template.temperature.or(self.temperature),
+            max_tokens: template.max_tokens.or(self.max_tokens),
+            limit: template.limit()?.or(self.limit),
+        })
+    }
+
+    /// What differs in `after`, one short entry per setting.
+    ///
+    /// ```
+    /// use llm::annotation::Settings;
+    /// use llm::config::Template;
+    ///
+    /// let before = Settings {
+    ///     model: "openai/gpt-4o".to_string(),
+    ///     system_prompt: "You are a helpful assistant.".to_string(),
+    ///     temperature: Some(0.7),
+    ///     ..Settings::default()
+    /// };
+    /// let terse = Template {
+    ///     system_prompt: Some("Answer in one sentence.\nNo lists.".to_string()),
+    ///     temperature: Some(0.2),
+    ///     limit: Some("50 words".to_string()),
+    ///     ..Template::default()
+    /// };
+    /// let after = before.with_template(&terse).unwrap();
+    /// assert_eq!(
+    ///     before.changes(&after),
+    ///     [
+    ///         "system prompt \"You are a helpful assistant.\" → \"Answer in one sentence.…\"",
+    ///         "temperature 0.7 → 0.2",
+    ///         "limit none → 50 words",
+    ///     ]
+    /// );
+    /// assert!(after.changes(&after).is_empty());
+    /// ```
+    pub fn changes(&self, after: &Settings) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.model != after.model {
+            changes.push(format!("model {} → {}", self.model, after.model));
+        }
+        if self.system_prompt.trim() != after.system_prompt.trim() {
+            changes.push(format!(
+                "system prompt {} → {}",
+                prompt_preview(&self.system_prompt),
+                prompt_preview(&after.system_prompt)
+            ));
+        }
+        let or_default = |value: Option<String>| value.unwrap_or_else(|| "default".to_string());
+        if self.temperature != after.temperature {
+            changes.push(format!(
+                "temperature {} → {}",
+                or_default(self.temperature.map(|t| t.to_string())),
+                or_default(after.temperature.map(|t| t.to_string()))
+            ));
+        }
+        if self.max_tokens != after.max_tokens {
+            changes.push(format!(
+                "max tokens {} → {}",
+                or_default(self.max_tokens.map(|n| n.to_string())),
+                or_default(after.max_tokens.map(|n| n.to_string()))
+            ));
+        }
+        if self.limit != after.limit {
+            let limit = |limit: Option<Limit>| limit.map_or_else(|| "none".to_string(), |limit| limit.to_string());
+            changes.push(format!("limit {} → {}", limit(self.limit), limit(after.limit)));
+        }
+        changes
+    }
+}
+
+/// The first line of `prompt` in quotes, shortened; `none` when it is empty.
+fn prompt_preview(prompt: &str) -> String {
+    let prompt = prompt.trim();
+    let Some(first) = prompt.lines().next() else {
+        return "none".to_string();
+    };
+    let mut preview: String = first.chars().take(PROMPT_PREVIEW).collect();
+    if preview.len() < prompt.len() {
+        preview.push('…');
+    }
+    format!("\"{}\"", preview)
+}
+
+/// A note in the conversation recording what changed and why, such as
+/// "Template 'terse': temperature 0.7 → 0.2". It is shown, but never sent to
+/// the model nor saved with the conversation.
+pub fn note(cause: &str, changes: &[String]) -> ChatMessageRequest {
+    let mut note = ChatMessageRequest::new("system", format!("{}: {}", cause, changes.join(", ")));
+    note.display_only = true;
+    note
+}
+
+/// Whether `message` is a [`note`].
+pub fn is_note(message: &ChatMessageRequest) -> bool {
+    message.display_only && message.role == "system"
+}
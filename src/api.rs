@@ -0,0 +1,892 @@
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+
+use crate::attachment::{AttachmentInfo, ImageAttachment};
+use crate::error::{Error, Result};
+use crate::flatten::FlattenTemplate;
+use crate::incognito;
+use crate::paste::Cut;
+use crate::rating::{Rating, Rejected};
+use crate::recorder::Exchange;
+use crate::retry::RetryPolicy;
+use crate::stream::ChunkTiming;
+use crate::timeout;
+
+/// The OpenRouter chat completions endpoint, used unless `OPENROUTER_API_URL` is set.
+pub const DEFAULT_API_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+/// The model used until another one is picked.
+pub const DEFAULT_MODEL: &str = "deepseek/deepseek-chat-v3-0324:free";
+/// Model suffix that turns on OpenRouter's web search for any model.
+pub const ONLINE_SUFFIX: &str = ":online";
+
+/// `model` with web search turned on, i.e. its `:online` variant.
+pub fn online_model(model: &str) -> String {
+    if model.ends_with(ONLINE_SUFFIX) {
+        model.to_string()
+    } else {
+        format!("{}{}", model, ONLINE_SUFFIX)
+    }
+}
+
+/// A comma-separated list of OpenRouter transforms, as in `--transforms`.
+/// `none` (or nothing) is the empty list, which turns off OpenRouter's defaults.
+///
+/// ```
+/// use llm::api::parse_transforms;
+///
+/// assert_eq!(parse_transforms("middle-out"), ["middle-out"]);
+/// assert_eq!(parse_transforms(" middle-out, other ,"), ["middle-out", "other"]);
+/// assert!(parse_transforms("none").is_empty());
+/// ```
+pub fn parse_transforms(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && *name != "none")
+        .map(str::to_string)
+        .collect()
+}
+
+/// The app name sent as `X-Title` unless one is configured.
+pub const DEFAULT_TITLE: &str = concat!("cli_llm ", env!("CARGO_PKG_VERSION"));
+/// Sent as `User-Agent` unless one is configured.
+pub const DEFAULT_USER_AGENT: &str = concat!("cli_llm/", env!("CARGO_PKG_VERSION"));
+
+/// Which app requests come from, for OpenRouter's app attribution and
+/// leaderboards. Set in the config and per profile; an empty value sends no
+/// header at all.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attribution {
+    /// The app's site, sent as `HTTP-Referer`. `HTTP_REFERER` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referer: Option<String>,
+    /// The app's name, sent as `X-Title`. `X_TITLE` when unset, then
+    /// [`DEFAULT_TITLE`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Sent as `User-Agent` instead of [`DEFAULT_USER_AGENT`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+impl Attribution {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These settings, with those unset taken from `fallback`.
+    pub fn or(&self, fallback: &Attribution) -> Attribution {
+        Attribution {
+            referer: self.referer.clone().or_else(|| fallback.referer.clone()),
+            title: self.title.clone().or_else(|| fallback.title.clone()),
+            user_agent: self.user_agent.clone().or_else(|| fallback.user_agent.clone()),
+        }
+    }
+
+    /// Set the attribution headers in `headers`, falling back to the
+    /// environment and the defaults, replacing any there were.
+    pub fn apply_to(&self, headers: &mut HeaderMap) -> Result<()> {
+        headers.remove("HTTP-Referer");
+        headers.remove("X-Title");
+        headers.remove(USER_AGENT);
+        let referer = self.referer.clone().or_else(|| env::var("HTTP_REFERER").ok());
+        let title = self.title.clone().or_else(|| env::var("X_TITLE").ok());
+        let user_agent = self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        if let Some(referer) = referer.filter(|referer| !referer.is_empty()) {
+            headers.insert("HTTP-Referer", header_value("The referer", &referer)?);
+        }
+        match title.as_deref() {
+            Some("") => {}
+            Some(title) => {
+                headers.insert("X-Title", header_value("The title", title)?);
+            }
+            None => {
+                headers.insert("X-Title", HeaderValue::from_static(DEFAULT_TITLE));
+            }
+        }
+        if !user_agent.is_empty() {
+            headers.insert(USER_AGENT, header_value("The user agent", user_agent)?);
+        }
+        Ok(())
+    }
+}
+
+/// `value` as a header value. Characters outside ASCII are sent
+/// percent-encoded, as their UTF-8 bytes; control characters, which could end
+/// the header early, are refused.
+///
+/// ```
+/// use llm::api::header_value;
+///
+/// assert_eq!(header_value("The title", "My app").unwrap(), "My app");
+/// assert_eq!(header_value("The title", "Café 🚀").unwrap(), "Caf%C3%A9 %F0%9F%9A%80");
+/// let error = header_value("The title", "Two\nlines").unwrap_err();
+/// assert_eq!(error.to_string(), "The title contains a line break or control character");
+/// ```
+pub fn header_value(name: &str, value: &str) -> Result<HeaderValue> {
+    if value.chars().any(char::is_control) {
+        return Err(Error::Config(format!("{} contains a line break or control character", name)));
+    }
+    let mut encoded = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii() {
+            encoded.push(c);
+        } else {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    HeaderValue::from_str(&encoded)
+        .map_err(|_| Error::Config(format!("{} contains characters not allowed in a header", name)))
+}
+
+/// Prepare the default headers: JSON content type, bearer authorization (skipped for
+/// an empty key) and the [`Attribution`] headers.
+pub fn build_headers(api_key: &str, attribution: &Attribution) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if !api_key.is_empty() {
+        headers.insert(AUTHORIZATION, header_value("The API key", &format!("Bearer {}", api_key))?);
+    }
+    attribution.apply_to(&mut headers)?;
+    Ok(headers)
+}
+
+/// A chat message that we store in the conversation.
+#[derive(Clone)]
+pub struct ChatMessageRequest {
+    pub role: String,
+    pub content: String,
+    /// Persona that wrote this message in a group chat. Sent as the API `name`
+    /// field (reduced to the characters the API allows).
+    pub name: Option<String>,
+    /// Images sent along with the text as `image_url` content parts.
+    pub images: Vec<ImageAttachment>,
+    /// Attachments of a reopened conversation whose files are gone. They are
+    /// listed with the message and saved again, but not sent.
+    pub missing_attachments: Vec<AttachmentInfo>,
+    /// When the message was added to the conversation.
+    pub timestamp: Instant,
+    /// How many continuation requests were stitched into this reply after the
+    /// stream dropped (0 for an uninterrupted response).
+    pub resumed: u32,
+    /// How long the request for this reply waited in the shared
+    /// [queue](crate::queue) before it was sent.
+    pub queued: Duration,
+    /// Kept in the conversation but left out of requests to the model.
+    pub excluded: bool,
+    /// Id of the exchange a user message starts, 0 until the conversation
+    /// [numbers it](crate::conversation::Conversation::exchanges).
+    pub exchange: u64,
+    /// Shown to the user but never part of the model's context, like the greeting.
+    pub display_only: bool,
+    /// Arrival times of the streamed chunks that make up the content.
+    pub chunk_timings: Vec<ChunkTiming>,
+    /// Web sources the reply cites, listed under it.
+    pub citations: Vec<Citation>,
+    /// The provider that served the reply, as reported by OpenRouter.
+    pub provider: Option<String>,
+    /// The routing preference the reply was requested with.
+    pub route: Option<Route>,
+    /// The reply as the model sent it, when the profile's filters changed it.
+    pub unfiltered: Option<String>,
+    /// The message as typed, when long pasted blocks were shortened to send it.
+    pub untruncated: Option<String>,
+    /// How its pasted blocks were shortened, in block order.
+    pub cuts: Vec<Cut>,
+    /// The model that wrote the reply.
+    pub model: Option<String>,
+    /// How the user rated the reply.
+    pub rating: Option<Rating>,
+    /// Rated-down replies this one was asked for in place of.
+    pub rejected: Vec<Rejected>,
+    /// Tools the reply asks to call, such as the [`shell`](crate::tool) tool.
+    pub tool_calls: Vec<ToolCall>,
+    /// The call a `tool` message answers.
+    pub tool_call_id: Option<String>,
+}
+
+/// A tool call in a reply.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_kind")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+/// The function a [`ToolCall`] calls, with its arguments as a JSON string.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+fn function_kind() -> String {
+    "function".to_string()
+}
+
+impl ToolCall {
+    /// A call of the function `name`.
+    pub fn function(id: &str, name: &str, arguments: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            kind: function_kind(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+}
+
+impl ChatMessageRequest {
+    /// Who the message is from, for labels: its `name` (a persona or the
+    /// assistant's name when it arrived), else "You", "System", "Tool", or `assistant`
+    /// for unnamed replies.
+    ///
+    /// ```
+    /// use llm::api::ChatMessageRequest;
+    ///
+    /// let mut reply = ChatMessageRequest::new("assistant", "Hi");
+    /// assert_eq!(reply.speaker("LLM"), "LLM");
+    /// reply.name = Some("Marvin".to_string());
+    /// assert_eq!(reply.speaker("LLM"), "Marvin");
+    /// assert_eq!(ChatMessageRequest::new("user", "Hello").speaker("LLM"), "You");
+    /// assert_eq!(ChatMessageRequest::new("tool", "{}").speaker("LLM"), "Tool");
+    /// ```
+    pub fn speaker<'a>(&'a self, assistant: &'a str) -> &'a str {
+        match (&self.name, self.role.as_str()) {
+            (Some(name), _) => name,
+            (None, "user") => "You",
+            (None, "system") => "System",
+            (None, "tool") => "Tool",
+            (None, _) => assistant,
+        }
+    }
+
+    /// Overwrite what the message says with zeros, as [`incognito::wipe`] does.
+    pub fn wipe(&mut self) {
+        incognito::wipe(&mut self.content);
+        for text in [&mut self.unfiltered, &mut self.untruncated].into_iter().flatten() {
+            incognito::wipe(text);
+        }
+        for image in &mut self.images {
+            incognito::wipe(&mut image.data_url);
+        }
+        for call in &mut self.tool_calls {
+            incognito::wipe(&mut call.function.arguments);
+        }
+        for rejected in &mut self.rejected {
+            incognito::wipe(&mut rejected.content);
+        }
+    }
+
+    /// The text as typed, before long pasted blocks were shortened to send it.
+    pub fn typed(&self) -> &str {
+        self.untruncated.as_deref().unwrap_or(&self.content)
+    }
+
+    /// Create a message stamped with the current time.
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+            name: None,
+            images: Vec::new(),
+            missing_attachments: Vec::new(),
+            timestamp: Instant::now(),
+            resumed: 0,
+            queued: Duration::ZERO,
+            excluded: false,
+            exchange: 0,
+            display_only: false,
+            chunk_timings: Vec::new(),
+            citations: Vec::new(),
+            provider: None,
+            route: None,
+            unfiltered: None,
+            untruncated: None,
+            cuts: Vec::new(),
+            model: None,
+            rating: None,
+            rejected: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+}
+
+/// Only the API fields are sent: `role`, `content` (a list of content parts when
+/// images are attached), `name`, and the tool calls of replies and the call
+/// `tool` messages answer.
+impl Serialize for ChatMessageRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut message = serializer.serialize_struct("ChatMessageRequest", 5)?;
+        message.serialize_field("role", &self.role)?;
+        if self.images.is_empty() {
+            message.serialize_field("content", &self.content)?;
+        } else {
+            let text = (!self.content.is_empty()).then(|| json!({ "type": "text", "text": self.content }));
+            let parts: Vec<_> = text
+                .into_iter()
+                .chain(self.images.iter().map(|image| {
+                    json!({ "type": "image_url", "image_url": { "url": image.data_url } })
+                }))
+                .collect();
+            message.serialize_field("content", &parts)?;
+        }
+        if let Some(name) = &self.name {
+            message.serialize_field("name", &api_name(name))?;
+        }
+        if !self.tool_calls.is_empty() {
+            message.serialize_field("tool_calls", &self.tool_calls)?;
+        }
+        if let Some(id) = &self.tool_call_id {
+            message.serialize_field("tool_call_id", id)?;
+        }
+        message.end()
+    }
+}
+
+/// A persona name as an API `name`, which only allows `[a-zA-Z0-9_-]{1,64}`.
+fn api_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .take(64)
+        .collect()
+}
+
+/// The request body for sending to your model endpoint.
+///
+/// With `prompt` set instead of `messages` it is a text completion request, for
+/// the [`completions_url`] endpoint.
+#[derive(Serialize)]
+pub struct OpenRouterChatRequest {
+    pub model: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<ChatMessageRequest>,
+    /// The whole conversation as text, for base models without a chat format.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// Sequences that end the completion when the model writes them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    pub stream: bool,
+    /// Ask streaming endpoints to report token usage in the final chunk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    /// Expected output, letting supporting models skip regenerating unchanged text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prediction: Option<Prediction>,
+    /// Sampling temperature; the endpoint's default when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// Upper limit on the reply length, in tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// How OpenRouter picks among the providers serving the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<ProviderPreferences>,
+    /// OpenRouter prompt transforms such as `middle-out`; an empty list turns
+    /// off the ones OpenRouter applies by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<Vec<String>>,
+    /// A stable identifier of the end user, for the provider's abuse monitoring.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Tools the model may call, as JSON function definitions.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Value>,
+}
+
+impl OpenRouterChatRequest {
+    /// A non-streaming request with no optional parameters set.
+    pub fn new(model: String, messages: Vec<ChatMessageRequest>) -> Self {
+        Self {
+            model,
+            messages,
+            prompt: None,
+            stop: Vec::new(),
+            stream: false,
+            stream_options: None,
+            prediction: None,
+            temperature: None,
+            max_tokens: None,
+            provider: None,
+            transforms: None,
+            user: None,
+            tools: Vec::new(),
+        }
+    }
+}
+
+/// Options for streamed requests.
+#[derive(Serialize)]
+pub struct StreamOptions {
+    pub include_usage: bool,
+}
+
+/// Predicted output for speculative decoding (`{"type": "content", "content": ...}`).
+#[derive(Serialize)]
+pub struct Prediction {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub content: String,
+}
+
+/// The OpenRouter `provider` object: how the providers serving a model are
+/// ordered. Unset fields keep OpenRouter's defaults.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct ProviderPreferences {
+    /// `price`, `throughput` or `latency`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<&'static str>,
+    /// Try other providers when the preferred one fails.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_fallbacks: Option<bool>,
+}
+
+/// A routing preference, mapped onto [`ProviderPreferences`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Route {
+    /// The lowest price first.
+    Cheapest,
+    /// The lowest latency first.
+    Fastest,
+    /// OpenRouter's default balancing, which avoids providers with recent
+    /// outages, with fallbacks always allowed.
+    Reliable,
+}
+
+impl Route {
+    pub const ALL: [Route; 3] = [Route::Cheapest, Route::Fastest, Route::Reliable];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Route::Cheapest => "cheapest",
+            Route::Fastest => "fastest",
+            Route::Reliable => "reliable",
+        }
+    }
+
+    /// The `provider` object sent for this preference.
+    pub fn preferences(self) -> ProviderPreferences {
+        match self {
+            Route::Cheapest => ProviderPreferences {
+                sort: Some("price"),
+                allow_fallbacks: None,
+            },
+            Route::Fastest => ProviderPreferences {
+                sort: Some("latency"),
+                allow_fallbacks: None,
+            },
+            Route::Reliable => ProviderPreferences {
+                sort: None,
+                allow_fallbacks: Some(true),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Route {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "cheapest" | "price" => Ok(Route::Cheapest),
+            "fastest" | "latency" => Ok(Route::Fastest),
+            "reliable" | "most-reliable" | "most reliable" => Ok(Route::Reliable),
+            _ => Err(Error::Config(format!(
+                "Unknown routing preference '{}': use cheapest, fastest or reliable",
+                text
+            ))),
+        }
+    }
+}
+
+/// Settings that shape every request sent for the conversation.
+#[derive(Clone)]
+pub struct RequestOptions {
+    pub model: String,
+    /// Stream the reply token by token.
+    pub stream: bool,
+    /// Retry behaviour for failed requests and dropped streams.
+    pub retry: RetryPolicy,
+    /// Expected output passed as the `prediction` field.
+    pub prediction: Option<String>,
+    /// Sampling temperature; the endpoint's default when unset.
+    pub temperature: Option<f64>,
+    /// Upper limit on the reply length, in tokens.
+    pub max_tokens: Option<u32>,
+    /// Provider routing preference; OpenRouter's default when unset.
+    pub route: Option<Route>,
+    /// Send the conversation as one prompt written out with this template.
+    pub flatten: Option<FlattenTemplate>,
+    /// Send the conversation as raw text written out with this template to the
+    /// text completions endpoint, for base models. Takes precedence over `flatten`.
+    pub raw: Option<FlattenTemplate>,
+    /// Only send this many of the latest turns, each a user message and what
+    /// follows it. The whole history is kept and shown regardless.
+    pub context_turns: Option<usize>,
+    /// For metered connections: send a summary of the older turns made
+    /// locally instead of the turns themselves, only the latest few in full,
+    /// and no images. See [`Conversation::context`](crate::conversation::Conversation::context).
+    pub low_bandwidth: bool,
+    /// OpenRouter transforms applied to the prompt, e.g. `middle-out` to
+    /// compress one too long for the model's context; OpenRouter's default
+    /// when unset.
+    pub transforms: Option<Vec<String>>,
+    /// Identifier of the end user sent as `user`, for applications serving
+    /// several people; not sent when unset.
+    pub user: Option<String>,
+    /// Tools offered to the model, such as [`tool::shell_definition`](crate::tool::shell_definition).
+    pub tools: Vec<Value>,
+}
+
+impl RequestOptions {
+    /// Build the request body for `messages` with these options applied.
+    pub fn body(&self, messages: Vec<ChatMessageRequest>) -> OpenRouterChatRequest {
+        let (messages, prompt) = match (&self.raw, &self.flatten) {
+            (Some(template), _) => (Vec::new(), Some(template.apply(messages))),
+            (None, Some(template)) => (vec![template.apply(messages)], None),
+            (None, None) => (messages, None),
+        };
+        let mut body = OpenRouterChatRequest::new(self.model.clone(), messages);
+        if self.stream {
+            body.stream = true;
+            body.stream_options = Some(StreamOptions { include_usage: true });
+        }
+        body.prediction = self.prediction.clone().map(|content| Prediction {
+            kind: "content",
+            content,
+        });
+        body.temperature = self.temperature;
+        body.max_tokens = self.max_tokens;
+        body.provider = self.route.map(Route::preferences);
+        body.transforms = self.transforms.clone();
+        body.user = self.user.clone();
+        body.tools = self.tools.clone();
+        if let (Some(template), Some(prompt)) = (&self.raw, prompt) {
+            if !prompt.images.is_empty() {
+                log::warn!("Raw completions are text only; leaving out {} image(s)", prompt.images.len());
+            }
+            body.prompt = Some(prompt.content);
+            body.stop = template.stop.clone();
+            body.max_tokens = body.max_tokens.or(Some(RAW_MAX_TOKENS));
+        }
+        body
+    }
+
+    /// Where requests with these options go, given the chat endpoint.
+    pub fn url(&self, chat_url: &str) -> String {
+        match self.raw {
+            Some(_) => completions_url(chat_url),
+            None => chat_url.to_string(),
+        }
+    }
+}
+
+/// Reply length asked for in raw completions when no limit is set, since
+/// completion endpoints tend to default to a few words.
+const RAW_MAX_TOKENS: u32 = 1024;
+
+/// The text completions endpoint next to the chat endpoint `chat_url`
+/// (`…/v1/chat/completions` becomes `…/v1/completions`). Other URLs are taken
+/// to point at a completions endpoint already.
+pub fn completions_url(chat_url: &str) -> String {
+    let url = chat_url.trim_end_matches('/');
+    match url.strip_suffix("/chat/completions") {
+        Some(base) => format!("{}/completions", base),
+        None => url.to_string(),
+    }
+}
+
+/// Token usage reported by the model endpoint.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+    /// Breakdown of `completion_tokens`, when the endpoint reports it.
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+/// Detailed accounting of completion tokens.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct CompletionTokensDetails {
+    /// Predicted tokens that appeared in the completion.
+    pub accepted_prediction_tokens: Option<u64>,
+    /// Predicted tokens that did not appear in the completion (still billed).
+    pub rejected_prediction_tokens: Option<u64>,
+    /// Tokens a reasoning model spent thinking before it answered; billed as
+    /// completion tokens, though most providers don't return their text.
+    pub reasoning_tokens: Option<u64>,
+}
+
+impl Usage {
+    /// Add another usage record to this one.
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        if let Some(other_details) = other.completion_tokens_details {
+            let details = self.completion_tokens_details.get_or_insert_default();
+            let sum = |a: Option<u64>, b: Option<u64>| match (a, b) {
+                (None, None) => None,
+                (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+            };
+            details.accepted_prediction_tokens =
+                sum(details.accepted_prediction_tokens, other_details.accepted_prediction_tokens);
+            details.rejected_prediction_tokens =
+                sum(details.rejected_prediction_tokens, other_details.rejected_prediction_tokens);
+            details.reasoning_tokens = sum(details.reasoning_tokens, other_details.reasoning_tokens);
+        }
+    }
+
+    /// The completion tokens spent on reasoning, when the endpoint reported any.
+    pub fn reasoning_tokens(&self) -> Option<u64> {
+        self.completion_tokens_details?.reasoning_tokens.filter(|&tokens| tokens > 0)
+    }
+
+    /// How the completion tokens split into reasoning and answer, for reasoning
+    /// models that report it.
+    ///
+    /// ```
+    /// use llm::api::Usage;
+    ///
+    /// let usage: Usage = serde_json::from_str(
+    ///     r#"{"prompt_tokens": 12, "completion_tokens": 840, "total_tokens": 852,
+    ///         "completion_tokens_details": {"reasoning_tokens": 812}}"#,
+    /// )
+    /// .unwrap();
+    /// assert_eq!(usage.reasoning_summary().unwrap(), "812 reasoning + 28 answer tokens");
+    ///
+    /// // Models that don't reason report none, or zero.
+    /// for json in [
+    ///     r#"{"completion_tokens": 28}"#,
+    ///     r#"{"completion_tokens": 28, "completion_tokens_details": null}"#,
+    ///     r#"{"completion_tokens": 28, "completion_tokens_details": {"reasoning_tokens": 0}}"#,
+    /// ] {
+    ///     assert_eq!(serde_json::from_str::<Usage>(json).unwrap().reasoning_summary(), None);
+    /// }
+    /// ```
+    pub fn reasoning_summary(&self) -> Option<String> {
+        let reasoning = self.reasoning_tokens()?;
+        Some(format!(
+            "{} reasoning + {} answer tokens",
+            reasoning,
+            self.completion_tokens.saturating_sub(reasoning)
+        ))
+    }
+
+    /// Summary of how much of a prediction was used, if the endpoint reported it.
+    pub fn prediction_summary(&self) -> Option<String> {
+        let details = self.completion_tokens_details?;
+        let accepted = details.accepted_prediction_tokens?;
+        Some(match details.rejected_prediction_tokens {
+            Some(rejected) => format!("Prediction: {} tokens accepted, {} rejected", accepted, rejected),
+            None => format!("Prediction: {} tokens accepted", accepted),
+        })
+    }
+}
+
+/// A chat message from the model response.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChatMessage {
+    pub role: String,
+    /// The text; empty when the reply only calls tools and has it as `null`.
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: String,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+    /// Notes on the content, such as the sources web search found.
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    /// The provider that served the reply, taken from the response.
+    #[serde(skip)]
+    pub provider: Option<String>,
+}
+
+/// A string that may be `null`, as the empty string.
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// A web source cited in a reply, as returned by `:online` models.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Citation {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// An annotation on a reply's content. Only URL citations are used.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Annotation {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url_citation: Option<Citation>,
+}
+
+/// Add the URL citations among `annotations` to `citations`, skipping sources
+/// already listed.
+pub fn collect_citations(citations: &mut Vec<Citation>, annotations: &[Annotation]) {
+    for annotation in annotations {
+        if annotation.kind == "url_citation"
+            && let Some(citation) = &annotation.url_citation
+            && !citations.iter().any(|known| known.url == citation.url)
+        {
+            citations.push(citation.clone());
+        }
+    }
+}
+
+/// A single choice from the model response: a `message` from the chat
+/// endpoint, or `text` from the text completions endpoint.
+#[derive(Deserialize, Debug)]
+pub struct ChatChoice {
+    #[serde(default)]
+    pub index: Option<u32>,
+    #[serde(default)]
+    pub message: Option<ChatMessage>,
+    #[serde(default)]
+    pub text: Option<String>,
+    pub finish_reason: Option<String>,
+}
+
+/// The overall JSON response structure.
+#[derive(Deserialize, Debug)]
+pub struct OpenRouterChatResponse {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub choices: Vec<ChatChoice>,
+    pub usage: Option<Usage>,
+    /// The provider OpenRouter routed the request to.
+    #[serde(default)]
+    pub provider: Option<String>,
+}
+
+/// Render headers for logging with the authorization value redacted.
+pub fn redacted_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if name == AUTHORIZATION {
+                "Bearer <redacted>"
+            } else {
+                value.to_str().unwrap_or("<non-ascii>")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Send a non-streaming request and return the first choice with the reported usage.
+///
+/// Fails with [`Error::Timeout`] when the request takes longer than the
+/// [`timeout`] settings allow.
+pub async fn fetch_reply<B: Serialize>(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    request_body: &B,
+) -> Result<(ChatMessage, Option<Usage>)> {
+    let mut exchange = Exchange::start(url, headers, request_body);
+    let fetch = fetch(client, url, headers, request_body, &mut exchange);
+    let result = timeout::within(timeout::get().request, "no complete reply", fetch).await;
+    exchange.finish(result.as_ref().err());
+    result
+}
+
+async fn fetch<B: Serialize>(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    request_body: &B,
+    exchange: &mut Exchange,
+) -> Result<(ChatMessage, Option<Usage>)> {
+    // Make the POST request.
+    let send = client.post(url).headers(headers.clone()).json(request_body).send();
+    let response = timeout::within(timeout::get().first_byte, "no response", async { Ok(send.await?) });
+    let response = response.await?;
+
+    log::debug!(
+        "Response status={} headers=[{}]",
+        response.status(),
+        redacted_headers(response.headers())
+    );
+    let status = response.status();
+    exchange.status(status.as_u16());
+    // Read the entire response as text.
+    let response_text = response.text().await?;
+    exchange.response(&response_text);
+    if !status.is_success() {
+        return Err(Error::from_response(status, &response_text));
+    }
+    parse_reply(&response_text)
+}
+
+/// The first choice and the usage of a non-streaming response body, as
+/// [`fetch_reply`] returns them.
+pub fn parse_reply(response_text: &str) -> Result<(ChatMessage, Option<Usage>)> {
+    // Parse into our typed struct.
+    let chat_response: OpenRouterChatResponse = serde_json::from_str(response_text).inspect_err(|_| {
+        log::trace!("Raw response: {}", response_text);
+    })?;
+    log::debug!(
+        "Response id={} object={} created={} choices={} provider={:?}",
+        chat_response.id,
+        chat_response.object,
+        chat_response.created,
+        chat_response.choices.len(),
+        chat_response.provider
+    );
+
+    // Extract only the first choice's content.
+    let choice = chat_response
+        .choices
+        .first()
+        .ok_or(Error::EmptyResponse)?;
+    log::debug!(
+        "Choice index={:?} role={:?} finish_reason={:?}",
+        choice.index,
+        choice.message.as_ref().map(|message| &message.role),
+        choice.finish_reason
+    );
+    log::debug!("Usage {:?}", chat_response.usage);
+    let (content, annotations, tool_calls) = match (&choice.message, &choice.text) {
+        (Some(message), _) => (message.content.clone(), message.annotations.clone(), message.tool_calls.clone()),
+        (None, Some(text)) => (text.clone(), Vec::new(), Vec::new()),
+        (None, None) => return Err(Error::EmptyResponse),
+    };
+    let message = ChatMessage {
+        role: "assistant".to_string(),
+        content,
+        tool_calls,
+        annotations,
+        provider: chat_response.provider.clone(),
+    };
+    Ok((message, chat_response.usage))
+}
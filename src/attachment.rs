@@ -0,0 +1,166 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Default limit for the longer side of an attached image, in pixels. Larger images are
+/// downscaled before sending; most vision models resize them to about this anyway.
+pub const DEFAULT_MAX_DIMENSION: u32 = 1568;
+
+/// An image attached to a user message, encoded as a data URL.
+#[derive(Debug, Clone)]
+pub struct ImageAttachment {
+    /// `data:<mime>;base64,...`
+    pub data_url: String,
+    pub width: u32,
+    pub height: u32,
+    /// Size before downscaling, when the image was made smaller.
+    pub original_size: Option<(u32, u32)>,
+    /// The file the image was loaded from; `None` for pasted images.
+    pub source: Option<PathBuf>,
+}
+
+/// What is kept of an attachment in a saved conversation: where it came from
+/// and what it was, but not the image data, which would bloat the file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentInfo {
+    /// File name, or `pasted image` for clipboard images.
+    pub name: String,
+    /// Absolute path of the file, from which the image is loaded again on reopening.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub mime: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ImageAttachment {
+    /// Load an image file, downscaling it so neither side exceeds `max_dimension`.
+    pub fn from_path(path: &Path, max_dimension: u32) -> Result<Self> {
+        let bytes = fs::read(path).map_err(Error::file(path))?;
+        let format = image::guess_format(&bytes).map_err(|_| {
+            Error::Config(format!("{} is not a supported image (PNG, JPEG, GIF or WebP)", path.display()))
+        })?;
+        let image = image::load_from_memory_with_format(&bytes, format)?;
+        let source = Some(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+        if image.width().max(image.height()) <= max_dimension {
+            // Small enough: send the file as it is rather than re-encoding it.
+            return Ok(Self {
+                data_url: data_url(format, &bytes),
+                width: image.width(),
+                height: image.height(),
+                original_size: None,
+                source,
+            });
+        }
+        Ok(Self {
+            source,
+            ..Self::encode(image, format, max_dimension)?
+        })
+    }
+
+    /// Build an attachment from raw RGBA pixels, such as a clipboard image.
+    pub fn from_rgba(width: u32, height: u32, rgba: Vec<u8>, max_dimension: u32) -> Result<Self> {
+        let image = RgbaImage::from_raw(width, height, rgba)
+            .ok_or_else(|| Error::Config("Image data does not match its size".to_string()))?;
+        Self::encode(DynamicImage::ImageRgba8(image), ImageFormat::Png, max_dimension)
+    }
+
+    /// Downscale `image` if needed and encode it. JPEG stays JPEG; everything else
+    /// becomes PNG.
+    fn encode(image: DynamicImage, format: ImageFormat, max_dimension: u32) -> Result<Self> {
+        let original = (image.width(), image.height());
+        let image = if original.0.max(original.1) > max_dimension {
+            image.resize(max_dimension, max_dimension, FilterType::Triangle)
+        } else {
+            image
+        };
+        let (format, image) = if format == ImageFormat::Jpeg {
+            (ImageFormat::Jpeg, DynamicImage::ImageRgb8(image.to_rgb8()))
+        } else {
+            (ImageFormat::Png, image)
+        };
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), format)?;
+        let size = (image.width(), image.height());
+        Ok(Self {
+            data_url: data_url(format, &bytes),
+            width: size.0,
+            height: size.1,
+            original_size: (size != original).then_some(original),
+            source: None,
+        })
+    }
+
+    /// Dimensions and encoded size, e.g. `1568×882, 412 KB encoded (downscaled from 3840×2160)`.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{}×{}, {} encoded",
+            self.width,
+            self.height,
+            human_size(self.data_url.len())
+        );
+        if let Some((width, height)) = self.original_size {
+            summary.push_str(&format!(" (downscaled from {}×{})", width, height));
+        }
+        summary
+    }
+
+    /// The metadata kept when the message is saved.
+    pub fn info(&self) -> AttachmentInfo {
+        let mime = self
+            .data_url
+            .strip_prefix("data:")
+            .and_then(|rest| rest.split_once(';'))
+            .map_or("image/png", |(mime, _)| mime);
+        AttachmentInfo {
+            name: self
+                .source
+                .as_deref()
+                .and_then(Path::file_name)
+                .map_or_else(|| "pasted image".to_string(), |name| name.to_string_lossy().into_owned()),
+            path: self.source.clone(),
+            mime: mime.to_string(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+impl AttachmentInfo {
+    /// Load the image again from its file. Pasted images weren't kept, and the
+    /// file may have been moved or deleted since; both are errors.
+    pub fn restore(&self, max_dimension: u32) -> Result<ImageAttachment> {
+        let path = self
+            .path
+            .as_deref()
+            .ok_or_else(|| Error::Config(format!("{} was not saved with the conversation", self.name)))?;
+        ImageAttachment::from_path(path, max_dimension)
+    }
+
+    /// E.g. `chart.png (image/png, 800×600)`.
+    pub fn label(&self) -> String {
+        format!("{} ({}, {}×{})", self.name, self.mime, self.width, self.height)
+    }
+}
+
+/// Encode `bytes` as a base64 data URL.
+fn data_url(format: ImageFormat, bytes: &[u8]) -> String {
+    format!("data:{};base64,{}", format.to_mime_type(), STANDARD.encode(bytes))
+}
+
+/// A byte count in B, KB or MB.
+pub fn human_size(bytes: usize) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.0} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
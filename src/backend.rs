@@ -0,0 +1,85 @@
+use std::fmt;
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::api;
+
+/// Where a conversation's messages go: the profile and its endpoint's host.
+/// Kept for the last message sent, so that the next one isn't sent somewhere
+/// else by mistake after the profile or its URL changed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Backend {
+    #[serde(default)]
+    pub profile: String,
+    /// Empty when not known, as for conversations saved before hosts were kept.
+    #[serde(default)]
+    pub host: String,
+}
+
+impl Backend {
+    pub fn new(profile: &str, url: &str) -> Self {
+        Self {
+            profile: profile.to_string(),
+            host: host(url),
+        }
+    }
+
+    /// Whether a message sent here after one sent to `previous` goes somewhere
+    /// else. Without a known host, only the profiles are compared.
+    ///
+    /// ```
+    /// use llm::backend::Backend;
+    ///
+    /// let local = Backend::new("local", "http://localhost:11434/v1/chat/completions");
+    /// let cloud = Backend::new("default", "https://openrouter.ai/api/v1/chat/completions");
+    /// assert!(cloud.differs_from(&local));
+    /// assert!(!local.differs_from(&Backend::new("local", "http://localhost:11434/v1/completions")));
+    ///
+    /// // The same profile, edited to point somewhere else.
+    /// assert!(cloud.differs_from(&Backend::new("default", "http://127.0.0.1:8080/v1/chat/completions")));
+    ///
+    /// let saved = Backend { profile: "local".to_string(), host: String::new() };
+    /// assert!(!local.differs_from(&saved));
+    /// assert!(cloud.differs_from(&saved));
+    /// ```
+    pub fn differs_from(&self, previous: &Backend) -> bool {
+        let hosts_known = !self.host.is_empty() && !previous.host.is_empty();
+        self.profile != previous.profile || (hosts_known && self.host != previous.host)
+    }
+
+    /// Whether this is OpenRouter's host, which the prompt doesn't name.
+    pub fn is_default(&self) -> bool {
+        self.host == host(api::DEFAULT_API_URL)
+    }
+}
+
+impl fmt::Display for Backend {
+    /// `localhost:11434 (profile 'local')`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host.as_str() {
+            "" => write!(f, "profile '{}'", self.profile),
+            host => write!(f, "{} (profile '{}')", host, self.profile),
+        }
+    }
+}
+
+/// The host of `url`, with its port when it has one; the whole of `url` when it
+/// can't be parsed.
+///
+/// ```
+/// use llm::backend::host;
+///
+/// assert_eq!(host("https://openrouter.ai/api/v1/chat/completions"), "openrouter.ai");
+/// assert_eq!(host("http://127.0.0.1:8080/v1/chat/completions"), "127.0.0.1:8080");
+/// ```
+pub fn host(url: &str) -> String {
+    let Some(parsed) = Url::parse(url).ok().filter(|parsed| parsed.host_str().is_some()) else {
+        return url.to_string();
+    };
+    let host = parsed.host_str().unwrap_or_default();
+    match parsed.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+use futures_util::stream::{self, StreamExt};
+use reqwest::header::HeaderMap;
+
+use crate::api::{ChatMessageRequest, OpenRouterChatRequest, Route, StreamOptions};
+use crate::error::Result;
+use crate::queue;
+use crate::stream as sse;
+use crate::tokenizer;
+
+/// Prompt sent when none is given: short to read, long enough to answer that
+/// throughput is measured over a few hundred tokens.
+pub const DEFAULT_PROMPT: &str = "Explain in about 150 words how a hash map works.";
+
+/// A throughput benchmark: the same prompt sent to each model `repeat` times,
+/// with up to `concurrency` requests in flight.
+#[derive(Debug, Clone)]
+pub struct Bench {
+    pub prompt: String,
+    pub repeat: usize,
+    pub concurrency: usize,
+    pub route: Option<Route>,
+}
+
+/// Timing of one streamed reply.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// From sending the request to the end of the reply.
+    pub latency: Duration,
+    /// From sending the request to the first content.
+    pub first_token: Duration,
+    /// Reply tokens, as reported by the endpoint or else estimated.
+    pub tokens: u64,
+    /// From the start of the benchmark to sending the request: the time the
+    /// run waited for one of the `concurrency` slots and then for its turn in
+    /// the shared [queue](crate::queue), not spent on the model.
+    pub queued: Duration,
+}
+
+impl Sample {
+    /// Generation speed: reply tokens over the time after the first token.
+    pub fn tokens_per_sec(&self) -> f64 {
+        let generating = self.latency.saturating_sub(self.first_token);
+        let secs = if generating.is_zero() { self.latency } else { generating }.as_secs_f64();
+        if secs > 0.0 { self.tokens as f64 / secs } else { 0.0 }
+    }
+}
+
+/// Averages over the successful runs of one model.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub model: String,
+    pub runs: usize,
+    pub errors: usize,
+    pub latency: Option<Duration>,
+    pub first_token: Option<Duration>,
+    pub tokens_per_sec: Option<f64>,
+    pub queued: Option<Duration>,
+    /// The last error, when runs failed.
+    pub last_error: Option<String>,
+}
+
+impl Summary {
+    fn new(model: &str, results: &[Result<Sample>]) -> Self {
+        let samples: Vec<&Sample> = results.iter().filter_map(|result| result.as_ref().ok()).collect();
+        let count = samples.len() as u32;
+        let average = |value: fn(&Sample) -> Duration| {
+            (count > 0).then(|| samples.iter().map(|s| value(s)).sum::<Duration>() / count)
+        };
+        Self {
+            model: model.to_string(),
+            runs: results.len(),
+            errors: results.len() - samples.len(),
+            latency: average(|s| s.latency),
+            first_token: average(|s| s.first_token),
+            queued: average(|s| s.queued),
+            tokens_per_sec: (count > 0)
+                .then(|| samples.iter().map(|s| s.tokens_per_sec()).sum::<f64>() / f64::from(count)),
+            last_error: results.iter().rev().find_map(|result| result.as_ref().err()).map(|e| e.to_string()),
+        }
+    }
+}
+
+impl Bench {
+    /// Run the benchmark on every model in `models`, calling `on_sample` as each
+    /// run finishes, and return a summary per model in the order given.
+    pub async fn run(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        headers: &HeaderMap,
+        models: &[String],
+        mut on_sample: impl FnMut(&str, &Result<Sample>),
+    ) -> Vec<Summary> {
+        let runs = models
+            .iter()
+            .enumerate()
+            .flat_map(|(index, model)| std::iter::repeat_n((index, model), self.repeat));
+        let mut results: Vec<Vec<Result<Sample>>> = models.iter().map(|_| Vec::new()).collect();
+        // Runs only start once a slot is free, so the time until then is queueing.
+        let start = Instant::now();
+        let mut pending = stream::iter(runs)
+            .map(|(index, model)| async move {
+                let queued = start.elapsed();
+                let result = self.measure(client, url, headers, model).await;
+                let result = result.map(|sample| Sample {
+                    queued: queued + sample.queued,
+                    ..sample
+                });
+                (index, result)
+            })
+            .buffer_unordered(self.concurrency.max(1));
+        while let Some((index, result)) = pending.next().await {
+            on_sample(&models[index], &result);
+            results[index].push(result);
+        }
+        models
+            .iter()
+            .zip(&results)
+            .map(|(model, results)| Summary::new(model, results))
+            .collect()
+    }
+
+    /// Send the prompt to `model` once, streaming the reply to time its first token.
+    async fn measure(&self, client: &reqwest::Client, url: &str, headers: &HeaderMap, model: &str) -> Result<Sample> {
+        let mut body = OpenRouterChatRequest::new(model.to_string(), vec![ChatMessageRequest::new("user", &*self.prompt)]);
+        body.stream = true;
+        body.stream_options = Some(StreamOptions { include_usage: true });
+        body.provider = self.route.map(Route::preferences);
+
+        let ticket = queue::enter(model).await?;
+        let started = Instant::now();
+        let mut first_token = None;
+        let mut content = String::new();
+        let end = sse::stream_chat(client, url, headers, &body, |delta| {
+            first_token.get_or_insert_with(|| started.elapsed());
+            content.push_str(delta);
+        })
+        .await?;
+        let latency = started.elapsed();
+        let tokens = end
+            .usage
+            .map(|usage| usage.completion_tokens)
+            .filter(|&tokens| tokens > 0)
+            .unwrap_or(tokenizer::estimate(&content) as u64);
+        Ok(Sample {
+            latency,
+            first_token: first_token.unwrap_or(latency),
+            tokens,
+            queued: ticket.queued(),
+        })
+    }
+}
@@ -0,0 +1,200 @@
+/// Reading direction of a paragraph or a run of text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+/// Bidirectional class of a character, reduced to what the renderers need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    /// Strong left-to-right: Latin, Greek, Cyrillic, CJK, ...
+    Ltr,
+    /// Strong right-to-left: Hebrew, Arabic, Syriac, Thaana, N'Ko, ...
+    Rtl,
+    /// Digits, which read left to right but don't set the direction.
+    Number,
+    /// Combining marks, which take the class of the character they follow.
+    Mark,
+    /// Spaces and punctuation, which take the direction of their surroundings.
+    Neutral,
+}
+
+fn class(c: char) -> Class {
+    match u32::from(c) {
+        0x0300..=0x036F
+        | 0x0591..=0x05BD
+        | 0x05BF
+        | 0x05C1..=0x05C2
+        | 0x05C4..=0x05C5
+        | 0x05C7
+        | 0x0610..=0x061A
+        | 0x064B..=0x065F
+        | 0x0670
+        | 0x06D6..=0x06DC
+        | 0x06DF..=0x06E4
+        | 0x06E7..=0x06E8
+        | 0x06EA..=0x06ED => Class::Mark,
+        0x0660..=0x0669 | 0x06F0..=0x06F9 => Class::Number,
+        0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFC | 0x10800..=0x10FFF | 0x1E800..=0x1EFFF => Class::Rtl,
+        _ if c.is_numeric() => Class::Number,
+        _ if c.is_alphabetic() => Class::Ltr,
+        _ => Class::Neutral,
+    }
+}
+
+/// Classes of the characters of `chars`, with `inline code` spans counted as
+/// left-to-right text so code inside right-to-left prose keeps its order.
+fn classes(chars: &[char]) -> Vec<Class> {
+    let mut classes: Vec<Class> = chars.iter().map(|&c| class(c)).collect();
+    let ticks: Vec<usize> = chars.iter().enumerate().filter(|(_, c)| **c == '`').map(|(i, _)| i).collect();
+    for pair in ticks.chunks_exact(2) {
+        classes[pair[0]..=pair[1]].fill(Class::Ltr);
+    }
+    classes
+}
+
+/// Base direction of a paragraph: that of its first strong character, outside
+/// inline code. `None` when it has no letters at all.
+pub fn base_direction(text: &str) -> Option<Direction> {
+    let chars: Vec<char> = text.chars().collect();
+    let classes = classes(&chars);
+    let mut in_code = false;
+    chars.iter().zip(classes).find_map(|(&c, class)| {
+        if c == '`' {
+            in_code = !in_code;
+        }
+        match class {
+            _ if in_code => None,
+            Class::Ltr => Some(Direction::Ltr),
+            Class::Rtl => Some(Direction::Rtl),
+            _ => None,
+        }
+    })
+}
+
+/// Whether `text` holds any right-to-left characters.
+pub fn has_rtl(text: &str) -> bool {
+    text.chars().any(|c| class(c) == Class::Rtl)
+}
+
+/// Whether most letters of `text` are right-to-left.
+pub fn is_rtl_dominant(text: &str) -> bool {
+    let (mut ltr, mut rtl) = (0usize, 0usize);
+    for c in text.chars() {
+        match class(c) {
+            Class::Ltr => ltr += 1,
+            Class::Rtl => rtl += 1,
+            _ => {}
+        }
+    }
+    rtl > ltr
+}
+
+/// One line of text put into display order for a renderer that draws
+/// characters strictly left to right, following the implicit rules of the
+/// Unicode bidirectional algorithm: right-to-left runs are reversed, numbers
+/// and left-to-right runs inside them keep their order, and brackets in
+/// reversed runs are mirrored. Break text into lines before reordering.
+///
+/// ```
+/// use llm::bidi::{visual_line, Direction};
+///
+/// // Hebrew with a number and a code span: the words swap, the rest stays readable.
+/// assert_eq!(visual_line("שלום 42 `foo()` עולם", Direction::Rtl), "םלוע `foo()` 42 םולש");
+/// // An Arabic word in English prose, with its parenthesis mirrored.
+/// assert_eq!(visual_line("say (مرحبا) now", Direction::Ltr), "say (ابحرم) now");
+/// // English in a Hebrew sentence, with the full stop at its end.
+/// assert_eq!(visual_line("זה Rust.", Direction::Rtl), ".Rust הז");
+/// ```
+pub fn visual_line(line: &str, base: Direction) -> String {
+    let mut chars: Vec<char> = line.chars().collect();
+    let mut classes = classes(&chars);
+
+    // Marks join the preceding character; numbers after left-to-right text are part of it.
+    let mut last_strong = base;
+    for i in 0..classes.len() {
+        match classes[i] {
+            Class::Mark => classes[i] = if i == 0 { Class::Neutral } else { classes[i - 1] },
+            Class::Number if last_strong == Direction::Ltr => classes[i] = Class::Ltr,
+            Class::Ltr => last_strong = Direction::Ltr,
+            Class::Rtl => last_strong = Direction::Rtl,
+            _ => {}
+        }
+    }
+
+    // Neutrals between text of one direction take it; others take the base.
+    let side = |class: Class| match class {
+        Class::Ltr => Some(Direction::Ltr),
+        Class::Rtl | Class::Number => Some(Direction::Rtl),
+        _ => None,
+    };
+    let mut resolved = Vec::with_capacity(classes.len());
+    for (i, &class) in classes.iter().enumerate() {
+        resolved.push(side(class).unwrap_or_else(|| {
+            let before = classes[..i].iter().rev().find_map(|&c| side(c)).unwrap_or(base);
+            let after = classes[i + 1..].iter().find_map(|&c| side(c)).unwrap_or(base);
+            if before == after { before } else { base }
+        }));
+    }
+
+    // Embedding levels: even reads left to right, odd right to left.
+    let base_level = u8::from(base == Direction::Rtl);
+    let mut levels: Vec<u8> = classes
+        .iter()
+        .zip(&resolved)
+        .map(|(&class, &direction)| match (class, direction, base) {
+            (Class::Number, _, _) => 2,
+            (_, Direction::Ltr, Direction::Ltr) => 0,
+            (_, Direction::Rtl, _) => 1,
+            (_, Direction::Ltr, Direction::Rtl) => 2,
+        })
+        .collect();
+    // Trailing spaces stay at the end of the line.
+    for (level, c) in levels.iter_mut().zip(&chars).rev() {
+        if !c.is_whitespace() {
+            break;
+        }
+        *level = base_level;
+    }
+
+    for (c, &level) in chars.iter_mut().zip(&levels) {
+        if level % 2 == 1 {
+            *c = mirror(*c);
+        }
+    }
+    let highest = levels.iter().copied().max().unwrap_or(0);
+    for level in (1..=highest).rev() {
+        let mut i = 0;
+        while i < chars.len() {
+            if levels[i] < level {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < chars.len() && levels[i] >= level {
+                i += 1;
+            }
+            chars[start..i].reverse();
+            levels[start..i].reverse();
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// The mirror image of a bracket, for text shown right to left.
+fn mirror(c: char) -> char {
+    match c {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '«' => '»',
+        '»' => '«',
+        other => other,
+    }
+}
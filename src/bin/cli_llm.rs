@@ -0,0 +1,4451 @@
+//! Terminal chat client: an interactive prompt, or a single question with `-p`.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use llm::annotation::{self, Settings};
+use llm::api::{self, ChatMessageRequest, Citation, Route, Usage};
+use llm::attachment::{self, ImageAttachment};
+use llm::backend::Backend;
+use llm::bench::{self, Bench};
+use llm::checkpoint;
+use llm::boilerplate::{self, Dedupe};
+use llm::codediff::{self, Diff, Snippet};
+use llm::completion::{self, Names};
+use llm::config::{Config, Connection, Transfer};
+use llm::conversation::{self, Client, Conversation, Exchange};
+use llm::credits;
+use llm::digest::{self, Refreshed, Throttle};
+use llm::doctor;
+use llm::export::{self, HtmlOptions, Theme, Transcript};
+use llm::filter::Pipeline;
+use llm::flatten::FlattenTemplate;
+use llm::history::{self, SavedConversation};
+use llm::hyperlink::{self, LinkWriter};
+use llm::incognito;
+use llm::instance;
+use llm::ledger;
+use llm::limit::Limit;
+use llm::lint;
+use llm::locale::LocaleContext;
+use llm::models::{self, ModelInfo};
+use llm::output::{self, Output};
+use llm::paste::{self, Cut, TextAttachment};
+use llm::project::{self, ProjectContext};
+use llm::queue::{self, Queue};
+use llm::rating::{self, Rating, Rejected, Tally, Verdict};
+use llm::recorder::{self, Record};
+use llm::renderer::{self, Renderer, Renderers};
+use llm::replace::Replace;
+use llm::speech::{self, Speaker};
+use llm::storage;
+use llm::stream;
+use llm::structured;
+use llm::timeout;
+use llm::think::{self, Hider};
+use llm::tokenizer::{self, Pressure};
+use llm::tool::{self, ToolResult};
+use llm::vault;
+use llm::Result;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Editor, Helper};
+use tokio::sync::{oneshot, watch};
+
+/// Command-line options understood by the terminal client.
+#[derive(Debug)]
+struct Args {
+    /// Verbosity level: 0 = quiet, 1 = `-v` (config), 2 = `-vv` (request/response metadata).
+    verbosity: u8,
+    /// Stream responses token by token (disable with `--no-stream`).
+    stream: bool,
+    /// Print plain text without terminal hyperlinks.
+    plain: bool,
+    /// Pretty-print JSON in replies once they are complete (`--render json`).
+    render_json: bool,
+    /// Read replies aloud (`--speak`).
+    speak: bool,
+    /// Read code blocks aloud too instead of skipping them (`--speak-code`).
+    speak_code: bool,
+    /// Print the `<think>` reasoning of replies (`--show-thinking`).
+    show_thinking: bool,
+    /// Model to chat with instead of the profile's default.
+    model: Option<String>,
+    /// Use the model's `:online` variant, which searches the web (`--online`).
+    online: bool,
+    /// Send the conversation as one flattened prompt (`--flatten`).
+    flatten: bool,
+    /// Send the conversation as raw text to the completions endpoint (`--raw`),
+    /// written out with this template (`--raw-template`) or the config's.
+    raw: Option<Option<FlattenTemplate>>,
+    /// Load the instructions in the directory's `.cli_llm.md` (disable with `--no-project-file`).
+    project_file: bool,
+    /// Ask before exiting would lose work (disable with `--no-confirm`).
+    confirm: bool,
+    /// Apply templates without asking first (`--yes`).
+    yes: bool,
+    /// Send to another backend than the conversation's last message went to
+    /// without asking (`--allow-backend-change`).
+    allow_backend_change: bool,
+    /// Offer the model the shell tool, asking before each command (`--allow-shell`).
+    allow_shell: bool,
+    /// Have every configured persona answer each message (`--group`).
+    group: bool,
+    /// Write nothing to disk and make no background requests (`--incognito`).
+    incognito: bool,
+    /// Apply the profile's reply filters (disable with `--no-filter`).
+    filter: bool,
+    /// Check prompts for likely mistakes before sending (disable with `--no-lint`).
+    lint: bool,
+    /// Length limit asked of every reply (`--limit "200 words"`).
+    limit: Option<Limit>,
+    /// Only send the latest turns to the model (`--context-turns 5`).
+    context_turns: Option<usize>,
+    /// Print at most this many characters of a reply (`--max-display-chars`).
+    max_display_chars: Option<usize>,
+    /// Start in low-bandwidth mode whatever the profile says.
+    low_bandwidth: bool,
+    /// Hide boilerplate repeated across replies whatever the config says.
+    dedupe_boilerplate: bool,
+    /// Tell the model the date and locale whatever the config says (`--locale-context`).
+    locale_context: bool,
+    /// Tell the model only the date (`--date-only`).
+    date_only: bool,
+    /// OpenRouter prompt transforms (`--transforms middle-out`).
+    transforms: Option<Vec<String>>,
+    /// Config profile to use instead of the default one.
+    profile: Option<String>,
+    /// Ask this one question and exit instead of starting the interactive prompt.
+    prompt: Option<String>,
+    /// Run the prompts and commands in this file instead of reading the terminal.
+    script: Option<PathBuf>,
+    /// Write replies to this file instead of stdout (`--output-file`), which may
+    /// hold `{timestamp}` and `{model}`.
+    output_file: Option<String>,
+    /// Append to the output file instead of replacing it.
+    append: bool,
+    /// How long Ctrl+C lets a script's request in flight finish (`--grace`).
+    grace: Duration,
+    /// Leave diagrams and calendar events in replies as code (`--no-render`).
+    no_render: bool,
+    /// Images to attach to the first message (`--image`, repeatable).
+    images: Vec<PathBuf>,
+    /// Text files to attach to the first message (`--file`, repeatable).
+    files: Vec<PathBuf>,
+    /// Conversation template to start from (`new --template <NAME>`).
+    template: Option<String>,
+    /// Saved conversation to continue (`--resume <NAME>`).
+    resume: Option<String>,
+    /// `history ...`: manage saved conversations instead of chatting.
+    history: Option<Vec<String>>,
+    /// `export ...`: export data from saved conversations instead of chatting.
+    export: Option<Vec<String>>,
+    /// `models ...`: list the available models instead of chatting.
+    models: Option<Vec<String>>,
+    /// `vault ...`: manage encryption of saved conversations instead of chatting.
+    vault: Option<Vec<String>>,
+    /// `debug ...`: show or replay recorded requests instead of chatting.
+    debug: Option<Vec<String>>,
+    /// `bench ...`: measure the speed of models instead of chatting.
+    bench: Option<Vec<String>>,
+    /// `init ...`: create a project file instead of chatting.
+    init: Option<Vec<String>>,
+    /// `doctor ...`: check the setup instead of chatting.
+    doctor: Option<Vec<String>>,
+    /// `usage ...`: show the tokens used per model instead of chatting.
+    usage: Option<Vec<String>>,
+    /// `queue ...`: show or change the shared request queue instead of chatting.
+    queue: Option<Vec<String>>,
+    /// Settings export or import to run instead of chatting.
+    transfer: Transfer,
+    /// Print the credit balance instead of chatting (`--credits`).
+    credits: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            verbosity: 0,
+            stream: true,
+            plain: false,
+            render_json: false,
+            speak: false,
+            speak_code: false,
+            show_thinking: false,
+            model: None,
+            online: false,
+            flatten: false,
+            raw: None,
+            project_file: true,
+            confirm: true,
+            yes: false,
+            allow_backend_change: false,
+            allow_shell: false,
+            group: false,
+            incognito: false,
+            filter: true,
+            lint: true,
+            limit: None,
+            context_turns: None,
+            max_display_chars: None,
+            low_bandwidth: false,
+            dedupe_boilerplate: false,
+            locale_context: false,
+            date_only: false,
+            transforms: None,
+            profile: None,
+            prompt: None,
+            script: None,
+            output_file: None,
+            grace: SCRIPT_GRACE,
+            append: false,
+            no_render: false,
+            credits: false,
+            images: Vec::new(),
+            files: Vec::new(),
+            template: None,
+            resume: None,
+            history: None,
+            export: None,
+            models: None,
+            vault: None,
+            debug: None,
+            bench: None,
+            init: None,
+            doctor: None,
+            usage: None,
+            queue: None,
+            transfer: Transfer::default(),
+        }
+    }
+}
+
+impl Args {
+    /// Parse options from the process arguments, exiting on `--help` or unknown flags.
+    fn parse() -> Self {
+        let mut args = Args::default();
+        let mut iter = env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-v" | "--verbose" => args.verbosity += 1,
+                "--no-stream" => args.stream = false,
+                "--plain" => args.plain = true,
+                "--show-thinking" => args.show_thinking = true,
+                "--render" => match Self::value(&mut iter, &arg).as_str() {
+                    "json" => args.render_json = true,
+                    "text" => args.render_json = false,
+                    other => {
+                        eprintln!("Unknown --render mode: {}. Use json or text.", other);
+                        std::process::exit(2);
+                    }
+                },
+                "--speak" => args.speak = true,
+                "--speak-code" => args.speak_code = true,
+                "-m" | "--model" => args.model = Some(Self::value(&mut iter, &arg)),
+                "--online" => args.online = true,
+                "--flatten" => args.flatten = true,
+                "--raw" => args.raw = Some(args.raw.flatten()),
+                "--raw-template" => match Self::value(&mut iter, &arg).parse() {
+                    Ok(template) => args.raw = Some(Some(template)),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(2);
+                    }
+                },
+                "--no-project-file" => args.project_file = false,
+                "--no-confirm" => args.confirm = false,
+                "-y" | "--yes" => args.yes = true,
+                "--allow-backend-change" => args.allow_backend_change = true,
+                "--allow-shell" => args.allow_shell = true,
+                "--group" => args.group = true,
+                "--incognito" => args.incognito = true,
+                "--no-filter" => args.filter = false,
+                "--no-lint" => args.lint = false,
+                "--limit" => match Self::value(&mut iter, &arg).parse() {
+                    Ok(limit) => args.limit = Some(limit),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(2);
+                    }
+                },
+                "--context-turns" => match Self::value(&mut iter, &arg).parse() {
+                    Ok(turns) if turns > 0 => args.context_turns = Some(turns),
+                    _ => {
+                        eprintln!("--context-turns needs a number of turns, 1 or more");
+                        std::process::exit(2);
+                    }
+                },
+                "--max-display-chars" => match Self::value(&mut iter, &arg).parse() {
+                    Ok(chars) if chars > 0 => args.max_display_chars = Some(chars),
+                    _ => {
+                        eprintln!("--max-display-chars needs a number of characters, 1 or more");
+                        std::process::exit(2);
+                    }
+                },
+                "--low-bandwidth" => args.low_bandwidth = true,
+                "--dedupe-boilerplate" => args.dedupe_boilerplate = true,
+                "--locale-context" => args.locale_context = true,
+                "--date-only" => args.date_only = true,
+                "--transforms" => args.transforms = Some(api::parse_transforms(&Self::value(&mut iter, &arg))),
+                "--profile" => args.profile = Some(Self::value(&mut iter, &arg)),
+                "--export-config" => args.transfer.export = Some(Self::value(&mut iter, &arg).into()),
+                "--import-config" => args.transfer.import = Some(Self::value(&mut iter, &arg).into()),
+                "--include-keys" => args.transfer.include_keys = true,
+                "--replace" => args.transfer.replace = true,
+                "-p" | "--prompt" => args.prompt = Some(Self::value(&mut iter, &arg)),
+                "--repl-script" => args.script = Some(Self::value(&mut iter, &arg).into()),
+                "-o" | "--output-file" => args.output_file = Some(Self::value(&mut iter, &arg)),
+                "--grace" => {
+                    let value = Self::value(&mut iter, &arg);
+                    args.grace = parse_duration(&value).unwrap_or_else(|| {
+                        eprintln!("Invalid --grace '{}'; use e.g. 30s or 0. Try --help.", value);
+                        std::process::exit(2);
+                    });
+                }
+                "--append" => args.append = true,
+                "--no-render" => args.no_render = true,
+                "--credits" => args.credits = true,
+                "--image" => args.images.push(Self::value(&mut iter, &arg).into()),
+                "--file" => args.files.push(Self::value(&mut iter, &arg).into()),
+                "-t" | "--template" => args.template = Some(Self::value(&mut iter, &arg)),
+                "-r" | "--resume" => args.resume = Some(Self::value(&mut iter, &arg)),
+                "history" => {
+                    args.history = Some(iter.by_ref().collect());
+                }
+                "export" => {
+                    args.export = Some(iter.by_ref().collect());
+                }
+                "models" => {
+                    args.models = Some(iter.by_ref().collect());
+                }
+                "vault" => {
+                    args.vault = Some(iter.by_ref().collect());
+                }
+                "debug" => {
+                    args.debug = Some(iter.by_ref().collect());
+                }
+                "bench" => {
+                    args.bench = Some(iter.by_ref().collect());
+                }
+                "init" => {
+                    args.init = Some(iter.by_ref().collect());
+                }
+                "doctor" => {
+                    args.doctor = Some(iter.by_ref().collect());
+                }
+                "usage" => {
+                    args.usage = Some(iter.by_ref().collect());
+                }
+                "queue" => {
+                    args.queue = Some(iter.by_ref().collect());
+                }
+                // Every run starts a new conversation; `new` just reads well with --template.
+                "new" => {}
+                "-h" | "--help" => {
+                    println!("Usage: cli_llm [new] [OPTIONS]");
+                    println!("       cli_llm history [list [--all] | search [--rated up|down] <TEXT> | stats]");
+                    println!("       cli_llm history [archive <NAME> | unarchive <NAME>]");
+                    println!("       cli_llm history [checkpoints <NAME> | restore-checkpoint <NAME> <N>]");
+                    println!("       cli_llm history export <NAME> [PATH] [--dark] [--anonymize] [--redact-system]");
+                    println!("       cli_llm export ratings [--format jsonl] [PATH]");
+                    println!("       cli_llm models [--free | info <ID>]");
+                    println!("       cli_llm vault [status | enable | rekey]");
+                    println!("       cli_llm debug [last | list | replay <ID> [--model <ID>]]");
+                    println!("       cli_llm bench [<MODEL>...] [-n <RUNS>] [--concurrency <N>] [--prompt <TEXT>]");
+                    println!("       cli_llm init");
+                    println!("       cli_llm doctor");
+                    println!("       cli_llm usage");
+                    println!("       cli_llm queue [list | cancel <ID> | promote <ID>]\n");
+                    println!("  -p, --prompt <TEXT> Ask a single question, print the reply and exit.");
+                    println!("  -m, --model <ID>    Chat with this model instead of the default. `auto:free`");
+                    println!("                      picks the best free model that is currently answering.");
+                    println!("      --online        Let the model search the web (OpenRouter's :online");
+                    println!("                      variant); cited sources are listed under replies.");
+                    println!("      --flatten       Send the conversation as one prompt (\"User: …\\nAssistant: …\")");
+                    println!("                      for models that handle chat formatting poorly; the");
+                    println!("                      prefixes are set in the config's [flatten] section.");
+                    println!("      --raw           Talk to a base model through the text completions endpoint,");
+                    println!("                      sending the conversation written out with the [flatten]");
+                    println!("                      template and stopping where the next user turn would begin.");
+                    println!("      --raw-template <NAME>");
+                    println!("                      Like --raw, with a built-in template: chat, alpaca or plain.");
+                    println!("      --no-project-file");
+                    println!("                      Don't load standing instructions from a .cli_llm.md in");
+                    println!("                      this directory or above it; use in untrusted directories.");
+                    println!("      --no-confirm    Exit without offering to save the conversation, and send");
+                    println!("                      a bare \"quit\" to the model without asking; for scripts.");
+                    println!("  -y, --yes           Apply templates mid-conversation without asking; what they");
+                    println!("                      change is still shown and noted in the conversation.");
+                    println!("      --allow-backend-change");
+                    println!("                      Send to another host or profile than the conversation's last");
+                    println!("                      message went to without asking; without a terminal, such a");
+                    println!("                      message is refused unless this is given.");
+                    println!("      --allow-shell   Let the model ask to run shell commands; each one is shown");
+                    println!("                      and only runs once you confirm it. Needs a terminal.");
+                    println!("      --group         Group chat: every persona of the config's [[personas]]");
+                    println!("                      answers each message in turn, with its name on its reply.");
+                    println!("      --incognito     Write nothing to disk: no saved conversation, output file,");
+                    println!("                      flight recorder, renders or models cache, and no");
+                    println!("                      background requests. The conversation is wiped on exit.");
+                    println!("      --no-filter     Show and keep replies as the model sent them, without the");
+                    println!("                      profile's filters.");
+                    println!("      --no-lint       Don't warn about unclosed code fences, unfilled");
+                    println!("                      {{{{placeholders}}}} or code blocks that look cut off.");
+                    println!("      --no-render     Don't render mermaid and dot diagrams or save ics calendar");
+                    println!("                      events from replies, nor run the config's [renderers].");
+                    println!("      --repl-script <PATH>");
+                    println!("                      Run the prompts and /commands in a file as if typed, then");
+                    println!("                      exit. `!expect TEXT` fails unless the last reply contains");
+                    println!("                      TEXT; `!wait 2s` pauses; `#` starts a comment. Ctrl+C");
+                    println!("                      stops after the current reply, keeps the replies so far,");
+                    println!("                      and writes the lines left to run to PATH.resume.");
+                    println!("      --grace <DURATION>");
+                    println!("                      How long Ctrl+C lets a script's reply finish before");
+                    println!("                      cancelling it (default {}s); a second Ctrl+C cancels it", SCRIPT_GRACE.as_secs());
+                    println!("                      at once.");
+                    println!("  -o, --output-file <PATH>");
+                    println!("                      With --prompt or --repl-script, write replies to this file");
+                    println!("                      instead of stdout, as they stream in. {{timestamp}} and");
+                    println!("                      {{model}} in PATH are filled in. Replaces the file unless");
+                    println!("                      --append is given.");
+                    println!("      --append        Add replies to the end of the --output-file.");
+                    println!("      --image <PATH>  Attach an image to the first message; repeat for more.");
+                    println!("      --file <PATH>   Attach a text file to the first message, shortened like a");
+                    println!("                      long pasted block; repeat for more.");
+                    println!("      --limit <LIMIT> Ask for replies within a length limit such as \"200 words\",");
+                    println!("                      \"500 chars\" or \"72 subject\" (first line), and ask once");
+                    println!("                      for a shorter reply when it is exceeded.");
+                    println!("      --context-turns <N>");
+                    println!("                      Only send the latest N exchanges (and the system prompt)");
+                    println!("                      to the model; the whole conversation is still kept.");
+                    println!("      --max-display-chars <N>");
+                    println!("                      Print at most N characters of a reply; the rest is kept in");
+                    println!("                      the conversation and saved to a file named in a note.");
+                    println!("      --low-bandwidth Send a summary of older exchanges and only the latest {}", conversation::LOW_BANDWIDTH_TURNS);
+                    println!("                      in full, without images, and don't refresh the models");
+                    println!("                      list; for metered connections. /low-bandwidth toggles it.");
+                    println!("      --dedupe-boilerplate");
+                    println!("                      Hide opening and closing paragraphs repeated across replies.");
+                    println!("                      The [boilerplate] config section sets the thresholds.");
+                    println!("      --locale-context");
+                    println!("                      Tell the model today's date, your timezone, locale and units");
+                    println!("                      with every request. /debug context shows the line.");
+                    println!("      --date-only     Tell the model only today's date.");
+                    println!("      --transforms <LIST>");
+                    println!("                      Comma-separated OpenRouter prompt transforms, such as");
+                    println!("                      middle-out, which compresses prompts too long for the");
+                    println!("                      model's context; `none` turns off OpenRouter's defaults.");
+                    println!("  -t, --template <NAME>");
+                    println!("                      Start from a conversation template in the config file.");
+                    println!("  -r, --resume <NAME> Continue a conversation saved with /save.");
+                    println!("                      Encrypted conversations (`vault enable`) ask for the");
+                    println!("                      passphrase, or read it from CLI_LLM_PASSPHRASE.");
+                    println!("      --profile <NAME>");
+                    println!("                      Use this profile from the config file.");
+                    println!("      --export-config <PATH>");
+                    println!("                      Write all settings to one portable file (JSON for .json,");
+                    println!("                      TOML otherwise) and exit. Add --include-keys to include");
+                    println!("                      API keys.");
+                    println!("      --import-config <PATH>");
+                    println!("                      Merge settings from an exported file and exit. Add");
+                    println!("                      --replace to replace the current settings instead.");
+                    println!("      --credits       Print the OpenRouter credits left and what the API key has");
+                    println!("                      used, then exit.");
+                    println!("      --no-stream     Wait for complete responses instead of streaming them.");
+                    println!("      --plain         Print URLs as plain text instead of terminal hyperlinks");
+                    println!("                      (also implied by NO_COLOR).");
+                    println!("      --render json   Pretty-print and colorize JSON in replies (the whole reply,");
+                    println!("                      or JSON code blocks). Replies are shown once complete.");
+                    println!("      --show-thinking Print the <think> reasoning models put in replies instead");
+                    println!("                      of hiding it. /thinking on|off switches it.");
+                    println!("      --speak         Read replies aloud with the system's text-to-speech;");
+                    println!("                      code blocks are skipped unless --speak-code is given.");
+                    println!("  -v, --verbose       Log the resolved config and model; repeat (-vv) to also");
+                    println!("                      log request/response metadata with the API key redacted.");
+                    println!("  -h, --help          Print this help.");
+                    std::process::exit(0);
+                }
+                // Stacked short flags such as `-vv`.
+                s if s.len() > 1 && s.starts_with('-') && s[1..].chars().all(|c| c == 'v') => {
+                    args.verbosity += (s.len() - 1) as u8;
+                }
+                other => {
+                    eprintln!("Unknown argument: {}. Try --help.", other);
+                    std::process::exit(2);
+                }
+            }
+        }
+        args
+    }
+
+    /// Take the value following `flag`, exiting with a usage error when it's missing.
+    fn value(iter: &mut impl Iterator<Item = String>, flag: &str) -> String {
+        iter.next().unwrap_or_else(|| {
+            eprintln!("{} requires a value. Try --help.", flag);
+            std::process::exit(2);
+        })
+    }
+}
+
+/// Help for the commands available at the prompt.
+const COMMANDS: &str = "\
+Commands:
+  /new [TEMPLATE]  Start a new conversation, optionally from a template.
+  /templates       List the conversation templates.
+  /template NAME   Use a template's system prompt, model and parameters in this conversation,
+                   after showing what changes. -y skips the question.
+  /save [NAME]     Save the conversation to continue later with --resume.
+  /reload          Load the saved conversation again, as another window saved it.
+  /open --gui      Save the conversation and open it in the GUI.
+  /export [PATH]   Write the conversation as a self-contained HTML page. Options:
+                   --dark, --anonymize (no date, user shown as \"User\"), --redact-system.
+  /profile [NAME]  Switch to another config profile, or list them.
+  /debug context   Show what is sent ahead of the conversation: the system prompt and
+                   the project file, with their estimated sizes.
+  /curl            Print the last request as a curl command, with $OPENROUTER_API_KEY for
+                   the key; --reveal-key puts the key in.
+  /context         List the pasted blocks shortened to their first and last lines to send
+                   them, and the files attached to your next message. `/context edit N
+                   [--head H] [--tail T]` sends block or file N with that many lines;
+                   `/context more N LINE [--lines K]` sends K lines (50 by default) either
+                   side of LINE of block N with your next message; `/context drop N`
+                   takes file N off your next message.
+  /attach PATH     Attach a text file to your next message, shortened like a long pasted
+                   block; /context shows how and changes it.
+  /paste-image     Attach the image on the clipboard to your next message.
+  /clearinput      Drop what is lined up for your next message: a template's message,
+                   attached files and images, and `/context more` lines. The
+                   conversation is kept.
+                   Ctrl+U clears the line being typed.
+  /show N          Print exchange N again (the number in the prompt, [N] >).
+  /goto N          Continue from exchange N, dropping the later ones after asking.
+  /exclude N       Stop sending exchange N to the model, keeping it in the conversation;
+                   /include N sends it again.
+  /grep TEXT       List the lines of the conversation containing TEXT, by exchange.
+  /replace FIND => REPLACE
+                   Replace text in all your messages, showing the changes first, and
+                   offer to send the conversation again from the first changed message.
+                   -i ignores case; --no-resend only edits the messages.
+  /diff [M N]      Show what changed between the last code block you sent and the last one
+                   in a reply, or between the code of messages M and N (counting every
+                   message from 1). Options: -w hides changes to whitespace only,
+                   --patch PATH also writes the diff as a patch file.
+  /rate up|down [NOTE]
+                   Rate the last reply, with a note on why if you like; `/rate clear`
+                   removes the rating. `cli_llm export ratings` collects the ratings.
+  /regenerate      Ask for the last reply again. A reply rated down is kept with the new
+                   one, to export them as a preference pair.
+  /undo-op         Undo the last /goto or /new, bringing back the messages it dropped.
+  /redo-op         Do the operation undone last again.
+  /model [ID]      Chat with another model from now on, or show the current one.
+  /low-bandwidth [on|off]
+                   Send a summary of older exchanges instead of them, and no images.
+  /route [PREF]    Order providers by cheapest, fastest or reliable; `default` leaves it
+                   to OpenRouter. Shows the current preference without an argument.
+  @model:ID TEXT   Send just this message to another model, e.g. @model:openai/gpt-4o-mini.
+  /group [on|off]  Have every persona of the config's [[personas]] answer each message in
+                   turn, or list them with the tokens each has used.
+  /thinking [on|off]
+                   Print the <think> reasoning of the last reply, or turn printing it
+                   as replies arrive on or off.
+  /speak           Turn reading replies aloud on or off.
+  /stop            Stop reading the current reply aloud.
+  /help            Show this help.
+  /quit, /exit     Exit, offering to save a conversation with unsaved messages.";
+
+/// What to do about a conversation another window saved since it was opened here.
+const RELOAD_ADVICE: &str = "/reload loads that version; /save NAME keeps this one under another name.";
+
+/// Shown when speech output is asked for but no speech program is available.
+const NO_SPEECH: &str = "No text-to-speech program found (looked for say, espeak-ng, espeak and \
+                         PowerShell); replies won't be read aloud.";
+
+/// A conversation with the model and everything needed to continue it.
+struct Chat {
+    config: Config,
+    /// The profile the endpoint, headers and model came from.
+    profile: String,
+    /// The profile the conversation was started under.
+    conversation_profile: String,
+    /// The messages, the model and system prompt, and the endpoint they go to.
+    conversation: Conversation,
+    /// Write URLs as OSC 8 hyperlinks.
+    links: bool,
+    /// Pretty-print JSON in replies, waiting for them to complete.
+    render_json: bool,
+    /// Write ANSI colours.
+    color: bool,
+    /// Images waiting to be sent with the next message.
+    pending_images: Vec<ImageAttachment>,
+    /// Text files waiting to be sent with the next message (`/attach`).
+    attachments: Vec<TextAttachment>,
+    /// A template's first message, sent when the user just presses Enter.
+    draft: Option<String>,
+    /// Name the conversation was saved or resumed under.
+    saved_name: Option<String>,
+    /// Reads replies aloud while set.
+    speaker: Option<Speaker>,
+    /// Read code blocks aloud instead of skipping them.
+    speak_code: bool,
+    /// Print the `<think>` reasoning of replies instead of hiding it.
+    show_thinking: bool,
+    /// Length limit asked of every reply.
+    limit: Option<Limit>,
+    /// Model answering just the current message (`@model:ID`), instead of the default.
+    turn_model: Option<String>,
+    /// Send to the models' `:online` variants.
+    online: bool,
+    /// Length of the history when it was last saved, resumed or started.
+    saved_len: usize,
+    /// Which save of the conversation is open, to notice saves from other windows.
+    saved_stamp: Option<history::Stamp>,
+    /// Another window saved the conversation since it was resumed or saved here.
+    saved_elsewhere: bool,
+    /// Ask before exiting would lose work: off with `--no-confirm`, and when
+    /// stdin is not a terminal.
+    confirm: bool,
+    /// Ask before a template changes the settings: off with `--yes`, and when
+    /// stdin is not a terminal.
+    confirm_settings: bool,
+    /// Where the conversation's last message was sent.
+    backend: Option<Backend>,
+    /// Send to another backend without asking: `--allow-backend-change`.
+    allow_backend_change: bool,
+    /// The model may ask to run shell commands: `--allow-shell`.
+    allow_shell: bool,
+    /// Reads the answers to whether to run them.
+    tool_answers: Input,
+    /// Every persona answers each message: `--group` or `/group`.
+    group: bool,
+    /// Tokens used by each persona's replies this session.
+    persona_usage: BTreeMap<String, Usage>,
+    /// Apply the profile's reply filters: off with `--no-filter`.
+    filter: bool,
+    /// Check messages for likely mistakes before sending: off with `--no-lint`.
+    lint: bool,
+    /// Show a progress line on stderr while a reply is awaited: when stderr
+    /// is a terminal and `--plain` is not given.
+    progress: bool,
+    /// Rate limit of the background refreshes of saved conversations' summaries.
+    refreshes: Throttle,
+    /// The summary refresh in flight, with the name of the conversation it is for.
+    refresh: Option<(String, oneshot::Receiver<Result<Refreshed>>)>,
+    /// Tokens spent on summary refreshes, kept apart from the conversations' own.
+    maintenance_usage: Usage,
+    /// File replies are written to instead of stdout (`--output-file`).
+    output: Option<Output>,
+    /// Turn diagrams and calendar events in replies into files.
+    renderers: Renderers,
+    /// How long Ctrl+C lets a script's request in flight finish (`--grace`).
+    grace: Duration,
+    /// More of shortened pasted blocks, sent with the next message (`/context more`).
+    excerpts: Vec<String>,
+    /// User messages changed by `/replace`, and their images, waiting to be sent again.
+    replay: VecDeque<(String, Vec<ImageAttachment>)>,
+    /// Rated-down replies `/regenerate` dropped, kept with the reply asked for
+    /// in their place.
+    regenerated: Vec<Rejected>,
+    /// Characters of a reply printed at most (`--max-display-chars`).
+    max_display: Option<usize>,
+}
+
+/// How long Ctrl+C lets a script's request in flight finish, by default.
+const SCRIPT_GRACE: Duration = Duration::from_secs(30);
+
+/// Exit status of a `--repl-script` run stopped by Ctrl+C, as for a shell's SIGINT.
+const INTERRUPTED_EXIT: u8 = 130;
+
+/// Lines either side of the one asked for that `/context more` sends.
+const EXCERPT_LINES: usize = 50;
+
+/// Unsaved user messages from which exiting offers to save the conversation.
+const SAVE_PROMPT_TURNS: usize = 2;
+
+/// Rounds of commands a reply may lead to before no more are run.
+const TOOL_ROUNDS: usize = 8;
+
+/// How long exiting waits for a summary refresh to finish.
+const REFRESH_EXIT_WAIT: Duration = Duration::from_secs(10);
+
+/// An incognito session's conversation and what was lined up for it are
+/// overwritten before the memory is freed, however the session ends.
+impl Drop for Chat {
+    fn drop(&mut self) {
+        if !incognito::is_on() {
+            return;
+        }
+        self.conversation.wipe();
+        for text in self.draft.iter_mut().chain(&mut self.excerpts) {
+            incognito::wipe(text);
+        }
+        for (text, _) in &mut self.replay {
+            incognito::wipe(text);
+        }
+        for image in &mut self.pending_images {
+            incognito::wipe(&mut image.data_url);
+        }
+        for attachment in &mut self.attachments {
+            incognito::wipe(&mut attachment.text);
+        }
+        for rejected in &mut self.regenerated {
+            incognito::wipe(&mut rejected.content);
+        }
+    }
+}
+
+impl Chat {
+    /// Run a `/command` typed at the prompt (without the leading slash).
+    fn command(&mut self, line: &str) {
+        let (name, arg) = line.split_once(' ').map_or((line, ""), |(name, arg)| (name, arg.trim()));
+        match name {
+            "new" => {
+                let template = Some(arg).filter(|name| !name.is_empty());
+                let before = self.settings();
+                if template.is_some_and(|name| !self.confirm_template(name)) {
+                    return;
+                }
+                match self.new_conversation(template) {
+                    Ok(()) => {
+                        println!("Started a new conversation.");
+                        if let Some(name) = template {
+                            self.annotate(&format!("Template '{}'", name), &before);
+                        }
+                        self.show_draft();
+                    }
+                    Err(error) => eprintln!("{}", error),
+                }
+            }
+            "template" if !arg.is_empty() => self.apply_template(arg),
+            "template" => eprintln!("Usage: /template NAME (/templates lists them)"),
+            "templates" => {
+                if self.config.templates.is_empty() {
+                    println!("No templates configured.");
+                }
+                for name in self.config.templates.keys() {
+                    println!("  {}", name);
+                }
+            }
+            "save" => {
+                self.save(arg);
+            }
+            "reload" => self.reload(),
+            "open" => match arg {
+                "--gui" => self.open_in_gui(),
+                _ => eprintln!("Usage: /open --gui"),
+            },
+            "export" => {
+                if let Err(error) = self.export(arg) {
+                    eprintln!("{}", error);
+                }
+            }
+            "speak" => {
+                if self.speaker.take().is_some() {
+                    println!("Replies are no longer read aloud.");
+                } else {
+                    self.speaker = Speaker::detect();
+                    match self.speaker {
+                        Some(_) => println!("Replies will be read aloud. /stop interrupts, /speak turns it off."),
+                        None => eprintln!("{}", NO_SPEECH),
+                    }
+                }
+            }
+            "stop" => {
+                if let Some(speaker) = &mut self.speaker {
+                    speaker.stop();
+                }
+            }
+            "profile" => self.profile_command(arg),
+            "model" => self.model_command(arg),
+            "route" => self.route_command(arg),
+            "low-bandwidth" => self.low_bandwidth_command(arg),
+            "thinking" => self.thinking_command(arg),
+            "group" => self.group_command(arg),
+            "attach" => self.attach_file(arg),
+            "paste-image" => self.paste_image(),
+            "clearinput" => self.clear_input(),
+            "debug" => match arg {
+                "context" => self.show_context(),
+                _ => eprintln!("Usage: /debug context"),
+            },
+            "curl" => match arg {
+                "" | "--reveal-key" => match self.conversation.last_request() {
+                    Some(request) => println!("{}", request.curl(arg == "--reveal-key")),
+                    None => println!("Nothing sent yet in this conversation."),
+                },
+                _ => eprintln!("Usage: /curl [--reveal-key]"),
+            },
+            "context" => {
+                if let Err(error) = self.context_command(arg) {
+                    eprintln!("{}", error);
+                }
+            }
+            "show" => match self.turn_arg(arg) {
+                Ok(number) if number > 0 => self.show_turn(number),
+                Ok(_) => eprintln!("Usage: /show N"),
+                Err(error) => eprintln!("{}", error),
+            },
+            "goto" => match self.turn_arg(arg) {
+                Ok(number) => self.goto_turn(number),
+                Err(error) => eprintln!("{}", error),
+            },
+            "exclude" | "include" => match self.turn_arg(arg) {
+                Ok(number) if number > 0 => self.exclude_turn(number, name == "exclude"),
+                Ok(_) => eprintln!("Usage: /{} N", name),
+                Err(error) => eprintln!("{}", error),
+            },
+            "grep" if !arg.is_empty() => self.grep(arg),
+            "grep" => eprintln!("Usage: /grep TEXT"),
+            "replace" => self.replace_command(arg),
+            "diff" => {
+                if let Err(error) = self.diff(arg) {
+                    eprintln!("{}", error);
+                }
+            }
+            "rate" => self.rate(arg),
+            "regenerate" => self.regenerate(),
+            "undo-op" => self.undo_operation(false),
+            "redo-op" => self.undo_operation(true),
+            "help" => println!("{}", COMMANDS),
+            other => eprintln!("Unknown command /{}. Type /help for a list.", other),
+        }
+    }
+
+    /// The exchanges of the conversation. Their numbers are the ids the
+    /// conversation keeps for them, so an exchange keeps its number through
+    /// `/undo-op`, `/regenerate` and exchanges removed before it.
+    fn turns(&self) -> Vec<Exchange> {
+        self.conversation.exchanges()
+    }
+
+    /// The exchange `number`, if the conversation has it.
+    fn turn(&self, number: u64) -> Option<Exchange> {
+        self.turns().into_iter().find(|turn| turn.id == number)
+    }
+
+    /// The exchange number given to `/show` or `/goto`, checked against the
+    /// conversation; 0 is before the first exchange.
+    fn turn_arg(&self, arg: &str) -> Result<u64> {
+        match arg.trim_start_matches('[').trim_end_matches(']').parse::<u64>() {
+            Ok(number) if number == 0 || self.turn(number).is_some() => Ok(number),
+            Ok(number) => Err(llm::Error::Config(format!(
+                "There is no exchange {}; /grep shows the numbers of those there are.",
+                number
+            ))),
+            Err(_) => Err(llm::Error::Config("Give the number of an exchange, e.g. 3.".to_string())),
+        }
+    }
+
+    /// `/show N`: print exchange `number` again.
+    fn show_turn(&self, number: u64) {
+        let Some(turn) = self.turn(number) else {
+            return;
+        };
+        let history = self.conversation.history();
+        for msg in &history[turn.range] {
+            println!("[{}] {}: {}", number, msg.speaker("LLM"), msg.content);
+            for command in msg.tool_calls.iter().filter_map(|call| tool::shell_command(call).ok()) {
+                println!("    asked to run: $ {}", command);
+            }
+            if let Some(rating) = &msg.rating {
+                println!("    rated {}", rating.label());
+            }
+            if msg.excluded {
+                println!("    excluded from context");
+            }
+        }
+    }
+
+    /// `/exclude N`, or `/include N` when not `excluded`: leave exchange
+    /// `number` out of what is sent to the model while keeping it in the
+    /// conversation, or send it again.
+    fn exclude_turn(&mut self, number: u64, excluded: bool) {
+        let Some(turn) = self.turn(number) else {
+            return;
+        };
+        for msg in &mut self.conversation.history_mut()[turn.range] {
+            msg.excluded = excluded;
+        }
+        match excluded {
+            true => println!("Exchange {} is no longer sent to the model; /include {} sends it again.", number, number),
+            false => println!("Exchange {} is sent to the model again.", number),
+        }
+    }
+
+    /// The index of the last reply, if the conversation ends with one.
+    fn last_reply(&self) -> Option<usize> {
+        let history = self.conversation.history();
+        history
+            .iter()
+            .rposition(|msg| !msg.display_only)
+            .filter(|&index| history[index].role == "assistant")
+    }
+
+    /// `/rate up|down [NOTE]` and `/rate clear`: rate the last reply.
+    fn rate(&mut self, arg: &str) {
+        let Some(index) = self.last_reply() else {
+            eprintln!("There is no reply to rate yet.");
+            return;
+        };
+        let (verdict, note) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+        let rating = match verdict {
+            "clear" => None,
+            verdict => match verdict.parse::<Verdict>() {
+                Ok(verdict) => Some(Rating::new(verdict, note)),
+                Err(_) => {
+                    eprintln!("Usage: /rate up|down [NOTE], or /rate clear");
+                    return;
+                }
+            },
+        };
+        match &rating {
+            Some(rating) => println!("Rated the last reply {}.", rating.label()),
+            None => println!("Cleared the rating of the last reply."),
+        }
+        self.conversation.history_mut()[index].rating = rating;
+    }
+
+    /// `/regenerate`: ask for the last reply again, keeping it with the new one
+    /// when it was rated down.
+    fn regenerate(&mut self) {
+        let history = self.conversation.history();
+        let prompt = self
+            .last_reply()
+            .and_then(|reply| history[..reply].iter().rposition(|msg| msg.role == "user" && !msg.display_only));
+        let (Some(reply), Some(prompt)) = (self.last_reply(), prompt) else {
+            eprintln!("There is no reply to ask for again.");
+            return;
+        };
+        self.regenerated = rating::rejected(&history[reply]);
+        let message = &history[prompt];
+        let typed = message.typed();
+        let text = self.limit.map_or(typed, |limit| limit.strip(typed)).to_string();
+        self.replay = VecDeque::from([(text, message.images.clone())]);
+        self.conversation.truncate(prompt);
+        self.saved_len = self.saved_len.min(prompt);
+    }
+
+    /// `/goto N`: drop the exchanges after `number`, so the next message
+    /// continues from there. Asks first unless `--no-confirm` is in effect.
+    fn goto_turn(&mut self, number: u64) {
+        let turns = self.turns();
+        let kept = turns.iter().position(|turn| turn.id == number).map_or(0, |position| position + 1);
+        let (Some(later), Some(last)) = (turns.get(kept), turns.last()) else {
+            println!("Already at exchange {}.", number);
+            return;
+        };
+        let dropped = turns.len() - kept;
+        if self.confirm {
+            print!("Drop exchanges {}-{} ({} in all)? [y/N] ", later.id, last.id, dropped);
+            let _ = io::stdout().flush();
+            let mut answer = String::new();
+            if io::stdin().lock().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y") {
+                println!("Kept the conversation as it is.");
+                return;
+            }
+        }
+        self.checkpoint(&format!("before /goto {}", number));
+        self.conversation.truncate(later.range.start);
+        self.saved_len = self.saved_len.min(later.range.start);
+        println!("Continuing from exchange {}; dropped {}. /undo-op brings them back.", number, dropped);
+    }
+
+    /// `/undo-op`, or `/redo-op` when `redo`: undo a `/goto` or `/new`, or do
+    /// it again.
+    fn undo_operation(&mut self, redo: bool) {
+        let result = match redo {
+            true => self.conversation.redo_operation(),
+            false => self.conversation.undo_operation(),
+        };
+        match result {
+            Ok(what) => {
+                self.saved_len = self.saved_len.min(self.conversation.history().len());
+                println!("{} {}.", if redo { "Redid" } else { "Undid" }, what);
+            }
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+
+    /// `/grep TEXT`: print the lines containing `text` (ignoring case), with the
+    /// numbers of their exchanges for `/show` and `/goto`.
+    fn grep(&self, text: &str) {
+        let needle = text.to_lowercase();
+        let history = self.conversation.history();
+        let mut found = 0;
+        for turn in self.turns() {
+            for msg in &history[turn.range] {
+                let speaker = msg.speaker("LLM");
+                for line in msg.content.lines().filter(|line| line.to_lowercase().contains(&needle)) {
+                    println!("[{}] {}: {}", turn.id, speaker, line.trim());
+                    found += 1;
+                }
+            }
+        }
+        if found == 0 {
+            println!("No lines contain '{}'.", text);
+        }
+    }
+
+    /// `/replace [-i] [--no-resend] FIND => REPLACE`: replace text in every user
+    /// message, after showing what changes. The conversation is then sent again
+    /// from the first changed message, or, when asked, the messages are only
+    /// edited and the replies kept.
+    fn replace_command(&mut self, arg: &str) {
+        const USAGE: &str = "Usage: /replace [-i] [--no-resend] FIND => REPLACE";
+        let mut replace = Replace::default();
+        let mut resend = true;
+        let mut rest = arg;
+        while let Some((option, more)) = rest.split_once(' ') {
+            match option {
+                "-i" | "--ignore-case" => replace.ignore_case = true,
+                "--no-resend" => resend = false,
+                _ => break,
+            }
+            rest = more.trim_start();
+        }
+        let parts = rest.split_once(" => ").or_else(|| rest.strip_suffix(" =>").map(|find| (find, "")));
+        let Some((find, with)) = parts.filter(|(find, _)| !find.is_empty()) else {
+            eprintln!("{}", USAGE);
+            return;
+        };
+        replace.find = find.to_string();
+        replace.replace = with.to_string();
+        let changes = replace.changes(self.conversation.history());
+        let Some(first) = changes.first().map(|change| change.index) else {
+            println!("None of your messages contain '{}'.", find);
+            return;
+        };
+
+        let turns = self.turns();
+        let exchange = |index: usize| turns.iter().find(|turn| turn.range.contains(&index)).map_or(0, |turn| turn.id);
+        let (red, green, reset) = match self.color {
+            true => ("\x1b[31m", "\x1b[32m", "\x1b[0m"),
+            false => ("", "", ""),
+        };
+        for change in &changes {
+            println!("[{}] {} replacement(s):", exchange(change.index), change.count);
+            for (before, after) in change.lines() {
+                println!("{}- {}{}", red, before, reset);
+                println!("{}+ {}{}", green, after, reset);
+            }
+        }
+        let later = turns.iter().filter(|turn| turn.range.end > first).count();
+        if self.confirm {
+            match resend {
+                true => print!(
+                    "Send the conversation again from exchange {} ({} message(s))? [y/N/e = only edit them] ",
+                    exchange(first),
+                    later
+                ),
+                false => print!("Edit {} message(s)? [y/N] ", changes.len()),
+            }
+            let _ = io::stdout().flush();
+            let mut answer = String::new();
+            let _ = io::stdin().lock().read_line(&mut answer);
+            match answer.trim().to_ascii_lowercase().as_str() {
+                "y" | "yes" => {}
+                "e" if resend => resend = false,
+                _ => {
+                    println!("Kept the conversation as it is.");
+                    return;
+                }
+            }
+        }
+        self.checkpoint("before /replace");
+
+        if !resend {
+            let limits = self.config.paste();
+            for change in &changes {
+                let edited = change.edit(&self.conversation.history()[change.index], &limits);
+                self.conversation.edit(change.index, edited);
+            }
+            println!("Edited {} message(s); the replies are as they were. /undo-op undoes each edit.", changes.len());
+            return;
+        }
+        let limit = self.limit;
+        let history = self.conversation.history();
+        self.replay = history[first..]
+            .iter()
+            .enumerate()
+            .filter(|(_, msg)| msg.role == "user" && !msg.display_only)
+            .map(|(offset, msg)| {
+                let typed = match changes.iter().find(|change| change.index == first + offset) {
+                    Some(change) => change.after.as_str(),
+                    None => msg.typed(),
+                };
+                let text = limit.map_or(typed, |limit| limit.strip(typed));
+                (text.to_string(), msg.images.clone())
+            })
+            .collect();
+        self.conversation.truncate(first);
+        self.saved_len = self.saved_len.min(first);
+    }
+
+    /// Send the messages lined up by `/replace` and `/regenerate` again, one at a
+    /// time. A failed or stopped reply leaves the rest unsent.
+    async fn replay(&mut self) {
+        let confirm = self.confirm;
+        while let Some((text, images)) = self.replay.pop_front() {
+            println!("{}{}", self.prompt(), text);
+            self.pending_images = images;
+            let before = self.conversation.history().len();
+            let result = tokio::select! {
+                result = self.send(&text) => Some(result),
+                () = interrupted(confirm) => None,
+            };
+            let regenerated = std::mem::take(&mut self.regenerated);
+            match result {
+                Some(Ok(())) => {
+                    if let Some(index) = self.last_reply() {
+                        self.conversation.history_mut()[index].rejected = regenerated;
+                    }
+                    continue;
+                }
+                Some(Err(error)) => eprintln!("Request failed: {}", error),
+                None => {
+                    println!();
+                    eprintln!("Reply stopped.");
+                    self.conversation.history_mut().truncate(before);
+                }
+            }
+            if !self.replay.is_empty() {
+                eprintln!("{} later message(s) weren't sent again.", self.replay.len());
+                self.replay.clear();
+            }
+        }
+    }
+
+    /// `/debug context`: show the messages sent ahead of the conversation.
+    fn show_context(&self) {
+        let system = self.conversation.system_prompt.trim();
+        if system.is_empty() {
+            println!("System prompt: none");
+        } else {
+            println!("System prompt: ~{} tokens", self.conversation.tokenizer.count(system));
+        }
+        match &self.conversation.project {
+            Some(project) => println!(
+                "Project file: {} (~{} tokens)",
+                project.path.display(),
+                self.conversation.tokenizer.count(&project.content)
+            ),
+            None => println!("Project file: none"),
+        }
+        match self.conversation.locale_message() {
+            Some(locale) => println!("Date and locale: {}", locale.content),
+            None => println!("Date and locale: not sent; --locale-context sends them"),
+        }
+        if !self.cut_blocks().is_empty() || !self.attachments.is_empty() {
+            self.list_cuts();
+        }
+        if let Some(turns) = self.conversation.options.context_turns {
+            let sent = self.conversation.context().len();
+            let total = self.conversation.history().iter().filter(|msg| !msg.display_only).count();
+            println!("History: the last {} turns are sent ({} of {} messages)", turns, sent, total);
+        }
+        if let Some(transforms) = &self.conversation.options.transforms {
+            match transforms.is_empty() {
+                true => println!("Transforms: none, not even OpenRouter's defaults"),
+                false => println!("Transforms: {}, applied by OpenRouter to what is sent", transforms.join(", ")),
+            }
+        }
+        if self.conversation.options.low_bandwidth {
+            match self.conversation.summary() {
+                Some(summary) => println!(
+                    "Low bandwidth: older turns sent as a ~{} token summary, the last {} in full",
+                    self.conversation.tokenizer.count(&summary.content),
+                    conversation::LOW_BANDWIDTH_TURNS
+                ),
+                None => println!("Low bandwidth: on; no turns old enough to summarize yet"),
+            }
+        }
+        if let Some(digest) = &self.conversation.digest {
+            println!("Summary (of {} messages): {}", digest.covers, digest.preview(200));
+        }
+        if self.maintenance_usage.total_tokens > 0 {
+            println!(
+                "Maintenance: {} tokens spent on summaries this session, not counted above",
+                self.maintenance_usage.total_tokens
+            );
+        }
+        // Staged excerpts and files go with the next message.
+        let staged = self.outgoing("").content;
+        let prompt = self.conversation.prompt_tokens(&staged);
+        println!("Whole prompt with the history: ~{} tokens", prompt);
+        println!("Request size: ~{}", attachment::human_size(self.conversation.request_bytes(&staged)));
+        let model = &self.conversation.options.model;
+        match models::metadata(model).and_then(|metadata| Some((metadata.info.context_length?, metadata.source()))) {
+            Some((context, source)) => println!(
+                "Context window: {} tokens, {:.0}% used ({})",
+                context,
+                prompt as f64 * 100.0 / context.max(1) as f64,
+                source
+            ),
+            None => println!("Context window: unknown for {}", model),
+        }
+    }
+
+    /// The shortened pasted blocks of the conversation, in the order `/context`
+    /// numbers them: the index of their message, and how they were cut.
+    fn cut_blocks(&self) -> Vec<(usize, Cut)> {
+        self.conversation
+            .history()
+            .iter()
+            .enumerate()
+            .filter(|(_, msg)| !msg.display_only)
+            .flat_map(|(index, msg)| msg.cuts.iter().map(move |cut| (index, *cut)))
+            .collect()
+    }
+
+    /// The number of the exchange message `index` belongs to, as in the prompt.
+    fn turn_of(&self, index: usize) -> u64 {
+        self.turns().iter().find(|turn| turn.range.contains(&index)).map_or(0, |turn| turn.id)
+    }
+
+    /// Print the shortened pasted blocks, then the files attached to the next
+    /// message, numbered on from them for `/context edit` and `/context more`.
+    fn list_cuts(&self) {
+        let cuts = self.cut_blocks();
+        if cuts.is_empty() && self.attachments.is_empty() {
+            println!("No pasted blocks were shortened.");
+            return;
+        }
+        if !cuts.is_empty() {
+            let omitted: usize = cuts.iter().map(|(_, cut)| cut.omitted()).sum();
+            println!("Long pasted blocks: {}, {} lines left out", cuts.len(), paste::group_digits(omitted));
+        }
+        for (n, (index, cut)) in cuts.iter().enumerate() {
+            println!("  {}. in [{}]: {}", n + 1, self.turn_of(*index), cut.describe());
+        }
+        if !self.attachments.is_empty() {
+            println!("Attached to your next message:");
+        }
+        let limits = self.config.paste();
+        for (n, attachment) in self.attachments.iter().enumerate() {
+            println!("  {}. {}: {}", cuts.len() + n + 1, attachment.name, attachment.cut(&limits).describe());
+        }
+    }
+
+    /// `/context edit` or `drop` of file `n` attached to the next message.
+    fn staged_command(&mut self, action: &str, n: usize, rest: &[&str]) -> Result<()> {
+        let number = self.cut_blocks().len() + n + 1;
+        match action {
+            "edit" => {
+                let options = context_args(rest, &["--head", "--tail"])?;
+                let limits = self.config.paste();
+                let attachment = &mut self.attachments[n];
+                attachment.head = Some(options.head.or(attachment.head).unwrap_or(limits.head));
+                attachment.tail = Some(options.tail.or(attachment.tail).unwrap_or(limits.tail));
+                println!("{} will be sent as {}.", attachment.name, attachment.cut(&limits).describe());
+            }
+            "drop" => {
+                let attachment = self.attachments.remove(n);
+                println!("{} is no longer attached.", attachment.name);
+            }
+            _ => {
+                return Err(llm::Error::Config(format!(
+                    "{} isn't sent yet; /context edit {} --head H --tail T changes how much of it is.",
+                    self.attachments[n].name, number
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// `/context`: list the shortened pasted blocks, change how one is cut, or
+    /// stage more of one for the next message.
+    fn context_command(&mut self, arg: &str) -> Result<()> {
+        const USAGE: &str = "Usage: /context [edit N [--head H] [--tail T] | more N LINE [--lines K] | drop N]";
+        let words: Vec<&str> = arg.split_whitespace().collect();
+        let (action, number, rest) = match words[..] {
+            [] => {
+                self.list_cuts();
+                return Ok(());
+            }
+            [action @ ("edit" | "more" | "drop"), number, ref rest @ ..] => (action, number, rest),
+            _ => return Err(llm::Error::Config(USAGE.to_string())),
+        };
+        let cuts = self.cut_blocks();
+        let total = cuts.len() + self.attachments.len();
+        let (index, cut) = match number.parse::<usize>() {
+            Ok(n) if n > cuts.len() && n <= total => return self.staged_command(action, n - cuts.len() - 1, rest),
+            Ok(n) if (1..=cuts.len()).contains(&n) && action != "drop" => cuts[n - 1],
+            _ if total == 0 => return Err(llm::Error::Config("No pasted blocks were shortened.".to_string())),
+            _ if action == "drop" && self.attachments.is_empty() => {
+                return Err(llm::Error::Config("No files are attached to your next message.".to_string()));
+            }
+            _ if action == "drop" => {
+                return Err(llm::Error::Config(format!(
+                    "Give the number of an attached file, {} to {}; /context lists them.",
+                    cuts.len() + 1,
+                    total
+                )));
+            }
+            _ => {
+                return Err(llm::Error::Config(format!(
+                    "Give the number of a shortened block or attached file, 1 to {}; /context lists them.",
+                    total
+                )));
+            }
+        };
+        if action == "edit" {
+            let options = context_args(rest, &["--head", "--tail"])?;
+            let limits = self.config.paste();
+            let message = &mut self.conversation.history_mut()[index];
+            match paste::recut(message, &limits, cut.block, options.head, options.tail)? {
+                Some(cut) => println!("Block {} is now sent as {}.", number, cut.describe()),
+                None => println!("Block {} is now sent whole.", number),
+            }
+            return Ok(());
+        }
+        let Some((line, rest)) = rest.split_first() else {
+            return Err(llm::Error::Config(USAGE.to_string()));
+        };
+        let line = line
+            .parse::<usize>()
+            .map_err(|_| llm::Error::Config(format!("Not a line number: {}", line)))?;
+        let radius = context_args(rest, &["--lines"])?.lines.unwrap_or(EXCERPT_LINES);
+        let message = &self.conversation.history()[index];
+        let (lines, mut text) = paste::excerpt(message, cut.block, line, radius)?;
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        self.excerpts.push(format!(
+            "More of what I pasted in [{}], lines {} to {}:\n```\n{}```",
+            self.turn_of(index),
+            lines.start(),
+            lines.end(),
+            text
+        ));
+        println!(
+            "Lines {} to {} of block {} will be sent with your next message.",
+            lines.start(),
+            lines.end(),
+            number
+        );
+        Ok(())
+    }
+
+    /// `/model`: switch to another model, or show the current one.
+    fn model_command(&mut self, id: &str) {
+        if id.is_empty() {
+            let model = &self.conversation.options.model;
+            match models::context_length(model) {
+                Some(context) => println!("Model: {} ({} tokens of context)", model, context),
+                None => println!("Model: {}", model),
+            }
+            return;
+        }
+        if id == models::AUTO_FREE {
+            eprintln!("auto:free is resolved at startup; start with -m auto:free to use it.");
+            return;
+        }
+        let before = self.settings();
+        self.conversation.options.model = if self.online { api::online_model(id) } else { id.to_string() };
+        self.annotate("Switched model", &before);
+        println!("Now chatting with {}.", self.conversation.options.model);
+        self.prefetch_metadata();
+    }
+
+    /// Refresh the models list in the background unless it is fresh and
+    /// describes the current model, for the context window.
+    fn prefetch_metadata(&self) {
+        if self.conversation.options.low_bandwidth {
+            return;
+        }
+        let client = self.conversation.client();
+        models::prefetch(client.url(), client.headers(), &self.conversation.options.model);
+    }
+
+    /// `/profile`: list the profiles, or switch to the named one.
+    fn profile_command(&mut self, name: &str) {
+        if name.is_empty() {
+            if self.config.profiles.is_empty() {
+                println!("No profiles configured; using '{}'.", self.profile);
+            }
+            for name in self.config.profiles.keys() {
+                let marker = if *name == self.profile { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+            return;
+        }
+        match self.config.connection(Some(name)) {
+            Ok(connection) => {
+                self.connect(connection);
+                println!("Switched to profile '{}' (model {}).", self.profile, self.conversation.options.model);
+                // Sending a conversation to a very different backend is usually a mistake.
+                if self.conversation.history().is_empty() {
+                    self.conversation_profile = self.profile.clone();
+                } else if self.conversation_profile != self.profile {
+                    eprintln!(
+                        "Note: this conversation was started with the '{}' profile.",
+                        self.conversation_profile
+                    );
+                }
+            }
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+
+    /// `/route`: show the provider routing preference, or change it for the
+    /// following replies.
+    fn route_command(&mut self, arg: &str) {
+        let name = |route: Option<Route>| route.map_or("default", Route::name);
+        let current = self.conversation.options.route;
+        let route = match arg {
+            "" => {
+                println!("Routing: {} (cheapest, fastest, reliable or default)", name(current));
+                return;
+            }
+            "default" => None,
+            arg => match arg.parse() {
+                Ok(route) => Some(route),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return;
+                }
+            },
+        };
+        self.conversation.options.route = route;
+        println!("Routing: {} (was {})", name(route), name(current));
+    }
+
+    /// `/thinking [on|off]`: print the reasoning of the last reply, or turn
+    /// printing reasoning as replies arrive on or off.
+    fn thinking_command(&mut self, arg: &str) {
+        match arg {
+            "" => {
+                let last = self.conversation.history().iter().rev().find(|msg| msg.role == "assistant");
+                let thoughts = last.map(|msg| think::split(&msg.content)).unwrap_or_default();
+                match thoughts.has_reasoning() {
+                    true if self.color => println!("\x1b[2m{}\x1b[0m", thoughts.reasoning),
+                    true => println!("{}", thoughts.reasoning),
+                    false => println!("The last reply has no <think> reasoning."),
+                }
+            }
+            "on" | "off" => {
+                self.show_thinking = arg == "on";
+                println!("Reasoning is {} from now on.", if self.show_thinking { "printed" } else { "hidden" });
+            }
+            _ => eprintln!("Usage: /thinking [on|off]"),
+        }
+    }
+
+    /// `/group [on|off]`: switch group chat, or list the personas and the
+    /// tokens each has used.
+    fn group_command(&mut self, arg: &str) {
+        match arg {
+            "" => {
+                if self.config.personas.is_empty() {
+                    println!("No personas configured; add [[personas]] with a name, model and system_prompt to config.toml.");
+                    return;
+                }
+                println!("Group chat is {}.", if self.group { "on" } else { "off" });
+                for persona in &self.config.personas {
+                    let model = Some(persona.model.as_str()).filter(|model| !model.is_empty());
+                    let tokens = self.persona_usage.get(&persona.name).map_or(0, |usage| usage.total_tokens);
+                    println!(
+                        "  {} ({}): {} tokens",
+                        persona.name,
+                        model.unwrap_or(&self.conversation.options.model),
+                        tokens
+                    );
+                }
+            }
+            "on" if self.config.personas.is_empty() => {
+                eprintln!("No personas configured; add [[personas]] to config.toml first.");
+            }
+            "on" | "off" => {
+                self.group = arg == "on";
+                println!("Group chat {}.", arg);
+            }
+            _ => eprintln!("Usage: /group [on|off]"),
+        }
+    }
+
+    /// `/low-bandwidth [on|off]`: switch low-bandwidth mode, showing how it
+    /// changes the size of the next request.
+    fn low_bandwidth_command(&mut self, arg: &str) {
+        let current = self.conversation.options.low_bandwidth;
+        let on = match arg {
+            "" => !current,
+            "on" => true,
+            "off" => false,
+            _ => {
+                eprintln!("Usage: /low-bandwidth [on|off]");
+                return;
+            }
+        };
+        let before = self.conversation.request_bytes("");
+        self.conversation.options.low_bandwidth = on;
+        let after = self.conversation.request_bytes("");
+        println!(
+            "Low-bandwidth mode {}: requests ~{} (were ~{}).",
+            if on { "on" } else { "off" },
+            attachment::human_size(after),
+            attachment::human_size(before)
+        );
+        if on && !self.pending_images.is_empty() {
+            println!("The {} attached image(s) won't be sent.", self.pending_images.len());
+        }
+    }
+
+    /// After a request was refused for being too long for the model, say what
+    /// would make the conversation fit and, when confirmations are on, offer to
+    /// do one of them. Returns whether something was changed, so the message
+    /// can be sent again.
+    async fn offer_context_fixes(&mut self, input: &mut Input) -> Result<bool> {
+        let options = &self.conversation.options;
+        let mut fixes = Vec::new();
+        let turns = options.context_turns.map_or(CONTEXT_FIX_TURNS, |turns| turns / 2);
+        if turns > 0 && self.turns().len() > turns {
+            fixes.push((
+                ContextFix::Turns(turns),
+                format!("Send only the last {} exchanges (like --context-turns {})", turns, turns),
+            ));
+        }
+        if !options.transforms.as_ref().is_some_and(|transforms| transforms.iter().any(|t| t == "middle-out")) {
+            fixes.push((
+                ContextFix::MiddleOut,
+                "Have OpenRouter leave out the middle of the prompt (like --transforms middle-out)".to_string(),
+            ));
+        }
+        if !options.low_bandwidth {
+            fixes.push((
+                ContextFix::LowBandwidth,
+                "Send older exchanges as a summary, and no images (/low-bandwidth on)".to_string(),
+            ));
+        }
+        if fixes.is_empty() {
+            eprintln!("The conversation is too long for the model even with what is left out. Start a /new one.");
+            return Ok(false);
+        }
+        eprintln!("The conversation is too long for {}. To make it fit:", self.conversation.options.model);
+        for (i, (_, description)) in fixes.iter().enumerate() {
+            eprintln!("  {}. {}", i + 1, description);
+        }
+        if !self.confirm {
+            return Ok(false);
+        }
+        eprint!("Pick one to send your message again with it, or press Enter to leave things as they are: ");
+        io::stderr().flush()?;
+        let answer = input.line_or_interrupt(true).await?.unwrap_or_default();
+        let Some((fix, _)) = answer.trim().parse::<usize>().ok().and_then(|n| fixes.get(n.wrapping_sub(1))) else {
+            return Ok(false);
+        };
+        match *fix {
+            ContextFix::Turns(turns) => {
+                self.conversation.options.context_turns = Some(turns);
+                println!("Sending the last {} exchanges from now on.", turns);
+            }
+            ContextFix::MiddleOut => {
+                self.conversation.options.transforms.get_or_insert_with(Vec::new).push("middle-out".to_string());
+                println!("OpenRouter compresses prompts that are too long from now on.");
+            }
+            ContextFix::LowBandwidth => self.low_bandwidth_command("on"),
+        }
+        Ok(true)
+    }
+
+    /// `/clearinput`: drop what would go with the next message, leaving the
+    /// conversation as it is.
+    fn clear_input(&mut self) {
+        let mut dropped = Vec::new();
+        if self.draft.take().is_some() {
+            dropped.push("the template message".to_string());
+        }
+        if !self.pending_images.is_empty() {
+            dropped.push(format!("{} attached image(s)", self.pending_images.len()));
+            self.pending_images.clear();
+        }
+        if !self.attachments.is_empty() {
+            dropped.push(format!("{} attached file(s)", self.attachments.len()));
+            self.attachments.clear();
+        }
+        if !self.excerpts.is_empty() {
+            dropped.push(format!("{} excerpt(s) of pasted blocks", self.excerpts.len()));
+            self.excerpts.clear();
+        }
+        match dropped.is_empty() {
+            true => println!("Nothing is lined up for your next message."),
+            false => println!("Dropped {}.", dropped.join(", ")),
+        }
+    }
+
+    /// `/attach PATH`: attach a text file to the next message.
+    fn attach_file(&mut self, path: &str) {
+        if path.is_empty() {
+            eprintln!("Usage: /attach PATH");
+            return;
+        }
+        match TextAttachment::read(Path::new(path)) {
+            Ok(attachment) => {
+                println!(
+                    "Attached {}: {}. It will be sent with your next message.",
+                    attachment.name,
+                    attachment.cut(&self.config.paste()).describe()
+                );
+                self.attachments.push(attachment);
+            }
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+
+    /// `/paste-image`: attach the clipboard image to the next message.
+    fn paste_image(&mut self) {
+        if self.conversation.options.low_bandwidth {
+            eprintln!("Images aren't sent in low-bandwidth mode; /low-bandwidth off to attach one.");
+            return;
+        }
+        let image = match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_image()) {
+            Ok(image) => image,
+            Err(arboard::Error::ContentNotAvailable) => {
+                eprintln!("The clipboard does not contain an image.");
+                return;
+            }
+            Err(e) => {
+                eprintln!("Cannot read the clipboard: {}", e);
+                return;
+            }
+        };
+        let (width, height) = (image.width as u32, image.height as u32);
+        let rgba = image.bytes.into_owned();
+
+        // Keep a copy on disk so it's easy to check what is being sent, unless incognito.
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let path = env::temp_dir().join(format!("cli_llm-paste-{}.png", millis));
+        let written = match incognito::is_on() {
+            true => Ok(false),
+            false => encode_png(&rgba, width, height).and_then(|png| storage::write_atomic(&path, png)).map(|()| true),
+        };
+        let saved = match written {
+            Ok(false) => String::new(),
+            Ok(true) if self.links => format!(", saved to {}", hyperlink::file_link(&path)),
+            Ok(true) => format!(", saved to {}", path.display()),
+            Err(e) => {
+                log::warn!("Could not save pasted image to {}: {}", path.display(), e);
+                String::new()
+            }
+        };
+
+        match ImageAttachment::from_rgba(width, height, rgba, self.config.image_max_dimension()) {
+            Ok(attachment) => {
+                println!(
+                    "Attached clipboard image: {}{}. It will be sent with your next message.",
+                    attachment.summary(),
+                    saved
+                );
+                self.pending_images.push(attachment);
+            }
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+
+    /// Drop the conversation and start over, set up from the named template if given.
+    ///
+    /// Template fields that are unset keep the current settings.
+    fn new_conversation(&mut self, template: Option<&str>) -> Result<()> {
+        let template = template.map(|name| self.config.template(name).cloned()).transpose()?;
+        let settings = template.as_ref().map(|template| self.settings().with_template(template)).transpose()?;
+        let messages = template.as_ref().map(|template| template.context_messages()).unwrap_or_default();
+        self.checkpoint("before /new");
+        self.conversation.clear(messages);
+        self.conversation.digest = None;
+        self.conversation_profile = self.profile.clone();
+        self.backend = None;
+        self.draft = None;
+        self.excerpts.clear();
+        self.saved_name = None;
+        self.saved_len = 0;
+        self.saved_stamp = None;
+        self.saved_elsewhere = false;
+        let (Some(template), Some(settings)) = (template, settings) else {
+            return Ok(());
+        };
+        self.apply_settings(settings);
+        self.draft = template.first_message;
+        self.saved_len = self.conversation.history().len();
+        Ok(())
+    }
+
+    /// The settings templates change.
+    fn settings(&self) -> Settings {
+        let options = &self.conversation.options;
+        Settings {
+            model: options.model.clone(),
+            system_prompt: self.conversation.system_prompt.clone(),
+            temperature: options.temperature,
+            max_tokens: options.max_tokens,
+            limit: self.limit,
+        }
+    }
+
+    fn apply_settings(&mut self, settings: Settings) {
+        let options = &mut self.conversation.options;
+        options.model = settings.model;
+        options.temperature = settings.temperature;
+        options.max_tokens = settings.max_tokens;
+        self.conversation.system_prompt = settings.system_prompt;
+        self.limit = settings.limit;
+    }
+
+    /// Show what template `name` would change, and ask whether to go ahead
+    /// unless `--yes` is in effect. False when the user declines, or there is no
+    /// such template.
+    fn confirm_template(&self, name: &str) -> bool {
+        let after = match self.config.template(name).and_then(|template| self.settings().with_template(template)) {
+            Ok(after) => after,
+            Err(error) => {
+                eprintln!("{}", error);
+                return false;
+            }
+        };
+        let changes = self.settings().changes(&after);
+        if changes.is_empty() {
+            return true;
+        }
+        println!("Template '{}' changes:", name);
+        for change in &changes {
+            println!("  {}", change);
+        }
+        if !self.confirm_settings {
+            return true;
+        }
+        print!("Apply it? [y/N] ");
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y") {
+            println!("Kept the current settings.");
+            return false;
+        }
+        true
+    }
+
+    /// `/template NAME`: use a template's settings in this conversation,
+    /// keeping its messages. The template's first message becomes the draft.
+    fn apply_template(&mut self, name: &str) {
+        let before = self.settings();
+        if !self.confirm_template(name) {
+            return;
+        }
+        let template = self.config.template(name).cloned();
+        let applied = template.and_then(|template| Ok((before.with_template(&template)?, template.first_message)));
+        let (settings, first_message) = match applied {
+            Ok(applied) => applied,
+            Err(error) => {
+                eprintln!("{}", error);
+                return;
+            }
+        };
+        self.apply_settings(settings);
+        self.annotate(&format!("Template '{}'", name), &before);
+        println!("Using template '{}'.", name);
+        if first_message.is_some() {
+            self.draft = first_message;
+            self.show_draft();
+        }
+    }
+
+    /// Note in the conversation what changed since `before`, and why.
+    fn annotate(&mut self, cause: &str, before: &Settings) {
+        let changes = before.changes(&self.settings());
+        if !changes.is_empty() {
+            self.conversation.history_mut().push(annotation::note(cause, &changes));
+        }
+    }
+
+    /// User messages added since the conversation was last saved, resumed or started.
+    fn unsaved_turns(&self) -> usize {
+        self.conversation
+            .history()
+            .iter()
+            .skip(self.saved_len)
+            .filter(|msg| msg.role == "user")
+            .count()
+    }
+
+    /// Before exiting, offer to save a conversation with unsaved messages. Ctrl+C
+    /// or the end of input exits without saving.
+    async fn confirm_exit(&mut self, input: &mut Input) -> Result<()> {
+        if !self.confirm || self.unsaved_turns() < SAVE_PROMPT_TURNS || incognito::is_on() {
+            return Ok(());
+        }
+        loop {
+            print!("Save the conversation before exiting? [y/N/name] ");
+            io::stdout().flush()?;
+            let Some(answer) = input.line_or_interrupt(true).await? else {
+                println!();
+                return Ok(());
+            };
+            let saved = match answer.trim() {
+                "" => return Ok(()),
+                answer if answer.eq_ignore_ascii_case("n") || answer.eq_ignore_ascii_case("no") => return Ok(()),
+                answer if answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes") => self.save(""),
+                name => self.save(name),
+            };
+            if saved {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Continue a saved conversation. Archived conversations are read-only.
+    fn resume(&mut self, name: &str) -> Result<()> {
+        unlock_vault()?;
+        let saved = history::load(name)?;
+        if saved.archived {
+            return Err(history::archived_error(name));
+        }
+        self.saved_stamp = Some(saved.stamp());
+        self.saved_elsewhere = false;
+        self.backend = saved.last_backend();
+        *self.conversation.history_mut() = saved.requests();
+        self.conversation.forget_operations();
+        let missing: Vec<String> = self
+            .conversation
+            .history()
+            .iter()
+            .flat_map(|msg| &msg.missing_attachments)
+            .map(|info| info.label())
+            .collect();
+        if !missing.is_empty() {
+            eprintln!("These attachments could not be found and won't be sent again:");
+            for label in missing {
+                eprintln!("  📎 {}", label);
+            }
+        }
+        self.conversation.system_prompt = saved.system_prompt.unwrap_or_default();
+        if !saved.model.is_empty() {
+            self.conversation.options.model = saved.model;
+        }
+        if saved.route.is_some() {
+            self.conversation.options.route = saved.route;
+        }
+        if saved.raw.is_some() {
+            self.conversation.options.raw = saved.raw;
+        }
+        if !saved.profile.is_empty() {
+            self.conversation_profile = saved.profile;
+        }
+        self.conversation.digest = saved.summary;
+        self.saved_name = Some(saved.name);
+        self.saved_len = self.conversation.history().len();
+        Ok(())
+    }
+
+    /// `/save [NAME]`: save the conversation, under its previous name by default.
+    /// Returns whether it was saved.
+    fn save(&mut self, name: &str) -> bool {
+        let name = match (name, &self.saved_name) {
+            ("", Some(saved)) => saved.clone(),
+            ("", None) => {
+                let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+                format!("chat-{}", secs)
+            }
+            (name, _) => name.to_string(),
+        };
+        let mut saved = self.snapshot(&name);
+        // Saving over the copy that is open here must not undo another window's save.
+        let stamp = self.saved_stamp.as_mut().filter(|_| self.saved_name.as_deref() == Some(name.as_str()));
+        // Refused before the vault could ask for its passphrase.
+        let result = incognito::refuse("Saving conversations")
+            .and_then(|()| unlock_vault())
+            .and_then(|()| match stamp {
+                Some(stamp) => saved.save_over(stamp),
+                None => saved.save(),
+            });
+        match result {
+            Ok(()) => {
+                println!("Saved as '{}'. Continue later with --resume {}.", name, name);
+                self.saved_name = Some(name);
+                self.saved_len = self.conversation.history().len();
+                self.saved_stamp = Some(saved.stamp());
+                self.saved_elsewhere = false;
+                true
+            }
+            Err(error @ llm::Error::Conflict { .. }) => {
+                self.saved_elsewhere = true;
+                eprintln!("Not saved: {}.\n{}", error, RELOAD_ADVICE);
+                false
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    /// The conversation as it would be saved under `name`.
+    fn snapshot(&self, name: &str) -> SavedConversation {
+        let mut saved = SavedConversation::new(name, self.conversation.history());
+        saved.profile = self.conversation_profile.clone();
+        saved.backend = self.backend.clone();
+        saved.model = self.conversation.options.model.clone();
+        saved.route = self.conversation.options.route;
+        saved.raw = self.conversation.options.raw.clone();
+        saved.system_prompt = Some(self.conversation.system_prompt.clone()).filter(|p| !p.trim().is_empty());
+        saved.summary = self.conversation.digest.clone();
+        saved
+    }
+
+    /// Take a checkpoint of the conversation, if it was saved, before `reason`
+    /// rewrites its history. It is taken before going on, so it is on disk
+    /// even if the client stops right after.
+    fn checkpoint(&self, reason: &str) {
+        let Some(name) = &self.saved_name else {
+            return;
+        };
+        if let Err(error) = checkpoint::take(&self.snapshot(name), reason) {
+            eprintln!("No checkpoint taken: {}", error);
+        }
+    }
+
+    /// Tell the user, once, when another window saved the conversation since it
+    /// was resumed or saved here.
+    fn check_saved(&mut self) {
+        if self.saved_elsewhere {
+            return;
+        }
+        if let (Some(name), Some(stamp)) = (&self.saved_name, &mut self.saved_stamp)
+            && history::changed(name, stamp)
+        {
+            eprintln!("'{}' was saved in another window since it was opened here.\n{}", name, RELOAD_ADVICE);
+            self.saved_elsewhere = true;
+        }
+    }
+
+    /// `/reload`: load the saved conversation again, dropping what wasn't saved here.
+    fn reload(&mut self) {
+        let Some(name) = self.saved_name.clone() else {
+            eprintln!("The conversation isn't saved; there is nothing to reload.");
+            return;
+        };
+        let dropped = self.unsaved_turns();
+        match self.resume(&name) {
+            Ok(()) if dropped > 0 => println!("Reloaded '{}', dropping {} unsaved message(s).", name, dropped),
+            Ok(()) => println!("Reloaded '{}'.", name),
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+
+    /// `/open --gui`: save the conversation and open it in the GUI.
+    fn open_in_gui(&mut self) {
+        if !self.save("") {
+            return;
+        }
+        let Some(name) = self.saved_name.clone() else {
+            return;
+        };
+        match instance::open_in_gui(&name) {
+            Ok(()) => println!("Opened '{}' in the GUI. To carry on here after chatting there, /reload first.", name),
+            Err(error) => eprintln!("Cannot start the GUI (llm): {}", error),
+        }
+    }
+
+    /// Keep the saved conversation's summary up to date: take in a finished
+    /// refresh, and start one when it is due, unless background requests are
+    /// turned off. Unsaved conversations aren't summarized.
+    fn maintain(&mut self) {
+        if let Some((name, pending)) = &mut self.refresh {
+            let result = match pending.try_recv() {
+                Err(oneshot::error::TryRecvError::Empty) => return,
+                Ok(result) => result,
+                Err(oneshot::error::TryRecvError::Closed) => Err(llm::Error::Config("the task ended".to_string())),
+            };
+            let name = name.clone();
+            self.refresh = None;
+            self.apply_refresh(&name, result);
+        }
+        let Some(name) = self.saved_name.clone() else {
+            return;
+        };
+        let history = self.conversation.history();
+        if self.config.no_background_requests
+            || incognito::is_on()
+            || !digest::due(self.conversation.digest.as_ref(), history)
+            || !self.refreshes.start()
+        {
+            return;
+        }
+        let client = self.conversation.client().clone();
+        let configured = self.config.summary_model.as_deref();
+        let model = digest::model(configured, &client, &self.conversation.options.model).to_string();
+        let previous = self.conversation.digest.clone();
+        let messages = history.to_vec();
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = tx.send(digest::refresh(&client, &model, previous.as_ref(), &messages).await);
+        });
+        self.refresh = Some((name, rx));
+    }
+
+    /// Store a refreshed summary with the conversation saved as `name`.
+    fn apply_refresh(&mut self, name: &str, result: Result<Refreshed>) {
+        self.refreshes.finish();
+        let (digest, usage) = match result {
+            Ok(refreshed) => refreshed,
+            Err(error) => {
+                log::info!("Could not refresh the summary of '{}': {}", name, error);
+                return;
+            }
+        };
+        if let Some(usage) = usage {
+            self.maintenance_usage.add(&usage);
+        }
+        if self.saved_name.as_deref() == Some(name) {
+            self.conversation.digest = Some(digest.clone());
+        }
+        if let Err(error) = history::set_summary(name, digest) {
+            log::warn!("Could not save the summary of '{}': {}", name, error);
+        }
+    }
+
+    /// Before exiting, give a summary refresh in flight, or one due, a few
+    /// seconds to finish. Ctrl+C stops waiting.
+    async fn finish_maintenance(&mut self) {
+        self.maintain();
+        let Some((name, pending)) = self.refresh.take() else {
+            return;
+        };
+        eprintln!("Updating the summary of '{}'... (Ctrl+C skips)", name);
+        let result = tokio::select! {
+            result = pending => result.unwrap_or_else(|_| Err(llm::Error::Config("the task ended".to_string()))),
+            () = tokio::time::sleep(REFRESH_EXIT_WAIT) => return,
+            () = interrupted(true) => return,
+        };
+        self.apply_refresh(&name, result);
+    }
+
+    /// `/export [PATH] [OPTIONS]`: write the conversation, with its images, as a
+    /// self-contained HTML page.
+    fn export(&self, arg: &str) -> Result<()> {
+        let words: Vec<&str> = arg.split_whitespace().collect();
+        let (path, options) = export_args(&words)?;
+        let title = self.saved_name.as_deref().unwrap_or("conversation");
+        let transcript = Transcript {
+            title,
+            model: &self.conversation.options.model,
+            saved_at: None,
+            system_prompt: &self.conversation.system_prompt,
+            messages: self.conversation.history(),
+        };
+        let path = path.unwrap_or_else(|| export::default_path(title));
+        export::write_html(&path, &transcript, &options)?;
+        let shown = if self.links { hyperlink::file_link(&path) } else { path.display().to_string() };
+        println!("Exported to {}", shown);
+        Ok(())
+    }
+
+    /// `/diff [M N] [-w] [--patch PATH]`: print what changed between two code
+    /// blocks of the conversation as a unified diff.
+    fn diff(&self, arg: &str) -> Result<()> {
+        let words: Vec<&str> = arg.split_whitespace().collect();
+        let args = diff_args(&words)?;
+        let history = self.conversation.history();
+        let (old, new) = match args.messages {
+            Some((old, new)) => codediff::between(history, old, new)?,
+            None => codediff::latest(history)?,
+        };
+        let diff = Diff::snippets(&old, &new, args.ignore_whitespace);
+        let speaker = |snippet: &Snippet| history[snippet.message - 1].speaker("LLM").to_string();
+        println!(
+            "Comparing the code of message {} ({}) with message {} ({}).",
+            old.message,
+            speaker(&old),
+            new.message,
+            speaker(&new)
+        );
+        if diff.is_empty() {
+            match args.ignore_whitespace {
+                true => println!("Only whitespace changed."),
+                false => println!("No changes."),
+            }
+        } else {
+            let patch = diff.patch(&old, &new);
+            for (i, line) in patch.lines().enumerate() {
+                let color = match line.as_bytes().first() {
+                    _ if !self.color => None,
+                    // The file names.
+                    _ if i < 2 => Some("\x1b[1m"),
+                    Some(b'@') => Some("\x1b[36m"),
+                    Some(b'+') => Some("\x1b[32m"),
+                    Some(b'-') => Some("\x1b[31m"),
+                    _ => None,
+                };
+                match color {
+                    Some(color) => println!("{}{}\x1b[0m", color, line),
+                    None => println!("{}", line),
+                }
+            }
+            let (added, removed) = diff.counts();
+            let hidden = match args.ignore_whitespace {
+                true => 0,
+                false => added.saturating_sub(Diff::snippets(&old, &new, true).counts().0),
+            };
+            let note = match hidden {
+                0 => String::new(),
+                n => format!("; {} only change whitespace, /diff -w hides them", n),
+            };
+            println!("{} added, {} removed{}.", added, removed, note);
+        }
+        if let Some(path) = &args.patch {
+            incognito::refuse("Writing patches")?;
+            storage::write_atomic(path, diff.patch(&old, &new)).map_err(llm::Error::file(path))?;
+            let shown = if self.links { hyperlink::file_link(path) } else { path.display().to_string() };
+            println!("Wrote the patch to {}", shown);
+        }
+        Ok(())
+    }
+
+    /// List the web sources a reply cites, numbered, with clickable links.
+    fn print_sources(&self, citations: &[Citation]) {
+        if citations.is_empty() {
+            return;
+        }
+        println!("Sources:");
+        for (i, citation) in citations.iter().enumerate() {
+            let title = citation.title.as_deref().filter(|title| !title.trim().is_empty());
+            let line = match (title, self.links) {
+                (Some(title), true) => hyperlink::osc8(&citation.url, title),
+                (Some(title), false) => format!("{} <{}>", title, citation.url),
+                (None, true) => hyperlink::linkify(&citation.url),
+                (None, false) => citation.url.clone(),
+            };
+            println!("  [{}] {}", i + 1, line);
+        }
+    }
+
+    /// Run a `--repl-script` file: each line is echoed and handled as if typed at
+    /// the prompt, so scripted demos and end-to-end checks go through the same paths.
+    ///
+    /// Blank lines and `#` comments are skipped. Two directives are understood:
+    /// `!wait <DURATION>` pauses (`2`, `1.5s`, `500ms`), and `!expect <TEXT>` stops
+    /// the script with an error unless the last reply contains TEXT (any reply at
+    /// all when TEXT is empty).
+    ///
+    /// Ctrl+C stops the script before its next line. A request in flight gets
+    /// [`grace`](Self::grace) to finish, or is cancelled by a second Ctrl+C; a
+    /// cancelled reply is taken back, from the output file too. The lines left
+    /// are then written out by [`stop_script`](Self::stop_script).
+    async fn run_script(&mut self, path: &Path) -> Result<()> {
+        let script = fs::read_to_string(path).map_err(llm::Error::file(path))?;
+        // Nobody is at the terminal to answer questions while a script runs.
+        self.confirm = false;
+        let lines: Vec<&str> = script.lines().collect();
+        let mut presses = count_interrupts();
+        let mut last_reply: Option<String> = None;
+        for (number, line) in lines.iter().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fail = |message: String| llm::Error::Config(format!("{}:{}: {}", path.display(), number + 1, message));
+            if let Some(directive) = line.strip_prefix('!') {
+                let (name, arg) = directive.split_once(' ').map_or((directive, ""), |(name, arg)| (name, arg.trim()));
+                match name {
+                    "wait" => {
+                        let duration = parse_duration(arg).ok_or_else(|| fail(format!("invalid duration '{}'", arg)))?;
+                        tokio::select! {
+                            () = tokio::time::sleep(duration) => {}
+                            _ = presses.changed() => {}
+                        }
+                    }
+                    "expect" => match &last_reply {
+                        Some(reply) if reply.contains(arg) => {}
+                        Some(_) => return Err(fail(format!("expected the reply to contain '{}'", arg))),
+                        None => return Err(fail("expected a reply, but there was none".to_string())),
+                    },
+                    _ => return Err(fail(format!("unknown directive '!{}'; use !wait or !expect", name))),
+                }
+                continue;
+            }
+
+            if *presses.borrow() > 0 {
+                return self.stop_script(path, &lines[number..]);
+            }
+            println!("> {}", line);
+            if matches!(line, "/quit" | "/exit") {
+                break;
+            }
+            if let Some(command) = line.strip_prefix('/') {
+                self.command(command);
+                self.replay().await;
+                continue;
+            }
+            self.draft = None;
+            let before = self.conversation.history().len();
+            let grace = self.grace;
+            let result = {
+                let send = self.send(line);
+                tokio::pin!(send);
+                let mut deadline = None;
+                loop {
+                    let expired = async {
+                        match deadline {
+                            Some(deadline) => tokio::time::sleep_until(deadline).await,
+                            None => std::future::pending().await,
+                        }
+                    };
+                    tokio::select! {
+                        result = &mut send => break Some(result),
+                        () = expired => break None,
+                        Ok(()) = presses.changed() => {
+                            if *presses.borrow() > 1 || grace.is_zero() {
+                                break None;
+                            }
+                            eprintln!(
+                                "Stopping the script after this reply (waiting up to {:?}); Ctrl+C again cancels it.",
+                                grace
+                            );
+                            deadline = Some(tokio::time::Instant::now() + grace);
+                        }
+                    }
+                }
+            };
+            let Some(result) = result else {
+                eprintln!("Reply cancelled.");
+                self.conversation.history_mut().truncate(before);
+                if let Some(output) = &mut self.output {
+                    output.discard()?;
+                }
+                return self.stop_script(path, &lines[number..]);
+            };
+            last_reply = match result {
+                Ok(()) => self
+                    .conversation
+                    .history()
+                    .last()
+                    .filter(|msg| msg.role == "assistant")
+                    .map(|msg| msg.content.clone()),
+                Err(error) => {
+                    eprintln!("Request failed: {}", error);
+                    if error.is_context_length() {
+                        eprintln!("{}", CONTEXT_ADVICE);
+                    }
+                    None
+                }
+            };
+        }
+        if let Some(speaker) = &mut self.speaker {
+            speaker.wait();
+        }
+        Ok(())
+    }
+
+    /// End a `--repl-script` run stopped by Ctrl+C: save the conversation so far
+    /// and write the `remaining` lines of the script to `PATH.resume`, which
+    /// carries on from there with `--resume`. Fails with [`llm::Error::Stopped`],
+    /// which exits with [`INTERRUPTED_EXIT`].
+    fn stop_script(&mut self, path: &Path, remaining: &[&str]) -> Result<()> {
+        let left = remaining.iter().map(|line| line.trim()).filter(|line| is_script_step(line)).count();
+        eprintln!("Interrupted with {} script lines left to run.", left);
+        if incognito::is_on() {
+            eprintln!("Nothing is saved to resume from in incognito mode.");
+            return Err(llm::Error::Stopped);
+        }
+        let has_messages = self.conversation.history().iter().any(|msg| !msg.display_only);
+        let resume = match has_messages {
+            false => Some(String::new()),
+            true if self.save("") => self.saved_name.as_deref().map(|name| format!(" --resume {}", name)),
+            true => None,
+        };
+        let manifest = match path.extension().is_some_and(|extension| extension == "resume") {
+            true => path.to_path_buf(),
+            false => PathBuf::from(format!("{}.resume", path.display())),
+        };
+        let output = self
+            .output
+            .as_ref()
+            .map(|output| format!(" --output-file {} --append", output.path().display()))
+            .unwrap_or_default();
+        let command = resume.map(|resume| format!("cli_llm{} --repl-script {}{}", resume, manifest.display(), output));
+        let mut text = format!("# The rest of {}, interrupted", path.display());
+        if let Some(command) = &command {
+            text.push_str(&format!(". Continue with:\n#   {}", command));
+        }
+        text.push('\n');
+        for line in remaining {
+            text.push_str(line);
+            text.push('\n');
+        }
+        storage::write_atomic(&manifest, text).map_err(llm::Error::file(&manifest))?;
+        match command {
+            Some(command) => eprintln!("Continue with: {}", command),
+            None => eprintln!("The lines left are in {}.", manifest.display()),
+        }
+        Err(llm::Error::Stopped)
+    }
+
+    /// Offer the template's first message, if there is one.
+    fn show_draft(&self) {
+        if let Some(draft) = &self.draft {
+            println!("Template message (press Enter to send it, or type your own):\n  {}", draft);
+        }
+    }
+
+    /// Where the next message goes.
+    fn current_backend(&self) -> Backend {
+        let url = self.conversation.options.url(self.conversation.client().url());
+        Backend::new(&self.profile, &url)
+    }
+
+    /// The backend the conversation's last message went to and the current one,
+    /// when they differ and `--allow-backend-change` isn't in effect.
+    fn backend_change(&self) -> Option<(Backend, Backend)> {
+        let current = self.current_backend();
+        let previous = self.backend.as_ref().filter(|previous| current.differs_from(previous))?;
+        (!self.allow_backend_change).then(|| (previous.clone(), current))
+    }
+
+    /// Ask before the next message goes to another backend than the last one
+    /// did. False when the user declines.
+    async fn confirm_backend(&mut self, input: &mut Input) -> Result<bool> {
+        let Some((previous, current)) = self.backend_change() else {
+            return Ok(true);
+        };
+        println!("The last message of this conversation went to {}.", previous);
+        print!("Send this one to {} instead? [y/N] ", current);
+        io::stdout().flush()?;
+        let answer = input.line_or_interrupt(true).await?;
+        if !answer.is_some_and(|answer| answer.trim().eq_ignore_ascii_case("y")) {
+            println!("Not sent; press Enter to try again, or /profile to switch back.");
+            return Ok(false);
+        }
+        self.backend = Some(current);
+        Ok(true)
+    }
+
+    /// Warn about likely mistakes in `text` before it is sent and, when
+    /// someone is at the terminal, ask whether to send it anyway. False when
+    /// the user declines.
+    async fn confirm_lint(&self, text: &str, input: &mut Input) -> Result<bool> {
+        let issues = lint::lint_prompt(text);
+        if issues.is_empty() {
+            return Ok(true);
+        }
+        for issue in &issues {
+            eprintln!("Warning: {}", issue);
+        }
+        if !self.confirm {
+            return Ok(true);
+        }
+        print!("Send it anyway? [y/N] ");
+        io::stdout().flush()?;
+        let answer = input.line_or_interrupt(true).await?;
+        if !answer.is_some_and(|answer| answer.trim().eq_ignore_ascii_case("y")) {
+            println!("Not sent; press Enter to be asked again, or type the message again with the fix.");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Warn when sending `text` would nearly fill the model's context window
+    /// and, from [`tokenizer::DANGER_SHARE`] of it, ask whether to send it
+    /// anyway when someone is at the terminal. False when the user declines.
+    async fn confirm_context(&self, text: &str, input: &mut Input) -> Result<bool> {
+        let (model, text) = split_model_prefix(text).unwrap_or((&self.conversation.options.model, text));
+        let prompt = self.conversation.prompt_tokens(&self.outgoing(text).content);
+        let context = models::context_length(model).unwrap_or(0);
+        let Some(warning) = tokenizer::warning(prompt, context) else {
+            return Ok(true);
+        };
+        eprintln!("Warning: {}", warning);
+        if !self.confirm || Pressure::of(prompt, context) < Pressure::Danger {
+            return Ok(true);
+        }
+        print!("Send it anyway? [y/N] ");
+        io::stdout().flush()?;
+        let answer = input.line_or_interrupt(true).await?;
+        if !answer.is_some_and(|answer| answer.trim().eq_ignore_ascii_case("y")) {
+            println!("Not sent; press Enter to ask again, or make room with /context drop, /low-bandwidth on or /new.");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// The prompt: the number of the next exchange, after the host it goes to
+    /// unless that is OpenRouter's, and after "incognito" in incognito mode.
+    fn prompt(&self) -> String {
+        let number = self.conversation.next_exchange();
+        let backend = self.current_backend();
+        let prompt = match backend.is_default() {
+            true => format!("[{}] > ", number),
+            false => format!("{} [{}] > ", backend.host, number),
+        };
+        match incognito::is_on() {
+            true => format!("incognito {}", prompt),
+            false => prompt,
+        }
+    }
+
+    /// Use the endpoint, headers and model of `connection` from now on.
+    fn connect(&mut self, connection: Connection) {
+        log::info!(
+            "Profile: {} (endpoint {}, model {})",
+            connection.profile,
+            connection.url,
+            connection.model
+        );
+        self.conversation.set_client(Client::from_connection(&connection));
+        self.conversation.filters = if self.filter { connection.filters } else { Pipeline::default() };
+        self.conversation.options.low_bandwidth = connection.low_bandwidth;
+        self.profile = connection.profile;
+        self.conversation.options.model = connection.model;
+    }
+
+    /// Replace `auto:free` with the best free model that answers, announcing the
+    /// choice, and switch to the `:online` variant with `--online`.
+    async fn resolve_model(&mut self) -> Result<()> {
+        if self.conversation.options.model == models::AUTO_FREE {
+            let model = self.select_free().await?;
+            eprintln!("Using free model {}.", model);
+            self.conversation.options.model = model;
+        }
+        if self.online {
+            self.conversation.options.model = api::online_model(&self.conversation.options.model);
+        }
+        Ok(())
+    }
+
+    /// The best free model that answers right now.
+    async fn select_free(&self) -> Result<String> {
+        let available = self.models(false).await?;
+        let client = self.conversation.client();
+        let model = models::select_free(client.http(), client.url(), client.headers(), &available, &self.config.free_models).await?;
+        log::info!("auto:free resolved to {}", model);
+        Ok(model)
+    }
+
+    /// The available models: the cached list, or a fresh one when there is no
+    /// cache or `refresh` is set.
+    async fn models(&self, refresh: bool) -> Result<Vec<ModelInfo>> {
+        if !refresh && let Some(cached) = models::load_cache() {
+            return Ok(cached);
+        }
+        let client = self.conversation.client();
+        let fetched = models::fetch_models(client.http(), client.url(), client.headers()).await?;
+        models::save_cache(&fetched);
+        Ok(fetched)
+    }
+
+    /// Check the model named in an `@model:ID` prefix and resolve `auto:free`.
+    ///
+    /// A model missing from the cached list is looked up again in a fresh one
+    /// before it's rejected. When the endpoint has no models list at all, the model
+    /// is used unchecked.
+    async fn turn_model(&self, model: &str) -> Result<String> {
+        if model == models::AUTO_FREE {
+            return self.select_free().await;
+        }
+        let mut available = Vec::new();
+        for refresh in [false, true] {
+            available = match self.models(refresh).await {
+                Ok(available) => available,
+                Err(error) => {
+                    log::warn!("Cannot check model {}: {}", model, error);
+                    return Ok(model.to_string());
+                }
+            };
+            if available.iter().any(|m| m.id == model) {
+                return Ok(model.to_string());
+            }
+        }
+        let wanted = model.to_lowercase();
+        let similar: Vec<&str> = available
+            .iter()
+            .map(|m| m.id.as_str())
+            .filter(|id| id.to_lowercase().contains(&wanted))
+            .take(5)
+            .collect();
+        Err(llm::Error::Config(if similar.is_empty() {
+            format!("Unknown model '{}'. Run `cli_llm models` to list the available ones.", model)
+        } else {
+            format!("Unknown model '{}'. Did you mean: {}?", model, similar.join(", "))
+        }))
+    }
+
+    /// Send `input` as the next user message and print the reply.
+    ///
+    /// On failure the user message is taken back out of the conversation, so the
+    /// question can simply be asked again.
+    ///
+    /// An `@model:ID` prefix sends just this message to another model; unknown
+    /// models are refused before anything is sent.
+    async fn send(&mut self, input: &str) -> Result<()> {
+        if let Some((previous, current)) = self.backend_change() {
+            return Err(llm::Error::Config(format!(
+                "Not sent: the last message went to {}, and this one would go to {}. \
+                 Pass --allow-backend-change to send it anyway.",
+                previous, current
+            )));
+        }
+        self.backend = Some(self.current_backend());
+        if let Some(speaker) = &mut self.speaker {
+            speaker.stop();
+        }
+        let Some((model, prompt)) = split_model_prefix(input) else {
+            // A profile switched to at the prompt may use `auto:free` too.
+            self.resolve_model().await?;
+            return self.send_turn(input).await;
+        };
+        if model.is_empty() || prompt.is_empty() {
+            return Err(llm::Error::Config("Usage: @model:ID <message>".to_string()));
+        }
+        let mut model = self.turn_model(model).await?;
+        if self.online {
+            model = api::online_model(&model);
+        }
+        let default = std::mem::replace(&mut self.conversation.options.model, model.clone());
+        self.turn_model = Some(model);
+        let result = self.send_turn(prompt).await;
+        self.conversation.options.model = default;
+        self.turn_model = None;
+        result
+    }
+
+    /// Send `input` as the next user message and print the reply.
+    async fn send_turn(&mut self, input: &str) -> Result<()> {
+        let mut message = self.outgoing(input);
+        let excerpts = std::mem::take(&mut self.excerpts);
+        let attachments = std::mem::take(&mut self.attachments);
+        message.images = std::mem::take(&mut self.pending_images);
+        for cut in &message.cuts {
+            eprintln!("Shortened a pasted block of {}. /context lists it.", cut.describe());
+        }
+        let images = message.images.clone();
+        match self.exchange_all(message).await {
+            Ok(mut content) => {
+                content = self.run_tools(content).await;
+                if let Some(limit) = self.limit
+                    && let Some(size) = limit.exceeded_by(&content)
+                {
+                    content = self.tighten(limit, size, content).await;
+                }
+                self.render_blocks(&content);
+                let answer = think::split(&content).answer;
+                if let Some(speaker) = &mut self.speaker
+                    && let Err(e) = speaker.speak(&speech::speech_text(&answer, self.speak_code))
+                {
+                    eprintln!("Cannot read the reply aloud: {}", e);
+                }
+                Ok(())
+            }
+            Err(error) => {
+                // Keep the attachments for the next attempt.
+                self.pending_images = images;
+                self.attachments = attachments;
+                self.excerpts = excerpts;
+                Err(error)
+            }
+        }
+    }
+
+    /// The user message `input` is sent as: followed by the excerpts and files
+    /// staged for it, with the length limit and the paste limits applied.
+    fn outgoing(&self, input: &str) -> ChatMessageRequest {
+        let mut input = input.to_string();
+        for excerpt in &self.excerpts {
+            input.push_str("\n\n");
+            input.push_str(excerpt);
+        }
+        let content = match self.limit {
+            Some(limit) => limit.apply(&input),
+            None => input,
+        };
+        let mut message = ChatMessageRequest::new("user", content);
+        paste::attach(&mut message, &self.attachments, &self.config.paste());
+        message
+    }
+
+    /// Answer the commands the latest reply, `content`, asks to run, and those
+    /// of the replies to the results, for at most [`TOOL_ROUNDS`] rounds.
+    /// Returns the reply that asks for none. A failed request leaves the
+    /// results in the conversation for the next message to build on.
+    async fn run_tools(&mut self, mut content: String) -> String {
+        for round in 1.. {
+            let stop = round > TOOL_ROUNDS;
+            if !self.answer_tool_calls(stop).await {
+                break;
+            }
+            if stop {
+                eprintln!("Not running more commands after {} rounds.", TOOL_ROUNDS);
+                break;
+            }
+            let Some(result) = self.conversation.history_mut().pop() else {
+                break;
+            };
+            match self.exchange(result.clone()).await {
+                Ok(reply) => content = reply,
+                Err(error) => {
+                    self.conversation.history_mut().push(result);
+                    eprintln!("Request failed: {}", error);
+                    break;
+                }
+            }
+        }
+        content
+    }
+
+    /// Add a result for each tool call of the last reply, running the commands
+    /// the user agrees to, or none when `stop`. False when it makes no calls.
+    async fn answer_tool_calls(&mut self, stop: bool) -> bool {
+        let Some(reply) = self.conversation.history().last().filter(|msg| !msg.tool_calls.is_empty()) else {
+            return false;
+        };
+        let calls = reply.tool_calls.clone();
+        for call in &calls {
+            let result = match tool::shell_command(call) {
+                Err(error) => ToolResult::failed(error.to_string()),
+                Ok(_) if stop => ToolResult::failed("Not run: too many commands in a row."),
+                Ok(command) => {
+                    print!("The model wants to run:\n    $ {}\nRun it? [y/N] ", tool::shown_command(&command));
+                    let _ = io::stdout().flush();
+                    let answer = self.tool_answers.line_or_interrupt(true).await;
+                    match matches!(answer, Ok(Some(answer)) if answer.trim().eq_ignore_ascii_case("y")) {
+                        true => {
+                            let result = tool::run_shell(&command);
+                            eprintln!("{}", tool_summary(&result));
+                            result
+                        }
+                        false => ToolResult::failed("The user declined to run the command."),
+                    }
+                }
+            };
+            self.conversation.history_mut().push(result.message(call));
+        }
+        true
+    }
+
+    /// Render the code blocks of the latest reply, `content`, that have a
+    /// renderer, saving the results under the renders directory and linking to
+    /// them. A renderer that runs a program is only run once the user allows
+    /// it, which is asked the first time; nothing is asked when nobody is at
+    /// the terminal. Blocks that aren't rendered stay as printed.
+    fn render_blocks(&self, content: &str) {
+        let blocks = self.renderers.blocks(content);
+        let Some(dir) = renderer::renders_dir().filter(|_| !blocks.is_empty()) else {
+            return;
+        };
+        let message = self.conversation.history().len();
+        let title = self.saved_name.clone().unwrap_or_else(|| {
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            format!("chat-{}", secs)
+        });
+        for (i, (block, renderer)) in blocks.iter().enumerate() {
+            if !renderer.is_available() {
+                log::info!("The {} renderer isn't installed; leaving the block as it is", renderer.language);
+                continue;
+            }
+            if !renderer.is_allowed() && !self.allow_renderer(renderer) {
+                continue;
+            }
+            let path = dir.join(renderer::file_name(&title, message, i + 1, renderer));
+            let result = renderer.render(&block.code, &path);
+            let shown = if self.links { hyperlink::file_link(&path) } else { path.display().to_string() };
+            match result {
+                Ok(()) if renderer.command_line().is_none() => println!("Saved the {} block as {}", renderer.language, shown),
+                Ok(()) => println!("Rendered the {} block: {}", renderer.language, shown),
+                Err(error) => eprintln!("Could not render the {} block: {}", renderer.language, error),
+            }
+        }
+    }
+
+    /// Ask whether `renderer` may run its program, remembering a yes.
+    fn allow_renderer(&self, renderer: &Renderer) -> bool {
+        let command = renderer.command_line().unwrap_or_default();
+        if !self.confirm {
+            eprintln!(
+                "Not running the {} renderer ({}) without permission; allow it once in an interactive session.",
+                renderer.language, command
+            );
+            return false;
+        }
+        print!(
+            "Render the {} block with `{}`? It runs with a bare environment in a scratch directory, \
+             and isn't asked about again once allowed. [y/N] ",
+            renderer.language, command
+        );
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y") {
+            return false;
+        }
+        if let Err(error) = renderer.allow() {
+            eprintln!("Could not remember the permission: {}", error);
+        }
+        true
+    }
+
+    /// Ask once for a shorter version of `reply`, which measured `size` against
+    /// `limit`, and return the reply to keep. Gives up with a warning when the
+    /// shorter one is still over the limit or can't be had.
+    async fn tighten(&mut self, limit: Limit, size: usize, reply: String) -> String {
+        eprintln!("The reply is over the {} limit ({}); asking for a shorter one.", limit, size);
+        match self.exchange(ChatMessageRequest::new("user", limit.tighten_request(size))).await {
+            Ok(shorter) => {
+                if let Some(size) = limit.exceeded_by(&shorter) {
+                    eprintln!("Warning: the shorter reply is still over the {} limit ({}).", limit, size);
+                }
+                shorter
+            }
+            Err(error) => {
+                eprintln!("Warning: could not get a shorter reply: {}", error);
+                reply
+            }
+        }
+    }
+
+    /// Add `message` to the conversation and print the reply as it arrives.
+    ///
+    /// On failure the message is taken back out of the conversation.
+    async fn exchange(&mut self, message: ChatMessageRequest) -> Result<String> {
+        self.exchange_reply(Some(message)).await.map(|(content, _)| content)
+    }
+
+    /// Send `message` and print the reply, or in a group chat every persona's
+    /// reply to it in turn, each with the persona's name. Returns the last
+    /// reply. Once the first persona has answered, a failed request stops the
+    /// round without taking the message back.
+    async fn exchange_all(&mut self, message: ChatMessageRequest) -> Result<String> {
+        if !self.group || self.config.personas.is_empty() {
+            return self.exchange(message).await;
+        }
+        let mut message = Some(message);
+        let mut last = String::new();
+        for persona in self.config.personas.clone() {
+            let model = match persona.model.is_empty() {
+                true => self.conversation.options.model.clone(),
+                false => persona.model.clone(),
+            };
+            let model = std::mem::replace(&mut self.conversation.options.model, model);
+            let prompt = std::mem::replace(&mut self.conversation.system_prompt, persona.system_prompt.clone());
+            let name = self.conversation.assistant_name.replace(persona.name.clone());
+            let first = message.is_some();
+            let result = self.exchange_reply(message.take()).await;
+            self.conversation.options.model = model;
+            self.conversation.system_prompt = prompt;
+            self.conversation.assistant_name = name;
+            match result {
+                Ok((content, usage)) => {
+                    if let Some(usage) = usage {
+                        self.persona_usage.entry(persona.name.clone()).or_default().add(&usage);
+                    }
+                    last = content;
+                }
+                Err(error) if first => return Err(error),
+                Err(error) => {
+                    eprintln!("{} did not answer: {}", persona.name, error);
+                    break;
+                }
+            }
+        }
+        Ok(last)
+    }
+
+    /// Send `message`, or ask for a reply to the conversation as it is when
+    /// there is none, and print the reply. Returns it with its token usage.
+    async fn exchange_reply(&mut self, message: Option<ChatMessageRequest>) -> Result<(String, Option<Usage>)> {
+        // Replies from a one-off model say which model answered.
+        let name = self.conversation.assistant_name.as_deref().unwrap_or("LLM");
+        let label = match &self.turn_model {
+            Some(model) => format!("{} ({}): ", name, model),
+            None => format!("{}: ", name),
+        };
+        let mut out = LinkWriter::new(io::stdout(), self.links);
+        let printed_prefix = Cell::new(false);
+        let render_json = self.render_json;
+        // Boilerplate can only be told apart once there are enough replies to repeat it.
+        let replies = self.conversation.history().iter().filter(|msg| msg.role == "assistant").count();
+        let dedupe = self.conversation.dedupe.is_some_and(|dedupe| replies + 1 >= dedupe.min_replies);
+        // Rendered, filtered and deduplicated replies are printed whole once they are complete.
+        let whole = render_json || !self.conversation.filters.is_empty() || dedupe;
+        // Until the reply shows, a status line says how it is coming along.
+        let status = StatusLine::new(self.progress);
+        let received = Cell::new(0);
+        let max_display = self.max_display.unwrap_or(usize::MAX);
+        let shown = Cell::new(0);
+        let pending = message.as_ref().map_or("", |message| message.content.as_str());
+        let sent = self.conversation.options.low_bandwidth.then(|| self.conversation.request_bytes(pending));
+        let mut output = self.output.as_mut();
+        // The first failure to write to the output file; the reply is still kept.
+        let mut write_error = None;
+        // Reasoning is hidden as it arrives; the status line runs on meanwhile.
+        let mut hider = (!self.show_thinking).then(Hider::default);
+        let result = {
+            let on_delta = |delta: &str| {
+                received.set(received.get() + delta.chars().count());
+                if whole {
+                    return;
+                }
+                if let Some(output) = &mut output {
+                    if write_error.is_none() {
+                        write_error = output.write(delta).err();
+                    }
+                    return;
+                }
+                let delta = match &mut hider {
+                    Some(hider) => hider.push(delta),
+                    None => delta.to_string(),
+                };
+                if delta.is_empty() {
+                    return;
+                }
+                if !printed_prefix.get() {
+                    status.clear();
+                    print!("{}", label);
+                    printed_prefix.set(true);
+                }
+                let _ = out.write(displayed(&delta, &shown, max_display));
+            };
+            let conversation = &mut self.conversation;
+            let send = async move {
+                match message {
+                    Some(message) => conversation.send_with(message, on_delta).await,
+                    None => conversation.reply_with(on_delta).await,
+                }
+            };
+            tokio::pin!(send);
+            let started = Instant::now();
+            let first = tokio::time::Instant::now() + STATUS_INTERVAL;
+            let mut ticks = tokio::time::interval_at(first, STATUS_INTERVAL);
+            loop {
+                tokio::select! {
+                    result = &mut send => break result,
+                    // The reply may have started printing after the guard was checked.
+                    _ = ticks.tick(), if !printed_prefix.get() => {
+                        if !printed_prefix.get() {
+                            status.show(received.get(), started.elapsed());
+                        }
+                    }
+                }
+            }
+        };
+        status.clear();
+        let rest = hider.as_mut().map(Hider::finish).unwrap_or_default();
+        if !rest.is_empty() && !whole && self.output.is_none() {
+            if !printed_prefix.get() {
+                print!("{}", label);
+                printed_prefix.set(true);
+            }
+            let _ = out.write(displayed(&rest, &shown, max_display));
+        }
+        let printed_prefix = printed_prefix.get();
+        let _ = out.finish();
+        // The Enter that stopped the reply already ended the line.
+        if printed_prefix && !self.conversation.stop.is_stopped() {
+            println!();
+        }
+        let reply = match result {
+            Ok(reply) => reply,
+            Err(error) => {
+                // Only complete replies go to the output file.
+                if let Some(output) = &mut self.output
+                    && let Err(e) = output.discard()
+                {
+                    log::warn!("Cannot take back the failed reply: {}", e);
+                }
+                return Err(error);
+            }
+        };
+        // What is printed of the reply: all of it, or its answer without the reasoning.
+        let thoughts = hider.is_some().then(|| think::split(&reply.content));
+        let answer = thoughts.as_ref().map_or_else(|| reply.content.clone(), |thoughts| thoughts.answer.clone());
+        if let Some(output) = &mut self.output {
+            if whole && write_error.is_none() {
+                let content = match render_json {
+                    true => structured::render_json(&reply.content, false),
+                    false => reply.content.clone(),
+                };
+                write_error = output.write(&content).err();
+            }
+            if write_error.is_none() {
+                write_error = output.end().err();
+            }
+        } else if render_json {
+            let rendered = structured::render_json(&answer, self.color);
+            print!("{}", label);
+            if rendered == answer {
+                let _ = out.write(displayed(&answer, &shown, max_display));
+                let _ = out.finish();
+            } else {
+                print!("{}", displayed(&rendered, &shown, max_display));
+                if self.color && shown.get() == max_display {
+                    // Don't leave the colour of a cut-off token on.
+                    print!("\x1b[0m");
+                }
+            }
+            println!();
+        } else if whole {
+            print!("{}", label);
+            let boilerplate = self.conversation.boilerplate().filter(|_| dedupe);
+            let split = boilerplate.as_ref().map(|boilerplate| boilerplate.split(&answer));
+            match split.filter(|split| split.is_hidden()) {
+                Some(split) => {
+                    let marker = match self.color {
+                        true => format!("\x1b[2m{}\x1b[0m", boilerplate::MARKER),
+                        false => boilerplate::MARKER.to_string(),
+                    };
+                    if !split.head.is_empty() {
+                        println!("{}", marker);
+                    }
+                    let _ = out.write(displayed(split.body, &shown, max_display));
+                    let _ = out.finish();
+                    if !split.tail.is_empty() {
+                        print!("\n{}", marker);
+                    }
+                }
+                None => {
+                    let _ = out.write(displayed(&answer, &shown, max_display));
+                    let _ = out.finish();
+                }
+            }
+            println!();
+        }
+        if self.output.is_none() && shown.get() == max_display && answer.chars().count() > max_display {
+            println!("{}", truncation_note(&reply.content));
+        }
+        if let Some(reason) = &reply.interrupted {
+            eprintln!("[reply cut short: {}]", reason);
+        }
+        self.print_sources(&reply.citations);
+        let mut notes = Vec::new();
+        if let Some(provider) = &reply.provider {
+            notes.push(format!("via {}", provider));
+        }
+        if reply.queued >= Duration::from_secs(1) {
+            notes.push(format!("waited {:.1}s in the queue", reply.queued.as_secs_f64()));
+        }
+        if reply.resumed > 0 {
+            notes.push(format!("resumed after {} dropped connection(s)", reply.resumed));
+        }
+        if let Some(bytes) = sent {
+            notes.push(format!("~{} sent in low-bandwidth mode", attachment::human_size(bytes)));
+        }
+        if thoughts.is_some_and(|thoughts| thoughts.has_reasoning()) {
+            notes.push("reasoning hidden; /thinking shows it".to_string());
+        }
+        if let Some(summary) = reply.usage.as_ref().and_then(Usage::reasoning_summary) {
+            notes.push(summary);
+        }
+        if !notes.is_empty() {
+            if self.color {
+                println!("\x1b[2m({})\x1b[0m", notes.join(", "));
+            } else {
+                println!("({})", notes.join(", "));
+            }
+        }
+        if let Some(usage) = &reply.usage {
+            ledger::note(&reply.model, usage);
+        }
+        if let Some(error) = write_error {
+            return Err(error);
+        }
+        Ok((reply.content, reply.usage))
+    }
+}
+
+/// The part of `text` still printed when `shown` characters of the reply have
+/// been already and at most `max` may be, counted as shown.
+fn displayed<'a>(text: &'a str, shown: &Cell<usize>, max: usize) -> &'a str {
+    let room = max.saturating_sub(shown.get());
+    let end = text.char_indices().nth(room).map_or(text.len(), |(end, _)| end);
+    shown.set(shown.get() + text[..end].chars().count());
+    &text[..end]
+}
+
+/// A pasted image as a PNG file's bytes.
+fn encode_png(rgba: &[u8], width: u32, height: u32) -> io::Result<Vec<u8>> {
+    let mut png = io::Cursor::new(Vec::new());
+    image::write_buffer_with_format(&mut png, rgba, width, height, image::ExtendedColorType::Rgba8, image::ImageFormat::Png)
+        .map_err(io::Error::other)?;
+    Ok(png.into_inner())
+}
+
+/// The note ending a reply cut short by `--max-display-chars`, which saves the
+/// whole of `content` to a file to read it in, except in incognito mode.
+fn truncation_note(content: &str) -> String {
+    if incognito::is_on() {
+        return "[...truncated; /show prints the whole reply]".to_string();
+    }
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+    let path = env::temp_dir().join(format!("cli_llm-reply-{}.md", millis));
+    match storage::write_atomic(&path, content) {
+        Ok(()) => format!("[...truncated, full text saved to {}]", path.display()),
+        Err(e) => {
+            log::warn!("Cannot save the reply to {}: {}", path.display(), e);
+            "[...truncated; /show prints the whole reply]".to_string()
+        }
+    }
+}
+
+/// How often the status line is redrawn.
+const STATUS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A spinner line on stderr while a reply is awaited and none of it is
+/// printed yet, redrawn in place and cleared before anything else is written.
+struct StatusLine {
+    enabled: bool,
+    frame: Cell<usize>,
+    shown: Cell<bool>,
+}
+
+impl StatusLine {
+    const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            frame: Cell::new(0),
+            shown: Cell::new(false),
+        }
+    }
+
+    /// Draw the line for `chars` received after `elapsed`.
+    fn show(&self, chars: usize, elapsed: Duration) {
+        if !self.enabled {
+            return;
+        }
+        let frame = self.frame.replace(self.frame.get() + 1);
+        let text = match chars {
+            0 => format!("Thinking… {:.1} s", elapsed.as_secs_f64()),
+            _ => format!("Receiving… {}", stream::progress(chars, elapsed)),
+        };
+        eprint!("\r\x1b[2K{} {}", Self::SPINNER[frame % Self::SPINNER.len()], text);
+        let _ = io::stderr().flush();
+        self.shown.set(true);
+    }
+
+    fn clear(&self) {
+        if self.shown.replace(false) {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+        }
+    }
+}
+
+/// A reply stopped with Ctrl+C leaves no status behind.
+impl Drop for StatusLine {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// A `!wait` duration: seconds (`2`, `1.5s`) or milliseconds (`500ms`).
+fn parse_duration(text: &str) -> Option<Duration> {
+    if let Some(millis) = text.strip_suffix("ms") {
+        return millis.trim().parse().ok().map(Duration::from_millis);
+    }
+    let seconds: f64 = text.strip_suffix('s').unwrap_or(text).trim().parse().ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// One line on how a command the model asked for went, such as
+/// `(exit 0, 12 lines of output sent back)`.
+fn tool_summary(result: &ToolResult) -> String {
+    let lines = result.stdout.lines().count() + result.stderr.lines().count();
+    let mut summary = match (&result.error, result.exit_code) {
+        (Some(error), _) => error.clone(),
+        (None, Some(code)) => format!("exit {}", code),
+        (None, None) => "killed".to_string(),
+    };
+    summary.push_str(&format!(", {} lines of output sent back", lines));
+    if result.truncated {
+        summary.push_str(", shortened");
+    }
+    format!("({})", summary)
+}
+
+/// Whether ANSI colours should be written to stdout: not with `plain`, when
+/// `NO_COLOR` is set, or when stdout is not a terminal.
+fn supports_color(plain: bool) -> bool {
+    !plain && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal()
+}
+
+/// `cli_llm history ...`: list, search, archive and unarchive saved conversations,
+/// and list or restore their checkpoints.
+fn history_command(args: &[String]) -> Result<()> {
+    unlock_vault()?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] | ["list"] | ["list", "--all"] => {
+            let all = args.contains(&"--all");
+            let entries = history::list()?;
+            let shown: Vec<_> = entries.iter().filter(|entry| all || !entry.archived).collect();
+            if entries.is_empty() {
+                println!("No saved conversations. Use /save in a chat to save one.");
+            }
+            for entry in &shown {
+                println!("{}{}", entry.name, if entry.archived { " (archived)" } else { "" });
+                if let Some(digest) = history::summary(entry) {
+                    println!("    {}", digest.preview(100));
+                }
+            }
+            let hidden = entries.len() - shown.len();
+            if hidden > 0 {
+                println!("{} archived conversation(s) not shown; use `history list --all`.", hidden);
+            }
+        }
+        ["search", rest @ ..] if !rest.is_empty() => {
+            let (rated, words) = match rest {
+                ["--rated", verdict, words @ ..] => (Some(verdict.parse::<Verdict>()?), words),
+                words => (None, words),
+            };
+            let query = words.join(" ");
+            let hits = history::search(&query, rated)?;
+            match rated {
+                _ if !hits.is_empty() => {}
+                Some(verdict) if query.is_empty() => println!("No saved replies are rated {}.", verdict.name()),
+                Some(verdict) => println!("No saved replies rated {} contain '{}'.", verdict.name(), query),
+                None => println!("No saved messages contain '{}'.", query),
+            }
+            for hit in hits {
+                let archived = if hit.archived { ", archived" } else { "" };
+                let rating = hit.rating.map_or_else(String::new, |rating| format!(", {}", rating.label()));
+                println!(
+                    "{} #{} ({}{}{}): {}",
+                    hit.conversation,
+                    hit.index + 1,
+                    hit.role,
+                    archived,
+                    rating,
+                    hit.snippet
+                );
+            }
+        }
+        ["stats"] => {
+            let mut tallies = BTreeMap::new();
+            for saved in history::load_all()? {
+                rating::tally(&saved.messages, &saved.model, &mut tallies);
+            }
+            if tallies.is_empty() {
+                println!("No saved replies yet.");
+            }
+            let width = tallies.keys().map(|model| model.chars().count()).max().unwrap_or(0);
+            for (model, tally) in &tallies {
+                println!("{:<width$}  {}", model, tally_line(tally), width = width);
+            }
+        }
+        ["archive", name] => {
+            history::set_archived(name, true)?;
+            println!("Archived '{}'. It stays searchable but can't be resumed until unarchived.", name);
+        }
+        ["unarchive", name] => {
+            history::set_archived(name, false)?;
+            println!("Unarchived '{}'.", name);
+        }
+        ["checkpoints", name] => {
+            let checkpoints = checkpoint::list(name)?;
+            if checkpoints.is_empty() {
+                println!("No checkpoints of '{}'. One is taken before /new, /goto or /replace rewrites it.", name);
+            }
+            for checkpoint in &checkpoints {
+                println!(
+                    "{:>3}  {}  {} message(s), {}",
+                    checkpoint.number,
+                    export::format_date(checkpoint.taken_at),
+                    checkpoint.conversation.messages.len(),
+                    checkpoint.reason
+                );
+            }
+        }
+        ["restore-checkpoint", name, number] => {
+            let number = number
+                .parse()
+                .map_err(|_| llm::Error::Config(format!("Invalid checkpoint number '{}'", number)))?;
+            let restored = checkpoint::restore(name, number)?;
+            println!(
+                "Restored '{}' to checkpoint {} ({} message(s)). The version it replaced is the latest checkpoint now.",
+                name,
+                number,
+                restored.messages.len()
+            );
+        }
+        ["export", name, rest @ ..] => {
+            let (path, options) = export_args(rest)?;
+            let saved = history::load(name)?;
+            let messages = saved.requests();
+            let transcript = Transcript {
+                title: name,
+                model: &saved.model,
+                saved_at: Some(saved.saved_at).filter(|&secs| secs > 0),
+                system_prompt: saved.system_prompt.as_deref().unwrap_or_default(),
+                messages: &messages,
+            };
+            let path = path.unwrap_or_else(|| export::default_path(name));
+            export::write_html(&path, &transcript, &options)?;
+            println!("Exported '{}' to {}", name, path.display());
+        }
+        _ => {
+            return Err(llm::Error::Config(
+                "Usage: cli_llm history [list [--all] | search [--rated up|down] <TEXT> | stats | archive <NAME> | \
+                 unarchive <NAME> | export <NAME> [PATH] [--dark] [--anonymize] [--redact-system] | \
+                 checkpoints <NAME> | restore-checkpoint <NAME> <N>]"
+                    .to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A model's line in `history stats`: its replies, how many were rated up and
+/// down, and the share rated up.
+fn tally_line(tally: &Tally) -> String {
+    let approval = tally
+        .approval()
+        .map_or_else(String::new, |share| format!("  {:.0}% up", share * 100.0));
+    format!("{:>5} replies  {:>4} 👍  {:>4} 👎{}", tally.replies, tally.up, tally.down, approval)
+}
+
+/// `cli_llm export ratings [--format jsonl] [PATH]`: write the rated replies of
+/// every saved conversation as JSON lines, to stdout unless a path is given.
+fn export_command(args: &[String]) -> Result<()> {
+    let usage = || llm::Error::Config("Usage: cli_llm export ratings [--format jsonl] [PATH]".to_string());
+    let mut iter = args.iter().map(String::as_str);
+    if iter.next() != Some("ratings") {
+        return Err(usage());
+    }
+    let mut path = None;
+    while let Some(arg) = iter.next() {
+        match arg {
+            "--format" => match iter.next() {
+                Some("jsonl") => {}
+                Some(format) => {
+                    return Err(llm::Error::Config(format!("Unknown format '{}': ratings export as jsonl", format)));
+                }
+                None => return Err(usage()),
+            },
+            path_arg if path.is_none() && !path_arg.starts_with('-') => path = Some(PathBuf::from(path_arg)),
+            _ => return Err(usage()),
+        }
+    }
+    unlock_vault()?;
+    let mut lines = String::new();
+    let mut count = 0;
+    for saved in history::load_all()? {
+        let examples = rating::examples(&saved.name, &saved.model, saved.system_prompt.as_deref(), &saved.messages);
+        for example in examples {
+            lines.push_str(&serde_json::to_string(&example)?);
+            lines.push('\n');
+            count += 1;
+        }
+    }
+    match path {
+        Some(path) => {
+            incognito::refuse("Exporting ratings")?;
+            storage::write_atomic(&path, lines.as_bytes())?;
+            eprintln!("Wrote {} rated example(s) to {}", count, path.display());
+        }
+        None => print!("{}", lines),
+    }
+    Ok(())
+}
+
+/// Environment variable holding the new passphrase for `vault rekey`.
+const NEW_PASSPHRASE_ENV: &str = "CLI_LLM_NEW_PASSPHRASE";
+
+/// `cli_llm vault ...`: show whether saved conversations are encrypted, encrypt
+/// them, or re-encrypt them under a new passphrase.
+fn vault_command(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] | ["status"] => match vault::vault_path() {
+            Some(path) if vault::is_enabled() => println!("Saved conversations are encrypted ({}).", path.display()),
+            _ => println!("Saved conversations are not encrypted. Use `cli_llm vault enable` to encrypt them."),
+        },
+        ["enable"] => {
+            let passphrase = if vault::is_enabled() {
+                passphrase()?
+            } else {
+                new_passphrase(vault::PASSPHRASE_ENV)?
+            };
+            let summary = vault::enable(&passphrase)?;
+            print_summary("Encrypted", &summary);
+        }
+        ["rekey"] => {
+            if !vault::is_enabled() {
+                return Err(llm::Error::Config(
+                    "Saved conversations are not encrypted; use `cli_llm vault enable` first".to_string(),
+                ));
+            }
+            let old = passphrase()?;
+            let new = new_passphrase(NEW_PASSPHRASE_ENV)?;
+            let summary = vault::rekey(&old, &new)?;
+            print_summary("Re-encrypted", &summary);
+        }
+        _ => return Err(llm::Error::Config("Usage: cli_llm vault [status | enable | rekey]".to_string())),
+    }
+    Ok(())
+}
+
+fn print_summary(action: &str, summary: &vault::Summary) {
+    println!("{} {} file(s) of saved conversations and checkpoints.", action, summary.encrypted);
+}
+
+/// Ask for the vault passphrase, once per process, when saved conversations are
+/// encrypted. `CLI_LLM_PASSPHRASE` is used instead of prompting when set.
+fn unlock_vault() -> Result<()> {
+    if !vault::is_enabled() || vault::is_unlocked() {
+        return Ok(());
+    }
+    vault::unlock(&passphrase()?)
+}
+
+/// Encrypt the saved conversations when the config asks for it and they aren't yet.
+fn migrate_vault(config: &Config) -> Result<()> {
+    if !config.encrypt_history || vault::is_enabled() || incognito::is_on() {
+        return Ok(());
+    }
+    eprintln!("encrypt_history is on: choose a passphrase for your saved conversations.");
+    let summary = vault::enable(&new_passphrase(vault::PASSPHRASE_ENV)?)?;
+    if summary.encrypted > 0 {
+        eprintln!("Encrypted {} file(s) of saved conversations and checkpoints.", summary.encrypted);
+    }
+    Ok(())
+}
+
+/// The current passphrase, from `CLI_LLM_PASSPHRASE` or a prompt.
+fn passphrase() -> Result<String> {
+    match env::var(vault::PASSPHRASE_ENV) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => read_passphrase("Passphrase: "),
+    }
+}
+
+/// A new passphrase, from `var` or typed twice at a prompt.
+fn new_passphrase(var: &str) -> Result<String> {
+    if let Ok(passphrase) = env::var(var) {
+        return Ok(passphrase);
+    }
+    let passphrase = read_passphrase("New passphrase: ")?;
+    if read_passphrase("Repeat the passphrase: ")? != passphrase {
+        return Err(llm::Error::Config("The passphrases don't match".to_string()));
+    }
+    Ok(passphrase)
+}
+
+/// Read a line from stdin after printing `prompt` to stderr, without echoing it
+/// when stdin is a terminal (on systems with `stty`).
+fn read_passphrase(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let hidden = io::stdin().is_terminal() && set_echo(false);
+    let mut line = String::new();
+    let read = io::stdin().lock().read_line(&mut line);
+    if hidden {
+        set_echo(true);
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Turn terminal echo on or off; `false` when that isn't possible.
+fn set_echo(on: bool) -> bool {
+    std::process::Command::new("stty")
+        .arg(if on { "echo" } else { "-echo" })
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// The path and options of `/export` and `history export`: an optional path, then
+/// `--dark` (or `--theme <light|dark>`), `--anonymize` and `--redact-system`.
+fn export_args(args: &[&str]) -> Result<(Option<PathBuf>, HtmlOptions)> {
+    let mut path = None;
+    let mut options = HtmlOptions::default();
+    let mut iter = args.iter();
+    while let Some(&arg) = iter.next() {
+        match arg {
+            "--dark" => options.theme = Theme::Dark,
+            "--theme" => {
+                let theme = iter.next().ok_or_else(|| llm::Error::Config("--theme requires light or dark".to_string()))?;
+                options.theme = theme.parse()?;
+            }
+            "--anonymize" => options.anonymize = true,
+            "--redact-system" => options.redact_system = true,
+            flag if flag.starts_with("--") => {
+                return Err(llm::Error::Config(format!(
+                    "Unknown export option {}; use --dark, --theme, --anonymize or --redact-system",
+                    flag
+                )));
+            }
+            other if path.is_none() => path = Some(PathBuf::from(other)),
+            other => return Err(llm::Error::Config(format!("Unexpected argument: {}", other))),
+        }
+    }
+    Ok((path, options))
+}
+
+/// What `/diff` was asked to compare, and how.
+#[derive(Debug, Default)]
+struct DiffArgs {
+    /// Numbers of the two messages, counting from 1.
+    messages: Option<(usize, usize)>,
+    ignore_whitespace: bool,
+    patch: Option<PathBuf>,
+}
+
+/// Options of `/context edit` and `/context more`.
+#[derive(Debug, Default)]
+struct ContextArgs {
+    head: Option<usize>,
+    tail: Option<usize>,
+    lines: Option<usize>,
+}
+
+/// Parse the options of a `/context` action, which takes those in `allowed`.
+fn context_args(args: &[&str], allowed: &[&str]) -> Result<ContextArgs> {
+    let mut parsed = ContextArgs::default();
+    let mut iter = args.iter();
+    while let Some(&flag) = iter.next() {
+        if !allowed.contains(&flag) {
+            return Err(llm::Error::Config(format!("Unknown option {}; use {}", flag, allowed.join(" or "))));
+        }
+        let value = iter
+            .next()
+            .and_then(|value| value.parse::<usize>().ok())
+            .ok_or_else(|| llm::Error::Config(format!("{} requires a number of lines", flag)))?;
+        match flag {
+            "--head" => parsed.head = Some(value),
+            "--tail" => parsed.tail = Some(value),
+            _ => parsed.lines = Some(value),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Parse the arguments of `/diff`.
+fn diff_args(args: &[&str]) -> Result<DiffArgs> {
+    let mut parsed = DiffArgs::default();
+    let mut numbers = Vec::new();
+    let mut iter = args.iter();
+    while let Some(&arg) = iter.next() {
+        match arg {
+            "-w" | "--ignore-whitespace" => parsed.ignore_whitespace = true,
+            "--patch" => {
+                let path = iter.next().ok_or_else(|| llm::Error::Config("--patch requires a path".to_string()))?;
+                parsed.patch = Some(PathBuf::from(path));
+            }
+            flag if flag.starts_with('-') => {
+                return Err(llm::Error::Config(format!("Unknown diff option {}; use -w or --patch PATH", flag)));
+            }
+            number => match number.parse::<usize>() {
+                Ok(number) if number > 0 => numbers.push(number),
+                _ => return Err(llm::Error::Config(format!("Not a message number: {}", number))),
+            },
+        }
+    }
+    parsed.messages = match numbers[..] {
+        [] => None,
+        [old, new] => Some((old, new)),
+        _ => return Err(llm::Error::Config("Usage: /diff [M N] [-w] [--patch PATH]".to_string())),
+    };
+    Ok(parsed)
+}
+
+/// Split an `@model:ID message` input into the model and the message.
+fn split_model_prefix(input: &str) -> Option<(&str, &str)> {
+    let rest = input.strip_prefix("@model:")?;
+    let (model, prompt) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((model, prompt.trim_start()))
+}
+
+/// Run `cli_llm models [--free]`: print the available models with their context
+/// lengths, refreshing the cache. `cli_llm models info <ID>` prints what the
+/// cache knows about one model instead.
+async fn models_command(args: &[String], connection: &Connection) -> Result<()> {
+    let free_only = match args {
+        [] => false,
+        [flag] if flag == "--free" => true,
+        [info, id] if info == "info" => return model_info(id),
+        _ => return Err(llm::Error::Config("Usage: cli_llm models [--free | info <ID>]".to_string())),
+    };
+    let client = reqwest::Client::new();
+    let available = match models::fetch_models(&client, &connection.url, &connection.headers).await {
+        Ok(fetched) => {
+            models::save_cache(&fetched);
+            fetched
+        }
+        Err(error) => {
+            let cached = models::load_cache().ok_or(error)?;
+            eprintln!("Could not refresh the models list; showing the cached copy.");
+            cached
+        }
+    };
+    let shown: Vec<&ModelInfo> = available.iter().filter(|m| !free_only || m.is_free()).collect();
+    if shown.is_empty() {
+        println!("No {}models available.", if free_only { "free " } else { "" });
+    }
+    for model in shown {
+        match model.context_length {
+            Some(context) => println!("{:<60} {:>9} tokens", model.id, context),
+            None => println!("{}", model.id),
+        }
+    }
+    Ok(())
+}
+
+/// Run `cli_llm --credits`: print what the account and the API key have left
+/// to spend.
+async fn credits_command(connection: &Connection) -> Result<()> {
+    let client = reqwest::Client::new();
+    match credits::fetch(&client, &connection.url, &connection.headers).await? {
+        Some(balance) => {
+            for line in balance.details() {
+                println!("{}", line);
+            }
+        }
+        None => println!("{} doesn't report a credit balance.", connection.url),
+    }
+    Ok(())
+}
+
+/// `cli_llm models info <ID>`: the cached metadata of one model and how fresh it is.
+fn model_info(id: &str) -> Result<()> {
+    let metadata = models::metadata(id).ok_or_else(|| {
+        llm::Error::Config(format!(
+            "Nothing cached about {}. Run `cli_llm models` to fetch the models list.",
+            id
+        ))
+    })?;
+    let info = &metadata.info;
+    println!("{}", info.id);
+    if let Some(name) = &info.name {
+        println!("  Name:     {}", name);
+    }
+    match info.context_length {
+        Some(context) => println!("  Context:  {} tokens", context),
+        None => println!("  Context:  unknown"),
+    }
+    if let Some(pricing) = &info.pricing {
+        println!("  Pricing:  {} prompt, {} completion (USD per token)", pricing.prompt, pricing.completion);
+    }
+    println!("  Source:   {}", metadata.source());
+    Ok(())
+}
+
+/// `cli_llm debug ...`: show requests kept by the flight recorder, or send one
+/// again, optionally to another model.
+async fn debug_command(args: &[String], connection: &Connection, color: bool) -> Result<()> {
+    unlock_vault()?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] | ["last"] => match recorder::last()? {
+            Some(record) => print_record(&record, color),
+            None => println!("Nothing recorded yet. Set flight_recorder = true in config.toml to record requests."),
+        },
+        ["list"] => {
+            for record in recorder::records()?.take(20) {
+                let outcome = match (record.status, &record.error) {
+                    (Some(status), _) => status.to_string(),
+                    (None, Some(_)) => "error".to_string(),
+                    (None, None) => "-".to_string(),
+                };
+                println!(
+                    "{:<18} {}  {:<5} {:>6} ms  {}",
+                    record.id,
+                    export::format_date(record.at),
+                    outcome,
+                    record.duration_ms,
+                    record.request["model"].as_str().unwrap_or("?")
+                );
+            }
+        }
+        ["replay", id] | ["replay", id, "--model", _] => {
+            let record = recorder::find(id)?;
+            let mut body = record.request;
+            if let ["replay", _, "--model", model] = args.as_slice() {
+                body["model"] = (*model).into();
+            }
+            eprintln!("Replaying {} to {} with {}", id, record.url, body["model"].as_str().unwrap_or("?"));
+            let client = reqwest::Client::new();
+            if body["stream"].as_bool() == Some(true) {
+                let end = stream::stream_chat(&client, &record.url, &connection.headers, &body, |delta| {
+                    print!("{}", delta);
+                    let _ = io::stdout().flush();
+                })
+                .await?;
+                println!();
+                eprintln!("finish_reason: {}", end.finish_reason.as_deref().unwrap_or("-"));
+            } else {
+                let (message, _) = api::fetch_reply(&client, &record.url, &connection.headers, &body).await?;
+                println!("{}", message.content);
+            }
+        }
+        _ => {
+            return Err(llm::Error::Config(
+                "Usage: cli_llm debug [last | list | replay <ID> [--model <ID>]]".to_string(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// `cli_llm init`: create a project file in the current directory.
+fn init_command(args: &[String]) -> Result<()> {
+    if !args.is_empty() {
+        return Err(llm::Error::Config("Usage: cli_llm init".to_string()));
+    }
+    let path = project::init(&env::current_dir()?)?;
+    println!("Created {}. Its contents are sent as standing instructions from now on.", path.display());
+    Ok(())
+}
+
+/// `cli_llm doctor`: check the config file, API key, endpoint and model, and
+/// print what to do about anything that is wrong.
+async fn doctor_command(args: &[String], profile: Option<&str>, model: Option<&str>) -> Result<()> {
+    if !args.is_empty() {
+        return Err(llm::Error::Config("Usage: cli_llm doctor".to_string()));
+    }
+    let checks = doctor::run(profile, model).await;
+    for check in &checks {
+        let mark = match check.status {
+            doctor::Status::Pass => "✓",
+            doctor::Status::Fail => "✗",
+            doctor::Status::Skipped => "-",
+        };
+        println!("{} {}: {}", mark, check.name, check.detail.trim_end().replace('\n', "\n    "));
+        if let Some(hint) = &check.hint {
+            println!("    {}", hint);
+        }
+    }
+    doctor::outcome(&checks)
+}
+
+/// `cli_llm usage`: the tokens replies used per model, from the ledger both
+/// front ends record to.
+fn usage_command(args: &[String]) -> Result<()> {
+    if !args.is_empty() {
+        return Err(llm::Error::Config("Usage: cli_llm usage".to_string()));
+    }
+    let Some(path) = ledger::ledger_path() else {
+        return Err(llm::Error::Config("No data directory to find the usage ledger in".to_string()));
+    };
+    let entries = ledger::read(&path)?;
+    if entries.is_empty() {
+        println!("No token usage recorded yet.");
+        return Ok(());
+    }
+    let totals = ledger::totals(&entries);
+    let width = totals.keys().map(|model| model.chars().count()).max().unwrap_or(0);
+    for (model, usage) in &totals {
+        let replies = entries.iter().filter(|entry| &entry.model == model).count();
+        println!(
+            "{:<width$}  {:>5} replies  {:>9} prompt  {:>9} completion  {:>9} total",
+            model, replies, usage.prompt_tokens, usage.completion_tokens, usage.total_tokens,
+        );
+    }
+    Ok(())
+}
+
+const QUEUE_USAGE: &str = "Usage: cli_llm queue [list | cancel <ID> | promote <ID>]";
+
+/// `cli_llm queue ...`: the requests of all running instances waiting for
+/// their turn, or cancel one or move it to the front.
+fn queue_command(args: &[String]) -> Result<()> {
+    let Some(dir) = queue::queue_dir() else {
+        return Err(llm::Error::Config("No cache directory to find the request queue in".to_string()));
+    };
+    let queue = Queue::new(dir);
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] | ["list"] => {
+            let entries = queue.entries();
+            if entries.is_empty() {
+                println!("No requests queued or running.");
+                return Ok(());
+            }
+            println!("{:<14}  {:<10}  {:>6}  {:<8}  MODEL", "ID", "STATE", "AGE", "SOURCE");
+            let mut position = 0;
+            for entry in &entries {
+                let state = if entry.is_running() {
+                    "running".to_string()
+                } else {
+                    position += 1;
+                    format!("waiting {}", position)
+                };
+                println!(
+                    "{:<14}  {:<10}  {:>5}s  {:<8}  {}",
+                    entry.id,
+                    state,
+                    entry.age().as_secs(),
+                    entry.source,
+                    entry.model
+                );
+            }
+            Ok(())
+        }
+        ["cancel", id] => {
+            let entry = queue.cancel(id)?;
+            println!("Cancelled request {} to {}.", entry.id, entry.model);
+            Ok(())
+        }
+        ["promote", id] => {
+            let entry = queue.promote(id)?;
+            println!("Request {} to {} is next in the queue.", entry.id, entry.model);
+            Ok(())
+        }
+        _ => Err(llm::Error::Config(QUEUE_USAGE.to_string())),
+    }
+}
+
+const BENCH_USAGE: &str = "Usage: cli_llm bench [<MODEL>...] [-n <RUNS>] [--concurrency <N>] [--prompt <TEXT>]";
+
+/// `cli_llm bench ...`: send the same prompt to each model several times and
+/// compare their latency, time to first token and tokens per second.
+async fn bench_command(args: &[String], connection: &Connection, config: &Config) -> Result<()> {
+    let mut bench = Bench {
+        prompt: bench::DEFAULT_PROMPT.to_string(),
+        repeat: 3,
+        concurrency: 1,
+        route: config.route,
+    };
+    let mut models = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| llm::Error::Config(BENCH_USAGE.to_string()));
+        let count = |text: &String| match text.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(llm::Error::Config(format!("Expected a positive number, got '{}'", text))),
+        };
+        match arg.as_str() {
+            "-n" | "--repeat" => bench.repeat = count(value()?)?,
+            "-c" | "--concurrency" => bench.concurrency = count(value()?)?,
+            "-p" | "--prompt" => bench.prompt = value()?.clone(),
+            "-m" | "--model" => models.push(value()?.clone()),
+            flag if flag.starts_with('-') => return Err(llm::Error::Config(BENCH_USAGE.to_string())),
+            model => models.push(model.to_string()),
+        }
+    }
+    if models.is_empty() {
+        models.push(connection.model.clone());
+    }
+
+    eprintln!(
+        "Sending the prompt {} time(s) to {} model(s), {} at a time...",
+        bench.repeat,
+        models.len(),
+        bench.concurrency
+    );
+    let client = reqwest::Client::new();
+    let summaries = bench
+        .run(&client, &connection.url, &connection.headers, &models, |model, result| match result {
+            Ok(sample) => eprintln!(
+                "  {}: {} ms, first token {} ms, {} tokens, queued {} ms",
+                model,
+                sample.latency.as_millis(),
+                sample.first_token.as_millis(),
+                sample.tokens,
+                sample.queued.as_millis()
+            ),
+            Err(error) => eprintln!("  {}: {}", model, error),
+        })
+        .await;
+
+    let width = models.iter().map(|model| model.len()).max().unwrap_or(0).max("Model".len());
+    let millis = |duration: Option<Duration>| duration.map_or("-".to_string(), |d| format!("{} ms", d.as_millis()));
+    println!(
+        "\n{:<width$}  {:>4}  {:>6}  {:>11}  {:>11}  {:>9}  {:>11}",
+        "Model", "Runs", "Errors", "Latency", "First token", "Tokens/s", "Queued"
+    );
+    for summary in &summaries {
+        println!(
+            "{:<width$}  {:>4}  {:>6}  {:>11}  {:>11}  {:>9}  {:>11}",
+            summary.model,
+            summary.runs,
+            summary.errors,
+            millis(summary.latency),
+            millis(summary.first_token),
+            summary.tokens_per_sec.map_or("-".to_string(), |rate| format!("{:.1}", rate)),
+            millis(summary.queued)
+        );
+    }
+    if summaries.iter().all(|summary| summary.errors == summary.runs) {
+        let error = summaries.iter().find_map(|summary| summary.last_error.clone());
+        return Err(llm::Error::Config(error.unwrap_or_else(|| "Every run failed".to_string())));
+    }
+    Ok(())
+}
+
+/// A recorded exchange, with its JSON pretty-printed.
+fn print_record(record: &Record, color: bool) {
+    println!("Exchange {} at {}, {} ms", record.id, export::format_date(record.at), record.duration_ms);
+    println!("POST {}", record.url);
+    println!("Headers: {}", record.headers);
+    println!("\nRequest:\n{}", structured::pretty_json(&record.request, color));
+    if let Some(status) = record.status {
+        println!("\nStatus: {}", status);
+    }
+    if let Some(response) = &record.response {
+        println!("\nResponse:\n{}", structured::pretty_json(response, color));
+    }
+    if let Some(reply) = &record.reply {
+        println!("\nStreamed reply:\n{}", reply);
+    }
+    if let (Some(chunks), Some(first), Some(last)) = (record.chunks, record.first_chunk_ms, record.last_chunk_ms) {
+        println!("{} chunks, the first after {} ms, the last after {} ms", chunks, first, last);
+    }
+    for delta in &record.deltas {
+        println!("  {:>6} ms  {:?}", delta.ms, delta.text);
+    }
+    if let Some(reason) = &record.finish_reason {
+        println!("Finish reason: {}", reason);
+    }
+    if let Some(error) = &record.error {
+        println!("\nError: {}", error);
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    llm::init_logging(env!("CARGO_CRATE_NAME"), args.verbosity);
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        // What was interrupted has already been reported.
+        Err(llm::Error::Stopped) => ExitCode::from(INTERRUPTED_EXIT),
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            if error.is_context_length() {
+                eprintln!("{}", CONTEXT_ADVICE);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<()> {
+    // Before anything could be written.
+    incognito::set(args.incognito);
+    if args.output_file.is_some() && args.prompt.is_none() && args.script.is_none() {
+        return Err(llm::Error::Config("--output-file works with --prompt or --repl-script".to_string()));
+    }
+    if args.grace != SCRIPT_GRACE && args.script.is_none() {
+        return Err(llm::Error::Config("--grace works with --repl-script".to_string()));
+    }
+    if args.append && args.output_file.is_none() {
+        return Err(llm::Error::Config("--append needs an --output-file to append to".to_string()));
+    }
+    if args.transfer.is_requested() {
+        println!("{}", args.transfer.run()?);
+        return Ok(());
+    }
+    if let Some(command) = &args.history {
+        return history_command(command);
+    }
+    if let Some(command) = &args.export {
+        return export_command(command);
+    }
+    if let Some(command) = &args.vault {
+        return vault_command(command);
+    }
+    if let Some(command) = &args.init {
+        return init_command(command);
+    }
+    if let Some(command) = &args.usage {
+        return usage_command(command);
+    }
+    if let Some(command) = &args.queue {
+        return queue_command(command);
+    }
+
+    // Load environment variables from .env (if present).
+    dotenv::dotenv().ok();
+    if let Some(command) = &args.doctor {
+        return doctor_command(command, args.profile.as_deref(), args.model.as_deref()).await;
+    }
+    let config = Config::load()?;
+    let connection = config.connection(args.profile.as_deref())?;
+    recorder::set_enabled(config.flight_recorder);
+    recorder::set_granularity(config.flight_recorder_granularity.unwrap_or_default());
+    // Records are encrypted like conversations, so the recorder needs the key
+    // before the first request.
+    if config.flight_recorder && args.debug.is_none() && !incognito::is_on() {
+        unlock_vault()?;
+    }
+    timeout::set(config.timeouts.unwrap_or_default());
+    queue::set_slots(config.queue_slots.unwrap_or(queue::DEFAULT_SLOTS));
+    queue::set_source(match (&args.bench, &args.script) {
+        (Some(_), _) => "bench",
+        (None, Some(_)) => "script",
+        (None, None) => "cli",
+    });
+    if let Some(command) = &args.models {
+        return models_command(command, &connection).await;
+    }
+    if args.credits {
+        return credits_command(&connection).await;
+    }
+    if let Some(command) = &args.debug {
+        return debug_command(command, &connection, supports_color(args.plain)).await;
+    }
+    if let Some(command) = &args.bench {
+        return bench_command(command, &connection, &config).await;
+    }
+    migrate_vault(&config)?;
+
+    let links = hyperlink::supports_hyperlinks(args.plain);
+    log::info!("Streaming: {}", args.stream);
+    log::info!("Hyperlinks: {}", links);
+
+    let show_thinking = args.show_thinking || config.show_thinking;
+    let mut chat = Chat {
+        renderers: Renderers::new(&config.renderers),
+        config,
+        profile: String::new(),
+        conversation_profile: connection.profile.clone(),
+        conversation: Conversation::new(Client::from_connection(&connection)).stream(args.stream),
+        links,
+        render_json: args.render_json,
+        color: supports_color(args.plain),
+        pending_images: Vec::new(),
+        attachments: Vec::new(),
+        draft: None,
+        saved_name: None,
+        speaker: None,
+        speak_code: args.speak_code,
+        show_thinking,
+        limit: None,
+        turn_model: None,
+        online: args.online,
+        saved_len: 0,
+        saved_stamp: None,
+        saved_elsewhere: false,
+        confirm: args.confirm && io::stdin().is_terminal(),
+        confirm_settings: !args.yes && io::stdin().is_terminal(),
+        backend: None,
+        allow_backend_change: args.allow_backend_change,
+        allow_shell: args.allow_shell,
+        tool_answers: Input::new(None),
+        group: args.group,
+        persona_usage: BTreeMap::new(),
+        filter: args.filter,
+        lint: args.lint,
+        progress: !args.plain && io::stderr().is_terminal(),
+        refreshes: Throttle::default(),
+        refresh: None,
+        maintenance_usage: Usage::default(),
+        output: None,
+        excerpts: Vec::new(),
+        replay: VecDeque::new(),
+        regenerated: Vec::new(),
+        grace: args.grace,
+        max_display: args.max_display_chars,
+    };
+    if args.no_render || args.incognito {
+        chat.renderers = Renderers::default();
+    }
+    if args.incognito {
+        eprintln!("Incognito: nothing from this session is written to disk.");
+    }
+    if args.speak {
+        chat.speaker = Speaker::detect();
+        if chat.speaker.is_none() {
+            eprintln!("{}", NO_SPEECH);
+        }
+    }
+    chat.connect(connection);
+    chat.conversation.assistant_name = chat.config.assistant_name.clone().filter(|name| !name.trim().is_empty());
+    chat.conversation.options.route = chat.config.route;
+    chat.conversation.options.user = chat.config.user();
+    chat.conversation.locale = match args.locale_context || args.date_only {
+        true => Some(LocaleContext {
+            enabled: true,
+            date_only: args.date_only || chat.config.locale_context.as_ref().is_some_and(|locale| locale.date_only),
+            ..chat.config.locale_context.clone().unwrap_or_default()
+        }),
+        false => chat.config.locale_context(),
+    };
+    chat.conversation.dedupe = match args.dedupe_boilerplate {
+        true => Some(Dedupe {
+            enabled: true,
+            ..chat.config.boilerplate.unwrap_or_default()
+        }),
+        false => chat.config.boilerplate(),
+    };
+    chat.conversation.options.context_turns = args.context_turns;
+    chat.conversation.options.low_bandwidth |= args.low_bandwidth;
+    chat.conversation.options.transforms = args.transforms;
+    if args.allow_shell {
+        if !io::stdin().is_terminal() {
+            return Err(llm::Error::Config(
+                "--allow-shell asks before running each command, so it needs a terminal".to_string(),
+            ));
+        }
+        chat.conversation.options.tools = vec![tool::shell_definition()];
+    }
+    if args.group && chat.config.personas.is_empty() {
+        return Err(llm::Error::Config(
+            "--group needs personas: add [[personas]] with a name to config.toml".to_string(),
+        ));
+    }
+    if args.flatten {
+        chat.conversation.options.flatten = Some(chat.config.flatten.clone().unwrap_or_default());
+    }
+    if let Some(template) = args.raw {
+        chat.conversation.options.raw = Some(template.unwrap_or_else(|| chat.config.flatten.clone().unwrap_or_default()));
+    }
+    if args.project_file {
+        chat.conversation.project = ProjectContext::discover(&env::current_dir()?)?;
+        if let Some(project) = &chat.conversation.project {
+            eprintln!("Loaded project instructions from {}", project.path.display());
+        }
+    }
+    chat.new_conversation(args.template.as_deref())?;
+    if let Some(name) = &args.resume {
+        chat.resume(name)?;
+        eprintln!("Resumed '{}' ({} messages).", name, chat.conversation.history().len());
+    }
+    if let Some(model) = args.model {
+        chat.conversation.options.model = model;
+    }
+    chat.resolve_model().await?;
+    if args.limit.is_some() {
+        chat.limit = args.limit;
+    }
+    if chat.conversation.options.low_bandwidth && !args.images.is_empty() {
+        eprintln!("Images aren't sent in low-bandwidth mode; leaving out {}.", args.images.len());
+    }
+    for path in args.images.iter().filter(|_| !chat.conversation.options.low_bandwidth) {
+        let attachment = ImageAttachment::from_path(path, chat.config.image_max_dimension())?;
+        eprintln!("Attached {}: {}", path.display(), attachment.summary());
+        chat.pending_images.push(attachment);
+    }
+    for path in &args.files {
+        let attachment = TextAttachment::read(path)?;
+        eprintln!("Attached {}: {}", path.display(), attachment.cut(&chat.config.paste()).describe());
+        chat.attachments.push(attachment);
+    }
+    if let Some(template) = &args.output_file {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let path = output::expand_path(template, &chat.conversation.options.model, secs);
+        chat.output = Some(Output::open(&path, args.append)?);
+    }
+
+    if let Some(prompt) = args.prompt {
+        chat.send(&prompt).await?;
+        if let Some(speaker) = &mut chat.speaker {
+            speaker.wait();
+        }
+        return Ok(());
+    }
+    if let Some(path) = &args.script {
+        return chat.run_script(path).await;
+    }
+
+    chat.prefetch_metadata();
+    println!("Chat with the LLM. Type your message and press Enter. Type /quit to exit or /help for commands.");
+    chat.show_draft();
+    let mut input = Input::new(Some(PromptHelper::new(&chat.config)));
+    let confirm = chat.confirm;
+    // Enter stops a streaming reply; a line typed meanwhile is sent next.
+    let watch_keys = io::stdin().is_terminal();
+    let mut typed_ahead = None;
+    loop {
+        chat.maintain();
+        chat.check_saved();
+        let prompt = chat.prompt();
+        let line = match typed_ahead.take() {
+            Some(line) => {
+                println!("{}{}", prompt, line);
+                line
+            }
+            None => match input.prompt_line(&prompt, confirm).await? {
+                Some(line) => line,
+                None => {
+                    println!();
+                    break;
+                }
+            },
+        };
+        let text = match line.trim() {
+            "" => match chat.draft.take() {
+                Some(draft) => draft,
+                None => continue,
+            },
+            text => text.to_string(),
+        };
+        if matches!(text.as_str(), "/quit" | "/exit") {
+            break;
+        }
+        // Exiting takes a slash now; ask whoever types the old word what they meant.
+        if confirm && (text.eq_ignore_ascii_case("quit") || text.eq_ignore_ascii_case("exit")) {
+            print!("Send \"{}\" to the model, or exit? [s/E] ", text);
+            io::stdout().flush()?;
+            let answer = input.line_or_interrupt(true).await?;
+            if !answer.is_some_and(|answer| answer.trim().eq_ignore_ascii_case("s")) {
+                break;
+            }
+        }
+        if let Some(command) = text.strip_prefix('/') {
+            chat.command(command);
+            chat.replay().await;
+            continue;
+        }
+        // Asked here, where the answer can be read; without a terminal, send() refuses.
+        if watch_keys && !chat.confirm_backend(&mut input).await? {
+            chat.draft = Some(text);
+            continue;
+        }
+        if chat.lint && !chat.confirm_lint(&text, &mut input).await? {
+            chat.draft = Some(text);
+            continue;
+        }
+        if !chat.confirm_context(&text, &mut input).await? {
+            chat.draft = Some(text);
+            continue;
+        }
+        chat.draft = None;
+        // Ctrl+C stops the reply, leaving the conversation as it was before the message.
+        // Enter stops it too, but keeps what arrived as the reply.
+        let before = chat.conversation.history().len();
+        let stop = chat.conversation.stop.clone();
+        stop.reset();
+        // Commands the model asks to run are confirmed on stdin, which the
+        // watcher would read first.
+        let watch = watch_keys && chat.conversation.options.stream && !chat.allow_shell;
+        let mut ended = false;
+        let result = {
+            let send = chat.send(&text);
+            tokio::pin!(send);
+            loop {
+                tokio::select! {
+                    result = &mut send => break Some(result),
+                    () = interrupted(confirm) => break None,
+                    line = input.line_or_interrupt(false), if watch && !stop.is_stopped() => {
+                        stop.stop();
+                        match line? {
+                            // Esc only reaches us followed by Enter.
+                            Some(line) => {
+                                let line = line.replace('\x1b', "");
+                                typed_ahead = Some(line).filter(|line| !line.trim().is_empty());
+                            }
+                            None => ended = true,
+                        }
+                    }
+                }
+            }
+        };
+        match result {
+            Some(Ok(())) => {}
+            Some(Err(llm::Error::Stopped)) => eprintln!("Reply stopped before anything arrived."),
+            Some(Err(error)) if error.is_context_length() => {
+                eprintln!("Request failed: {}", error);
+                if chat.offer_context_fixes(&mut input).await? {
+                    typed_ahead = Some(text);
+                }
+            }
+            Some(Err(error)) => eprintln!("Request failed: {}", error),
+            None => {
+                println!();
+                eprintln!("Reply stopped.");
+                chat.conversation.history_mut().truncate(before);
+            }
+        }
+        if ended {
+            break;
+        }
+    }
+    chat.confirm_exit(&mut input).await?;
+    chat.finish_maintenance().await;
+    Ok(())
+}
+
+/// Exchanges kept by the first offer to shorten a conversation too long for
+/// the model; later offers halve what is kept.
+const CONTEXT_FIX_TURNS: usize = 5;
+
+/// A way to make a conversation fit the model's context window.
+#[derive(Debug, Clone, Copy)]
+enum ContextFix {
+    Turns(usize),
+    MiddleOut,
+    LowBandwidth,
+}
+
+/// Printed when a prompt or script fails for being too long for the model.
+const CONTEXT_ADVICE: &str = "The conversation is too long for the model. Send fewer exchanges with \
+                              --context-turns 5, have OpenRouter compress the prompt with --transforms \
+                              middle-out, or send older exchanges as a summary with --low-bandwidth.";
+
+/// Lines typed at the prompt.
+///
+/// Each line is read on a thread of its own so Ctrl+C can be noticed while
+/// waiting. A read that Ctrl+C cut short stays pending and delivers the next
+/// line typed. A plain thread rather than a blocking task, because the runtime
+/// would wait for that at exit.
+///
+/// At the chat prompt a terminal gets a line editor, which completes commands
+/// and hints at what they take. Other lines, like answers to questions and the
+/// Enter that stops a reply, are read as typed.
+struct Input {
+    pending: Option<Pending>,
+    editor: Option<Arc<Mutex<Editor<PromptHelper, DefaultHistory>>>>,
+}
+
+/// A line being read on its thread.
+struct Pending {
+    rx: oneshot::Receiver<io::Result<Read>>,
+    /// Asks a plain read to give up while nothing has been typed.
+    cancel: Arc<AtomicBool>,
+}
+
+/// How reading a line ended.
+enum Read {
+    Line(String),
+    End,
+    Cancelled,
+    Interrupted,
+}
+
+impl Pending {
+    fn spawn(read: impl FnOnce(&AtomicBool) -> io::Result<Read> + Send + 'static) -> Self {
+        let (tx, rx) = oneshot::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        std::thread::spawn(move || {
+            let _ = tx.send(read(&flag));
+        });
+        Self { rx, cancel }
+    }
+}
+
+impl Input {
+    /// Reads lines, with the line editor for the chat prompt when stdin is a
+    /// terminal and `helper` is given.
+    fn new(helper: Option<PromptHelper>) -> Self {
+        let editor = helper.filter(|_| io::stdin().is_terminal()).and_then(|helper| {
+            let mut editor = Editor::new().map_err(|e| log::warn!("No line editing at the prompt: {}", e)).ok()?;
+            editor.set_helper(Some(helper));
+            Some(Arc::new(Mutex::new(editor)))
+        });
+        Self { pending: None, editor }
+    }
+
+    /// The next line, without its line break, or `None` at the end of input or,
+    /// when `interruptible`, at Ctrl+C.
+    async fn line_or_interrupt(&mut self, interruptible: bool) -> Result<Option<String>> {
+        if self.pending.is_none() {
+            self.pending = Some(Pending::spawn(read_plain));
+        }
+        let read = self.read(interruptible).await?;
+        Ok(outcome(read, interruptible))
+    }
+
+    /// The line typed at the chat prompt `prompt`, edited with completion
+    /// when there is a line editor; as [`Input::line_or_interrupt`].
+    async fn prompt_line(&mut self, prompt: &str, interruptible: bool) -> Result<Option<String>> {
+        let Some(editor) = self.editor.clone() else {
+            print!("{}", prompt);
+            io::stdout().flush()?;
+            return self.line_or_interrupt(interruptible).await;
+        };
+        // A read left from watching for Enter during a reply. A line it has
+        // taken already is the one for this prompt.
+        if let Some(pending) = &self.pending {
+            pending.cancel.store(true, Ordering::Relaxed);
+            match self.read(interruptible).await? {
+                Read::Cancelled => {}
+                Read::Line(line) => {
+                    println!("{}{}", prompt, line);
+                    return Ok(Some(line));
+                }
+                read => return Ok(outcome(read, interruptible)),
+            }
+        }
+        let prompt = prompt.to_string();
+        self.pending = Some(Pending::spawn(move |_| {
+            let mut editor = editor.lock().unwrap_or_else(|e| e.into_inner());
+            match editor.readline(&prompt) {
+                Ok(line) => {
+                    let _ = editor.add_history_entry(line.as_str());
+                    Ok(Read::Line(line))
+                }
+                Err(ReadlineError::Eof) => Ok(Read::End),
+                Err(ReadlineError::Interrupted) => Ok(Read::Interrupted),
+                Err(ReadlineError::Io(e)) => Err(e),
+                Err(e) => Err(io::Error::other(e)),
+            }
+        }));
+        let read = self.read(interruptible).await?;
+        Ok(outcome(read, interruptible))
+    }
+
+    /// Wait for the pending read, which stays pending when Ctrl+C cuts the
+    /// wait short.
+    async fn read(&mut self, interruptible: bool) -> Result<Read> {
+        let Some(pending) = self.pending.as_mut() else {
+            return Ok(Read::End);
+        };
+        let read = tokio::select! {
+            read = &mut pending.rx => read,
+            () = interrupted(interruptible) => return Ok(Read::Interrupted),
+        };
+        self.pending = None;
+        match read {
+            Ok(read) => Ok(read?),
+            Err(_) => Ok(Read::End),
+        }
+    }
+}
+
+/// The line read, if any. Ctrl+C ends the program unless `interruptible`: the
+/// line editor reads it as a key, so it doesn't do that by itself.
+fn outcome(read: Read, interruptible: bool) -> Option<String> {
+    match read {
+        Read::Line(line) => Some(line),
+        Read::End | Read::Cancelled => None,
+        Read::Interrupted if interruptible => None,
+        Read::Interrupted => std::process::exit(130),
+    }
+}
+
+/// Read a line from stdin as typed. On a terminal, gives up once `cancel` is
+/// set while nothing has been entered.
+fn read_plain(cancel: &AtomicBool) -> io::Result<Read> {
+    while io::stdin().is_terminal() && !stdin_ready()? {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(Read::Cancelled);
+        }
+    }
+    let mut line = String::new();
+    Ok(match io::stdin().lock().read_line(&mut line)? {
+        0 => Read::End,
+        _ => Read::Line(line.trim_end_matches(['\r', '\n']).to_string()),
+    })
+}
+
+/// Whether stdin has a line to read, waiting up to a tenth of a second.
+#[cfg(unix)]
+fn stdin_ready() -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `fd` is one valid pollfd, as the count says.
+    match unsafe { libc::poll(&mut fd, 1, 100) } {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+            e => Err(e),
+        },
+        ready => Ok(ready > 0),
+    }
+}
+
+/// Without `poll`, reads wait for a line and can't be cancelled.
+#[cfg(not(unix))]
+fn stdin_ready() -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Completes commands and their arguments at the chat prompt, and hints at
+/// what a command takes.
+struct PromptHelper {
+    commands: Vec<completion::Command>,
+    templates: Vec<String>,
+    profiles: Vec<String>,
+}
+
+impl PromptHelper {
+    fn new(config: &Config) -> Self {
+        Self {
+            commands: completion::commands(COMMANDS),
+            templates: config.templates.keys().cloned().collect(),
+            profiles: config.profiles.keys().cloned().collect(),
+        }
+    }
+
+    /// The names completed for an argument. Models and saved conversations
+    /// are looked up as they are needed, as they change while chatting.
+    fn names(&self, names: Names) -> Vec<String> {
+        match names {
+            Names::Models => models::load_cache().unwrap_or_default().into_iter().map(|model| model.id).collect(),
+            Names::Conversations => history::list().unwrap_or_default().into_iter().map(|entry| entry.name).collect(),
+            Names::Templates => self.templates.clone(),
+            Names::Profiles => self.profiles.clone(),
+        }
+    }
+}
+
+impl Completer for PromptHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(completion::complete(line, pos, &self.commands, |names| self.names(names)))
+    }
+}
+
+/// A command's syntax shown after the cursor. Accepting it types only the
+/// rest of the command's name.
+struct CommandHint(completion::Hint);
+
+impl Hint for CommandHint {
+    fn display(&self) -> &str {
+        &self.0.text
+    }
+
+    fn completion(&self) -> Option<&str> {
+        Some(&self.0.text[..self.0.name_len]).filter(|name| !name.is_empty())
+    }
+}
+
+impl Hinter for PromptHelper {
+    type Hint = CommandHint;
+
+    fn hint(&self, line: &str, pos: usize, _: &rustyline::Context<'_>) -> Option<CommandHint> {
+        match pos == line.len() {
+            true => completion::hint(line, &self.commands).map(CommandHint),
+            false => None,
+        }
+    }
+}
+
+impl Highlighter for PromptHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+}
+
+impl Validator for PromptHelper {}
+
+impl Helper for PromptHelper {}
+
+/// Count Ctrl+C presses from now on, instead of letting the first one end the
+/// program.
+fn count_interrupts() -> watch::Receiver<u32> {
+    let (tx, rx) = watch::channel(0);
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            tx.send_modify(|presses| *presses += 1);
+        }
+    });
+    rx
+}
+
+/// Whether a trimmed `--repl-script` line does something when run: isn't
+/// blank, a comment or a directive.
+fn is_script_step(line: &str) -> bool {
+    !(line.is_empty() || line.starts_with('#') || line.starts_with('!'))
+}
+
+/// Resolves at Ctrl+C when `enabled`. Otherwise never, and Ctrl+C is left to
+/// end the program as usual.
+async fn interrupted(enabled: bool) {
+    if enabled && tokio::signal::ctrl_c().await.is_ok() {
+        return;
+    }
+    std::future::pending().await
+}
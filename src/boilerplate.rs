@@ -0,0 +1,244 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// Paragraphs at each end of a reply that can be boilerplate.
+const EDGE_PARAGRAPHS: usize = 3;
+
+/// Words in each shingle paragraphs are compared by.
+const SHINGLE_WORDS: usize = 2;
+
+/// When opening and closing paragraphs repeated across replies, such as "Great
+/// question! I'd be happy to help.", count as boilerplate. Set in the
+/// `[boilerplate]` section of the config file; off unless enabled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Dedupe {
+    pub enabled: bool,
+    /// Replies a paragraph has to open or close before it is boilerplate.
+    pub min_replies: usize,
+    /// How alike two paragraphs have to be to count as the same, from 0 to 1:
+    /// the share of their word pairs they have in common.
+    pub similarity: f64,
+    /// Also leave boilerplate out of the history sent to the model. Saved
+    /// conversations always keep the replies whole.
+    pub strip_from_context: bool,
+}
+
+impl Default for Dedupe {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_replies: 3,
+            similarity: 0.6,
+            strip_from_context: false,
+        }
+    }
+}
+
+/// The normalized word pairs of a paragraph, hashed.
+type Shingles = HashSet<u64>;
+
+/// Paragraphs found opening or closing enough replies of a conversation to be
+/// boilerplate, learned with [`Boilerplate::learn`].
+#[derive(Debug, Clone, Default)]
+pub struct Boilerplate {
+    leading: Vec<Shingles>,
+    trailing: Vec<Shingles>,
+    similarity: f64,
+}
+
+/// A reply cut into its boilerplate and the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Split<'a> {
+    /// Opening boilerplate, trimmed; empty when there is none.
+    pub head: &'a str,
+    pub body: &'a str,
+    /// Closing boilerplate, trimmed; empty when there is none.
+    pub tail: &'a str,
+}
+
+impl Split<'_> {
+    /// Whether any boilerplate was found.
+    pub fn is_hidden(&self) -> bool {
+        !self.head.is_empty() || !self.tail.is_empty()
+    }
+}
+
+/// What replaces hidden boilerplate on screen.
+pub const MARKER: &str = "(boilerplate hidden)";
+
+impl Boilerplate {
+    /// Find the paragraphs that open, or close, at least `settings.min_replies`
+    /// of `replies` in near-identical wording.
+    ///
+    /// ```
+    /// use llm::boilerplate::{Boilerplate, Dedupe};
+    ///
+    /// let replies = [
+    ///     "Great question! I'm happy to help you with that.\n\nA borrow checks references.\n\nLet me know if you have any other questions!",
+    ///     "Great question!! I am happy to help you with that.\n\nUse `Rc` to share ownership.\n\nLet me know if you have any other questions.",
+    ///     "Great question! I'm so happy to help you with that.\n\nLifetimes name how long references live.",
+    ///     "Lifetimes can be elided in simple cases.\n\nLet me know if you have any other questions!",
+    /// ];
+    /// let boilerplate = Boilerplate::learn(&replies, &Dedupe::default());
+    ///
+    /// let split = boilerplate.split(replies[0]);
+    /// assert_eq!(split.head, "Great question! I'm happy to help you with that.");
+    /// assert_eq!(split.body, "A borrow checks references.");
+    /// assert_eq!(split.tail, "Let me know if you have any other questions!");
+    /// assert_eq!(boilerplate.split(replies[2]).body, "Lifetimes name how long references live.");
+    /// assert_eq!(boilerplate.split(replies[3]).head, "");
+    ///
+    /// // Two replies aren't enough to make a paragraph boilerplate.
+    /// let boilerplate = Boilerplate::learn(&replies[..2], &Dedupe::default());
+    /// assert!(boilerplate.is_empty());
+    /// assert!(!boilerplate.split(replies[0]).is_hidden());
+    /// ```
+    pub fn learn(replies: &[&str], settings: &Dedupe) -> Self {
+        let edges: Vec<(Vec<Shingles>, Vec<Shingles>)> = replies
+            .iter()
+            .map(|reply| {
+                let paragraphs = paragraphs(reply);
+                let shingled = |range: &Range<usize>| shingles(&reply[range.clone()]);
+                let head = eligible(reply, paragraphs.iter()).map(shingled).collect();
+                let tail = eligible(reply, paragraphs.iter().rev()).map(shingled).collect();
+                (head, tail)
+            })
+            .collect();
+        let similarity = settings.similarity;
+        let heads: Vec<&[Shingles]> = edges.iter().map(|(head, _)| head.as_slice()).collect();
+        let tails: Vec<&[Shingles]> = edges.iter().map(|(_, tail)| tail.as_slice()).collect();
+        Self {
+            leading: repeated(&heads, settings.min_replies, similarity),
+            trailing: repeated(&tails, settings.min_replies, similarity),
+            similarity,
+        }
+    }
+
+    /// Whether no boilerplate was found.
+    pub fn is_empty(&self) -> bool {
+        self.leading.is_empty() && self.trailing.is_empty()
+    }
+
+    /// `reply` cut into its opening boilerplate, the rest, and its closing
+    /// boilerplate. At least one paragraph is always left in the body.
+    pub fn split<'a>(&self, reply: &'a str) -> Split<'a> {
+        let paragraphs = paragraphs(reply);
+        let matches = |patterns: &[Shingles], range: &Range<usize>| {
+            let paragraph = shingles(&reply[range.clone()]);
+            patterns.iter().any(|pattern| similar(pattern, &paragraph, self.similarity))
+        };
+        let head = eligible(reply, paragraphs.iter())
+            .take(paragraphs.len().saturating_sub(1))
+            .take_while(|range| matches(&self.leading, range))
+            .count();
+        let tail = eligible(reply, paragraphs.iter().rev())
+            .take(paragraphs.len().saturating_sub(head + 1))
+            .take_while(|range| matches(&self.trailing, range))
+            .count();
+        let (start, end) = match paragraphs.get(head..paragraphs.len() - tail) {
+            Some([first, .., last]) => (first.start, last.end),
+            Some([only]) => (only.start, only.end),
+            _ => (0, reply.len()),
+        };
+        Split {
+            head: reply[..start].trim(),
+            body: &reply[start..end],
+            tail: reply[end..].trim(),
+        }
+    }
+
+    /// `reply` without its boilerplate, as sent to the model.
+    pub fn strip(&self, reply: &str) -> String {
+        self.split(reply).body.to_string()
+    }
+}
+
+/// The byte ranges of the paragraphs of `text`, which blank lines separate,
+/// without the whitespace around them.
+fn paragraphs(text: &str) -> Vec<Range<usize>> {
+    let mut paragraphs = Vec::new();
+    let mut current: Option<Range<usize>> = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            paragraphs.extend(current.take());
+        } else {
+            let start = offset + line.len() - line.trim_start().len();
+            let end = offset + line.trim_end().len();
+            current = Some(current.map_or(start, |range| range.start)..end);
+        }
+        offset += line.len();
+    }
+    paragraphs.extend(current);
+    paragraphs
+}
+
+/// The first [`EDGE_PARAGRAPHS`] of `paragraphs` that can be boilerplate:
+/// those before the first code block. Code is never boilerplate.
+fn eligible<'a>(
+    text: &'a str,
+    paragraphs: impl Iterator<Item = &'a Range<usize>> + 'a,
+) -> impl Iterator<Item = &'a Range<usize>> + 'a {
+    paragraphs
+        .take(EDGE_PARAGRAPHS)
+        .take_while(move |range| !text[(*range).clone()].lines().any(|line| {
+            let line = line.trim_start();
+            line.starts_with("```") || line.starts_with("~~~")
+        }))
+}
+
+/// The hashed runs of [`SHINGLE_WORDS`] words of `paragraph`, lowercased and
+/// without punctuation, so that small changes in wording still look alike.
+fn shingles(paragraph: &str) -> Shingles {
+    let words: Vec<String> = paragraph
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words
+        .windows(SHINGLE_WORDS.min(words.len()).max(1))
+        .map(|run| {
+            let mut hasher = DefaultHasher::new();
+            run.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// Whether `a` and `b` share at least `similarity` of their shingles.
+fn similar(a: &Shingles, b: &Shingles, similarity: f64) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    let shared = a.intersection(b).count();
+    shared as f64 / (a.len() + b.len() - shared) as f64 >= similarity
+}
+
+/// The paragraphs of `edges`, one slice per reply, that at least `min_replies`
+/// replies have a near-identical one of.
+fn repeated(edges: &[&[Shingles]], min_replies: usize, similarity: f64) -> Vec<Shingles> {
+    let mut patterns: Vec<Shingles> = Vec::new();
+    for (index, paragraphs) in edges.iter().enumerate() {
+        for paragraph in paragraphs.iter() {
+            if paragraph.is_empty() || patterns.iter().any(|pattern| similar(pattern, paragraph, similarity)) {
+                continue;
+            }
+            let others = edges
+                .iter()
+                .enumerate()
+                .filter(|&(other, candidates)| {
+                    other != index && candidates.iter().any(|candidate| similar(candidate, paragraph, similarity))
+                })
+                .count();
+            if others + 1 >= min_replies.max(2) {
+                patterns.push(paragraph.clone());
+            }
+        }
+    }
+    patterns
+}
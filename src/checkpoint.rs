@@ -0,0 +1,176 @@
+//! Snapshots of saved conversations, taken before operations that rewrite
+//! their history: starting over, dropping later exchanges, or replacing text
+//! across messages.
+//!
+//! A conversation's checkpoints are kept as `<number>.json` in its own
+//! directory under [`checkpoints_dir`], numbered from 1 up. They are written
+//! before the operation runs, so one is left behind even if the app stops right
+//! after it. Only the last [`KEEP`] are kept. Like saved conversations, they are
+//! encrypted when the [`vault`] is on, and none are taken in
+//! [`incognito`] mode.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::history::{self, SavedConversation};
+use crate::{incognito, storage, vault};
+
+/// How many checkpoints are kept per conversation.
+pub const KEEP: usize = 10;
+
+/// A conversation as it was before an operation rewrote it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    #[serde(skip)]
+    pub number: u64,
+    /// What was about to happen, e.g. "before /goto 2".
+    pub reason: String,
+    /// When it was taken, in seconds since the Unix epoch.
+    pub taken_at: u64,
+    pub conversation: SavedConversation,
+}
+
+/// Where checkpoints are kept, one directory per conversation.
+pub fn checkpoints_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cli_llm").join("checkpoints"))
+}
+
+fn dir(name: &str) -> Result<PathBuf> {
+    history::validate_name(name)?;
+    let dir = checkpoints_dir().ok_or_else(|| Error::Config("No data directory on this system".to_string()))?;
+    Ok(dir.join(name))
+}
+
+/// Snapshot `conversation` under its name, then drop the checkpoints beyond
+/// the last [`KEEP`]. Returns the new checkpoint's number, or `None` in
+/// incognito mode.
+pub fn take(conversation: &SavedConversation, reason: &str) -> Result<Option<u64>> {
+    if incognito::is_on() {
+        return Ok(None);
+    }
+    let dir = dir(&conversation.name)?;
+    let mut numbers = numbers(&conversation.name)?;
+    let number = numbers.last().map_or(1, |last| last + 1);
+    let checkpoint = Checkpoint {
+        number,
+        reason: reason.to_string(),
+        taken_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        conversation: conversation.clone(),
+    };
+    let path = dir.join(format!("{}.json", number));
+    let json = serde_json::to_vec(&checkpoint)?;
+    storage::write_atomic(&path, vault::seal(json)?).map_err(Error::file(&path))?;
+    numbers.push(number);
+    for old in stale(&numbers) {
+        let path = dir.join(format!("{}.json", old));
+        if let Err(error) = fs::remove_file(&path) {
+            log::warn!("Could not remove old checkpoint {}: {}", path.display(), error);
+        }
+    }
+    Ok(Some(number))
+}
+
+/// The checkpoints to remove so that only the last [`KEEP`] of `numbers`, in
+/// ascending order, remain. The most recent is never among them.
+///
+/// ```
+/// use llm::checkpoint::{stale, KEEP};
+///
+/// let numbers: Vec<u64> = (1..=12).collect();
+/// assert_eq!(stale(&numbers), [1, 2]);
+/// assert_eq!(stale(&numbers[..KEEP]), [] as [u64; 0]);
+/// assert_eq!(stale(&[7]), [] as [u64; 0]);
+/// ```
+pub fn stale(numbers: &[u64]) -> &[u64] {
+    &numbers[..numbers.len().saturating_sub(KEEP.max(1))]
+}
+
+/// The numbers of the checkpoints of the conversation called `name`, oldest first.
+fn numbers(name: &str) -> Result<Vec<u64>> {
+    let dir = dir(name)?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::File { path: dir, source: e }),
+    };
+    let mut numbers = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(Error::file(&dir))?.path();
+        if let Some(number) = path
+            .file_name()
+            .and_then(|file| file.to_str())
+            .and_then(|file| file.strip_suffix(".json"))
+            .and_then(|number| number.parse().ok())
+        {
+            numbers.push(number);
+        }
+    }
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+/// The files of every checkpoint of every conversation, for the [`vault`] to
+/// re-encrypt.
+pub(crate) fn files() -> Result<Vec<PathBuf>> {
+    let Some(root) = checkpoints_dir() else {
+        return Ok(Vec::new());
+    };
+    let entries = match fs::read_dir(&root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::File { path: root, source: e }),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let Some(name) = entry.map_err(Error::file(&root))?.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if history::validate_name(&name).is_err() {
+            continue;
+        }
+        let dir = root.join(&name);
+        files.extend(numbers(&name)?.into_iter().map(|number| dir.join(format!("{}.json", number))));
+    }
+    Ok(files)
+}
+
+/// The checkpoints of the conversation called `name`, oldest first.
+pub fn list(name: &str) -> Result<Vec<Checkpoint>> {
+    numbers(name)?.into_iter().map(|number| load(name, number)).collect()
+}
+
+/// Checkpoint `number` of the conversation called `name`.
+pub fn load(name: &str, number: u64) -> Result<Checkpoint> {
+    let path = dir(name)?.join(format!("{}.json", number));
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::Config(format!("Conversation '{}' has no checkpoint {}", name, number)));
+        }
+        Err(e) => return Err(Error::File { path, source: e }),
+    };
+    let mut checkpoint: Checkpoint = serde_json::from_slice(&vault::open(data)?)?;
+    checkpoint.number = number;
+    checkpoint.conversation.name = name.to_string();
+    checkpoint.conversation.archived = false;
+    Ok(checkpoint)
+}
+
+/// Save checkpoint `number` as the conversation called `name` again, after
+/// taking a checkpoint of the saved conversation so the restore can be undone
+/// too. Returns the conversation as restored.
+pub fn restore(name: &str, number: u64) -> Result<SavedConversation> {
+    let mut conversation = load(name, number)?.conversation;
+    if let Ok(current) = history::load(name) {
+        if current.archived {
+            return Err(history::archived_error(name));
+        }
+        take(&current, &format!("before restoring checkpoint {}", number))?;
+    }
+    conversation.save()?;
+    Ok(conversation)
+}
@@ -0,0 +1,314 @@
+use std::ops::Range;
+
+use similar::{Algorithm, ChangeTag, DiffOp, DiffTag, TextDiff};
+
+use crate::api::ChatMessageRequest;
+use crate::error::{Error, Result};
+use crate::markdown::{self, CodeBlock};
+
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+/// A code block of a conversation and where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// Number of the message it is in, counting from 1.
+    pub message: usize,
+    pub role: String,
+    pub block: CodeBlock,
+}
+
+impl Snippet {
+    /// Its name in patch headers, e.g. `message-12.rs`.
+    fn file_name(&self) -> String {
+        let extension = match self.block.language.as_str() {
+            "" => "txt",
+            "rust" => "rs",
+            "python" => "py",
+            "javascript" => "js",
+            "typescript" => "ts",
+            "bash" | "shell" => "sh",
+            "markdown" => "md",
+            "yaml" => "yml",
+            language => language,
+        };
+        format!("message-{}.{}", self.message, extension)
+    }
+}
+
+/// The code blocks to compare by default: the latest one the assistant
+/// returned, and the latest one of yours before it in the same language.
+pub fn latest(messages: &[ChatMessageRequest]) -> Result<(Snippet, Snippet)> {
+    let new = messages
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, msg)| msg.role == "assistant" && !msg.display_only)
+        .find_map(|(index, msg)| snippet(messages, index, &msg.role, None))
+        .ok_or_else(|| Error::Config("No reply has a code block to compare.".to_string()))?;
+    let old = messages[..new.message - 1]
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, msg)| msg.role == "user")
+        .find_map(|(index, msg)| snippet(messages, index, &msg.role, Some(&new.block.language)))
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "None of your messages before message {} has a code block{} to compare.",
+                new.message,
+                language_note(&new.block.language)
+            ))
+        })?;
+    Ok((old, new))
+}
+
+/// The code blocks of messages `old` and `new` (numbered from 1): the last
+/// block of each, in the same language where both have one.
+pub fn between(messages: &[ChatMessageRequest], old: usize, new: usize) -> Result<(Snippet, Snippet)> {
+    let message = |number: usize| {
+        number
+            .checked_sub(1)
+            .and_then(|index| messages.get(index))
+            .ok_or_else(|| Error::Config(format!("There is no message {} (1-{}).", number, messages.len())))
+    };
+    let no_code = |number: usize| Error::Config(format!("Message {} has no code block.", number));
+    let new_snippet = snippet(messages, new - 1, &message(new)?.role, None).ok_or_else(|| no_code(new))?;
+    let old_role = &message(old)?.role;
+    let old_snippet = snippet(messages, old - 1, old_role, Some(&new_snippet.block.language))
+        .or_else(|| snippet(messages, old - 1, old_role, None))
+        .ok_or_else(|| no_code(old))?;
+    let new_snippet = snippet(messages, new - 1, &message(new)?.role, Some(&old_snippet.block.language))
+        .unwrap_or(new_snippet);
+    Ok((old_snippet, new_snippet))
+}
+
+/// The last code block of message `index`, in `language` when given. Blocks
+/// without a language match any.
+fn snippet(messages: &[ChatMessageRequest], index: usize, role: &str, language: Option<&str>) -> Option<Snippet> {
+    let block = markdown::code_blocks(&messages[index].content).into_iter().rev().find(|block| match language {
+        Some(language) => block.language == language || block.language.is_empty() || language.is_empty(),
+        None => true,
+    })?;
+    Some(Snippet {
+        message: index + 1,
+        role: role.to_string(),
+        block,
+    })
+}
+
+fn language_note(language: &str) -> String {
+    match language {
+        "" => String::new(),
+        language => format!(" in {}", language),
+    }
+}
+
+/// Part of a line in a side-by-side view, marked when it is what changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub changed: bool,
+}
+
+/// One side of a [`Row`]: a line number, from 1, and the line's text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Side {
+    pub number: usize,
+    pub spans: Vec<Span>,
+}
+
+/// A line of a side-by-side view. A side is missing where lines were only
+/// added or only removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub old: Option<Side>,
+    pub new: Option<Side>,
+    pub changed: bool,
+}
+
+/// Changed lines with the unchanged ones around them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// The unified diff header, e.g. `@@ -3,7 +3,8 @@`.
+    pub header: String,
+    pub rows: Vec<Row>,
+}
+
+/// The line-by-line differences between two code blocks.
+///
+/// ```
+/// use llm::codediff::Diff;
+///
+/// let old = "fn add(a: i32, b: i32) -> i32 {\n    a+b\n}\n";
+/// let new = "fn add(a: i64, b: i64) -> i64 {\n    a + b\n}\n";
+/// let diff = Diff::new(old, new, false);
+/// assert_eq!(diff.counts(), (2, 2));
+/// let patch = diff.unified("a/add.rs", "b/add.rs");
+/// assert!(patch.starts_with("--- a/add.rs\n+++ b/add.rs\n@@ -1,3 +1,3 @@\n-fn add(a: i32"));
+/// assert!(patch.contains("\n-    a+b\n+fn add(a: i64, b: i64) -> i64 {\n+    a + b\n }\n"));
+///
+/// // With whitespace changes collapsed, only the signature changed.
+/// let diff = Diff::new(old, new, true);
+/// assert_eq!(diff.counts(), (1, 1));
+/// let row = &diff.hunks()[0].rows[0];
+/// let changed: Vec<_> = row.new.as_ref().unwrap().spans.iter().filter(|span| span.changed).map(|span| span.text.as_str()).collect();
+/// assert_eq!(changed, ["i64,", "i64)", "i64"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Diff {
+    old: Vec<String>,
+    new: Vec<String>,
+    groups: Vec<Vec<DiffOp>>,
+}
+
+impl Diff {
+    /// Compare `old` with `new`. With `ignore_whitespace`, lines that only
+    /// differ in whitespace count as unchanged.
+    pub fn new(old: &str, new: &str, ignore_whitespace: bool) -> Self {
+        let old: Vec<String> = old.lines().map(str::to_string).collect();
+        let new: Vec<String> = new.lines().map(str::to_string).collect();
+        let key = |line: &String| match ignore_whitespace {
+            true => line.split_whitespace().collect::<String>(),
+            false => line.clone(),
+        };
+        let old_keys: Vec<String> = old.iter().map(key).collect();
+        let new_keys: Vec<String> = new.iter().map(key).collect();
+        let ops = similar::capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys);
+        let groups = similar::group_diff_ops(ops, CONTEXT);
+        Self { old, new, groups }
+    }
+
+    /// Compare two snippets.
+    pub fn snippets(old: &Snippet, new: &Snippet, ignore_whitespace: bool) -> Self {
+        Self::new(&old.block.code, &new.block.code, ignore_whitespace)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Lines added and removed.
+    pub fn counts(&self) -> (usize, usize) {
+        let ops = self.groups.iter().flatten();
+        ops.fold((0, 0), |(added, removed), op| match op.tag() {
+            DiffTag::Equal => (added, removed),
+            _ => (added + op.new_range().len(), removed + op.old_range().len()),
+        })
+    }
+
+    /// The diff in unified format, as a patch of `old_name` into `new_name`.
+    pub fn unified(&self, old_name: &str, new_name: &str) -> String {
+        let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+        for group in &self.groups {
+            out.push_str(&header(group));
+            out.push('\n');
+            for op in group {
+                let (tag, old, new) = op.as_tag_tuple();
+                if tag == DiffTag::Equal {
+                    lines(&mut out, ' ', &self.new[new]);
+                } else {
+                    lines(&mut out, '-', &self.old[old]);
+                    lines(&mut out, '+', &self.new[new]);
+                }
+            }
+        }
+        out
+    }
+
+    /// The diff as a patch of one snippet into the other.
+    pub fn patch(&self, old: &Snippet, new: &Snippet) -> String {
+        self.unified(&format!("a/{}", old.file_name()), &format!("b/{}", new.file_name()))
+    }
+
+    /// The changes side by side. Changed lines are paired up in order, with
+    /// the words that differ marked.
+    pub fn hunks(&self) -> Vec<Hunk> {
+        self.groups
+            .iter()
+            .map(|group| Hunk {
+                header: header(group),
+                rows: group.iter().flat_map(|op| self.rows(op)).collect(),
+            })
+            .collect()
+    }
+
+    fn rows(&self, op: &DiffOp) -> Vec<Row> {
+        let (tag, old, new) = op.as_tag_tuple();
+        let side = |lines: &[String], range: &Range<usize>, i: usize, spans: Vec<Span>| {
+            (i < range.len()).then(|| Side {
+                number: range.start + i + 1,
+                spans: if spans.is_empty() { plain(&lines[range.start + i], tag != DiffTag::Equal) } else { spans },
+            })
+        };
+        (0..old.len().max(new.len()))
+            .map(|i| {
+                let (old_spans, new_spans) = match tag == DiffTag::Replace && i < old.len() && i < new.len() {
+                    true => words(&self.old[old.start + i], &self.new[new.start + i]),
+                    false => (Vec::new(), Vec::new()),
+                };
+                Row {
+                    old: side(&self.old, &old, i, old_spans),
+                    new: side(&self.new, &new, i, new_spans),
+                    changed: tag != DiffTag::Equal,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The `@@ -a,b +c,d @@` line of a group of operations.
+fn header(group: &[DiffOp]) -> String {
+    let (first, last) = (&group[0], &group[group.len() - 1]);
+    let old = first.old_range().start..last.old_range().end;
+    let new = first.new_range().start..last.new_range().end;
+    let range = |range: Range<usize>| match range.len() {
+        0 => format!("{},0", range.start),
+        1 => format!("{}", range.start + 1),
+        n => format!("{},{}", range.start + 1, n),
+    };
+    format!("@@ -{} +{} @@", range(old), range(new))
+}
+
+fn lines(out: &mut String, prefix: char, lines: &[String]) {
+    for line in lines {
+        out.push(prefix);
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+fn plain(line: &str, changed: bool) -> Vec<Span> {
+    vec![Span {
+        text: line.to_string(),
+        changed,
+    }]
+}
+
+/// Two versions of a line split into words, with those that differ marked.
+fn words(old: &str, new: &str) -> (Vec<Span>, Vec<Span>) {
+    let diff = TextDiff::from_words(old, new);
+    let (mut old_spans, mut new_spans) = (Vec::new(), Vec::new());
+    for change in diff.iter_all_changes() {
+        let text = change.value();
+        match change.tag() {
+            ChangeTag::Equal => {
+                push_span(&mut old_spans, text, false);
+                push_span(&mut new_spans, text, false);
+            }
+            ChangeTag::Delete => push_span(&mut old_spans, text, !text.trim().is_empty()),
+            ChangeTag::Insert => push_span(&mut new_spans, text, !text.trim().is_empty()),
+        }
+    }
+    (old_spans, new_spans)
+}
+
+/// Add `text` to `spans`, merging it into the last span when marked alike.
+fn push_span(spans: &mut Vec<Span>, text: &str, changed: bool) {
+    match spans.last_mut() {
+        Some(last) if last.changed == changed => last.text.push_str(text),
+        _ => spans.push(Span {
+            text: text.to_string(),
+            changed,
+        }),
+    }
+}
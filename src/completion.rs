@@ -0,0 +1,212 @@
+//! Tab completion and hints for the terminal client's prompt.
+//!
+//! The commands are read from the prompt's help text, where each line starting
+//! with `/` gives one or more commands and what they take, e.g. `/model [ID]`
+//! or `/quit, /exit`. Names match by prefix and, when nothing does, by having
+//! the typed letters in order, so `/lb` finds `/low-bandwidth`.
+
+/// A command of the prompt and what follows it, e.g. `model` and `[ID]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub name: String,
+    pub syntax: String,
+}
+
+/// The commands in `help`, in its order.
+///
+/// ```
+/// use llm::completion::commands;
+///
+/// let help = "Commands:\n  /model [ID]      Chat with another model.\n                   More about it.\n\
+///             /rate up|down [NOTE]\n                   Rate the last reply.\n  /quit, /exit     Exit.";
+/// let found: Vec<_> = commands(help).into_iter().map(|c| (c.name, c.syntax)).collect();
+/// assert_eq!(found, [
+///     ("model".to_string(), "[ID]".to_string()),
+///     ("rate".to_string(), "up|down [NOTE]".to_string()),
+///     ("quit".to_string(), String::new()),
+///     ("exit".to_string(), String::new()),
+/// ]);
+/// ```
+pub fn commands(help: &str) -> Vec<Command> {
+    let mut found = Vec::new();
+    for line in help.lines().map(str::trim_start) {
+        if !line.starts_with('/') {
+            continue;
+        }
+        let usage = line.split("  ").next().unwrap_or(line);
+        for usage in usage.split(", ") {
+            let Some(usage) = usage.strip_prefix('/') else {
+                continue;
+            };
+            let (name, syntax) = usage.split_once(' ').unwrap_or((usage, ""));
+            found.push(Command {
+                name: name.to_string(),
+                syntax: syntax.trim().to_string(),
+            });
+        }
+    }
+    found
+}
+
+/// Names that change as the app is used, completed as arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Names {
+    /// Model IDs, from the models list.
+    Models,
+    /// Saved conversations.
+    Conversations,
+    /// Conversation templates.
+    Templates,
+    /// Config profiles.
+    Profiles,
+}
+
+/// What the first argument of `command` can be.
+enum Argument {
+    Names(Names),
+    Words(&'static [&'static str]),
+}
+
+fn argument(command: &str) -> Option<Argument> {
+    Some(match command {
+        "model" => Argument::Names(Names::Models),
+        "save" => Argument::Names(Names::Conversations),
+        "new" | "template" => Argument::Names(Names::Templates),
+        "profile" => Argument::Names(Names::Profiles),
+        "route" => Argument::Words(&["cheapest", "fastest", "reliable", "default"]),
+        "rate" => Argument::Words(&["up", "down", "clear"]),
+        "low-bandwidth" | "thinking" => Argument::Words(&["on", "off"]),
+        "context" => Argument::Words(&["edit", "more", "drop"]),
+        "debug" => Argument::Words(&["context"]),
+        "open" => Argument::Words(&["--gui"]),
+        "curl" => Argument::Words(&["--reveal-key"]),
+        "export" => Argument::Words(&["--dark", "--anonymize", "--redact-system"]),
+        _ => return None,
+    })
+}
+
+/// Where the word being completed starts in `line`, and what it can become.
+/// Completes command names, their first argument, and the model of an
+/// `@model:ID` message; `names` gives the [`Names`] an argument asks for.
+///
+/// ```
+/// use llm::completion::{commands, complete, Names};
+///
+/// let commands = commands("  /model [ID]  Chat with another model.\n  /low-bandwidth [on|off]\n  /load  Load.");
+/// let names = |names: Names| match names {
+///     Names::Models => vec!["openai/gpt-4o".to_string(), "anthropic/claude-3.5-sonnet".to_string()],
+///     _ => Vec::new(),
+/// };
+/// assert_eq!(complete("/lo", 3, &commands, names), (1, vec!["low-bandwidth".to_string(), "load".to_string()]));
+/// // Without a prefix match, the letters only have to come in order.
+/// assert_eq!(complete("/lbw", 4, &commands, names), (1, vec!["low-bandwidth".to_string()]));
+/// assert_eq!(complete("/low-bandwidth o", 16, &commands, names).1, ["on", "off"]);
+/// assert_eq!(complete("/model gpt", 10, &commands, names), (7, vec!["openai/gpt-4o".to_string()]));
+/// assert_eq!(complete("@model:claude", 13, &commands, names).1, ["anthropic/claude-3.5-sonnet"]);
+/// // Messages aren't completed.
+/// assert_eq!(complete("hello wor", 9, &commands, names).1, Vec::<String>::new());
+/// ```
+pub fn complete(
+    line: &str,
+    pos: usize,
+    commands: &[Command],
+    names: impl Fn(Names) -> Vec<String>,
+) -> (usize, Vec<String>) {
+    let line = &line[..pos];
+    if let Some(model) = line.strip_prefix("@model:")
+        && !model.contains(char::is_whitespace)
+    {
+        return (pos - model.len(), matching(model, &names(Names::Models)));
+    }
+    let Some(command) = line.strip_prefix('/') else {
+        return (pos, Vec::new());
+    };
+    let Some((name, rest)) = command.split_once(' ') else {
+        let known: Vec<String> = commands.iter().map(|command| command.name.clone()).collect();
+        return (1, matching(command, &known));
+    };
+    let rest = rest.trim_start();
+    if rest.contains(char::is_whitespace) {
+        return (pos, Vec::new());
+    }
+    let start = pos - rest.len();
+    match argument(name) {
+        Some(Argument::Names(kind)) => (start, matching(rest, &names(kind))),
+        Some(Argument::Words(words)) => {
+            let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+            (start, matching(rest, &words))
+        }
+        None => (pos, Vec::new()),
+    }
+}
+
+/// The candidates starting with `typed`, ignoring case, or when none do,
+/// those with its letters in order.
+fn matching(typed: &str, candidates: &[String]) -> Vec<String> {
+    let typed = typed.to_lowercase();
+    let prefixed: Vec<String> = candidates
+        .iter()
+        .filter(|candidate| candidate.to_lowercase().starts_with(&typed))
+        .cloned()
+        .collect();
+    if !prefixed.is_empty() {
+        return prefixed;
+    }
+    candidates
+        .iter()
+        .filter(|candidate| {
+            let candidate = candidate.to_lowercase();
+            let mut letters = candidate.chars();
+            typed.chars().all(|c| letters.any(|letter| letter == c))
+        })
+        .cloned()
+        .collect()
+}
+
+/// A hint shown after what is typed: the rest of a command's name, then what
+/// it takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hint {
+    pub text: String,
+    /// How much of `text` is the rest of the name, which accepting the hint
+    /// types; the syntax after it is only shown.
+    pub name_len: usize,
+}
+
+/// The hint for `line` when it is a command being typed: the one command its
+/// name starts, or the command itself followed by a space.
+///
+/// ```
+/// use llm::completion::{commands, hint, Hint};
+///
+/// let commands = commands("  /model [ID]  Chat with another model.\n  /models  List them.\n  /route [PREF]  Order.");
+/// assert_eq!(hint("/ro", &commands), Some(Hint { text: "ute [PREF]".to_string(), name_len: 3 }));
+/// assert_eq!(hint("/model ", &commands), Some(Hint { text: "[ID]".to_string(), name_len: 0 }));
+/// // Two commands start with "mod"; an argument is already typed.
+/// assert_eq!(hint("/mod", &commands), None);
+/// assert_eq!(hint("/route cheapest", &commands), None);
+/// ```
+pub fn hint(line: &str, commands: &[Command]) -> Option<Hint> {
+    let typed = line.strip_prefix('/')?;
+    if let Some(name) = typed.strip_suffix(' ') {
+        let command = commands.iter().find(|command| command.name == name)?;
+        return (!command.syntax.is_empty()).then(|| Hint {
+            text: command.syntax.clone(),
+            name_len: 0,
+        });
+    }
+    if typed.is_empty() || typed.contains(' ') {
+        return None;
+    }
+    let mut started = commands.iter().filter(|command| command.name.starts_with(typed));
+    let command = started.next()?;
+    if started.next().is_some() && command.name != typed {
+        return None;
+    }
+    let rest = &command.name[typed.len()..];
+    let text = match command.syntax.as_str() {
+        "" => rest.to_string(),
+        syntax => format!("{} {}", rest, syntax),
+    };
+    (!text.is_empty()).then_some(Hint { text, name_len: rest.len() })
+}
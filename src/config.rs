@@ -0,0 +1,703 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{Attribution, ChatMessageRequest, Route};
+use crate::boilerplate::Dedupe;
+use crate::error::{Error, Result};
+use crate::filter::{Filter, Pipeline};
+use crate::flatten::FlattenTemplate;
+use crate::limit::Limit;
+use crate::locale::LocaleContext;
+use crate::paste::PasteLimits;
+use crate::recorder::Granularity;
+use crate::renderer::RendererCommand;
+use crate::timeout::Timeouts;
+use crate::{api, attachment, incognito, storage};
+
+/// Name used for the connection built from environment variables alone.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Greeting shown at the top of new GUI conversations unless configured otherwise.
+pub const DEFAULT_GREETING: &str = "Hello! I'm an AI assistant. How can I help you today?";
+
+/// Format version of files written by [`Config::export`].
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Settings read from `config.toml` in the user's config directory.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Config {
+    /// Profile used when none is picked on the command line.
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    /// Named API settings, e.g. `[profiles.work]`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Longest side, in pixels, of images attached to messages; larger ones are downscaled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_max_dimension: Option<u32>,
+    /// Starting points for new conversations, e.g. `[templates.review]`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, Template>,
+    /// Greeting shown at the top of new conversations; an empty string shows none.
+    /// It is only displayed and never sent to the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub greeting: Option<String>,
+    /// What to call the assistant, e.g. "Marvin": the label on its replies, the
+    /// `name` they are sent back with, and `{{assistant_name}}` in system prompts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assistant_name: Option<String>,
+    /// Preference order for `--model auto:free`: model ids or id prefixes, best first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub free_models: Vec<String>,
+    /// Models the GUI's Ctrl+M cycles through, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub favorite_models: Vec<String>,
+    /// Participants of group chats, e.g. `[[personas]]`, in the order they answer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub personas: Vec<Persona>,
+    /// How voice prompts recorded in the GUI are turned into text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcription: Option<Transcription>,
+    /// Provider routing preference for new conversations: `cheapest`, `fastest`
+    /// or `reliable`. OpenRouter's default routing when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<Route>,
+    /// Identifier of the end user sent with every request, for the provider's
+    /// abuse monitoring; `OPENROUTER_USER` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// GUI: stronger colours and solid outlines.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub high_contrast: bool,
+    /// Show the `<think>` reasoning some models put in their replies: printed
+    /// in the terminal, expanded in the GUI. Hidden by default.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub show_thinking: bool,
+    /// GUI: static text instead of animations.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reduce_motion: bool,
+    /// GUI: Enter sends the message and Shift+Enter starts a new line, instead
+    /// of Enter starting a new line and Ctrl+Enter (Cmd+Enter on macOS) sending.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enter_sends: bool,
+    /// Encrypt saved conversations with a passphrase. Existing ones are
+    /// encrypted in place the next time a front end starts.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypt_history: bool,
+    /// GUI: what to do after a while without input, for kiosks and demos. Off
+    /// when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle: Option<Idle>,
+    /// GUI: save the open conversation by itself once it changed. Off when
+    /// unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autosave: Option<Autosave>,
+    /// Role prefixes, cue and stop sequences used with `--flatten` and `--raw`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flatten: Option<FlattenTemplate>,
+    /// Never send requests the user didn't ask for that carry conversation
+    /// content, such as the background refreshes of conversation summaries.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_background_requests: bool,
+    /// Cheap model that writes the summaries of saved conversations; see
+    /// [`digest::model`](crate::digest::model) for the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary_model: Option<String>,
+    /// Keep every request and response, for debugging prompts and providers.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub flight_recorder: bool,
+    /// Whether the flight recorder keeps streamed replies whole (`message`, the
+    /// default) or every chunk of them too (`chunk`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flight_recorder_granularity: Option<Granularity>,
+    /// Limits on how long requests to the endpoint may take.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeouts: Option<Timeouts>,
+    /// How many requests of all running instances may be sent at once; the rest
+    /// wait in the [queue](crate::queue). 0 turns the queue off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_slots: Option<usize>,
+    /// Programs rendering code blocks by fence language, e.g.
+    /// `[renderers.plantuml]`, besides the built-in mermaid, dot and ics ones.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub renderers: BTreeMap<String, RendererCommand>,
+    /// When and how long pasted blocks are shortened before sending.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paste: Option<PasteLimits>,
+    /// Hiding opening and closing paragraphs that replies keep repeating.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boilerplate: Option<Dedupe>,
+    /// Telling the model the date, timezone, locale and units with every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale_context: Option<LocaleContext>,
+    /// The app requests are attributed to, unless a profile sets its own.
+    #[serde(default, skip_serializing_if = "Attribution::is_empty")]
+    pub attribution: Attribution,
+}
+
+/// What the GUI does when nobody has used it for a while.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Idle {
+    /// Seconds without input before acting; 0 turns it off.
+    pub seconds: u64,
+    #[serde(default)]
+    pub action: IdleAction,
+}
+
+impl Idle {
+    /// How long without input before acting, unless turned off.
+    pub fn timeout(&self) -> Option<Duration> {
+        (self.seconds > 0).then(|| Duration::from_secs(self.seconds))
+    }
+}
+
+/// `prompt` asks whether anyone is still there; `reset` clears the conversation
+/// back to the greeting.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    #[default]
+    Prompt,
+    Reset,
+}
+
+/// When the GUI saves the open conversation by itself. Conversations that were
+/// never saved get a name of their own.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Autosave {
+    /// Seconds to wait, as `mode` says; 0 turns it off.
+    pub seconds: u64,
+    #[serde(default)]
+    pub mode: AutosaveMode,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self {
+            seconds: 5,
+            mode: AutosaveMode::AfterChange,
+        }
+    }
+}
+
+impl Autosave {
+    /// How long to wait before saving, unless turned off.
+    pub fn delay(&self) -> Option<Duration> {
+        (self.seconds > 0).then(|| Duration::from_secs(self.seconds))
+    }
+}
+
+/// `after_change` saves once the conversation stopped changing for a while, so
+/// a burst of messages is saved once; `interval` saves at most that often while
+/// it keeps changing.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AutosaveMode {
+    #[default]
+    AfterChange,
+    Interval,
+}
+
+/// Settings for turning recorded voice prompts into text: an OpenAI-compatible
+/// `/audio/transcriptions` endpoint, or a local program such as whisper.cpp.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Transcription {
+    /// Transcription endpoint, e.g. `https://api.openai.com/v1/audio/transcriptions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The endpoint's API key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Name of the environment variable holding the endpoint's API key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// Model sent to the endpoint; `whisper-1` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Spoken language as an ISO 639-1 code, when the endpoint shouldn't guess it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Local program and arguments used instead of the endpoint; `{file}` is
+    /// replaced by the recording's path and the transcript is read from stdout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Vec<String>>,
+}
+
+impl Transcription {
+    /// The API key for the endpoint, if one is configured.
+    pub fn api_key(&self) -> Option<String> {
+        self.api_key
+            .clone()
+            .or_else(|| self.api_key_env.as_ref().and_then(|var| env::var(var).ok()))
+    }
+}
+
+/// One set of API settings that can be switched to at runtime.
+///
+/// Unset fields fall back to the environment (`OPENROUTER_API_KEY`,
+/// `OPENROUTER_API_URL`) and the built-in defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Profile {
+    /// The API key itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Name of the environment variable holding the API key, to keep it out of the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// Chat completions endpoint (any OpenAI-compatible backend).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Model selected when switching to this profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Applied in order to every reply from this profile's endpoint, e.g.
+    /// `["strip-think", { pattern = "...", replace = "" }]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<Filter>,
+    /// Start conversations in low-bandwidth mode: older turns summarized,
+    /// no images, no models list refreshes.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub low_bandwidth: bool,
+    /// The app this profile's requests are attributed to, e.g. a company's
+    /// app for a work profile; unset fields come from the config's.
+    #[serde(default, skip_serializing_if = "Attribution::is_empty")]
+    pub attribution: Attribution,
+}
+
+/// A named participant in a group chat, answering every message in turn.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Persona {
+    pub name: String,
+    /// The model it asks; the conversation's when empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub model: String,
+    /// Sent instead of the conversation's system prompt.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub system_prompt: String,
+}
+
+/// A preset for starting a new conversation.
+///
+/// Unset fields keep whatever the client currently uses.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Template {
+    /// Sent as the system message of every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Offered as the first user message for editing; never sent on its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_message: Option<String>,
+    /// Reference messages the conversation starts with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context: Vec<TemplateMessage>,
+    /// Length limit asked of every reply, e.g. `"200 words"` or `"72 subject"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<String>,
+}
+
+/// A message kept in a template as reference context.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TemplateMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl Template {
+    /// The messages a conversation started from this template begins with.
+    pub fn context_messages(&self) -> Vec<ChatMessageRequest> {
+        self.context
+            .iter()
+            .map(|msg| ChatMessageRequest::new(&msg.role, msg.content.clone()))
+            .collect()
+    }
+
+    /// The template's reply length limit, if it sets one.
+    pub fn limit(&self) -> Result<Option<Limit>> {
+        self.limit.as_deref().map(str::parse).transpose()
+    }
+}
+
+/// A portable copy of the settings, as written by `--export-config`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ExportFile {
+    version: u32,
+    #[serde(default)]
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    image_max_dimension: Option<u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    templates: BTreeMap<String, Template>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    greeting: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    free_models: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    favorite_models: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transcription: Option<Transcription>,
+    #[serde(default, skip_serializing_if = "Attribution::is_empty")]
+    attribution: Attribution,
+}
+
+/// A settings export or import requested on the command line.
+#[derive(Debug, Default)]
+pub struct Transfer {
+    /// `--export-config <PATH>`
+    pub export: Option<PathBuf>,
+    /// `--import-config <PATH>`
+    pub import: Option<PathBuf>,
+    /// `--include-keys`: write API keys into the export.
+    pub include_keys: bool,
+    /// `--replace`: replace the current settings on import instead of merging.
+    pub replace: bool,
+}
+
+impl Transfer {
+    /// Whether an export or import was asked for.
+    pub fn is_requested(&self) -> bool {
+        self.export.is_some() || self.import.is_some()
+    }
+
+    /// Carry out the export or import and describe what was done.
+    pub fn run(&self) -> Result<String> {
+        if self.export.is_some() && self.import.is_some() {
+            return Err(Error::Config(
+                "Pass either --export-config or --import-config, not both".to_string(),
+            ));
+        }
+        let mut config = Config::load()?;
+        if let Some(path) = &self.import {
+            let count = config.import(path, self.replace)?;
+            config.save()?;
+            return Ok(format!(
+                "{} {} profile(s) from {}",
+                if self.replace { "Replaced settings with" } else { "Merged" },
+                count,
+                path.display()
+            ));
+        }
+        let Some(path) = &self.export else {
+            return Ok(String::new());
+        };
+        config.export(path, self.include_keys)?;
+        let mut summary = format!("Exported {} profile(s) to {}", config.profiles.len(), path.display());
+        if !self.include_keys && config.profiles.values().any(|p| p.api_key.is_some()) {
+            summary.push_str(" (API keys left out; pass --include-keys to include them)");
+        }
+        Ok(summary)
+    }
+}
+
+/// Everything needed to talk to an endpoint, resolved from a profile.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    /// The profile this was resolved from, or [`DEFAULT_PROFILE`].
+    pub profile: String,
+    pub url: String,
+    pub model: String,
+    pub headers: HeaderMap,
+    /// The profile's reply filters.
+    pub filters: Pipeline,
+    /// Whether the profile starts conversations in low-bandwidth mode.
+    pub low_bandwidth: bool,
+}
+
+/// Where the config file lives.
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("cli_llm").join("config.toml"))
+}
+
+impl Config {
+    /// Load the config file; a missing file gives the empty default config.
+    pub fn load() -> Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| Error::Config(format!("Invalid config {}: {}", path.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::File { path, source: e }),
+        }
+    }
+
+    /// The configured limit for attached images, or the default.
+    pub fn image_max_dimension(&self) -> u32 {
+        self.image_max_dimension.unwrap_or(attachment::DEFAULT_MAX_DIMENSION)
+    }
+
+    /// The end user identifier sent with requests, from the config file or
+    /// `OPENROUTER_USER`; `None` when neither sets one.
+    pub fn user(&self) -> Option<String> {
+        self.user
+            .clone()
+            .or_else(|| env::var("OPENROUTER_USER").ok())
+            .filter(|user| !user.trim().is_empty())
+    }
+
+    /// The configured limits for pasted blocks, or the defaults.
+    pub fn paste(&self) -> PasteLimits {
+        self.paste.unwrap_or_default()
+    }
+
+    /// The settings for hiding boilerplate in replies, or `None` when it is off.
+    pub fn boilerplate(&self) -> Option<Dedupe> {
+        self.boilerplate.filter(|dedupe| dedupe.enabled)
+    }
+
+    /// The settings for telling the model the date and locale, or `None` when
+    /// that is off.
+    pub fn locale_context(&self) -> Option<LocaleContext> {
+        self.locale_context.clone().filter(|locale| locale.enabled)
+    }
+
+    /// The greeting for new conversations, or `None` when it is turned off.
+    pub fn greeting(&self) -> Option<&str> {
+        match self.greeting.as_deref() {
+            None => Some(DEFAULT_GREETING),
+            Some(greeting) if greeting.trim().is_empty() => None,
+            Some(greeting) => Some(greeting),
+        }
+    }
+
+    /// Write the config file, creating its directory if needed.
+    pub fn save(&self) -> Result<()> {
+        incognito::refuse("Saving settings")?;
+        let path = config_path().ok_or_else(|| Error::Config("No config directory on this system".to_string()))?;
+        let text = toml::to_string_pretty(self).map_err(|e| Error::Config(format!("Cannot serialize config: {}", e)))?;
+        storage::write_atomic(&path, text).map_err(Error::file(&path))
+    }
+
+    /// Write all settings to `path` as one portable file: JSON for a `.json`
+    /// extension, TOML otherwise. API keys are left out unless `include_keys` is set.
+    pub fn export(&self, path: &Path, include_keys: bool) -> Result<()> {
+        incognito::refuse("Exporting settings")?;
+        let mut profiles = self.profiles.clone();
+        let mut transcription = self.transcription.clone();
+        if !include_keys {
+            for profile in profiles.values_mut() {
+                profile.api_key = None;
+            }
+            if let Some(transcription) = &mut transcription {
+                transcription.api_key = None;
+            }
+        }
+        let file = ExportFile {
+            version: EXPORT_VERSION,
+            default_profile: self.default_profile.clone(),
+            profiles,
+            image_max_dimension: self.image_max_dimension,
+            templates: self.templates.clone(),
+            greeting: self.greeting.clone(),
+            free_models: self.free_models.clone(),
+            favorite_models: self.favorite_models.clone(),
+            transcription,
+            attribution: self.attribution.clone(),
+        };
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(&file).map_err(|e| e.to_string())
+        } else {
+            toml::to_string_pretty(&file).map_err(|e| e.to_string())
+        }
+        .map_err(|e| Error::Config(format!("Cannot serialize settings: {}", e)))?;
+        storage::write_atomic(path, text).map_err(Error::file(path))
+    }
+
+    /// Read a file written by [`Config::export`] into this config.
+    ///
+    /// By default imported profiles are merged in, replacing same-named ones; with
+    /// `replace` the imported settings replace the current ones entirely. Returns the
+    /// number of imported profiles.
+    pub fn import(&mut self, path: &Path, replace: bool) -> Result<usize> {
+        let text = fs::read_to_string(path).map_err(Error::file(path))?;
+        let file: ExportFile = if is_json(path) {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        }
+        .map_err(|e| Error::Config(format!("{} is not a settings export: {}", path.display(), e)))?;
+        if file.version == 0 || file.version > EXPORT_VERSION {
+            return Err(Error::Config(format!(
+                "{} has settings version {}, but this build reads up to version {}",
+                path.display(),
+                file.version,
+                EXPORT_VERSION
+            )));
+        }
+        if let Some(default) = &file.default_profile
+            && !file.profiles.contains_key(default)
+            && (replace || !self.profiles.contains_key(default))
+        {
+            return Err(Error::Config(format!(
+                "Default profile '{}' is not defined in {}",
+                default,
+                path.display()
+            )));
+        }
+
+        let count = file.profiles.len();
+        if replace {
+            self.default_profile = file.default_profile;
+            self.profiles = file.profiles;
+            self.image_max_dimension = file.image_max_dimension;
+            self.templates = file.templates;
+            self.greeting = file.greeting;
+            self.free_models = file.free_models;
+            self.favorite_models = file.favorite_models;
+            self.transcription = file.transcription;
+            self.attribution = file.attribution;
+        } else {
+            if file.image_max_dimension.is_some() {
+                self.image_max_dimension = file.image_max_dimension;
+            }
+            if file.default_profile.is_some() {
+                self.default_profile = file.default_profile;
+            }
+            self.templates.extend(file.templates);
+            if file.greeting.is_some() {
+                self.greeting = file.greeting;
+            }
+            if !file.free_models.is_empty() {
+                self.free_models = file.free_models;
+            }
+            for model in file.favorite_models {
+                if !self.favorite_models.contains(&model) {
+                    self.favorite_models.push(model);
+                }
+            }
+            self.attribution = file.attribution.or(&self.attribution);
+            if let Some(mut transcription) = file.transcription {
+                if transcription.api_key.is_none() {
+                    transcription.api_key = self.transcription.as_ref().and_then(|t| t.api_key.clone());
+                }
+                self.transcription = Some(transcription);
+            }
+            for (name, mut profile) in file.profiles {
+                // Exports leave keys out by default; keep the key this machine already has.
+                if profile.api_key.is_none()
+                    && let Some(existing) = self.profiles.get(&name)
+                {
+                    profile.api_key = existing.api_key.clone();
+                }
+                self.profiles.insert(name, profile);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Look up a conversation template by name.
+    pub fn template(&self, name: &str) -> Result<&Template> {
+        self.templates.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.templates.keys().map(String::as_str).collect();
+            Error::Config(if known.is_empty() {
+                format!("Unknown template '{}': no templates are configured", name)
+            } else {
+                format!("Unknown template '{}' (available: {})", name, known.join(", "))
+            })
+        })
+    }
+
+    /// The app requests of `profile` are attributed to: its own settings, then
+    /// the config's.
+    pub fn attribution(&self, profile: &str) -> Attribution {
+        match self.profiles.get(profile) {
+            Some(settings) => settings.attribution.or(&self.attribution),
+            None => self.attribution.clone(),
+        }
+    }
+
+    /// The favorite model after `current`, wrapping around; the first one when
+    /// `current` isn't a favorite, and `None` without favorites.
+    ///
+    /// ```
+    /// use llm::config::Config;
+    ///
+    /// let config = Config {
+    ///     favorite_models: vec!["openai/gpt-4o".to_string(), "anthropic/claude-3.5-sonnet".to_string()],
+    ///     ..Config::default()
+    /// };
+    /// assert_eq!(config.next_favorite("openai/gpt-4o"), Some("anthropic/claude-3.5-sonnet"));
+    /// assert_eq!(config.next_favorite("anthropic/claude-3.5-sonnet"), Some("openai/gpt-4o"));
+    /// assert_eq!(config.next_favorite("google/gemini-pro"), Some("openai/gpt-4o"));
+    /// assert_eq!(Config::default().next_favorite("openai/gpt-4o"), None);
+    /// ```
+    pub fn next_favorite(&self, current: &str) -> Option<&str> {
+        let next = match self.favorite_models.iter().position(|model| model == current) {
+            Some(index) => (index + 1) % self.favorite_models.len(),
+            None => 0,
+        };
+        self.favorite_models.get(next).map(String::as_str)
+    }
+
+    /// Resolve the connection for `profile`, or for the default profile when `None`.
+    ///
+    /// Without any profile the environment alone decides, as before profiles existed.
+    pub fn connection(&self, profile: Option<&str>) -> Result<Connection> {
+        let name = profile.or(self.default_profile.as_deref());
+        let Some(name) = name else {
+            let api_key = env::var("OPENROUTER_API_KEY")
+                .map_err(|_| Error::Config("OPENROUTER_API_KEY must be set in the environment".to_string()))?;
+            return Ok(Connection {
+                profile: DEFAULT_PROFILE.to_string(),
+                url: env::var("OPENROUTER_API_URL").unwrap_or_else(|_| api::DEFAULT_API_URL.to_string()),
+                model: api::DEFAULT_MODEL.to_string(),
+                headers: api::build_headers(&api_key, &self.attribution)?,
+                filters: Pipeline::default(),
+                low_bandwidth: false,
+            });
+        };
+
+        let settings = self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            Error::Config(if known.is_empty() {
+                format!("Unknown profile '{}': no profiles are configured", name)
+            } else {
+                format!("Unknown profile '{}' (available: {})", name, known.join(", "))
+            })
+        })?;
+        let api_key = match (&settings.api_key, &settings.api_key_env) {
+            (Some(key), _) => key.clone(),
+            (None, Some(var)) => {
+                env::var(var).map_err(|_| {
+                    Error::Config(format!("Profile '{}' reads its API key from {}, which is not set", name, var))
+                })?
+            }
+            // Local backends often need no key at all.
+            (None, None) => env::var("OPENROUTER_API_KEY").unwrap_or_default(),
+        };
+        Ok(Connection {
+            profile: name.to_string(),
+            url: settings
+                .url
+                .clone()
+                .or_else(|| env::var("OPENROUTER_API_URL").ok())
+                .unwrap_or_else(|| api::DEFAULT_API_URL.to_string()),
+            model: settings.model.clone().unwrap_or_else(|| api::DEFAULT_MODEL.to_string()),
+            headers: api::build_headers(&api_key, &self.attribution(name))
+                .map_err(|e| Error::Config(format!("Profile '{}': {}", name, e)))?,
+            filters: Pipeline::new(&settings.filters)
+                .map_err(|e| Error::Config(format!("Profile '{}': {}", name, e)))?,
+            low_bandwidth: settings.low_bandwidth,
+        })
+    }
+}
+
+/// Whether `path` names a JSON file rather than TOML.
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
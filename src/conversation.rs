@@ -0,0 +1,1020 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::alternation;
+use crate::api::{self, Attribution, ChatMessageRequest, Citation, RequestOptions, Route, ToolCall, Usage};
+use crate::boilerplate::{Boilerplate, Dedupe};
+use crate::config::Connection;
+use crate::curl::SentRequest;
+use crate::digest::Digest;
+use crate::error::{Error, Result};
+use crate::filter::Pipeline;
+use crate::history::SavedMessage;
+use crate::incognito;
+use crate::locale::LocaleContext;
+use crate::project::ProjectContext;
+use crate::queue;
+use crate::retry::RetryPolicy;
+use crate::seam::{self, Seam};
+use crate::stream;
+use crate::tokenizer::{Estimate, MESSAGE_TOKENS, Tokenizer};
+use crate::undo::{self, UndoStack};
+
+/// Where requests go: an OpenAI-compatible chat completions endpoint, the headers
+/// sent with every request, and the HTTP client.
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    url: String,
+    headers: HeaderMap,
+}
+
+impl Client {
+    /// A client for `url`, authenticating with `api_key` (none when empty).
+    pub fn new(url: impl Into<String>, api_key: &str) -> Result<Self> {
+        Ok(Self::with_headers(url, api::build_headers(api_key, &Attribution::default())?))
+    }
+
+    /// A client sending `headers`, as built by [`api::build_headers`].
+    pub fn with_headers(url: impl Into<String>, headers: HeaderMap) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.into(),
+            headers,
+        }
+    }
+
+    /// A client for a profile's endpoint and key.
+    pub fn from_connection(connection: &Connection) -> Self {
+        Self::with_headers(connection.url.clone(), connection.headers.clone())
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+}
+
+/// The OpenRouter endpoint without an API key, as restored conversations start
+/// out until [`Conversation::with_client`] attaches a real one.
+impl Default for Client {
+    fn default() -> Self {
+        Self::with_headers(api::DEFAULT_API_URL, api::build_headers("", &Attribution::default()).unwrap_or_default())
+    }
+}
+
+/// An exchange of a conversation: a user message and the messages after it
+/// up to the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    /// Its number, kept while it exists; see [`Conversation::exchanges`].
+    pub id: u64,
+    /// Where it is in the [history](Conversation::history).
+    pub range: Range<usize>,
+}
+
+/// The model's answer to a [`Conversation::send`].
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub content: String,
+    /// The model the request was sent to.
+    pub model: String,
+    /// Token usage, when the endpoint reports it.
+    pub usage: Option<Usage>,
+    /// Why a streamed reply ended early; `content` holds what arrived before.
+    pub interrupted: Option<String>,
+    /// Web sources the reply cites (`:online` models).
+    pub citations: Vec<Citation>,
+    /// The provider that served the reply, when the endpoint says.
+    pub provider: Option<String>,
+    /// Tools the reply asks to call; the reply stays in the history with them,
+    /// waiting for the results.
+    pub tool_calls: Vec<ToolCall>,
+    /// How long the request waited in the [queue](crate::queue) before it was sent.
+    pub queued: Duration,
+    /// How many times the stream dropped and the reply was resumed.
+    pub resumed: u32,
+}
+
+/// How a reply is coming along, for [`Conversation::reply_watching`].
+#[derive(Debug, Clone, Copy)]
+pub enum Progress<'a> {
+    /// The request's turn in the [queue](crate::queue) came after waiting this long.
+    Dequeued(Duration),
+    /// A piece of the reply. A continuation after a dropped stream leaves out
+    /// what it repeats of the reply so far.
+    Delta(&'a str),
+    /// The request failed, or the stream dropped, and is tried again.
+    Retrying { attempt: u32, max_retries: u32 },
+}
+
+/// A conversation with a model that keeps its own history.
+///
+/// Every [`send`](Conversation::send) appends the user message and the reply, so
+/// the next one is answered in context. The system prompt is sent ahead of the
+/// history with every request, and runs of same-role messages are merged before
+/// sending (see [`alternation::normalize`]).
+///
+/// Serializing keeps the settings and the text of the history; the client, with
+/// its API key, is left out and has to be attached again after loading with
+/// [`with_client`](Conversation::with_client).
+///
+/// ```
+/// use llm::conversation::{Client, Conversation};
+///
+/// # use std::io::{BufRead, BufReader, Read, Write};
+/// # use std::net::TcpListener;
+/// # /// A chat endpoint on a free local port that answers every request alike.
+/// # fn mock_endpoint() -> String {
+/// #     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// #     let url = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+/// #     std::thread::spawn(move || {
+/// #         for stream in listener.incoming().flatten() {
+/// #             let mut reader = BufReader::new(stream);
+/// #             let mut length = 0;
+/// #             let mut line = String::new();
+/// #             while reader.read_line(&mut line).unwrap() > 2 {
+/// #                 if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+/// #                     length = value.trim().parse().unwrap();
+/// #                 }
+/// #                 line.clear();
+/// #             }
+/// #             reader.read_exact(&mut vec![0; length]).unwrap();
+/// #             let body = r#"{"id":"mock","object":"chat.completion","created":0,"choices":[{"message":
+/// #                 {"role":"assistant","content":"The part of the compiler that checks references."}}]}"#;
+/// #             let head = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nConnection: close";
+/// #             write!(reader.get_mut(), "{}\r\nContent-Length: {}\r\n\r\n{}", head, body.len(), body).unwrap();
+/// #         }
+/// #     });
+/// #     url
+/// # }
+/// #
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> llm::Result<()> {
+/// # let url = mock_endpoint();
+/// let client = Client::new(url, "sk-test")?;
+/// let mut chat = Conversation::new(client)
+///     .system("You answer in one sentence.")
+///     .model("openai/gpt-4o-mini");
+///
+/// let reply = chat.send("What is a borrow checker?").await?;
+/// assert_eq!(reply.content, "The part of the compiler that checks references.");
+///
+/// // Try a different follow-up without touching the original.
+/// let mut branch = chat.fork();
+/// branch.send("Explain it to a five-year-old.").await?;
+/// assert_eq!(chat.history().len(), 2);
+/// assert_eq!(branch.history().len(), 4);
+///
+/// let saved = serde_json::to_string(&chat)?;
+/// let restored: Conversation = serde_json::from_str(&saved)?;
+/// assert_eq!(restored.history().len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(into = "ConversationFile", from = "ConversationFile")]
+pub struct Conversation {
+    client: Client,
+    /// Model and request parameters for every request.
+    pub options: RequestOptions,
+    /// Sent as the system message ahead of the history; none when blank.
+    pub system_prompt: String,
+    /// Standing instructions from a project file, sent after the system prompt.
+    /// Belongs to the session, so it isn't saved with the conversation.
+    pub project: Option<ProjectContext>,
+    /// Ends a streamed reply early, keeping what arrived. Stays signalled
+    /// until reset.
+    pub stop: stream::StopSignal,
+    /// Applied to every reply before it is added; the original is kept with it.
+    pub filters: Pipeline,
+    /// Given to replies as their `name` when they arrive, and to the system
+    /// prompt's [`ASSISTANT_NAME`] placeholder. Renaming doesn't touch earlier replies.
+    pub assistant_name: Option<String>,
+    /// Summary for conversation lists, saved with the conversation and
+    /// refreshed in the background by the front ends.
+    pub digest: Option<Digest>,
+    /// Hides boilerplate the replies keep repeating, and leaves it out of
+    /// requests if asked to. Belongs to the session, so it isn't saved.
+    pub dedupe: Option<Dedupe>,
+    /// Tells the model the date and where the user is, in a system message
+    /// made afresh for every request. Belongs to the session, so it isn't saved.
+    pub locale: Option<LocaleContext>,
+    /// Counts tokens for trimming, the token counter and the warning before
+    /// sending a prompt that nearly fills the context window. Not saved.
+    pub tokenizer: Arc<dyn Tokenizer>,
+    messages: Vec<ChatMessageRequest>,
+    /// Bumped whenever the messages change. Not saved.
+    generation: u64,
+    /// Edits, deletions, truncations and clears, for undoing them. Not saved.
+    undo: UndoStack,
+    /// The last request sent, for showing it as a `curl` command. Not saved.
+    last_request: Option<SentRequest>,
+}
+
+/// A [`Conversation::generation`] not given out before.
+fn next_generation() -> u64 {
+    static GENERATIONS: AtomicU64 = AtomicU64::new(0);
+    GENERATIONS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// What a serialized [`Conversation`] looks like.
+#[derive(Serialize, Deserialize)]
+struct ConversationFile {
+    model: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    system_prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    route: Option<Route>,
+    #[serde(default)]
+    messages: Vec<SavedMessage>,
+}
+
+impl From<Conversation> for ConversationFile {
+    fn from(conversation: Conversation) -> Self {
+        Self {
+            model: conversation.options.model,
+            system_prompt: conversation.system_prompt,
+            temperature: conversation.options.temperature,
+            max_tokens: conversation.options.max_tokens,
+            route: conversation.options.route,
+            messages: conversation
+                .messages
+                .iter()
+                .filter(|msg| !msg.display_only)
+                .map(SavedMessage::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<ConversationFile> for Conversation {
+    fn from(file: ConversationFile) -> Self {
+        let mut conversation = Conversation::new(Client::default())
+            .model(file.model)
+            .system(file.system_prompt);
+        conversation.options.temperature = file.temperature;
+        conversation.options.max_tokens = file.max_tokens;
+        conversation.options.route = file.route;
+        conversation.messages = file.messages.iter().map(SavedMessage::to_request).collect();
+        conversation
+    }
+}
+
+impl Conversation {
+    /// An empty conversation with [`api::DEFAULT_MODEL`], sending non-streaming
+    /// requests through `client`.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            options: RequestOptions {
+                model: api::DEFAULT_MODEL.to_string(),
+                stream: false,
+                retry: RetryPolicy::default(),
+                prediction: None,
+                temperature: None,
+                max_tokens: None,
+                route: None,
+                flatten: None,
+                raw: None,
+                context_turns: None,
+                low_bandwidth: false,
+                transforms: None,
+                user: None,
+                tools: Vec::new(),
+            },
+            system_prompt: String::new(),
+            project: None,
+            stop: stream::StopSignal::default(),
+            filters: Pipeline::default(),
+            assistant_name: None,
+            digest: None,
+            dedupe: None,
+            locale: None,
+            tokenizer: Arc::new(Estimate),
+            messages: Vec::new(),
+            generation: next_generation(),
+            undo: UndoStack::default(),
+            last_request: None,
+        }
+    }
+
+    /// Use `client` for the following requests.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set the system prompt.
+    pub fn system(mut self, prompt: impl Into<String>) -> Self {
+        self.system_prompt = prompt.into();
+        self
+    }
+
+    /// Set the model.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.options.model = model.into();
+        self
+    }
+
+    /// Set the sampling temperature.
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.options.temperature = Some(temperature);
+        self
+    }
+
+    /// Limit the length of replies, in tokens.
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.options.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Ask OpenRouter to order providers by `route`.
+    pub fn route(mut self, route: Route) -> Self {
+        self.options.route = Some(route);
+        self
+    }
+
+    /// Identify the end user to the provider with every request, for its abuse
+    /// monitoring.
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.options.user = Some(user.into());
+        self
+    }
+
+    /// Stream replies. Deltas reach the callback of [`send_with`](Self::send_with).
+    pub fn stream(mut self, stream: bool) -> Self {
+        self.options.stream = stream;
+        self
+    }
+
+    /// The client requests are sent through.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Replace the client, e.g. after switching profiles.
+    pub fn set_client(&mut self, client: Client) {
+        self.client = client;
+    }
+
+    /// The last request sent, as it was sent.
+    pub fn last_request(&self) -> Option<&SentRequest> {
+        self.last_request.as_ref()
+    }
+
+    /// The messages so far, oldest first.
+    pub fn history(&self) -> &[ChatMessageRequest] {
+        &self.messages
+    }
+
+    /// The messages, for editing the conversation in place. Changes made here
+    /// can't be undone, and keep the operations they conflict with from being
+    /// undone.
+    pub fn history_mut(&mut self) -> &mut Vec<ChatMessageRequest> {
+        self.touch();
+        &mut self.messages
+    }
+
+    /// A number that changes whenever the messages may have: with every
+    /// change through the methods here and every call of
+    /// [`history_mut`](Self::history_mut). No two conversations share one
+    /// unless forked from each other, so the same generation means the same
+    /// history, and work done on it can be kept without comparing the messages.
+    ///
+    /// ```
+    /// use llm::api::ChatMessageRequest;
+    /// use llm::conversation::{Client, Conversation};
+    ///
+    /// # fn main() -> llm::Result<()> {
+    /// let mut chat = Conversation::new(Client::new("http://localhost:8080/v1/chat/completions", "sk-test")?);
+    /// chat.history_mut().push(ChatMessageRequest::new("user", "Hi"));
+    /// let counted = chat.generation();
+    /// assert_eq!(chat.fork().generation(), counted);
+    /// // Same length, other words: still a change.
+    /// chat.history_mut()[0].content = "Yo".to_string();
+    /// assert_ne!(chat.generation(), counted);
+    /// let other = Conversation::new(Client::new("http://localhost:8080/v1/chat/completions", "sk-test")?);
+    /// assert_ne!(other.generation(), chat.generation());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Mark the messages as changed.
+    fn touch(&mut self) {
+        self.generation = next_generation();
+    }
+
+    /// The exchanges of the history, in order. What comes before the first
+    /// user message, such as a greeting, belongs to none.
+    ///
+    /// An exchange's id stays with its user message, through undoing, deleting,
+    /// editing and saving, so it keeps its number while it exists. A new exchange
+    /// takes the id after the highest in the history, which is stored when a
+    /// reply is requested. Ids rise along the history, so a message sent again
+    /// in place of the last exchanges gets back the number it had.
+    ///
+    /// ```
+    /// use llm::api::ChatMessageRequest;
+    /// use llm::conversation::{Client, Conversation};
+    ///
+    /// # fn main() -> llm::Result<()> {
+    /// let mut chat = Conversation::new(Client::new("http://localhost:8080/v1/chat/completions", "sk-test")?);
+    /// for text in ["One", "Two", "Three"] {
+    ///     chat.history_mut().push(ChatMessageRequest::new("user", text));
+    ///     chat.history_mut().push(ChatMessageRequest::new("assistant", "Noted."));
+    /// }
+    /// chat.number_exchanges();
+    /// chat.delete(2);
+    /// let ids: Vec<u64> = chat.exchanges().iter().map(|exchange| exchange.id).collect();
+    /// assert_eq!(ids, [1, 3]);
+    /// assert_eq!(chat.next_exchange(), 4);
+    /// chat.undo_operation()?;
+    /// assert_eq!(chat.exchanges()[1].id, 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        let starts = self.exchange_ids();
+        starts
+            .iter()
+            .enumerate()
+            .map(|(n, &(start, id))| Exchange {
+                id,
+                range: start..starts.get(n + 1).map_or(self.messages.len(), |&(next, _)| next),
+            })
+            .collect()
+    }
+
+    /// The id the next exchange gets.
+    pub fn next_exchange(&self) -> u64 {
+        self.exchange_ids().iter().map(|&(_, id)| id).max().unwrap_or(0) + 1
+    }
+
+    /// Store the ids of the exchanges that have none yet.
+    pub fn number_exchanges(&mut self) {
+        for (index, id) in self.exchange_ids() {
+            if self.messages[index].exchange != id {
+                self.messages[index].exchange = id;
+                self.touch();
+            }
+        }
+    }
+
+    /// Where each exchange starts in the history, with its id.
+    fn exchange_ids(&self) -> Vec<(usize, u64)> {
+        let mut last = self.messages.iter().map(|msg| msg.exchange).max().unwrap_or(0);
+        self.messages
+            .iter()
+            .enumerate()
+            .filter(|(_, msg)| msg.role == "user" && !msg.display_only)
+            .map(|(index, msg)| match msg.exchange {
+                0 => {
+                    last += 1;
+                    (index, last)
+                }
+                id => (index, id),
+            })
+            .collect()
+    }
+
+    /// Replace message `index` with `message`, so that it can be undone.
+    pub fn edit(&mut self, index: usize, message: ChatMessageRequest) {
+        let operation = undo::Operation::edit(&self.messages, index, message);
+        self.touch();
+        self.undo.record(&mut self.messages, operation);
+    }
+
+    /// Remove message `index`, so that it can be undone.
+    pub fn delete(&mut self, index: usize) {
+        let operation = undo::Operation::delete(&self.messages, index);
+        self.touch();
+        self.undo.record(&mut self.messages, operation);
+    }
+
+    /// Keep only the first `len` messages, so that the rest can be restored.
+    pub fn truncate(&mut self, len: usize) {
+        let operation = undo::Operation::truncate(&self.messages, len);
+        self.touch();
+        self.undo.record(&mut self.messages, operation);
+    }
+
+    /// Start over with `messages`, such as a template's, so that the old ones
+    /// can be restored.
+    pub fn clear(&mut self, messages: Vec<ChatMessageRequest>) {
+        let operation = undo::Operation::clear(&self.messages, messages);
+        self.touch();
+        self.undo.record(&mut self.messages, operation);
+    }
+
+    /// Undo the latest [`edit`](Self::edit), [`delete`](Self::delete),
+    /// [`truncate`](Self::truncate) or [`clear`](Self::clear), returning what
+    /// it was. Fails when there is none, or the messages changed since.
+    pub fn undo_operation(&mut self) -> Result<String> {
+        self.touch();
+        self.undo.undo(&mut self.messages)
+    }
+
+    /// Apply the operation undone last again, returning what it was.
+    pub fn redo_operation(&mut self) -> Result<String> {
+        self.touch();
+        self.undo.redo(&mut self.messages)
+    }
+
+    /// Forget what could be undone or redone, e.g. after loading other messages.
+    pub fn forget_operations(&mut self) {
+        self.undo.clear();
+    }
+
+    /// Overwrite the messages, those kept for undoing, and the system prompt
+    /// with zeros and forget them, as when an incognito session ends.
+    pub fn wipe(&mut self) {
+        for message in &mut self.messages {
+            message.wipe();
+        }
+        self.messages.clear();
+        self.touch();
+        self.undo.wipe();
+        incognito::wipe(&mut self.system_prompt);
+        self.digest = None;
+        self.last_request = None;
+    }
+
+    /// An independent copy to continue in another direction.
+    pub fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    /// Drop the oldest messages until the history fits in about `tokens` tokens,
+    /// keeping at least the latest message. Returns how many were dropped.
+    ///
+    /// Tokens are counted with the conversation's [`tokenizer`](Self::tokenizer).
+    /// Display-only messages are never sent, so they don't count.
+    pub fn trim_to(&mut self, tokens: usize) -> usize {
+        let tokenizer = Arc::clone(&self.tokenizer);
+        let sent_tokens = |msg: &ChatMessageRequest| match msg.display_only {
+            true => 0,
+            false => tokenizer.message(msg),
+        };
+        let mut total: usize = self.messages.iter().map(sent_tokens).sum();
+        let mut dropped = 0;
+        while total > tokens && self.messages.len() - dropped > 1 {
+            total -= sent_tokens(&self.messages[dropped]);
+            dropped += 1;
+        }
+        self.messages.drain(..dropped);
+        self.touch();
+        dropped
+    }
+
+    /// Estimated prompt size of the next request with `pending` sent as a new
+    /// message: the system prompt and project instructions, the history that is
+    /// sent, and `pending`, counted as [`Conversation::trim_to`] counts them.
+    pub fn prompt_tokens(&self, pending: &str) -> usize {
+        let tokenizer = self.tokenizer.as_ref();
+        let message_tokens = |msg: &ChatMessageRequest| tokenizer.message(msg);
+        let system = self.system_message().as_ref().map_or(0, message_tokens);
+        let project = self.project_message().as_ref().map_or(0, message_tokens);
+        let locale = self.locale_message().as_ref().map_or(0, message_tokens);
+        let pending = if pending.trim().is_empty() { 0 } else { tokenizer.count(pending) + MESSAGE_TOKENS };
+        let (left_out, sent) = self.sent_history(pending > 0);
+        let summary = self.summary_message(&left_out).as_ref().map_or(0, message_tokens);
+        let strip = self.stripped_boilerplate();
+        let history: usize = sent
+            .into_iter()
+            .map(|msg| match &strip {
+                Some(boilerplate) if msg.role == "assistant" => {
+                    tokenizer.count(boilerplate.split(&msg.content).body) + MESSAGE_TOKENS
+                }
+                _ => message_tokens(msg),
+            })
+            .sum();
+        system + project + locale + summary + history + pending
+    }
+
+    /// The history messages sent with the next request: all but excluded and
+    /// display-only ones, cut to the last [`context_turns`](RequestOptions::context_turns),
+    /// and to the last [`LOW_BANDWIDTH_TURNS`] in
+    /// [`low_bandwidth`](RequestOptions::low_bandwidth) mode.
+    ///
+    /// ```
+    /// use llm::api::ChatMessageRequest;
+    /// use llm::conversation::{Client, Conversation};
+    ///
+    /// let mut conversation = Conversation::new(Client::default());
+    /// let mut greeting = ChatMessageRequest::new("assistant", "Hello! How can I help?");
+    /// greeting.display_only = true;
+    /// conversation.history_mut().push(greeting);
+    /// conversation.history_mut().push(ChatMessageRequest::new("user", "Hi"));
+    /// let sent: Vec<&str> = conversation.context().iter().map(|msg| msg.content.as_str()).collect();
+    /// assert_eq!(sent, ["Hi"]);
+    /// assert_eq!(conversation.history().len(), 2);
+    /// ```
+    pub fn context(&self) -> Vec<&ChatMessageRequest> {
+        self.sent_history(false).1
+    }
+
+    /// The messages left out of the [`context`](Self::context) and those in
+    /// it, leaving room for one more turn when `pending` is set.
+    fn sent_history(&self, pending: bool) -> (Vec<&ChatMessageRequest>, Vec<&ChatMessageRequest>) {
+        let mut sent: Vec<&ChatMessageRequest> = self
+            .messages
+            .iter()
+            .filter(|msg| !msg.excluded && !msg.display_only)
+            .collect();
+        let low_bandwidth = self.options.low_bandwidth.then_some(LOW_BANDWIDTH_TURNS);
+        let turns = match (self.options.context_turns, low_bandwidth) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let Some(turns) = turns else {
+            return (Vec::new(), sent);
+        };
+        let keep = turns.saturating_sub(usize::from(pending));
+        let starts: Vec<usize> = (0..sent.len()).filter(|&i| sent[i].role == "user").collect();
+        let start = match keep {
+            0 => sent.len(),
+            _ if keep >= starts.len() => 0,
+            _ => starts[starts.len() - keep],
+        };
+        let left_out = sent.drain(..start).collect();
+        (left_out, sent)
+    }
+
+    /// In [`low_bandwidth`](RequestOptions::low_bandwidth) mode, the summary of
+    /// the turns left out of the [`context`](Self::context), sent as a system
+    /// message in their place.
+    pub fn summary(&self) -> Option<ChatMessageRequest> {
+        self.summary_message(&self.sent_history(false).0)
+    }
+
+    fn summary_message(&self, left_out: &[&ChatMessageRequest]) -> Option<ChatMessageRequest> {
+        if !self.options.low_bandwidth || left_out.is_empty() {
+            return None;
+        }
+        Some(ChatMessageRequest::new("system", summarize(left_out)))
+    }
+
+    /// The date and locale line as a system message, when it is sent.
+    pub fn locale_message(&self) -> Option<ChatMessageRequest> {
+        self.locale.as_ref().map(LocaleContext::message)
+    }
+
+    /// The boilerplate the replies so far repeat, when [`dedupe`](Self::dedupe)
+    /// is on.
+    pub fn boilerplate(&self) -> Option<Boilerplate> {
+        let settings = self.dedupe?;
+        let replies: Vec<&str> = self
+            .messages
+            .iter()
+            .filter(|msg| msg.role == "assistant" && !msg.display_only)
+            .map(|msg| msg.content.as_str())
+            .collect();
+        Some(Boilerplate::learn(&replies, &settings))
+    }
+
+    /// The boilerplate to leave out of requests, when asked to.
+    fn stripped_boilerplate(&self) -> Option<Boilerplate> {
+        self.dedupe
+            .filter(|dedupe| dedupe.strip_from_context)
+            .and_then(|_| self.boilerplate())
+            .filter(|boilerplate| !boilerplate.is_empty())
+    }
+
+    /// The system prompt as a message, with the [`ASSISTANT_NAME`] filled in;
+    /// none when the prompt is blank.
+    pub fn system_message(&self) -> Option<ChatMessageRequest> {
+        let prompt = self.system_prompt.trim();
+        let prompt = fill_assistant_name(prompt, self.assistant_name.as_deref());
+        (!prompt.is_empty()).then(|| ChatMessageRequest::new("system", prompt))
+    }
+
+    /// The project instructions as a system message, when there are any.
+    pub fn project_message(&self) -> Option<ChatMessageRequest> {
+        self.project.as_ref().and_then(ProjectContext::message)
+    }
+
+    /// The messages sent for the next request: the system prompt, the project
+    /// instructions, the date and locale, and the [`context`](Self::context),
+    /// with same-role runs merged.
+    ///
+    /// Returns the messages and how many were merged into the one before them.
+    pub fn request_messages(&self) -> (Vec<ChatMessageRequest>, usize) {
+        let mut messages = Vec::with_capacity(self.messages.len() + 2);
+        messages.extend(self.system_message());
+        messages.extend(self.project_message());
+        messages.extend(self.locale_message());
+        let (left_out, sent) = self.sent_history(false);
+        messages.extend(self.summary_message(&left_out));
+        let strip = self.stripped_boilerplate();
+        messages.extend(sent.into_iter().cloned().map(|mut msg| {
+            if self.options.low_bandwidth {
+                msg.images.clear();
+            }
+            if let Some(boilerplate) = &strip
+                && msg.role == "assistant"
+            {
+                msg.content = boilerplate.strip(&msg.content);
+            }
+            msg
+        }));
+        let merged = alternation::normalize(&mut messages);
+        (messages, merged)
+    }
+
+    /// About how many bytes the next request sends with `pending` as a new
+    /// message: the size of its JSON body.
+    pub fn request_bytes(&self, pending: &str) -> usize {
+        let (mut messages, _) = self.request_messages();
+        if !pending.trim().is_empty() {
+            messages.push(ChatMessageRequest::new("user", pending.trim()));
+        }
+        serde_json::to_vec(&self.options.body(messages)).map_or(0, |body| body.len())
+    }
+
+    /// Send `text` as a user message and wait for the whole reply.
+    pub async fn send(&mut self, text: &str) -> Result<Reply> {
+        self.send_with(ChatMessageRequest::new("user", text), |_| {}).await
+    }
+
+    /// Send `message`, passing the reply to `on_delta` as it streams in (all at
+    /// once when streaming is off).
+    ///
+    /// Both the message and the reply are added to the history; when the request
+    /// fails, the message is taken back out and nothing is added.
+    pub async fn send_with(&mut self, message: ChatMessageRequest, on_delta: impl FnMut(&str)) -> Result<Reply> {
+        self.messages.push(message);
+        self.touch();
+        let result = self.reply_with(on_delta).await;
+        if result.is_err() {
+            self.messages.pop();
+            self.touch();
+        }
+        result
+    }
+
+    /// Ask for a reply to the history as it is, e.g. after editing it, and add
+    /// the reply to it.
+    ///
+    /// Same as [`reply_watching`](Self::reply_watching), passing on only the
+    /// pieces of the reply.
+    pub async fn reply_with(&mut self, mut on_delta: impl FnMut(&str)) -> Result<Reply> {
+        self.reply_watching(|progress| {
+            if let Progress::Delta(delta) = progress {
+                on_delta(delta);
+            }
+        })
+        .await
+    }
+
+    /// Ask for a reply to the history as it is and add the reply to it, telling
+    /// `on_progress` how it is coming along: when its turn in the queue came,
+    /// each piece of the reply (all at once when streaming is off), and retries.
+    ///
+    /// Failed requests are retried following `options.retry`. A stream that drops
+    /// after some content arrived is resumed: what arrived is sent back as an
+    /// assistant prefill, and the continuation is joined onto it without
+    /// repeating the text at the seam. When that cannot go on, because the
+    /// retries ran out or the backend refuses the prefill, the partial reply is
+    /// kept and marked as [`interrupted`](Reply::interrupted). So is a reply
+    /// ended through [`stop`](Self::stop). The reply is passed through
+    /// [`filters`](Self::filters) once complete, so `on_progress` sees it unfiltered.
+    pub async fn reply_watching(&mut self, mut on_progress: impl FnMut(Progress<'_>)) -> Result<Reply> {
+        self.number_exchanges();
+        let (messages, merged) = self.request_messages();
+        if merged > 0 {
+            log::warn!("Merged {} message(s) into the one before them to keep roles alternating", merged);
+        }
+        let body = self.options.body(messages.clone());
+        let client = &self.client;
+        let url = self.options.url(&client.url);
+        self.last_request = Some(SentRequest::new(&url, &client.headers, &body));
+        log::debug!(
+            "POST {} model={} messages={} stream={} headers=[{}]",
+            url,
+            body.model,
+            body.messages.len(),
+            body.stream,
+            api::redacted_headers(&client.headers)
+        );
+
+        // Held until the reply is complete, retries included.
+        let ticket = queue::enter(&body.model).await?;
+        on_progress(Progress::Dequeued(ticket.queued()));
+        let retry = self.options.retry;
+        let mut attempt = 0;
+        // What arrived of a streamed reply before the stream dropped.
+        let mut partial = String::new();
+        let mut resumed = 0;
+        let reply = loop {
+            let result = if body.stream {
+                let resuming = !partial.is_empty();
+                let resume_body;
+                let request = match resuming {
+                    true => {
+                        let mut messages = messages.clone();
+                        messages.push(seam::prefill(&partial));
+                        resume_body = self.options.body(messages);
+                        &resume_body
+                    }
+                    false => &body,
+                };
+                // What the continuation repeats of the partial reply is left out.
+                let mut seam = resuming.then(|| Seam::new(&partial));
+                let mut append = |text: &str| {
+                    if !text.is_empty() {
+                        partial.push_str(text);
+                        on_progress(Progress::Delta(text));
+                    }
+                };
+                let on_delta = |delta: &str| match &mut seam {
+                    Some(seam) => append(&seam.push(delta)),
+                    None => append(delta),
+                };
+                let result =
+                    stream::stream_chat_until(&client.http, &url, &client.headers, request, &self.stop, on_delta).await;
+                if let Some(seam) = &mut seam {
+                    append(&seam.finish());
+                }
+                match result {
+                    Ok(end) => {
+                        log::debug!(
+                            "Stream finished finish_reason={:?} usage={:?} resumed={}",
+                            end.finish_reason,
+                            end.usage,
+                            resumed
+                        );
+                        let content = std::mem::take(&mut partial);
+                        Ok((content, end.usage, None, end.citations, end.provider, end.tool_calls))
+                    }
+                    // Nothing was passed on yet, so the request can be retried as if it never happened.
+                    Err(e) if partial.is_empty() => Err(e),
+                    Err(Error::Stopped) => {
+                        let content = std::mem::take(&mut partial);
+                        Ok((content, None, Some(Error::Stopped.to_string()), Vec::new(), None, Vec::new()))
+                    }
+                    Err(e) => {
+                        // A client error while resuming means the backend refused the prefill.
+                        let refused = resuming && e.status().is_some_and(|status| status.is_client_error());
+                        if refused || !e.is_retryable() || attempt >= retry.max_retries {
+                            log::warn!("Reply cut short: {}", e);
+                            let reason = match refused {
+                                true => "the connection dropped and the backend cannot resume a partial reply",
+                                false => &e.to_string(),
+                            };
+                            let content = std::mem::take(&mut partial);
+                            break (content, None, Some(reason.to_string()), Vec::new(), None, Vec::new());
+                        }
+                        attempt += 1;
+                        resumed += 1;
+                        log::warn!("Stream dropped: {}; resuming ({}/{})", e, attempt, retry.max_retries);
+                        on_progress(Progress::Retrying {
+                            attempt,
+                            max_retries: retry.max_retries,
+                        });
+                        tokio::time::sleep(retry.delay(attempt)).await;
+                        continue;
+                    }
+                }
+            } else {
+                api::fetch_reply(&client.http, &url, &client.headers, &body)
+                    .await
+                    .map(|(msg, usage)| {
+                        on_progress(Progress::Delta(&msg.content));
+                        let mut citations = Vec::new();
+                        api::collect_citations(&mut citations, &msg.annotations);
+                        (msg.content, usage, None, citations, msg.provider, msg.tool_calls)
+                    })
+            };
+            match result {
+                Ok(reply) => break reply,
+                Err(error) if error.is_retryable() && attempt < retry.max_retries => {
+                    attempt += 1;
+                    log::warn!("{}; retrying ({}/{})", error, attempt, retry.max_retries);
+                    on_progress(Progress::Retrying {
+                        attempt,
+                        max_retries: retry.max_retries,
+                    });
+                    tokio::time::sleep(retry.delay(attempt)).await;
+                }
+                Err(error) => return Err(error),
+            }
+        };
+
+        let (content, usage, interrupted, citations, provider, tool_calls) = reply;
+        let mut message = ChatMessageRequest::new("assistant", content);
+        message.tool_calls = tool_calls.clone();
+        message.citations = citations.clone();
+        message.provider = provider.clone();
+        message.route = self.options.route;
+        message.model = Some(self.options.model.clone());
+        message.name = self.assistant_name.clone();
+        message.resumed = resumed;
+        self.filters.apply_to(&mut message);
+        let content = message.content.clone();
+        self.messages.push(message);
+        self.touch();
+        Ok(Reply {
+            content,
+            model: body.model,
+            usage,
+            interrupted,
+            citations,
+            provider,
+            tool_calls,
+            queued: ticket.queued(),
+            resumed,
+        })
+    }
+}
+
+/// Stands for the assistant's name in system prompts.
+pub const ASSISTANT_NAME: &str = "{{assistant_name}}";
+
+/// `prompt` with [`ASSISTANT_NAME`] replaced by `name`, or by "Assistant"
+/// when the assistant has none.
+///
+/// ```
+/// use llm::conversation::fill_assistant_name;
+///
+/// assert_eq!(fill_assistant_name("You are {{assistant_name}}.", Some("Marvin")), "You are Marvin.");
+/// assert_eq!(fill_assistant_name("You are {{assistant_name}}.", None), "You are Assistant.");
+/// ```
+pub fn fill_assistant_name(prompt: &str, name: Option<&str>) -> String {
+    prompt.replace(ASSISTANT_NAME, name.unwrap_or("Assistant"))
+}
+
+/// How many of the latest turns are sent in full in
+/// [`low_bandwidth`](RequestOptions::low_bandwidth) mode.
+pub const LOW_BANDWIDTH_TURNS: usize = 3;
+
+/// The longest gist of one message in a low-bandwidth summary, in characters.
+const GIST_CHARS: usize = 160;
+
+/// How many of the latest left-out messages a low-bandwidth summary covers.
+const SUMMARY_MESSAGES: usize = 40;
+
+/// A summary of `messages` made without asking the model: the opening
+/// sentence of each, most recent last.
+///
+/// ```
+/// use llm::api::ChatMessageRequest;
+/// use llm::conversation::summarize;
+///
+/// let question = ChatMessageRequest::new("user", "What is a borrow checker? I keep hitting it.");
+/// let answer = ChatMessageRequest::new("assistant", "It is the part of the compiler that checks references.\n\nMore...");
+/// assert_eq!(
+///     summarize(&[&question, &answer]),
+///     "Summary of the earlier conversation, which is left out to save bandwidth:\n\
+///      - User: What is a borrow checker?\n\
+///      - Assistant: It is the part of the compiler that checks references."
+/// );
+/// ```
+pub fn summarize(messages: &[&ChatMessageRequest]) -> String {
+    let mut summary = String::from("Summary of the earlier conversation, which is left out to save bandwidth:");
+    let skipped = messages.len().saturating_sub(SUMMARY_MESSAGES);
+    if skipped > 0 {
+        summary.push_str(&format!("\n- ({} earlier messages)", skipped));
+    }
+    for msg in &messages[skipped..] {
+        let speaker = match msg.role.as_str() {
+            "user" => "User",
+            "assistant" => "Assistant",
+            _ => "Note",
+        };
+        summary.push_str(&format!("\n- {}: {}", speaker, gist(&msg.content)));
+    }
+    summary
+}
+
+/// The first sentence of `text`, or its first line, cut to [`GIST_CHARS`].
+fn gist(text: &str) -> String {
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("");
+    let sentence = line
+        .char_indices()
+        .find(|&(i, c)| matches!(c, '.' | '?' | '!') && line[i + 1..].starts_with(' '))
+        .map_or(line, |(i, _)| &line[..=i]);
+    match sentence.char_indices().nth(GIST_CHARS) {
+        Some((end, _)) => format!("{}…", &sentence[..end]),
+        None => sentence.to_string(),
+    }
+}
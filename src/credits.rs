@@ -0,0 +1,184 @@
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::timeout;
+
+/// How much an OpenRouter account and API key have left to spend.
+///
+/// Put together from the `/credits` endpoint, which reports the account, and
+/// `/auth/key`, which reports the key in use; either may be missing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Balance {
+    /// Credits bought for the account, and used by all its keys, in USD.
+    pub account: Option<Credits>,
+    /// What the key in use has spent, and its own limit.
+    pub key: Option<KeyInfo>,
+}
+
+/// The `data` of a `/credits` response.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Credits {
+    pub total_credits: f64,
+    pub total_usage: f64,
+}
+
+/// The `data` of an `/auth/key` response.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct KeyInfo {
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Spent with this key, in USD.
+    #[serde(default)]
+    pub usage: f64,
+    /// Spending limit of the key, if it has one.
+    #[serde(default)]
+    pub limit: Option<f64>,
+    #[serde(default)]
+    pub limit_remaining: Option<f64>,
+    #[serde(default)]
+    pub is_free_tier: bool,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Requests allowed per interval, such as 200 per `10s`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub requests: i64,
+    pub interval: String,
+}
+
+#[derive(Deserialize)]
+struct Data<T> {
+    data: T,
+}
+
+impl Balance {
+    /// What can still be spent: the smaller of the account's credits left and
+    /// the key's limit left, as far as they are known.
+    pub fn remaining(&self) -> Option<f64> {
+        let account = self.account.as_ref().map(|credits| credits.total_credits - credits.total_usage);
+        let key = self.key.as_ref().and_then(|key| key.limit_remaining);
+        match (account, key) {
+            (Some(account), Some(key)) => Some(account.min(key)),
+            (account, key) => account.or(key),
+        }
+    }
+
+    /// A few words for a status bar.
+    ///
+    /// ```
+    /// use llm::credits::{Balance, Credits, KeyInfo};
+    ///
+    /// let account = Some(Credits { total_credits: 10.0, total_usage: 5.731 });
+    /// assert_eq!(Balance { account, key: None }.summary(), "$4.27 left");
+    /// let key = Some(KeyInfo { usage: 1.2, ..KeyInfo::default() });
+    /// assert_eq!(Balance { account: None, key }.summary(), "$1.20 used");
+    /// ```
+    pub fn summary(&self) -> String {
+        match (self.remaining(), &self.key) {
+            (Some(left), _) => format!("{} left", dollars(left)),
+            (None, Some(key)) => format!("{} used", dollars(key.usage)),
+            (None, None) => "no balance".to_string(),
+        }
+    }
+
+    /// One line per thing known, for `--credits` and tooltips.
+    pub fn details(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(credits) = &self.account {
+            lines.push(format!(
+                "Account: {} bought, {} used, {} left",
+                dollars(credits.total_credits),
+                dollars(credits.total_usage),
+                dollars(credits.total_credits - credits.total_usage)
+            ));
+        }
+        if let Some(key) = &self.key {
+            let name = key.label.as_deref().map_or_else(String::new, |label| format!(" ({})", label));
+            lines.push(match (key.limit, key.limit_remaining) {
+                (Some(limit), Some(left)) => format!(
+                    "This key{}: {} used of its {} limit, {} left",
+                    name,
+                    dollars(key.usage),
+                    dollars(limit),
+                    dollars(left)
+                ),
+                _ => format!("This key{}: {} used, no limit of its own", name, dollars(key.usage)),
+            });
+            if key.is_free_tier {
+                lines.push("Free tier: only free models, with lower rate limits".to_string());
+            }
+            if let Some(rate) = &key.rate_limit {
+                lines.push(format!("Rate limit: {} requests per {}", rate.requests, rate.interval));
+            }
+        }
+        lines
+    }
+}
+
+/// `amount` in US dollars, to the cent.
+fn dollars(amount: f64) -> String {
+    match amount < 0.0 {
+        true => format!("-${:.2}", -amount),
+        false => format!("${:.2}", amount),
+    }
+}
+
+/// The URL of the API endpoint `path` next to the chat completions URL.
+///
+/// ```
+/// use llm::credits::endpoint_url;
+///
+/// let url = endpoint_url("https://openrouter.ai/api/v1/chat/completions", "auth/key");
+/// assert_eq!(url, "https://openrouter.ai/api/v1/auth/key");
+/// ```
+pub fn endpoint_url(chat_url: &str, path: &str) -> String {
+    match chat_url.strip_suffix("/chat/completions") {
+        Some(base) => format!("{}/{}", base, path),
+        None => format!("{}/{}", chat_url.trim_end_matches('/'), path),
+    }
+}
+
+/// Fetch the balance of the account and key behind `headers`. `None` means the
+/// endpoint doesn't report one, as with other providers than OpenRouter; errors
+/// are for failed requests and refused keys.
+pub async fn fetch(client: &reqwest::Client, chat_url: &str, headers: &HeaderMap) -> Result<Option<Balance>> {
+    let account = get::<Credits>(client, &endpoint_url(chat_url, "credits"), headers).await;
+    let key = get::<KeyInfo>(client, &endpoint_url(chat_url, "auth/key"), headers).await;
+    let balance = match (account, key) {
+        (Err(error), Err(_)) | (Err(error), Ok(None)) | (Ok(None), Err(error)) => return Err(error),
+        (account, key) => Balance {
+            account: account.unwrap_or_else(|error| {
+                log::debug!("No account credits: {}", error);
+                None
+            }),
+            key: key.unwrap_or_else(|error| {
+                log::debug!("No key information: {}", error);
+                None
+            }),
+        },
+    };
+    Ok(Some(balance).filter(|balance| balance.account.is_some() || balance.key.is_some()))
+}
+
+/// GET the `data` of `url`. `None` when there is no such endpoint, or it
+/// answers with something else than expected.
+async fn get<T: DeserializeOwned>(client: &reqwest::Client, url: &str, headers: &HeaderMap) -> Result<Option<T>> {
+    log::debug!("GET {}", url);
+    let body = timeout::within(timeout::get().request, "no balance", async {
+        let response = client.get(url).headers(headers.clone()).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        match status {
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => Ok(None),
+            status if !status.is_success() => Err(Error::from_response(status, &body)),
+            _ => Ok(Some(body)),
+        }
+    })
+    .await?;
+    Ok(body.and_then(|body| serde_json::from_str::<Data<T>>(&body).ok()).map(|data| data.data))
+}
@@ -0,0 +1,79 @@
+//! The last request as an equivalent `curl` command, for reproducing it outside
+//! the app or sharing it in a bug report.
+
+use reqwest::header::{HeaderMap, AUTHORIZATION};
+use serde::Serialize;
+use serde_json::Value;
+
+/// What the API key is replaced with unless it is asked for: the shell fills
+/// it in from the environment when the command runs.
+pub const KEY_PLACEHOLDER: &str = "$OPENROUTER_API_KEY";
+
+/// A request as it was sent: where to, its headers and its JSON body.
+#[derive(Debug, Clone)]
+pub struct SentRequest {
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl SentRequest {
+    pub fn new(url: &str, headers: &HeaderMap, body: &impl Serialize) -> Self {
+        Self {
+            url: url.to_string(),
+            headers: headers.clone(),
+            body: serde_json::to_value(body).unwrap_or_default(),
+        }
+    }
+
+    /// The `curl` command sending this request again, one option per line. The
+    /// API key is left out for [`KEY_PLACEHOLDER`] unless `reveal_key` is set.
+    ///
+    /// ```
+    /// use llm::api;
+    /// use llm::curl::SentRequest;
+    /// use serde_json::json;
+    ///
+    /// let headers = api::build_headers("sk-or-secret", &api::Attribution::default()).unwrap();
+    /// let body = json!({"model": "openai/gpt-4o", "messages": [{"role": "user", "content": "It's late"}]});
+    /// let request = SentRequest::new("https://openrouter.ai/api/v1/chat/completions", &headers, &body);
+    ///
+    /// let command = request.curl(false);
+    /// assert!(command.starts_with("curl -X POST 'https://openrouter.ai/api/v1/chat/completions' \\\n"));
+    /// assert!(command.contains(r#"-H "Authorization: Bearer $OPENROUTER_API_KEY""#));
+    /// assert!(!command.contains("sk-or-secret"));
+    /// assert!(command.contains(r#"--data-raw '{"messages":[{"content":"It'\''s late","role":"user"}]"#));
+    ///
+    /// assert!(request.curl(true).contains("-H 'authorization: Bearer sk-or-secret'"));
+    /// ```
+    pub fn curl(&self, reveal_key: bool) -> String {
+        let mut lines = vec![format!("curl -X POST {}", quote(&self.url))];
+        if self.body["stream"] == true {
+            // Shows the streamed chunks as they arrive.
+            lines[0].insert_str(4, " --no-buffer");
+        }
+        for (name, value) in &self.headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            lines.push(match name == AUTHORIZATION && !reveal_key {
+                // Double quotes, so that the shell expands the variable.
+                true => format!("-H \"Authorization: Bearer {}\"", KEY_PLACEHOLDER),
+                false => format!("-H {}", quote(&format!("{}: {}", name, value))),
+            });
+        }
+        lines.push(format!("--data-raw {}", quote(&self.body.to_string())));
+        lines.join(" \\\n  ")
+    }
+}
+
+/// `text` as a single shell word: in single quotes, inside which nothing is
+/// special, with each single quote closing them, escaped and reopening them.
+///
+/// ```
+/// use llm::curl::quote;
+///
+/// assert_eq!(quote("plain"), "'plain'");
+/// assert_eq!(quote(r#"{"text": "$HOME `ls` it's"}"#), r#"'{"text": "$HOME `ls` it'\''s"}'"#);
+/// ```
+pub fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
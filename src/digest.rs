@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::{self, ChatMessageRequest, OpenRouterChatRequest, Usage};
+use crate::conversation::Client;
+use crate::error::Result;
+
+/// How many new messages make a conversation's summary due for a refresh.
+pub const REFRESH_EVERY: usize = 10;
+
+/// The least time between two refreshes by one front end, however many
+/// conversations are due.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Longest part of a message sent to be summarized, in characters.
+const MESSAGE_CHARS: usize = 600;
+
+/// Upper limit on the length of a summary, in tokens.
+const MAX_TOKENS: u32 = 120;
+
+/// Label of refreshes in usage figures, kept apart from the conversations'
+/// own usage.
+pub const USAGE_CATEGORY: &str = "maintenance";
+
+/// A short summary of a saved conversation, for previews in conversation
+/// lists. Kept up to date in the background by a cheap model.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Digest {
+    /// Two or three sentences.
+    pub text: String,
+    /// How many of the conversation's messages it covers.
+    pub covers: usize,
+}
+
+impl Digest {
+    /// The summary cut to `max` characters, on one line.
+    pub fn preview(&self, max: usize) -> String {
+        let line = self.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        match line.char_indices().nth(max) {
+            Some((end, _)) => format!("{}…", &line[..end]),
+            None => line,
+        }
+    }
+}
+
+/// The messages a summary covers: all but display-only ones like the greeting,
+/// as in a saved conversation.
+fn summarized(messages: &[ChatMessageRequest]) -> Vec<&ChatMessageRequest> {
+    messages.iter().filter(|msg| !msg.display_only).collect()
+}
+
+/// Whether `messages`, summarized by `digest`, should be summarized again:
+/// once they hold an exchange and have no summary, after [`REFRESH_EVERY`] new
+/// messages, and when messages the summary covers were taken out.
+///
+/// ```
+/// use llm::api::ChatMessageRequest;
+/// use llm::digest::{self, Digest};
+///
+/// let messages = |n: usize| vec![ChatMessageRequest::new("user", "Sort this list"); n];
+/// assert!(!digest::due(None, &messages(1)));
+/// assert!(digest::due(None, &messages(2)));
+/// let digest = Digest { text: "About sorting.".into(), covers: 4 };
+/// assert!(!digest::due(Some(&digest), &messages(13)));
+/// assert!(digest::due(Some(&digest), &messages(14)));
+/// assert!(digest::due(Some(&digest), &messages(3)));
+/// ```
+pub fn due(digest: Option<&Digest>, messages: &[ChatMessageRequest]) -> bool {
+    let messages = summarized(messages).len();
+    match digest {
+        None => messages >= 2,
+        Some(digest) => messages < digest.covers || messages - digest.covers >= REFRESH_EVERY,
+    }
+}
+
+/// The model refreshes use: the `configured` one, else OpenRouter's free
+/// default model, or the conversation's `current` one on other endpoints.
+pub fn model<'a>(configured: Option<&'a str>, client: &Client, current: &'a str) -> &'a str {
+    configured.unwrap_or(if client.url().contains("openrouter.ai") { api::DEFAULT_MODEL } else { current })
+}
+
+/// Lets a refresh start at most every [`MIN_INTERVAL`], and one at a time.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    last: Option<Instant>,
+    running: bool,
+}
+
+impl Throttle {
+    /// Whether a refresh may start now; if so, it counts as started.
+    pub fn start(&mut self) -> bool {
+        if self.running || self.last.is_some_and(|last| last.elapsed() < MIN_INTERVAL) {
+            return false;
+        }
+        self.last = Some(Instant::now());
+        self.running = true;
+        true
+    }
+
+    /// The refresh started last has finished, or failed.
+    pub fn finish(&mut self) {
+        self.running = false;
+    }
+}
+
+/// The request for a new summary. To keep it small, only the messages the
+/// `previous` summary doesn't cover are sent along with it, each cut to a
+/// few hundred characters.
+pub fn request(model: &str, previous: Option<&Digest>, messages: &[ChatMessageRequest]) -> OpenRouterChatRequest {
+    let messages = summarized(messages);
+    let previous = previous.filter(|digest| digest.covers <= messages.len());
+    let start = previous.map_or(0, |digest| digest.covers);
+    let mut text = String::new();
+    if let Some(digest) = previous {
+        text.push_str(&format!("Summary so far: {}\n\nNew messages:\n", digest.text));
+    }
+    for msg in &messages[start..] {
+        let content: String = msg.content.chars().take(MESSAGE_CHARS).collect();
+        text.push_str(&format!("{}: {}\n", msg.role, content.trim()));
+    }
+    let instructions = "Summarize this conversation in two or three short sentences, for a list of saved \
+                        conversations. Say what it is about and where it ended up. Reply with the summary only.";
+    let mut body = OpenRouterChatRequest::new(
+        model.to_string(),
+        vec![
+            ChatMessageRequest::new("system", instructions),
+            ChatMessageRequest::new("user", text),
+        ],
+    );
+    body.max_tokens = Some(MAX_TOKENS);
+    body
+}
+
+/// A new summary, with the usage the endpoint reported for writing it.
+pub type Refreshed = (Digest, Option<Usage>);
+
+/// Ask `model` for a new summary of `messages`.
+pub async fn refresh(
+    client: &Client,
+    model: &str,
+    previous: Option<&Digest>,
+    messages: &[ChatMessageRequest],
+) -> Result<Refreshed> {
+    let body = request(model, previous, messages);
+    log::debug!("POST {} summary refresh model={}", client.url(), model);
+    let (reply, usage) = api::fetch_reply(client.http(), client.url(), client.headers(), &body).await?;
+    let digest = Digest {
+        text: reply.content.trim().to_string(),
+        covers: summarized(messages).len(),
+    };
+    Ok((digest, usage))
+}
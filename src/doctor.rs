@@ -0,0 +1,207 @@
+use reqwest::StatusCode;
+use reqwest::header::{AUTHORIZATION, HeaderMap};
+
+use crate::config::{self, Config, Connection};
+use crate::error::{Error, Result};
+use crate::models;
+use crate::timeout;
+
+/// How one check of `cli_llm doctor` went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    /// Not run because a check it depends on failed.
+    Skipped,
+}
+
+/// One line of the `cli_llm doctor` checklist.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    /// What was found.
+    pub detail: String,
+    /// What to do about a failure.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn skipped(name: &'static str, reason: &str) -> Self {
+        Self {
+            name,
+            status: Status::Skipped,
+            detail: reason.to_string(),
+            hint: None,
+        }
+    }
+}
+
+/// Check everything needed to chat with `profile` (the default one when
+/// `None`) and `model` (the profile's when `None`): the config file, the API
+/// key, the endpoint, and the model. Checks that depend on a failed one are
+/// skipped.
+pub async fn run(profile: Option<&str>, model: Option<&str>) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let config = match Config::load() {
+        Ok(config) => {
+            checks.push(match config::config_path() {
+                Some(path) if path.exists() => Check::pass("Config file", format!("{} is valid", path.display())),
+                _ => Check::pass("Config file", "none; using the defaults"),
+            });
+            config
+        }
+        Err(error) => {
+            checks.push(Check::fail(
+                "Config file",
+                error.to_string(),
+                "Fix the setting named above, or move the file away to start from the defaults.",
+            ));
+            Config::default()
+        }
+    };
+    timeout::set(config.timeouts.unwrap_or_default());
+
+    let connection = match config.connection(profile) {
+        Ok(connection) => connection,
+        Err(error) => {
+            checks.push(Check::fail(
+                "API key",
+                error.to_string(),
+                "Set OPENROUTER_API_KEY (in the environment or a .env file), or the profile's api_key \
+                 or api_key_env in the config file.",
+            ));
+            for name in ["Endpoint", "Key accepted", "Model"] {
+                checks.push(Check::skipped(name, "no connection settings"));
+            }
+            return checks;
+        }
+    };
+    let has_key = connection.headers.contains_key(AUTHORIZATION);
+    let openrouter = connection.url.contains("openrouter.ai");
+    checks.push(match (has_key, openrouter) {
+        (true, _) => Check::pass("API key", format!("set for profile {}", connection.profile)),
+        (false, true) => Check::fail(
+            "API key",
+            "empty",
+            "OpenRouter needs a key: create one at https://openrouter.ai/keys and set OPENROUTER_API_KEY.",
+        ),
+        (false, false) => Check::pass("API key", "none set; the endpoint may not need one"),
+    });
+
+    let client = reqwest::Client::new();
+    let models_url = models::models_url(&connection.url);
+    let reachable = get(&client, &models_url, &HeaderMap::new()).await;
+    checks.push(match &reachable {
+        Ok(_) => Check::pass("Endpoint", format!("{} answers", connection.url)),
+        Err(error) => Check::fail(
+            "Endpoint",
+            format!("{}: {}", models_url, error),
+            "Check the URL (OPENROUTER_API_URL or the profile's url), your network connection and any proxy.",
+        ),
+    });
+    if reachable.is_err() {
+        checks.push(Check::skipped("Key accepted", "endpoint unreachable"));
+        checks.push(Check::skipped("Model", "endpoint unreachable"));
+        return checks;
+    }
+
+    checks.push(check_key(&client, &connection, has_key).await);
+    let model = model.unwrap_or(&connection.model);
+    checks.push(check_model(&client, &connection, model).await);
+    checks
+}
+
+/// Make an authenticated call that costs nothing: OpenRouter's `/key`, which
+/// describes the key, or the models list on endpoints without it.
+async fn check_key(client: &reqwest::Client, connection: &Connection, has_key: bool) -> Check {
+    const NAME: &str = "Key accepted";
+    if !has_key {
+        return Check::skipped(NAME, "no key to check");
+    }
+    let models_url = models::models_url(&connection.url);
+    let key_url = format!("{}/key", models_url.strip_suffix("/models").unwrap_or(&models_url));
+    let status = match get(client, &key_url, &connection.headers).await {
+        Ok(StatusCode::NOT_FOUND) => get(client, &models_url, &connection.headers).await,
+        other => other,
+    };
+    match status {
+        Ok(status) if status.is_success() => Check::pass(NAME, "the endpoint accepted the key"),
+        Ok(status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)) => Check::fail(
+            NAME,
+            format!("rejected ({})", status),
+            "The key is wrong, revoked or for another service; copy it again from your provider's dashboard.",
+        ),
+        Ok(status) => Check::fail(NAME, format!("unexpected answer ({})", status), "Try again later."),
+        Err(error) => Check::fail(NAME, error.to_string(), "Try again later."),
+    }
+}
+
+/// Whether `model` is in the endpoint's models list (for `auto:free`, whether
+/// the list has free models).
+async fn check_model(client: &reqwest::Client, connection: &Connection, model: &str) -> Check {
+    const NAME: &str = "Model";
+    let fetch = models::fetch_models(client, &connection.url, &connection.headers);
+    let list = match timeout::within(timeout::get().request, "no models list", fetch).await {
+        Ok(list) => list,
+        Err(error) => {
+            return Check::fail(
+                NAME,
+                format!("could not fetch the models list: {}", error),
+                "The endpoint may not offer /models; chatting can still work.",
+            );
+        }
+    };
+    let hint = "Pick one from `cli_llm models`, then pass it with -m or set the profile's model.";
+    if model == models::AUTO_FREE {
+        let free = list.iter().filter(|info| info.is_free()).count();
+        return match free {
+            0 => Check::fail(NAME, "auto:free, but no model in the list is free", hint),
+            n => Check::pass(NAME, format!("auto:free can choose among {} free models", n)),
+        };
+    }
+    let base = model.strip_suffix(":online").unwrap_or(model);
+    if list.iter().any(|info| info.id == base) {
+        Check::pass(NAME, format!("{} is available", model))
+    } else {
+        Check::fail(NAME, format!("{} is not in the endpoint's list of {} models", model, list.len()), hint)
+    }
+}
+
+/// The status of a GET request to `url`, within the first-byte timeout.
+async fn get(client: &reqwest::Client, url: &str, headers: &HeaderMap) -> Result<StatusCode> {
+    log::debug!("GET {}", url);
+    let send = client.get(url).headers(headers.clone()).send();
+    let response = timeout::within(timeout::get().first_byte, "no response", async { Ok(send.await?) });
+    let response = response.await?;
+    log::debug!("Response status={}", response.status());
+    Ok(response.status())
+}
+
+/// The error `cli_llm doctor` exits with when `checks` has failures.
+pub fn outcome(checks: &[Check]) -> Result<()> {
+    let failed = checks.iter().filter(|check| check.status == Status::Fail).count();
+    if failed == 0 {
+        return Ok(());
+    }
+    Err(Error::Config(format!("{} of {} checks failed", failed, checks.len())))
+}
+
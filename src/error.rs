@@ -0,0 +1,129 @@
+use std::io;
+use std::path::PathBuf;
+
+use reqwest::StatusCode;
+
+/// Errors from talking to the model endpoint and from loading local settings.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The request could not be sent, or the connection broke while reading the reply.
+    #[error("network error: {0}")]
+    Network(#[from] reqwest::Error),
+    /// The endpoint rejected the API key (401 or 403).
+    #[error("authentication failed ({status}): {message}")]
+    Auth { status: StatusCode, message: String },
+    /// The endpoint answered with another non-success status.
+    #[error("request failed with {status}: {message}")]
+    Api { status: StatusCode, message: String },
+    /// A streamed reply ended before the server signalled completion.
+    #[error("stream ended before the response was complete")]
+    Interrupted,
+    /// The user stopped a streamed reply through a [`StopSignal`](crate::stream::StopSignal).
+    #[error("stopped by the user")]
+    Stopped,
+    /// The request was taken out of the [request queue](crate::queue) before
+    /// its turn came.
+    #[error("request {0} was cancelled in the queue")]
+    Cancelled(String),
+    /// The endpoint took longer than one of the configured timeouts.
+    #[error("timed out: {0}")]
+    Timeout(String),
+    /// The reply could not be parsed.
+    #[error("could not parse response: {0}")]
+    Parse(#[from] serde_json::Error),
+    /// The reply parsed but held no choices.
+    #[error("response contained no choices")]
+    EmptyResponse,
+    /// Invalid or inconsistent settings.
+    #[error("{0}")]
+    Config(String),
+    /// Another window saved a conversation since this one opened it.
+    #[error("'{name}' was saved by another window since it was opened here")]
+    Conflict { name: String },
+    /// Reading or writing a file failed.
+    #[error("{}: {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+    /// Other I/O, such as reading the terminal.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// An attached image could not be decoded or encoded.
+    #[error("image error: {0}")]
+    Image(#[from] image::ImageError),
+}
+
+/// How providers word the refusal of a request that is too long for the model.
+const CONTEXT_LENGTH_PHRASES: &[&str] = &[
+    "context length",
+    "context_length_exceeded",
+    "context window",
+    "maximum context",
+    "prompt is too long",
+    "input is too long",
+    "too many tokens",
+    "reduce the length of the messages",
+];
+
+/// Result type used throughout the crate.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Build the error for a non-success response, using the endpoint's own message
+    /// (`{"error": {"message": ...}}`) when the body carries one.
+    pub fn from_response(status: StatusCode, body: &str) -> Self {
+        let message = serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|json| json["error"]["message"].as_str().map(str::to_string))
+            .unwrap_or_else(|| body.trim().to_string());
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            Error::Auth { status, message }
+        } else {
+            Error::Api { status, message }
+        }
+    }
+
+    /// The HTTP status of a rejected request.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Auth { status, .. } | Error::Api { status, .. } => Some(*status),
+            Error::Network(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// Whether the endpoint refused the request for being longer than the
+    /// model's context window, going by the wording providers use for it.
+    ///
+    /// ```
+    /// use llm::Error;
+    /// use reqwest::StatusCode;
+    ///
+    /// let body = r#"{"error":{"message":"This endpoint's maximum context length is 8192 tokens. However, you requested about 9120 tokens."}}"#;
+    /// assert!(Error::from_response(StatusCode::BAD_REQUEST, body).is_context_length());
+    /// let body = r#"{"error":{"message":"prompt is too long: 215000 tokens > 200000 maximum"}}"#;
+    /// assert!(Error::from_response(StatusCode::BAD_REQUEST, body).is_context_length());
+    /// let body = r#"{"error":{"message":"openai/gpt-5 is not a valid model ID"}}"#;
+    /// assert!(!Error::from_response(StatusCode::BAD_REQUEST, body).is_context_length());
+    /// ```
+    pub fn is_context_length(&self) -> bool {
+        let Error::Api { status, message } = self else {
+            return false;
+        };
+        let message = message.to_lowercase();
+        *status == StatusCode::PAYLOAD_TOO_LARGE || CONTEXT_LENGTH_PHRASES.iter().any(|phrase| message.contains(phrase))
+    }
+
+    /// Whether retrying the same request has a reasonable chance to succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Network(_) | Error::Interrupted | Error::Timeout(_) => true,
+            Error::Api { status, .. } => status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS,
+            _ => false,
+        }
+    }
+
+    /// Attach the path to an I/O error.
+    pub fn file(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::File { path, source }
+    }
+}
@@ -0,0 +1,571 @@
+use std::env;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::api::{ChatMessageRequest, Route};
+use crate::error::{Error, Result};
+use crate::markdown::{self, Item};
+use crate::{incognito, language, storage, tool};
+
+/// Colour scheme of an HTML export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl FromStr for Theme {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        match text.to_lowercase().as_str() {
+            "light" => Ok(Theme::Light),
+            "dark" => Ok(Theme::Dark),
+            _ => Err(Error::Config(format!("Unknown theme '{}': use light or dark", text))),
+        }
+    }
+}
+
+/// How a conversation is written out as HTML.
+#[derive(Debug, Clone, Default)]
+pub struct HtmlOptions {
+    pub theme: Theme,
+    /// Leave out the save date and label the user's messages "User" instead of
+    /// their account name.
+    pub anonymize: bool,
+    /// Replace the system prompt, and any system messages, with a note.
+    pub redact_system: bool,
+}
+
+/// The conversation to export.
+pub struct Transcript<'a> {
+    pub title: &'a str,
+    pub model: &'a str,
+    /// When the conversation was saved, in seconds since the Unix epoch.
+    pub saved_at: Option<u64>,
+    /// Shown ahead of the messages; none when blank.
+    pub system_prompt: &'a str,
+    /// Display-only messages such as the greeting are left out.
+    pub messages: &'a [ChatMessageRequest],
+}
+
+/// Write `transcript` to `path` as a single self-contained HTML page.
+pub fn write_html(path: &Path, transcript: &Transcript, options: &HtmlOptions) -> Result<()> {
+    incognito::refuse("Exporting conversations")?;
+    storage::write_atomic(path, to_html(transcript, options)).map_err(Error::file(path))
+}
+
+/// A file name for exporting `title`: in the downloads folder when there is one,
+/// else the home directory, else the working directory.
+pub fn default_path(title: &str) -> PathBuf {
+    let stem: String = title
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_') { c } else { '-' })
+        .collect();
+    let stem = if stem.trim_matches('-').is_empty() { "conversation" } else { stem.trim_matches('-') };
+    dirs::download_dir()
+        .or_else(dirs::home_dir)
+        .unwrap_or_default()
+        .join(format!("{}.html", stem))
+}
+
+/// Render `transcript` as a single HTML page with inlined styles and images.
+///
+/// Nothing depends on when or where the export runs other than the user's account
+/// name (left out with `anonymize`), so exporting the same conversation twice gives
+/// the same file.
+pub fn to_html(transcript: &Transcript, options: &HtmlOptions) -> String {
+    let mut html = String::new();
+    let theme = match options.theme {
+        Theme::Light => LIGHT,
+        Theme::Dark => DARK,
+    };
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>\n{}{}</style>\n</head>\n<body>\n<main>\n",
+        escape(transcript.title),
+        theme,
+        STYLE
+    );
+
+    let _ = write!(
+        html,
+        "<header>\n<h1>{}</h1>\n<p class=\"meta\">{}",
+        escape(transcript.title),
+        escape(transcript.model)
+    );
+    if let Some(saved_at) = transcript.saved_at.filter(|_| !options.anonymize) {
+        let _ = write!(html, " · saved {}", format_date(saved_at));
+    }
+    html.push_str("</p>\n</header>\n");
+
+    if !transcript.system_prompt.trim().is_empty() {
+        write_system(&mut html, transcript.system_prompt, options);
+    }
+
+    let user_label = if options.anonymize { "User".to_string() } else { account_name() };
+    let mut personas: Vec<&str> = Vec::new();
+    // Routing of the previous reply, to mark where the preference changed.
+    let mut last_route = None;
+    for msg in transcript.messages.iter().filter(|msg| !msg.display_only) {
+        if msg.role == "system" {
+            write_system(&mut html, &msg.content, options);
+            continue;
+        }
+        if msg.role == "assistant" {
+            if last_route.is_some_and(|route| route != msg.route) {
+                let route = msg.route.map_or("default", Route::name);
+                let _ = writeln!(html, "<p class=\"note route\">Routing: {}</p>", route);
+            }
+            last_route = Some(msg.route);
+        }
+        let kind = match msg.role.as_str() {
+            "user" | "tool" => msg.role.as_str(),
+            _ => "assistant",
+        };
+        let mut classes = vec![kind.to_string()];
+        let label = match (&msg.name, msg.role.as_str()) {
+            (_, "user") => user_label.clone(),
+            (_, "tool") => "Tool".to_string(),
+            (Some(name), _) => {
+                // The same palette as the GUI, in order of first appearance.
+                let index = personas.iter().position(|p| p == name).unwrap_or_else(|| {
+                    personas.push(name);
+                    personas.len() - 1
+                });
+                classes.push(format!("persona-{}", index % 5));
+                name.clone()
+            }
+            (None, _) => "Assistant".to_string(),
+        };
+        if msg.excluded {
+            classes.push("excluded".to_string());
+        }
+
+        let _ = writeln!(html, "<section class=\"message {}\">", classes.join(" "));
+        let _ = write!(html, "<div class=\"label\">{}", escape(&label));
+        if let Some(provider) = &msg.provider {
+            let _ = write!(html, " <span class=\"note\">via {}</span>", escape(provider));
+        }
+        if msg.excluded {
+            html.push_str(" <span class=\"note\">(left out of the model's context)</span>");
+        }
+        html.push_str("</div>\n");
+        match msg.role.as_str() {
+            // What a command printed, shown as it came.
+            "tool" => {
+                let _ = writeln!(html, "<pre><code>{}</code></pre>", escape(msg.content.trim_end()));
+            }
+            _ => write_content(&mut html, &msg.content),
+        }
+        for call in &msg.tool_calls {
+            let _ = match tool::shell_command(call) {
+                Ok(command) => writeln!(html, "<p class=\"note\">Asked to run <code>$ {}</code></p>", escape(&command)),
+                Err(_) => writeln!(html, "<p class=\"note\">Called {}</p>", escape(&call.function.name)),
+            };
+        }
+        for image in &msg.images {
+            let _ = writeln!(
+                html,
+                "<img src=\"{}\" width=\"{}\" height=\"{}\" alt=\"Attached image, {}×{}\">",
+                escape(&image.data_url),
+                image.width,
+                image.height,
+                image.width,
+                image.height
+            );
+        }
+        for info in &msg.missing_attachments {
+            let _ = writeln!(
+                html,
+                "<p class=\"note\">Attached image {} (not found)</p>",
+                escape(&info.label())
+            );
+        }
+        if !msg.citations.is_empty() {
+            html.push_str("<ol class=\"sources\">\n");
+            for citation in &msg.citations {
+                let title = citation.title.as_deref().unwrap_or(&citation.url);
+                let _ = writeln!(html, "<li><a href=\"{}\">{}</a></li>", escape(&citation.url), escape(title));
+            }
+            html.push_str("</ol>\n");
+        }
+        html.push_str("</section>\n");
+    }
+
+    html.push_str("</main>\n</body>\n</html>\n");
+    html
+}
+
+/// The user's account name, used to label their messages.
+fn account_name() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .ok()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| "You".to_string())
+}
+
+fn write_system(html: &mut String, prompt: &str, options: &HtmlOptions) {
+    html.push_str("<section class=\"message system\">\n<div class=\"label\">System</div>\n");
+    if options.redact_system {
+        html.push_str("<p class=\"note\">System prompt redacted.</p>\n");
+    } else {
+        write_content(html, prompt);
+    }
+    html.push_str("</section>\n");
+}
+
+/// Message text as HTML: `<think>` sections become collapsible reasoning, and the
+/// rest is rendered like the GUI renders it.
+fn write_content(html: &mut String, content: &str) {
+    let mut rest = content;
+    while let Some(start) = rest.find("<think>") {
+        write_markdown(html, &rest[..start]);
+        let after = &rest[start + "<think>".len()..];
+        let (reasoning, next) = match after.find("</think>") {
+            Some(end) => (&after[..end], &after[end + "</think>".len()..]),
+            None => (after, ""),
+        };
+        html.push_str("<details class=\"reasoning\">\n<summary>Reasoning</summary>\n");
+        write_markdown(html, reasoning);
+        html.push_str("</details>\n");
+        rest = next;
+    }
+    write_markdown(html, rest);
+}
+
+/// The markdown the GUI understands: fenced code, `#` and `##` headings, bold and
+/// inline code, blockquotes and lists, with blank lines separating paragraphs.
+fn write_markdown(html: &mut String, text: &str) {
+    let mut prose: Vec<&str> = Vec::new();
+    let mut code: Option<(String, String)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(info) = trimmed.strip_prefix("```") {
+            match code.take() {
+                Some((language, block)) => write_code(html, &language, &block),
+                None => {
+                    write_prose(html, &prose);
+                    prose.clear();
+                    code = Some((info.trim().to_lowercase(), String::new()));
+                }
+            }
+        } else if let Some((_, block)) = &mut code {
+            block.push_str(line);
+            block.push('\n');
+        } else {
+            prose.push(line);
+        }
+    }
+    write_prose(html, &prose);
+    if let Some((language, block)) = code {
+        write_code(html, &language, &block);
+    }
+}
+
+/// Text between code blocks: paragraphs, headings, blockquotes and lists.
+fn write_prose(html: &mut String, lines: &[&str]) {
+    let mut paragraph: Vec<&str> = Vec::new();
+    let flush = |html: &mut String, paragraph: &mut Vec<&str>| {
+        if !paragraph.is_empty() {
+            let lines: Vec<String> = paragraph.iter().map(|line| inline(line)).collect();
+            let _ = writeln!(html, "<p>{}</p>", lines.join("<br>\n"));
+            paragraph.clear();
+        }
+    };
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if markdown::quote_text(line).is_some() {
+            flush(html, &mut paragraph);
+            let quoted: Vec<&str> = lines[i..].iter().map_while(|line| markdown::quote_text(line)).collect();
+            html.push_str("<blockquote>\n");
+            write_prose(html, &quoted);
+            html.push_str("</blockquote>\n");
+            i += quoted.len();
+            continue;
+        }
+        if markdown::marker(line).is_some() {
+            flush(html, &mut paragraph);
+            let (items, used) = markdown::list(&lines[i..]);
+            write_list(html, &items);
+            i += used;
+            continue;
+        }
+        if line.trim().is_empty() {
+            flush(html, &mut paragraph);
+        } else if let Some(heading) = line.strip_prefix("# ") {
+            flush(html, &mut paragraph);
+            let _ = writeln!(html, "<h2>{}</h2>", inline(heading));
+        } else if let Some(heading) = line.strip_prefix("## ") {
+            flush(html, &mut paragraph);
+            let _ = writeln!(html, "<h3>{}</h3>", inline(heading));
+        } else {
+            paragraph.push(line);
+        }
+        i += 1;
+    }
+    flush(html, &mut paragraph);
+}
+
+/// Nested `<ul>` and `<ol>` lists for `items`.
+fn write_list(html: &mut String, items: &[Item]) {
+    // Tags of the lists currently open, outermost first.
+    let mut open: Vec<&str> = Vec::new();
+    for item in items {
+        let depth = item.level + 1;
+        if open.len() >= depth {
+            while open.len() > depth {
+                let _ = writeln!(html, "</li>\n</{}>", open.pop().unwrap_or("ul"));
+            }
+            html.push_str("</li>\n");
+        }
+        while open.len() < depth {
+            let tag = if item.number.is_some() { "ol" } else { "ul" };
+            let start = item.number.as_deref().and_then(|n| n.trim_end_matches(['.', ')']).parse::<u32>().ok());
+            let _ = match start {
+                Some(start) if start != 1 => writeln!(html, "<ol start=\"{}\">", start),
+                _ => writeln!(html, "<{}>", tag),
+            };
+            open.push(tag);
+        }
+        let paragraphs: Vec<String> = item.paragraphs.iter().map(|paragraph| inline(paragraph)).collect();
+        let _ = write!(html, "<li>{}", paragraphs.join("<br>\n"));
+    }
+    while let Some(tag) = open.pop() {
+        let _ = writeln!(html, "</li>\n</{}>", tag);
+    }
+}
+
+fn write_code(html: &mut String, language: &str, code: &str) {
+    // Blocks whose fence names no language are highlighted as the one they
+    // seem to be in, when that's clear enough.
+    let language = match language {
+        "" => language::guess(code).unwrap_or_default(),
+        named => named,
+    };
+    let class = language
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '+' && c != '#')
+        .next()
+        .filter(|language| !language.is_empty())
+        .map(|language| format!(" class=\"language-{}\"", escape(language)))
+        .unwrap_or_default();
+    let _ = writeln!(html, "<pre><code{}>{}</code></pre>", class, highlight(language, code.trim_end_matches('\n')));
+}
+
+/// Escape a line and render `inline code` and **bold** in it. Unpaired marks
+/// are kept as they are.
+fn inline(line: &str) -> String {
+    let parts: Vec<&str> = line.split('`').collect();
+    let mut out = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i % 2 == 0 {
+            out.push_str(&bold(part));
+        } else if i + 1 < parts.len() {
+            let _ = write!(out, "<code>{}</code>", escape(part));
+        } else {
+            out.push('`');
+            out.push_str(&bold(part));
+        }
+    }
+    out
+}
+
+fn bold(text: &str) -> String {
+    let parts: Vec<String> = text.split("**").map(escape).collect();
+    let mut out = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            out.push_str(match (i % 2 == 1, i + 1 < parts.len()) {
+                (true, true) => "<strong>",
+                (true, false) => "**",
+                (false, _) => "</strong>",
+            });
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+/// Words highlighted as keywords, across the languages models write most.
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "case", "catch", "class", "const", "continue", "def", "default", "do", "elif",
+    "else", "enum", "export", "extends", "false", "False", "fn", "for", "from", "func", "function", "if", "impl",
+    "import", "in", "interface", "let", "loop", "match", "mod", "mut", "new", "nil", "None", "null", "package",
+    "pub", "return", "self", "Self", "static", "struct", "switch", "this", "throw", "trait", "true", "True", "try",
+    "type", "use", "var", "where", "while", "with", "yield",
+];
+
+/// Line comment markers by code block language. Blocks without a known language
+/// get no comment highlighting, since `#` and `//` mean different things.
+fn line_comment(language: &str) -> Option<&'static str> {
+    match language {
+        "rust" | "rs" | "c" | "cpp" | "c++" | "h" | "java" | "js" | "javascript" | "ts" | "typescript" | "jsx"
+        | "tsx" | "go" | "swift" | "kotlin" | "kt" | "cs" | "csharp" | "c#" | "scala" | "dart" | "php" => Some("//"),
+        "python" | "py" | "sh" | "bash" | "shell" | "zsh" | "toml" | "yaml" | "yml" | "ruby" | "rb" | "r" | "perl"
+        | "dockerfile" | "makefile" => Some("#"),
+        "sql" | "lua" | "haskell" | "hs" => Some("--"),
+        _ => None,
+    }
+}
+
+/// Escape `code` and wrap strings, comments, numbers and keywords in spans.
+fn highlight(language: &str, code: &str) -> String {
+    let comment = line_comment(language);
+    let rust = matches!(language, "rust" | "rs");
+    let mut out = String::new();
+    for (n, line) in code.split('\n').enumerate() {
+        if n > 0 {
+            out.push('\n');
+        }
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let rest: String = chars[i..].iter().collect();
+            if let Some(marker) = comment
+                && rest.starts_with(marker)
+            {
+                let _ = write!(out, "<span class=\"c\">{}</span>", escape(&rest));
+                break;
+            }
+            // In Rust a single quote usually starts a lifetime; only short char literals count.
+            let quote_end = match c {
+                '"' | '`' => closing_quote(&chars, i, usize::MAX),
+                '\'' => closing_quote(&chars, i, if rust { 3 } else { usize::MAX }),
+                _ => None,
+            };
+            if let Some(end) = quote_end {
+                let text: String = chars[i..=end].iter().collect();
+                let _ = write!(out, "<span class=\"s\">{}</span>", escape(&text));
+                i = end + 1;
+            } else if c.is_alphanumeric() || c == '_' {
+                // A word, or a number including its decimal point.
+                let start = i;
+                let number = c.is_ascii_digit();
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || (number && chars[i] == '.')) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                if number {
+                    let _ = write!(out, "<span class=\"n\">{}</span>", escape(&word));
+                } else if KEYWORDS.contains(&word.as_str()) {
+                    let _ = write!(out, "<span class=\"k\">{}</span>", word);
+                } else {
+                    out.push_str(&escape(&word));
+                }
+            } else {
+                out.push_str(&escape(&c.to_string()));
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Index of the quote closing the one at `start` on the same line, at most
+/// `max_len` characters further, skipping backslash escapes.
+fn closing_quote(chars: &[char], start: usize, max_len: usize) -> Option<usize> {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() && i - start <= max_len.saturating_add(1) {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return Some(i),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `secs` since the Unix epoch as `YYYY-MM-DD HH:MM UTC`.
+pub fn format_date(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs);
+    let minutes = secs % 86_400 / 60;
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, minutes / 60, minutes % 60)
+}
+
+/// The UTC year, month and day of `secs` since the Unix epoch.
+pub(crate) fn civil_date(secs: u64) -> (i64, i64, i64) {
+    let days = (secs / 86_400) as i64;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Colours matching the GUI's light mode.
+const LIGHT: &str = ":root {
+  --page: #fafafa; --text: #000; --muted: #777; --border: #c8c8c8;
+  --user: #d9eafb; --assistant: #f5f5f5; --system: #eeeeee; --code: #f5f5f5;
+  --persona-0: #e8f5e9; --persona-1: #fff3e0; --persona-2: #f3e5f5; --persona-3: #e0f7fa; --persona-4: #ffebee;
+  --keyword: #7c3aed; --string: #15803d; --comment: #8a8a8a; --number: #b45309;
+}
+";
+
+/// Colours matching the GUI's dark mode.
+const DARK: &str = ":root {
+  --page: #1e2128; --text: #fff; --muted: #9a9a9a; --border: #3c414d;
+  --user: #2c3349; --assistant: #373b46; --system: #2a2d35; --code: #282c34;
+  --persona-0: #2e4634; --persona-1: #4e3e28; --persona-2: #42324e; --persona-3: #244248; --persona-4: #502c32;
+  --keyword: #c678dd; --string: #98c379; --comment: #7f848e; --number: #d19a66;
+}
+";
+
+const STYLE: &str = "body { margin: 0; background: var(--page); color: var(--text);
+  font: 15px/1.5 system-ui, -apple-system, \"Segoe UI\", sans-serif; }
+main { max-width: 860px; margin: 0 auto; padding: 24px 16px; display: flex; flex-direction: column; gap: 12px; }
+header h1 { margin: 0; font-size: 22px; }
+.meta, .note { color: var(--muted); font-size: 13px; margin: 0; }
+.route { text-align: center; }
+.message { max-width: 85%; padding: 10px 14px; border-radius: 8px; background: var(--assistant); align-self: flex-start; }
+.message.user { background: var(--user); align-self: flex-end; }
+.message.system { background: var(--system); max-width: 100%; align-self: stretch; }
+.message.tool { background: var(--system); }
+.message.excluded { opacity: 0.55; }
+.persona-0 { background: var(--persona-0); } .persona-1 { background: var(--persona-1); }
+.persona-2 { background: var(--persona-2); } .persona-3 { background: var(--persona-3); }
+.persona-4 { background: var(--persona-4); }
+.label { font-weight: 600; margin-bottom: 4px; }
+.message p { margin: 6px 0; }
+.message ul, .message ol { margin: 6px 0; padding-left: 22px; }
+blockquote { margin: 6px 0; padding-left: 10px; border-left: 3px solid var(--border); }
+.message h2 { font-size: 20px; margin: 8px 0 4px; } .message h3 { font-size: 18px; margin: 8px 0 4px; }
+pre { background: var(--code); border: 1px solid var(--border); border-radius: 4px; padding: 8px; overflow-x: auto; }
+code { font: 13px/1.4 ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; }
+p code { background: var(--code); padding: 1px 4px; border-radius: 3px; }
+.k { color: var(--keyword); } .s { color: var(--string); } .c { color: var(--comment); font-style: italic; }
+.n { color: var(--number); }
+details.reasoning { border-left: 3px solid var(--border); padding-left: 10px; margin: 6px 0; color: var(--muted); }
+details.reasoning summary { cursor: pointer; }
+.sources { font-size: 13px; margin: 8px 0 0; padding-left: 22px; color: var(--muted); }
+.sources a { color: inherit; }
+img { display: block; max-width: 100%; height: auto; margin-top: 8px; border-radius: 4px; }
+";
@@ -0,0 +1,180 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::api::ChatMessageRequest;
+use crate::error::{Error, Result};
+
+/// Names of the built-in filters, for profiles' `filters` lists.
+pub const BUILTIN: [&str; 3] = ["strip-think", "smart-quotes", "collapse-blank-lines"];
+
+/// One entry of a profile's `filters` list: a built-in filter by name, or a
+/// regex replacement.
+///
+/// ```toml
+/// [profiles.local]
+/// filters = [
+///     "strip-think",
+///     { pattern = '(?s)\n+As an AI language model.*$', replace = "" },
+/// ]
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Filter {
+    /// One of [`BUILTIN`].
+    Named(String),
+    Rule(Rule),
+}
+
+/// A regex replacement applied to replies.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// A [`regex`](https://docs.rs/regex) pattern.
+    pub pattern: String,
+    /// What matches are replaced with; `$1` or `${name}` insert groups.
+    #[serde(default)]
+    pub replace: String,
+    /// Also apply inside fenced code blocks, which are left alone otherwise.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub in_code: bool,
+}
+
+/// The filters of a profile, compiled and in order.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    regex: Regex,
+    replace: String,
+    in_code: bool,
+}
+
+impl Step {
+    fn new(pattern: &str, replace: &str, in_code: bool) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| Error::Config(format!("Invalid filter pattern '{}': {}", pattern, e)))?;
+        Ok(Self {
+            regex,
+            replace: replace.to_string(),
+            in_code,
+        })
+    }
+
+    fn builtin(name: &str) -> Result<Vec<Self>> {
+        match name {
+            // Reasoning some local models leave in the content.
+            "strip-think" => Ok(vec![Self::new(r"(?s)<think>.*?</think>\s*", "", false)?]),
+            // Quotes in code have to be plain to compile, so this one is safe there.
+            "smart-quotes" => Ok(vec![
+                Self::new("[‘’‚‛]", "'", true)?,
+                Self::new("[“”„‟]", "\"", true)?,
+            ]),
+            "collapse-blank-lines" => Ok(vec![Self::new(r"\n(?:[ \t]*\n){2,}", "\n\n", false)?]),
+            _ => Err(Error::Config(format!(
+                "Unknown filter '{}': use {} or a {{ pattern, replace }} rule",
+                name,
+                BUILTIN.join(", ")
+            ))),
+        }
+    }
+
+    /// Apply to `text`, skipping the insides of code blocks unless `in_code`.
+    fn apply(&self, text: &str) -> String {
+        if self.in_code {
+            return self.regex.replace_all(text, self.replace.as_str()).into_owned();
+        }
+        let mut out = String::with_capacity(text.len());
+        for (code, part) in segments(text) {
+            if code {
+                out.push_str(part);
+            } else {
+                out.push_str(&self.regex.replace_all(part, self.replace.as_str()));
+            }
+        }
+        out
+    }
+}
+
+impl Pipeline {
+    /// Compile `filters`, failing on unknown names and invalid patterns.
+    pub fn new(filters: &[Filter]) -> Result<Self> {
+        let mut steps = Vec::new();
+        for filter in filters {
+            match filter {
+                Filter::Named(name) => steps.extend(Step::builtin(name)?),
+                Filter::Rule(rule) => steps.push(Step::new(&rule.pattern, &rule.replace, rule.in_code)?),
+            }
+        }
+        Ok(Self { steps })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// `text` after every filter in turn.
+    ///
+    /// ```
+    /// use llm::filter::{Filter, Pipeline};
+    ///
+    /// let names = ["strip-think", "smart-quotes", "collapse-blank-lines"];
+    /// let filters: Vec<Filter> = names.iter().map(|name| Filter::Named(name.to_string())).collect();
+    /// let pipeline = Pipeline::new(&filters).unwrap();
+    /// let reply = "<think>plan</think>\n“Hi”\n\n\n\nBye\n```\n<think>kept</think>\nx = “y”\n\n\n\n```\n";
+    /// assert_eq!(pipeline.apply(reply), "\"Hi\"\n\nBye\n```\n<think>kept</think>\nx = \"y\"\n\n\n\n```\n");
+    /// ```
+    ///
+    /// ```
+    /// use llm::filter::{Filter, Pipeline, Rule};
+    ///
+    /// let rule = Rule { pattern: r"(?s)\n+As an AI language model.*$".into(), replace: String::new(), in_code: false };
+    /// let pipeline = Pipeline::new(&[Filter::Rule(rule)]).unwrap();
+    /// assert_eq!(pipeline.apply("Paris.\n\nAs an AI language model, I..."), "Paris.");
+    /// assert!(Pipeline::new(&[Filter::Named("nope".into())]).is_err());
+    /// ```
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for step in &self.steps {
+            text = step.apply(&text);
+        }
+        text
+    }
+
+    /// Filter a finished reply in place, keeping the original in
+    /// [`unfiltered`](ChatMessageRequest::unfiltered) when anything changed.
+    /// Chunk timings point into the original, so they are dropped then.
+    pub fn apply_to(&self, message: &mut ChatMessageRequest) {
+        let filtered = self.apply(&message.content);
+        if filtered != message.content {
+            let original = std::mem::replace(&mut message.content, filtered);
+            message.unfiltered.get_or_insert(original);
+            message.chunk_timings.clear();
+        }
+    }
+}
+
+/// `text` split into runs of prose and of fenced code blocks, fence lines
+/// included; `true` marks code. A block left open runs to the end.
+fn segments(text: &str) -> Vec<(bool, &str)> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_code = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let fence = line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~");
+        if fence && !in_code {
+            parts.push((false, &text[start..offset]));
+            start = offset;
+            in_code = true;
+        } else if fence {
+            parts.push((true, &text[start..offset + line.len()]));
+            start = offset + line.len();
+            in_code = false;
+        }
+        offset += line.len();
+    }
+    parts.push((in_code, &text[start..]));
+    parts
+}
@@ -0,0 +1,119 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::ChatMessageRequest;
+use crate::error::{Error, Result};
+
+/// How a conversation is written out as one prompt for models that handle chat
+/// formatting poorly (`--flatten`), or for base models behind a text
+/// completions endpoint (`--raw`).
+///
+/// Set in the `[flatten]` section of the config file; unset fields keep their
+/// defaults, which give `User: …` and `Assistant: …` lines ending in an
+/// `Assistant:` cue. [`BUILTIN`] names other ready-made templates.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct FlattenTemplate {
+    pub system: String,
+    pub user: String,
+    pub assistant: String,
+    /// Between two messages.
+    pub separator: String,
+    /// After the last message, prompting the model to answer. Left out when the
+    /// last message is the assistant's, so a partial reply is continued.
+    pub cue: String,
+    /// Where a raw completion stops, so a base model doesn't go on to write
+    /// the user's next turn. Not sent with chat requests.
+    pub stop: Vec<String>,
+}
+
+/// Names of the built-in templates: `chat` (the default), `alpaca` for
+/// instruction-tuned models trained on `### Instruction:` / `### Response:`
+/// blocks, and `plain`, which joins the messages without any prefixes.
+pub const BUILTIN: [&str; 3] = ["chat", "alpaca", "plain"];
+
+impl Default for FlattenTemplate {
+    fn default() -> Self {
+        Self {
+            system: "System: ".to_string(),
+            user: "User: ".to_string(),
+            assistant: "Assistant: ".to_string(),
+            separator: "\n".to_string(),
+            cue: "Assistant:".to_string(),
+            stop: vec!["\nUser:".to_string()],
+        }
+    }
+}
+
+impl FromStr for FlattenTemplate {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "chat" => Ok(Self::default()),
+            "alpaca" => Ok(Self {
+                system: String::new(),
+                user: "### Instruction:\n".to_string(),
+                assistant: "### Response:\n".to_string(),
+                separator: "\n\n".to_string(),
+                cue: "### Response:\n".to_string(),
+                stop: vec!["### Instruction:".to_string()],
+            }),
+            "plain" => Ok(Self {
+                system: String::new(),
+                user: String::new(),
+                assistant: String::new(),
+                separator: "\n\n".to_string(),
+                cue: String::new(),
+                stop: Vec::new(),
+            }),
+            _ => Err(Error::Config(format!(
+                "Unknown prompt template '{}': use {}",
+                name,
+                BUILTIN.join(", ")
+            ))),
+        }
+    }
+}
+
+impl FlattenTemplate {
+    /// The name of the built-in template this is, or `None` for a custom one.
+    pub fn name(&self) -> Option<&'static str> {
+        BUILTIN
+            .into_iter()
+            .find(|name| name.parse::<Self>().is_ok_and(|builtin| builtin == *self))
+    }
+
+    /// The prefix for `role`; roles without one are written as `Role: `.
+    fn prefix(&self, role: &str) -> String {
+        match role {
+            "system" => self.system.clone(),
+            "user" => self.user.clone(),
+            "assistant" => self.assistant.clone(),
+            other => {
+                let mut chars = other.chars();
+                let first = chars.next().map(|c| c.to_uppercase().collect::<String>()).unwrap_or_default();
+                format!("{}{}: ", first, chars.as_str())
+            }
+        }
+    }
+
+    /// `messages` as a single user message: each one on its own, prefixed by
+    /// its role, followed by the cue. Images of all messages are kept.
+    pub fn apply(&self, messages: Vec<ChatMessageRequest>) -> ChatMessageRequest {
+        let ends_with_assistant = messages.last().is_some_and(|msg| msg.role == "assistant");
+        let mut parts = Vec::with_capacity(messages.len() + 1);
+        let mut images = Vec::new();
+        for message in messages {
+            parts.push(format!("{}{}", self.prefix(&message.role), message.content));
+            images.extend(message.images);
+        }
+        if !ends_with_assistant && !self.cue.is_empty() {
+            parts.push(self.cue.clone());
+        }
+        let mut prompt = ChatMessageRequest::new("user", parts.join(&self.separator));
+        prompt.images = images;
+        prompt
+    }
+}
@@ -0,0 +1,87 @@
+use eframe::egui;
+use egui::text::LayoutJob;
+use egui::{Color32, FontId, TextFormat};
+use llm::stream::ChunkTiming;
+
+/// How streamed text is tinted in the experimental heatmap view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeatmapMode {
+    /// Normal rendering.
+    Off,
+    /// Tint each chunk by how long it took to arrive (where the model "hesitated").
+    Latency,
+    /// Tint each chunk by its position in the reply.
+    Position,
+}
+
+impl HeatmapMode {
+    pub const ALL: [HeatmapMode; 3] = [HeatmapMode::Off, HeatmapMode::Latency, HeatmapMode::Position];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HeatmapMode::Off => "Off",
+            HeatmapMode::Latency => "Arrival latency",
+            HeatmapMode::Position => "Position",
+        }
+    }
+}
+
+/// Lay out `content` with each streamed chunk tinted according to `mode`.
+///
+/// Returns `None` when no usable timing exists — the reply wasn't streamed, or its
+/// text changed afterwards — so the caller falls back to normal rendering.
+pub fn layout_job(
+    content: &str,
+    timings: &[ChunkTiming],
+    mode: HeatmapMode,
+    font_id: FontId,
+    text_color: Color32,
+    wrap_width: f32,
+) -> Option<LayoutJob> {
+    if mode == HeatmapMode::Off || timings.is_empty() {
+        return None;
+    }
+    if timings.last()?.end != content.len()
+        || timings.windows(2).any(|w| w[0].end > w[1].end)
+        || timings.iter().any(|t| !content.is_char_boundary(t.end))
+    {
+        return None;
+    }
+
+    // The first gap is time-to-first-token, which would drown out the rest.
+    let slowest = timings
+        .iter()
+        .skip(1)
+        .map(|t| t.gap.as_secs_f32())
+        .fold(0.0, f32::max);
+
+    let mut job = LayoutJob::default();
+    job.wrap.max_width = wrap_width;
+    let mut start = 0;
+    for (index, timing) in timings.iter().enumerate() {
+        let heat = match mode {
+            HeatmapMode::Latency if index == 0 || slowest <= 0.0 => 0.0,
+            HeatmapMode::Latency => timing.gap.as_secs_f32() / slowest,
+            HeatmapMode::Position => index as f32 / timings.len().max(2).saturating_sub(1) as f32,
+            HeatmapMode::Off => 0.0,
+        };
+        let format = TextFormat {
+            font_id: font_id.clone(),
+            color: text_color,
+            background: heat_color(heat),
+            ..Default::default()
+        };
+        job.append(&content[start..timing.end], 0.0, format);
+        start = timing.end;
+    }
+    Some(job)
+}
+
+/// Translucent colour from cool (0.0) to hot (1.0).
+fn heat_color(heat: f32) -> Color32 {
+    let heat = heat.clamp(0.0, 1.0);
+    let r = (60.0 + 195.0 * heat) as u8;
+    let g = (170.0 - 110.0 * heat) as u8;
+    let b = (230.0 - 190.0 * heat) as u8;
+    Color32::from_rgba_unmultiplied(r, g, b, 70)
+}
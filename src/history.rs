@@ -0,0 +1,518 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{ChatMessageRequest, Citation, Route, ToolCall};
+use crate::attachment::{self, AttachmentInfo};
+use crate::backend::Backend;
+use crate::digest::Digest;
+use crate::error::{Error, Result};
+use crate::flatten::FlattenTemplate;
+use crate::paste::Cut;
+use crate::rating::{Rating, Rejected, Verdict};
+use crate::{incognito, storage, vault};
+
+/// A conversation saved to disk.
+///
+/// Conversations live as `<name>.json` in [`history_dir`]. Archived ones are
+/// read-only and kept gzip-compressed as `<name>.json.gz`, since they are rarely
+/// opened. Either kind is encrypted on disk when the [`vault`] is on.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SavedConversation {
+    #[serde(skip)]
+    pub name: String,
+    /// Archived conversations are kept for reference and must not be extended.
+    #[serde(default)]
+    pub archived: bool,
+    /// When the conversation was last saved, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub saved_at: u64,
+    /// Changes with every save, so that a window can tell when another one saved
+    /// the conversation since it opened it.
+    #[serde(default)]
+    pub revision: u64,
+    #[serde(default)]
+    pub profile: String,
+    /// Where the last message was sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<Backend>,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// Provider routing preference the conversation was using.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<Route>,
+    /// Template of a conversation held with a base model through raw completions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<FlattenTemplate>,
+    /// A short summary for conversation lists, refreshed in the background.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Digest>,
+    pub messages: Vec<SavedMessage>,
+}
+
+/// One message of a saved conversation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedMessage {
+    pub role: String,
+    pub content: String,
+    /// Persona that wrote the message in a group chat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Left out of the model's context.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub excluded: bool,
+    /// Id of the exchange a user message starts; 0 when it has none yet.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub exchange: u64,
+    /// Web sources the reply cites.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+    /// The provider that served the reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// The routing preference the reply was requested with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<Route>,
+    /// Attached images, by reference to their files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachmentInfo>,
+    /// The reply before the profile's filters changed it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unfiltered: Option<String>,
+    /// The message before its long pasted blocks were shortened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub untruncated: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cuts: Vec<Cut>,
+    /// The model that wrote the reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<Rating>,
+    /// Rated-down replies this one was asked for in place of.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rejected: Vec<Rejected>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// Which save of a conversation a window has open, from
+/// [`SavedConversation::stamp`], to notice when another window saves over it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    revision: u64,
+    modified: Option<SystemTime>,
+}
+
+/// A saved conversation found by [`list`].
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub archived: bool,
+    pub path: PathBuf,
+}
+
+/// A message matching a [`search`].
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub conversation: String,
+    pub archived: bool,
+    /// Index of the message within the conversation.
+    pub index: usize,
+    pub role: String,
+    /// The matching line, shortened.
+    pub snippet: String,
+    pub rating: Option<Rating>,
+}
+
+impl SavedMessage {
+    /// The message as it is kept in a live conversation. Attached images are
+    /// loaded from their files; those that can't be are kept as
+    /// [`missing_attachments`](ChatMessageRequest::missing_attachments).
+    pub fn to_request(&self) -> ChatMessageRequest {
+        let mut message = ChatMessageRequest::new(&self.role, self.content.clone());
+        message.name = self.name.clone();
+        message.excluded = self.excluded;
+        message.exchange = self.exchange;
+        message.citations = self.citations.clone();
+        message.provider = self.provider.clone();
+        message.route = self.route;
+        message.unfiltered = self.unfiltered.clone();
+        message.untruncated = self.untruncated.clone();
+        message.cuts = self.cuts.clone();
+        message.model = self.model.clone();
+        message.rating = self.rating.clone();
+        message.rejected = self.rejected.clone();
+        message.tool_calls = self.tool_calls.clone();
+        message.tool_call_id = self.tool_call_id.clone();
+        for info in &self.attachments {
+            match info.restore(attachment::DEFAULT_MAX_DIMENSION) {
+                Ok(image) => message.images.push(image),
+                Err(e) => {
+                    log::info!("Cannot reattach {}: {}", info.name, e);
+                    message.missing_attachments.push(info.clone());
+                }
+            }
+        }
+        message
+    }
+}
+
+impl From<&ChatMessageRequest> for SavedMessage {
+    fn from(message: &ChatMessageRequest) -> Self {
+        Self {
+            role: message.role.clone(),
+            content: message.content.clone(),
+            name: message.name.clone(),
+            excluded: message.excluded,
+            exchange: message.exchange,
+            citations: message.citations.clone(),
+            provider: message.provider.clone(),
+            route: message.route,
+            attachments: message
+                .images
+                .iter()
+                .map(|image| image.info())
+                .chain(message.missing_attachments.iter().cloned())
+                .collect(),
+            unfiltered: message.unfiltered.clone(),
+            untruncated: message.untruncated.clone(),
+            cuts: message.cuts.clone(),
+            model: message.model.clone(),
+            rating: message.rating.clone(),
+            rejected: message.rejected.clone(),
+            tool_calls: message.tool_calls.clone(),
+            tool_call_id: message.tool_call_id.clone(),
+        }
+    }
+}
+
+impl SavedConversation {
+    /// Capture a live conversation. Display-only messages such as the greeting are
+    /// left out.
+    pub fn new(name: &str, messages: &[ChatMessageRequest]) -> Self {
+        Self {
+            name: name.to_string(),
+            messages: messages
+                .iter()
+                .filter(|msg| !msg.display_only)
+                .map(SavedMessage::from)
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Where the last message was sent. Conversations saved before that was
+    /// kept only know the profile they were started under.
+    pub fn last_backend(&self) -> Option<Backend> {
+        let started = (!self.profile.is_empty()).then(|| Backend {
+            profile: self.profile.clone(),
+            host: String::new(),
+        });
+        self.backend.clone().or(started)
+    }
+
+    /// The messages for continuing the conversation.
+    pub fn requests(&self) -> Vec<ChatMessageRequest> {
+        self.messages.iter().map(SavedMessage::to_request).collect()
+    }
+
+    /// Write the conversation under its name, compressed when archived.
+    ///
+    /// Saving over an archived conversation is refused, so reference copies can't
+    /// be extended by accident.
+    pub fn save(&mut self) -> Result<()> {
+        validate_name(&self.name)?;
+        let dir = history_dir().ok_or_else(|| Error::Config("No data directory on this system".to_string()))?;
+        if !self.archived && dir.join(file_name(&self.name, true)).exists() {
+            return Err(archived_error(&self.name));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.saved_at = now.as_secs();
+        self.revision = (now.as_nanos() as u64).max(self.revision + 1);
+        self.write(&dir)
+    }
+
+    /// Save as [`save`](Self::save) does, unless another window saved the
+    /// conversation since `stamp` was taken of it, which fails with
+    /// [`Error::Conflict`] rather than lose what it saved.
+    pub fn save_over(&mut self, stamp: &mut Stamp) -> Result<()> {
+        if changed(&self.name, stamp) {
+            return Err(Error::Conflict { name: self.name.clone() });
+        }
+        self.save()
+    }
+
+    /// This save of the conversation, as just saved or loaded.
+    pub fn stamp(&self) -> Stamp {
+        Stamp {
+            revision: self.revision,
+            modified: modified(&self.name),
+        }
+    }
+
+    fn write(&self, dir: &Path) -> Result<()> {
+        incognito::refuse("Saving conversations")?;
+        let json = serde_json::to_vec_pretty(self)?;
+        let path = dir.join(file_name(&self.name, self.archived));
+        let data = if self.archived {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&json).and_then(|_| encoder.finish()).map_err(Error::file(&path))?
+        } else {
+            json
+        };
+        storage::write_atomic(&path, vault::seal(data)?).map_err(Error::file(&path))
+    }
+}
+
+/// Where saved conversations are kept.
+pub fn history_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cli_llm").join("conversations"))
+}
+
+/// All saved conversations, sorted by name.
+pub fn list() -> Result<Vec<Entry>> {
+    let Some(dir) = history_dir() else {
+        return Ok(Vec::new());
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::File { path: dir, source: e }),
+    };
+    let mut found = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(Error::file(&dir))?.path();
+        let Some(file) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let (name, archived) = match (file.strip_suffix(".json.gz"), file.strip_suffix(".json")) {
+            (Some(name), _) => (name, true),
+            (None, Some(name)) => (name, false),
+            (None, None) => continue,
+        };
+        if validate_name(name).is_ok() {
+            found.push(Entry {
+                name: name.to_string(),
+                archived,
+                path,
+            });
+        }
+    }
+    found.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(found)
+}
+
+/// Load a saved conversation by name, archived or not.
+pub fn load(name: &str) -> Result<SavedConversation> {
+    validate_name(name)?;
+    let entry = list()?.into_iter().find(|entry| entry.name == name).ok_or_else(|| {
+        let active: Vec<String> = list()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|entry| !entry.archived)
+            .map(|entry| entry.name)
+            .collect();
+        Error::Config(if active.is_empty() {
+            format!("No saved conversation named '{}'", name)
+        } else {
+            format!("No saved conversation named '{}' (saved: {})", name, active.join(", "))
+        })
+    })?;
+    read(&entry)
+}
+
+/// Read the conversation stored at `entry`.
+fn read(entry: &Entry) -> Result<SavedConversation> {
+    let raw = vault::open(fs::read(&entry.path).map_err(Error::file(&entry.path))?)?;
+    let json = if entry.archived {
+        let mut json = Vec::new();
+        GzDecoder::new(raw.as_slice())
+            .read_to_end(&mut json)
+            .map_err(Error::file(&entry.path))?;
+        json
+    } else {
+        raw
+    };
+    let mut conversation: SavedConversation = serde_json::from_slice(&json)?;
+    conversation.name = entry.name.clone();
+    conversation.archived = entry.archived;
+    Ok(conversation)
+}
+
+/// Archive or unarchive a saved conversation, moving it between the plain and the
+/// compressed file.
+pub fn set_archived(name: &str, archived: bool) -> Result<()> {
+    let mut conversation = load(name)?;
+    if conversation.archived == archived {
+        return Ok(());
+    }
+    let dir = history_dir().ok_or_else(|| Error::Config("No data directory on this system".to_string()))?;
+    let old = dir.join(file_name(name, conversation.archived));
+    conversation.archived = archived;
+    conversation.write(&dir)?;
+    fs::remove_file(&old).map_err(Error::file(&old))
+}
+
+/// Store a new summary in a saved conversation, leaving the rest of it and
+/// its save time as they are. Archived conversations keep theirs.
+pub fn set_summary(name: &str, summary: Digest) -> Result<()> {
+    let mut conversation = load(name)?;
+    if conversation.archived {
+        return Ok(());
+    }
+    let dir = history_dir().ok_or_else(|| Error::Config("No data directory on this system".to_string()))?;
+    conversation.summary = Some(summary);
+    conversation.write(&dir)
+}
+
+/// When the file of the conversation saved as `name` was last written.
+fn modified(name: &str) -> Option<SystemTime> {
+    let dir = history_dir()?;
+    [false, true]
+        .iter()
+        .find_map(|&archived| fs::metadata(dir.join(file_name(name, archived))).ok())
+        .and_then(|metadata| metadata.modified().ok())
+}
+
+/// A number that changes when what would be saved of `messages` does, for
+/// noticing unsaved changes without comparing whole conversations.
+///
+/// ```
+/// use llm::api::ChatMessageRequest;
+/// use llm::history;
+///
+/// let mut messages = vec![ChatMessageRequest::new("user", "Hi")];
+/// let before = history::fingerprint(&messages);
+/// assert_eq!(history::fingerprint(&messages.clone()), before);
+/// messages[0].excluded = true;
+/// assert_ne!(history::fingerprint(&messages), before);
+/// ```
+pub fn fingerprint(messages: &[ChatMessageRequest]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let saved = SavedConversation::new("", messages);
+    serde_json::to_vec(&saved.messages).unwrap_or_default().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Whether another window saved the conversation `name` since `stamp` was taken
+/// of it. A new summary or archiving it doesn't count, and is taken into `stamp`
+/// so the file isn't read again; neither does deleting it.
+pub fn changed(name: &str, stamp: &mut Stamp) -> bool {
+    let modified = modified(name);
+    if modified.is_none() || modified == stamp.modified {
+        return false;
+    }
+    match load(name) {
+        Ok(saved) if saved.revision != stamp.revision => true,
+        Ok(_) => {
+            stamp.modified = modified;
+            false
+        }
+        Err(error) => {
+            log::debug!("Cannot check '{}' for changes: {}", name, error);
+            false
+        }
+    }
+}
+
+/// The summary of the conversation at `entry`, if it has one and can be read.
+pub fn summary(entry: &Entry) -> Option<Digest> {
+    read(entry)
+        .inspect_err(|e| log::debug!("No summary for {}: {}", entry.path.display(), e))
+        .ok()?
+        .summary
+}
+
+/// Every saved conversation, archived ones included. Those that can't be read
+/// are skipped.
+pub fn load_all() -> Result<Vec<SavedConversation>> {
+    Ok(list()?
+        .iter()
+        .filter_map(|entry| {
+            read(entry)
+                .inspect_err(|e| log::warn!("Skipping unreadable conversation {}: {}", entry.path.display(), e))
+                .ok()
+        })
+        .collect())
+}
+
+/// Find messages containing `query` (case-insensitive) in every saved
+/// conversation, archived ones included. With `rated`, only replies rated so
+/// are found, and an empty `query` finds all of them.
+pub fn search(query: &str, rated: Option<Verdict>) -> Result<Vec<SearchHit>> {
+    let needle = query.to_lowercase();
+    let mut hits = Vec::new();
+    for conversation in load_all()? {
+        for (index, message) in conversation.messages.iter().enumerate() {
+            if rated.is_some() && message.rating.as_ref().map(|rating| rating.verdict) != rated {
+                continue;
+            }
+            let Some(line) = message.content.lines().find(|line| line.to_lowercase().contains(&needle)) else {
+                continue;
+            };
+            hits.push(SearchHit {
+                conversation: conversation.name.clone(),
+                archived: conversation.archived,
+                index,
+                role: message.role.clone(),
+                snippet: snippet(line.trim(), 100),
+                rating: message.rating.clone(),
+            });
+        }
+    }
+    Ok(hits)
+}
+
+/// Names may only use letters, digits, `-`, `_` and `.`, so they are safe file names.
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Config(format!(
+            "Invalid conversation name '{}': use letters, digits, '-', '_' and '.'",
+            name
+        )))
+    }
+}
+
+/// The error for trying to extend an archived conversation.
+pub fn archived_error(name: &str) -> Error {
+    Error::Config(format!("Conversation '{}' is archived; unarchive it to continue", name))
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+fn file_name(name: &str, archived: bool) -> String {
+    if archived {
+        format!("{}.json.gz", name)
+    } else {
+        format!("{}.json", name)
+    }
+}
+
+/// `text` cut to at most `max` characters.
+fn snippet(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        format!("{}…", text.chars().take(max).collect::<String>())
+    }
+}
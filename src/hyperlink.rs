@@ -0,0 +1,284 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Longest visible text for a link before it is shortened.
+const MAX_LABEL_CHARS: usize = 40;
+
+/// Whether OSC 8 hyperlinks should be written to stdout.
+///
+/// Links are off with `plain`, when `NO_COLOR` is set, when stdout is not a terminal, and
+/// on terminals not known to support them. `FORCE_HYPERLINK=1` (or `0`) overrides the
+/// terminal guess.
+pub fn supports_hyperlinks(plain: bool) -> bool {
+    if plain || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        return false;
+    }
+    if let Ok(force) = env::var("FORCE_HYPERLINK") {
+        return force != "0";
+    }
+    if !io::stdout().is_terminal() {
+        return false;
+    }
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    if matches!(
+        var("TERM_PROGRAM").as_str(),
+        "iTerm.app" | "WezTerm" | "vscode" | "ghostty" | "Hyper" | "Tabby"
+    ) {
+        return true;
+    }
+    if ["WT_SESSION", "KONSOLE_VERSION", "KITTY_WINDOW_ID"]
+        .iter()
+        .any(|name| env::var_os(name).is_some())
+    {
+        return true;
+    }
+    // GNOME Terminal and other VTE-based terminals gained support in 0.50.
+    if var("VTE_VERSION").parse::<u32>().is_ok_and(|v| v >= 5000) {
+        return true;
+    }
+    let term = var("TERM");
+    ["xterm-kitty", "alacritty", "foot", "wezterm"]
+        .iter()
+        .any(|prefix| term.starts_with(prefix))
+}
+
+/// Wrap `text` in an OSC 8 hyperlink pointing at `url`.
+///
+/// ```
+/// use llm::hyperlink::osc8;
+///
+/// assert_eq!(osc8("https://example.com", "example"), "\x1b]8;;https://example.com\x1b\\example\x1b]8;;\x1b\\");
+/// ```
+pub fn osc8(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+/// A hyperlink to a local file, labelled with its path. Bytes outside a
+/// URL's unreserved characters are percent-encoded.
+///
+/// ```
+/// use std::path::Path;
+/// use llm::hyperlink::{file_link, osc8};
+///
+/// let path = Path::new("/no such dir/résumé #1.md");
+/// assert_eq!(file_link(path), osc8("file:///no%20such%20dir/r%C3%A9sum%C3%A9%20%231.md", "/no such dir/résumé #1.md"));
+/// ```
+pub fn file_link(path: &Path) -> String {
+    let absolute = path
+        .canonicalize()
+        .unwrap_or_else(|_| path.to_path_buf());
+    let mut url = String::from("file://");
+    for byte in absolute.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    osc8(&url, &path.display().to_string())
+}
+
+/// Replace every `http(s)://` URL in `text` with a hyperlink whose visible text is
+/// the URL without its scheme, shortened when long.
+///
+/// ```
+/// use llm::hyperlink::{linkify, osc8};
+///
+/// assert_eq!(
+///     linkify("See https://example.com/docs."),
+///     format!("See {}.", osc8("https://example.com/docs", "example.com/docs"))
+/// );
+/// assert_eq!(
+///     linkify("(http://a.example/x_(y))"),
+///     format!("({})", osc8("http://a.example/x_(y)", "a.example/x_(y)"))
+/// );
+/// let long = "https://example.com/a/very/long/path/that/goes/on/and/on";
+/// assert_eq!(linkify(long), osc8(long, "example.com/a/very/long/path/that/goes/…"));
+/// assert_eq!(linkify("xhttps://example.com and https:// alone"), "xhttps://example.com and https:// alone");
+/// ```
+pub fn linkify(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for range in url_ranges(text) {
+        out.push_str(&text[last..range.start]);
+        let url = &text[range.clone()];
+        out.push_str(&osc8(url, &label(url)));
+        last = range.end;
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// The visible text for a link to `url`.
+fn label(url: &str) -> String {
+    let short = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    if short.chars().count() <= MAX_LABEL_CHARS {
+        return short.to_string();
+    }
+    let mut label: String = short.chars().take(MAX_LABEL_CHARS - 1).collect();
+    label.push('…');
+    label
+}
+
+/// Byte ranges of the URLs in `text`.
+fn url_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut from = 0;
+    while let Some(found) = text[from..].find("http") {
+        let start = from + found;
+        from = start + 4;
+        let rest = &text[start..];
+        let scheme_len = if rest.starts_with("https://") {
+            8
+        } else if rest.starts_with("http://") {
+            7
+        } else {
+            continue;
+        };
+        if text[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric())
+        {
+            continue;
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | '"' | '`'))
+            .unwrap_or(rest.len());
+        let url = trim_url(&rest[..end]);
+        if url.len() > scheme_len {
+            ranges.push(start..start + url.len());
+            from = start + url.len();
+        }
+    }
+    ranges
+}
+
+/// Strip trailing punctuation and unbalanced closing brackets that belong to the
+/// surrounding prose (`see https://example.com.` or `(https://example.com)`).
+fn trim_url(mut url: &str) -> &str {
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '*']);
+        let trimmed = match trimmed.chars().next_back() {
+            Some(')') if trimmed.matches(')').count() > trimmed.matches('(').count() => {
+                &trimmed[..trimmed.len() - 1]
+            }
+            Some(']') if trimmed.matches(']').count() > trimmed.matches('[').count() => {
+                &trimmed[..trimmed.len() - 1]
+            }
+            _ => trimmed,
+        };
+        if trimmed.len() == url.len() {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+/// Where a URL that more text could continue starts in `word`, the last word
+/// of the text so far: a URL, or the start of its scheme, not preceded by a
+/// letter or digit.
+fn url_start(word: &str) -> Option<usize> {
+    word.char_indices().map(|(i, _)| i).find(|&i| {
+        let rest = &word[i..];
+        word[..i].chars().next_back().is_none_or(|c| !c.is_alphanumeric())
+            && ["https://", "http://"].iter().any(|scheme| rest.starts_with(scheme) || scheme.starts_with(rest))
+    })
+}
+
+/// Writes streamed text, turning URLs into hyperlinks when enabled.
+///
+/// A URL can arrive split over several chunks, so the end of the text that may
+/// be the start of one is held back until more text arrives or
+/// [`LinkWriter::finish`] is called.
+pub struct LinkWriter<W: Write> {
+    out: W,
+    enabled: bool,
+    pending: String,
+}
+
+impl<W: Write> LinkWriter<W> {
+    pub fn new(out: W, enabled: bool) -> Self {
+        Self {
+            out,
+            enabled,
+            pending: String::new(),
+        }
+    }
+
+    /// Write a chunk of text, flushing everything that can be shown yet.
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        if !self.enabled {
+            self.out.write_all(text.as_bytes())?;
+            return self.out.flush();
+        }
+        self.pending.push_str(text);
+        let split = self
+            .pending
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let ready = url_start(&self.pending[split..]).map_or(self.pending.len(), |start| split + start);
+        let complete: String = self.pending.drain(..ready).collect();
+        self.out.write_all(linkify(&complete).as_bytes())?;
+        self.out.flush()
+    }
+
+    /// Write whatever is still held back.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let rest = std::mem::take(&mut self.pending);
+        self.out.write_all(linkify(&rest).as_bytes())?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a link writer has written after each of `chunks`.
+    fn written(chunks: &[&str]) -> Vec<String> {
+        let mut writer = LinkWriter::new(Vec::new(), true);
+        let mut seen = Vec::new();
+        for chunk in chunks {
+            writer.write(chunk).unwrap();
+            seen.push(String::from_utf8(writer.out.clone()).unwrap());
+        }
+        writer.finish().unwrap();
+        seen.push(String::from_utf8(writer.out).unwrap());
+        seen
+    }
+
+    #[test]
+    fn url_split_over_chunks_is_one_link() {
+        let link = osc8("https://example.com/a", "example.com/a");
+        assert_eq!(
+            written(&["Go to ht", "tps://exam", "ple.com/a now"]),
+            ["Go to ", "Go to ", &format!("Go to {} now", link), &format!("Go to {} now", link)]
+        );
+    }
+
+    #[test]
+    fn only_a_possible_url_is_held_back() {
+        assert_eq!(written(&["the httpclient crate"]), ["the httpclient crate"; 2]);
+        assert_eq!(written(&["use xhttps://"]), ["use xhttps://"; 2]);
+        assert_eq!(written(&["a long_identifier_without_spaces"]), ["a long_identifier_without_spaces"; 2]);
+        assert_eq!(written(&["much"]), ["much"; 2]);
+        assert_eq!(written(&["see h"]), ["see ", "see h"]);
+        assert_eq!(written(&["(at https:/"]), ["(at ", "(at https:/"]);
+        assert_eq!(written(&["link:https://a.b"]), ["link:", &format!("link:{}", osc8("https://a.b", "a.b"))]);
+    }
+
+    #[test]
+    fn disabled_writer_passes_text_through() {
+        let mut writer = LinkWriter::new(Vec::new(), false);
+        writer.write("see https://exa").unwrap();
+        assert_eq!(writer.out, b"see https://exa");
+    }
+}
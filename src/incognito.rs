@@ -0,0 +1,86 @@
+//! Incognito mode: a session that leaves nothing behind on disk.
+//!
+//! While it is on, conversations, settings, the models cache, flight recorder
+//! records, rendered diagrams and other files aren't written, and no background
+//! requests are made. Features that can't work without writing ask [`refuse`]
+//! first and say why they are off. Every write to disk goes through
+//! [`storage`](crate::storage), whose functions call [`check`] and so catch the
+//! ones that didn't ask: they fail, and debug builds panic so that tests notice.
+//! The GUI's lock file, which only holds a process id and is removed on exit,
+//! is the one exception.
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{Error, Result};
+
+static ON: AtomicBool = AtomicBool::new(false);
+
+/// Turn incognito mode on or off for the rest of the process.
+pub fn set(on: bool) {
+    ON.store(on, Ordering::Relaxed);
+}
+
+pub fn is_on() -> bool {
+    ON.load(Ordering::Relaxed)
+}
+
+/// An error naming `what` as off in incognito mode, when it is on.
+///
+/// ```
+/// use llm::incognito;
+///
+/// assert!(incognito::refuse("Saving conversations").is_ok());
+/// incognito::set(true);
+/// let error = incognito::refuse("Saving conversations").unwrap_err();
+/// assert_eq!(error.to_string(), "Saving conversations is off in incognito mode, which writes nothing to disk");
+/// ```
+pub fn refuse(what: &str) -> Result<()> {
+    match is_on() {
+        true => Err(Error::Config(format!("{} is off in incognito mode, which writes nothing to disk", what))),
+        false => Ok(()),
+    }
+}
+
+/// Allow writing `path` unless incognito mode is on. Reaching this in
+/// incognito mode means a caller forgot to [`refuse`], which debug builds
+/// treat as a bug.
+///
+/// ```should_panic
+/// use std::path::Path;
+/// use llm::incognito;
+///
+/// incognito::set(true);
+/// // Panics in debug builds; fails with `PermissionDenied` in release builds.
+/// let _ = incognito::check(Path::new("conversation.json"));
+/// ```
+pub fn check(path: &Path) -> io::Result<()> {
+    if !is_on() {
+        return Ok(());
+    }
+    if cfg!(debug_assertions) {
+        panic!("{} was about to be written in incognito mode", path.display());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "nothing is written to disk in incognito mode",
+    ))
+}
+
+/// Overwrite `text` with zeros and empty it, so that what it held doesn't
+/// linger in freed memory.
+///
+/// ```
+/// use llm::incognito;
+///
+/// let mut secret = String::from("my diagnosis");
+/// incognito::wipe(&mut secret);
+/// assert!(secret.is_empty());
+/// ```
+pub fn wipe(text: &mut String) {
+    let mut bytes = std::mem::take(text).into_bytes();
+    bytes.fill(0);
+    // Keeps the zeros from being optimized away as never read.
+    std::hint::black_box(&bytes);
+}
@@ -0,0 +1,247 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Sent by a second launch to the running instance, which answers with [`PONG`].
+const PING: &str = "cli_llm show";
+/// Sent instead of [`PING`], followed by a name, to also open that saved
+/// conversation.
+const OPEN: &str = "cli_llm open ";
+const PONG: &str = "cli_llm ok";
+
+/// How long a second launch waits for the running instance to answer.
+const PING_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Where the GUI keeps its instance lock.
+pub fn lock_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("cli_llm").join("gui.lock"))
+}
+
+/// Result of [`InstanceLock::acquire`].
+pub enum Instance {
+    /// No other instance is running; this process now holds the lock.
+    Primary(InstanceLock),
+    /// Another instance is running and has been asked to show its window, and
+    /// to open the conversation asked for, if any.
+    Secondary { pid: u32 },
+}
+
+/// Lock file marking the running instance, removed again when dropped.
+///
+/// The file holds the owner's process ID and the port of a local socket. A
+/// second launch connects to that socket to check the owner is still alive and to
+/// ask it to come to the front; a lock whose owner does not answer was left
+/// behind by a crash and is taken over.
+pub struct InstanceLock {
+    path: PathBuf,
+    listener: TcpListener,
+    contents: String,
+}
+
+impl InstanceLock {
+    /// Take the lock at `path`, or ping the instance already holding it, asking it
+    /// to open the saved conversation `open`.
+    pub fn acquire(path: &Path, open: Option<&str>) -> io::Result<Instance> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let contents = format!("{} {}\n", process::id(), listener.local_addr()?.port());
+        let dir = path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+
+        // Write the details to a private file first and hard-link it into place: the
+        // link fails if a lock exists, and a lock is never seen half-written.
+        let temp = dir.join(format!(".gui.lock.{}.tmp", process::id()));
+        fs::write(&temp, &contents)?;
+        let result = Self::link(&temp, path, open);
+        let _ = fs::remove_file(&temp);
+        match result? {
+            None => Ok(Instance::Primary(InstanceLock {
+                path: path.to_path_buf(),
+                listener,
+                contents,
+            })),
+            Some(pid) => Ok(Instance::Secondary { pid }),
+        }
+    }
+
+    /// Link `temp` to `path`, taking over stale locks. Returns the owner's process
+    /// ID when another live instance holds the lock.
+    fn link(temp: &Path, path: &Path, open: Option<&str>) -> io::Result<Option<u32>> {
+        // Another launch may take over the same stale lock at the same moment, so
+        // allow a couple of rounds before giving up.
+        for _ in 0..3 {
+            match fs::hard_link(temp, path) {
+                Ok(()) => return Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+            let owner = fs::read_to_string(path).ok().and_then(|text| parse_lock(&text));
+            if let Some((pid, port)) = owner
+                && ping(port, open)
+            {
+                return Ok(Some(pid));
+            }
+            log::info!("Removing stale instance lock {}", path.display());
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::other(format!("could not take the instance lock {}", path.display())))
+    }
+
+    /// Answer pings from later launches on a background thread, calling `on_show`
+    /// each time one asks this instance to come to the front, with the saved
+    /// conversation it asks to open, if any.
+    pub fn listen(&self, on_show: impl Fn(Option<String>) + Send + 'static) -> io::Result<()> {
+        let listener = self.listener.try_clone()?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Some(open) = answer(stream) {
+                    on_show(open);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Only remove the lock if it is still ours and wasn't taken over meanwhile.
+        if fs::read_to_string(&self.path).is_ok_and(|text| text == self.contents) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Read `<pid> <port>` from a lock file.
+fn parse_lock(text: &str) -> Option<(u32, u16)> {
+    let mut parts = text.split_whitespace();
+    let pid = parts.next()?.parse().ok()?;
+    let port = parts.next()?.parse().ok()?;
+    Some((pid, port))
+}
+
+/// Ask the instance listening on `port` to show itself, and open the saved
+/// conversation `open`. False when nothing, or something other than an instance
+/// of this app, answers.
+fn ping(port: u16, open: Option<&str>) -> bool {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let Ok(mut stream) = TcpStream::connect_timeout(&addr, PING_TIMEOUT) else {
+        return false;
+    };
+    let _ = stream.set_read_timeout(Some(PING_TIMEOUT));
+    let mut reply = String::new();
+    let request = match open {
+        Some(name) => format!("{}{}", OPEN, name),
+        None => PING.to_string(),
+    };
+    writeln!(stream, "{}", request).is_ok()
+        && BufReader::new(stream).read_line(&mut reply).is_ok()
+        && reply.trim_end() == PONG
+}
+
+/// Reply to one ping. `None` unless it was a valid request to show the window;
+/// then the conversation it asks to open, if any.
+fn answer(mut stream: TcpStream) -> Option<Option<String>> {
+    let _ = stream.set_read_timeout(Some(PING_TIMEOUT));
+    let mut request = String::new();
+    let reader = stream.try_clone().ok()?;
+    BufReader::new(reader).read_line(&mut request).ok()?;
+    let open = match request.trim_end() {
+        PING => None,
+        request => Some(request.strip_prefix(OPEN)?.to_string()),
+    };
+    writeln!(stream, "{}", PONG).ok()?;
+    Some(open)
+}
+
+/// Start the GUI with the saved conversation `name` open, or have the running
+/// one open it. The GUI is looked for next to this program, then on the `PATH`.
+pub fn open_in_gui(name: &str) -> io::Result<()> {
+    let program = format!("llm{}", env::consts::EXE_SUFFIX);
+    let beside = env::current_exe().ok().map(|exe| exe.with_file_name(&program)).filter(|path| path.is_file());
+    Command::new(beside.unwrap_or_else(|| PathBuf::from(program)))
+        .args(["--open", name])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::sync::mpsc;
+
+    fn primary(path: &Path) -> InstanceLock {
+        match InstanceLock::acquire(path, None).unwrap() {
+            Instance::Primary(lock) => lock,
+            Instance::Secondary { pid } => panic!("lock held by process {}", pid),
+        }
+    }
+
+    #[test]
+    fn second_launch_pings_the_first() {
+        let dir = TempDir::new("instance", "second");
+        let path = dir.0.join("cache").join("gui.lock");
+        let lock = primary(&path);
+        let (tx, rx) = mpsc::channel();
+        lock.listen(move |open| tx.send(open).unwrap()).unwrap();
+
+        let Instance::Secondary { pid } = InstanceLock::acquire(&path, None).unwrap() else {
+            panic!("took a lock that is held");
+        };
+        assert_eq!(pid, process::id());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), None);
+        assert!(matches!(InstanceLock::acquire(&path, Some("notes")).unwrap(), Instance::Secondary { .. }));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap().as_deref(), Some("notes"));
+
+        // Only the lock itself is left behind, and it goes with its owner.
+        let names: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(names, ["gui.lock"]);
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let dir = TempDir::new("instance", "stale");
+        let path = dir.0.join("gui.lock");
+        // A crashed owner whose port nothing listens on any more, and a damaged file.
+        let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap().port();
+        for stale in [format!("1 {}\n", closed), "half a lo".to_string()] {
+            fs::write(&path, stale).unwrap();
+            let lock = primary(&path);
+            let (pid, _) = parse_lock(&fs::read_to_string(&path).unwrap()).unwrap();
+            assert_eq!(pid, process::id());
+            drop(lock);
+        }
+    }
+
+    #[test]
+    fn lock_taken_over_meanwhile_is_kept() {
+        let dir = TempDir::new("instance", "kept");
+        let path = dir.0.join("gui.lock");
+        let lock = primary(&path);
+        fs::write(&path, "1 2\n").unwrap();
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1 2\n");
+    }
+
+    #[test]
+    fn parses_lock_files() {
+        assert_eq!(parse_lock("4242 51234\n"), Some((4242, 51234)));
+        assert_eq!(parse_lock("4242"), None);
+        assert_eq!(parse_lock("4242 99999"), None);
+        assert_eq!(parse_lock(""), None);
+    }
+}
@@ -0,0 +1,454 @@
+//! Guessing the language of code blocks whose fence doesn't name one, so that
+//! they can still be highlighted and rendered.
+//!
+//! A block is recognized outright by a shebang, a file name comment on its
+//! first line (`// src/main.rs`), its first line for diagrams, or being JSON.
+//! Otherwise each language scores the telltale pieces of code found in the
+//! block, each counted once, and the best wins only when it scores enough and
+//! well ahead of the next. Anything less certain stays plain text.
+
+use crate::structured;
+
+/// Least score a guess needs.
+const MIN_SCORE: u32 = 4;
+
+/// How far the best score has to be ahead of the next.
+const MIN_LEAD: u32 = 2;
+
+/// The names guesses use, as for a fence's language.
+pub const LANGUAGES: &[&str] = &[
+    "bash", "c", "cpp", "css", "dot", "go", "html", "java", "javascript", "json", "mermaid", "php", "python", "ruby",
+    "rust", "sql", "typescript",
+];
+
+/// Telltale code of a language, with how much it counts. A `^` at the start
+/// anchors a signal to the start of a (trimmed) line, a `$` at the end to its
+/// end, and `…` stands for anything in between.
+struct Signals {
+    language: &'static str,
+    /// The language this one extends: its score adds to this one's, once this
+    /// one's own signals show up.
+    extends: Option<&'static str>,
+    signals: &'static [(&'static str, u32)],
+}
+
+const SIGNALS: &[Signals] = &[
+    Signals {
+        language: "rust",
+        extends: None,
+        signals: &[
+            ("^fn ", 3),
+            ("^pub fn ", 3),
+            ("^pub struct ", 3),
+            ("^pub enum ", 3),
+            ("^use std::", 3),
+            ("^impl", 3),
+            ("^#[derive(", 4),
+            ("let mut ", 3),
+            ("println!(", 3),
+            (".unwrap()", 3),
+            ("&self", 2),
+            ("&mut ", 2),
+            ("Vec<", 2),
+            ("Result<", 2),
+            ("^mod ", 2),
+            ("^match ", 1),
+            (") -> ", 1),
+        ],
+    },
+    Signals {
+        language: "python",
+        extends: None,
+        signals: &[
+            ("^def …):$", 3),
+            ("^def …) -> …:$", 3),
+            ("^class …:$", 2),
+            ("^from … import ", 3),
+            ("^import ", 1),
+            ("^if __name__ ==", 4),
+            ("^elif ", 3),
+            ("__init__(self", 3),
+            ("self.", 1),
+            ("print(", 1),
+            ("^for … in …:$", 3),
+            ("^with … as …:$", 3),
+            (" is not None", 2),
+            ("^@", 1),
+        ],
+    },
+    Signals {
+        language: "javascript",
+        extends: None,
+        signals: &[
+            ("console.log(", 3),
+            ("^const … = require(", 3),
+            ("module.exports", 3),
+            ("^function ", 2),
+            ("async function", 2),
+            ("=> {", 2),
+            ("===", 2),
+            ("document.", 2),
+            ("^export default ", 2),
+            ("^import … from '", 2),
+            ("^import … from \"", 2),
+            ("^const ", 1),
+            ("^let ", 1),
+            ("^var ", 2),
+            (".then(", 1),
+        ],
+    },
+    Signals {
+        language: "typescript",
+        extends: Some("javascript"),
+        signals: &[
+            ("^interface … {", 3),
+            ("^export interface ", 3),
+            ("^type … = ", 2),
+            (": string", 3),
+            (": number", 3),
+            (": boolean", 3),
+            ("): void", 2),
+            (" as const", 2),
+            ("private readonly ", 2),
+        ],
+    },
+    Signals {
+        language: "go",
+        extends: None,
+        signals: &[
+            ("^package ", 2),
+            ("^func ", 3),
+            ("^import (", 3),
+            (":= ", 3),
+            ("fmt.", 3),
+            ("err != nil", 3),
+            ("^type … struct {", 3),
+            ("go func", 3),
+            ("^defer ", 2),
+        ],
+    },
+    Signals {
+        language: "java",
+        extends: None,
+        signals: &[
+            ("^package …;$", 3),
+            ("^import java", 3),
+            ("public class ", 3),
+            ("public static void main(", 3),
+            ("System.out.print", 3),
+            ("@Override", 3),
+            ("private final ", 2),
+            ("public void ", 2),
+            ("private void ", 2),
+            ("new ArrayList<", 3),
+            ("String[] ", 2),
+        ],
+    },
+    Signals {
+        language: "c",
+        extends: None,
+        signals: &[
+            ("^#include <", 3),
+            ("^#include \"", 3),
+            ("^#define ", 2),
+            ("^typedef ", 3),
+            ("int main(", 2),
+            ("printf(", 2),
+            ("malloc(", 3),
+            ("sizeof(", 2),
+            ("char *", 2),
+            ("return 0;", 2),
+            ("NULL", 1),
+        ],
+    },
+    Signals {
+        language: "cpp",
+        extends: Some("c"),
+        signals: &[
+            ("std::", 3),
+            ("^using namespace ", 3),
+            ("^namespace ", 2),
+            ("^template <", 3),
+            ("^template<", 3),
+            ("cout <<", 3),
+            ("nullptr", 3),
+            ("^public:", 3),
+            ("^private:", 3),
+        ],
+    },
+    Signals {
+        language: "bash",
+        extends: None,
+        signals: &[
+            ("^if [", 3),
+            ("; then", 3),
+            ("^fi$", 3),
+            ("; do", 3),
+            ("^done$", 3),
+            ("^echo ", 2),
+            ("^sudo ", 3),
+            ("apt-get install ", 3),
+            ("^apt install ", 3),
+            ("^brew install ", 3),
+            ("^pip install ", 3),
+            ("^npm install ", 3),
+            ("^cargo ", 3),
+            ("^git ", 3),
+            ("^cd ", 2),
+            ("^mkdir ", 2),
+            ("^chmod ", 3),
+            ("^export ", 1),
+            ("$(", 2),
+            ("| grep ", 2),
+            ("^$ ", 2),
+        ],
+    },
+    Signals {
+        language: "sql",
+        extends: None,
+        signals: &[
+            ("^SELECT ", 3),
+            ("^CREATE TABLE ", 4),
+            ("^INSERT INTO ", 4),
+            ("^UPDATE … SET ", 4),
+            ("^DELETE FROM ", 4),
+            ("^ALTER TABLE ", 4),
+            ("FROM ", 2),
+            ("WHERE ", 2),
+            ("JOIN ", 2),
+            ("GROUP BY ", 3),
+            ("ORDER BY ", 3),
+            ("PRIMARY KEY", 3),
+            ("VARCHAR(", 3),
+            ("^select … from ", 4),
+        ],
+    },
+    Signals {
+        language: "html",
+        extends: None,
+        signals: &[
+            ("<!DOCTYPE html", 5),
+            ("<html", 3),
+            ("<head>", 3),
+            ("<body", 3),
+            ("</div>", 2),
+            ("<script", 2),
+            ("<a href=", 2),
+            ("</p>", 1),
+            ("class=\"", 1),
+        ],
+    },
+    Signals {
+        language: "css",
+        extends: None,
+        signals: &[
+            ("^.… {$", 2),
+            ("^@media ", 3),
+            ("^color: ", 2),
+            ("^margin", 2),
+            ("^padding", 2),
+            ("^display: ", 2),
+            ("^font-", 2),
+            ("^background", 1),
+            ("px;$", 2),
+            ("rem;$", 2),
+            ("!important", 2),
+        ],
+    },
+    Signals {
+        language: "ruby",
+        extends: None,
+        signals: &[
+            ("^require '", 2),
+            ("^def ", 1),
+            ("^end$", 3),
+            ("puts ", 3),
+            (".each do |", 4),
+            (" do |", 3),
+            ("^class … < ", 3),
+            ("attr_accessor ", 4),
+            ("^module ", 2),
+            ("^elsif ", 3),
+            (".nil?", 3),
+        ],
+    },
+    Signals {
+        language: "php",
+        extends: None,
+        signals: &[
+            ("<?php", 6),
+            ("$this->", 4),
+            ("^$… = …;$", 3),
+            ("public function ", 2),
+            ("^namespace …;$", 2),
+        ],
+    },
+];
+
+/// The language of `code`, when it can be told with confidence; one of
+/// [`LANGUAGES`].
+///
+/// ```
+/// use llm::language::guess;
+///
+/// let corpus = [
+///     ("rust", "use std::collections::HashMap;\n\nfn main() {\n    let mut counts = HashMap::new();\n    \
+///               counts.insert(\"a\", 1);\n    println!(\"{:?}\", counts);\n}\n"),
+///     ("python", "import os\n\ndef list_files(path):\n    for name in os.listdir(path):\n        print(name)\n\n\
+///                 if __name__ == \"__main__\":\n    list_files(\".\")\n"),
+///     ("javascript", "const express = require('express');\nconst app = express();\n\n\
+///                     app.get('/', (req, res) => {\n  res.send('Hello');\n});\nconsole.log('listening');\n"),
+///     ("typescript", "interface User {\n  name: string;\n  age: number;\n}\n\n\
+///                     function greet(user: User): void {\n  console.log(`Hi ${user.name}`);\n}\n"),
+///     ("go", "package main\n\nimport \"fmt\"\n\nfunc main() {\n    names := []string{\"a\", \"b\"}\n    \
+///             fmt.Println(names)\n}\n"),
+///     ("java", "public class Hello {\n    public static void main(String[] args) {\n        \
+///               System.out.println(\"Hello\");\n    }\n}\n"),
+///     ("c", "#include <stdio.h>\n#include <stdlib.h>\n\nint main(void) {\n    char *buffer = malloc(64);\n    \
+///            printf(\"%s\\n\", buffer);\n    free(buffer);\n    return 0;\n}\n"),
+///     ("cpp", "#include <iostream>\n#include <vector>\n\nint main() {\n    std::vector<int> v{1, 2, 3};\n    \
+///              std::cout << v.size() << std::endl;\n    return 0;\n}\n"),
+///     ("bash", "#!/usr/bin/env bash\nset -e\nfor f in *.log; do\n  gzip \"$f\"\ndone\n"),
+///     ("bash", "sudo apt-get install -y build-essential\ncd my-project\ncargo build --release\n"),
+///     ("sql", "SELECT u.name, COUNT(o.id)\nFROM users u\nJOIN orders o ON o.user_id = u.id\n\
+///              GROUP BY u.name\nORDER BY 2 DESC;\n"),
+///     ("html", "<!DOCTYPE html>\n<html>\n<head>\n  <title>Page</title>\n</head>\n<body>\n  \
+///               <div class=\"main\"></div>\n</body>\n</html>\n"),
+///     ("css", ".card {\n  padding: 16px;\n  margin: 0 auto;\n  display: flex;\n}\n"),
+///     ("ruby", "class Dog < Animal\n  attr_accessor :name\n\n  def bark\n    puts \"Woof\"\n  end\nend\n"),
+///     ("php", "<?php\necho $greeting;\n"),
+///     ("json", "{\"name\": \"cli_llm\", \"version\": 1}\n"),
+///     ("mermaid", "graph TD\n  A --> B\n"),
+///     ("python", "# scripts/report.py\nreport()\n"),
+/// ];
+/// for (language, code) in corpus {
+///     assert_eq!(guess(code), Some(language), "{}", code);
+/// }
+///
+/// // Too little to go on, or not code at all.
+/// for code in ["ls\n", "x = 1\n", "Hello, world!\n", "TODO: fill in\n", "return x;\n", "1. Preheat the oven\n"] {
+///     assert_eq!(guess(code), None, "{}", code);
+/// }
+/// ```
+pub fn guess(code: &str) -> Option<&'static str> {
+    if let Some(language) = marked(code) {
+        return Some(language);
+    }
+    let lines: Vec<&str> = code.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let own = |signals: &Signals| -> u32 {
+        signals
+            .signals
+            .iter()
+            .filter(|(signal, _)| lines.iter().any(|line| matches(line, signal)))
+            .map(|(_, weight)| weight)
+            .sum()
+    };
+    let own_scores: Vec<(&Signals, u32)> = SIGNALS.iter().map(|signals| (signals, own(signals))).collect();
+    let base = |language: &str| own_scores.iter().find(|(signals, _)| signals.language == language).map_or(0, |s| s.1);
+    let mut scores: Vec<(&'static str, u32)> = own_scores
+        .iter()
+        // A dialect competes only once something of its own shows up.
+        .filter(|(_, score)| *score > 0)
+        .map(|(signals, score)| (signals.language, score + signals.extends.map_or(0, base)))
+        .collect();
+    scores.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    let (language, best) = *scores.first()?;
+    let next = scores.get(1).map_or(0, |s| s.1);
+    (best >= MIN_SCORE && best >= next + MIN_LEAD).then_some(language)
+}
+
+/// The language `code` says it is in, through a shebang, a file name comment
+/// or its first line, or by being JSON.
+fn marked(code: &str) -> Option<&'static str> {
+    let first = code.lines().map(str::trim).find(|line| !line.is_empty())?;
+    if let Some(interpreter) = first.strip_prefix("#!") {
+        let program = interpreter.split_whitespace().rev().find(|word| !word.starts_with('-'))?;
+        let program = program.rsplit('/').next().unwrap_or(program);
+        return match program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+            "sh" | "bash" | "zsh" => Some("bash"),
+            "python" => Some("python"),
+            "node" => Some("javascript"),
+            "ruby" => Some("ruby"),
+            "php" => Some("php"),
+            _ => None,
+        };
+    }
+    if let Some(language) = file_name_comment(first) {
+        return Some(language);
+    }
+    let diagram = first.split_whitespace().next().unwrap_or_default();
+    match diagram {
+        "graph" if first.ends_with('{') => return Some("dot"),
+        "digraph" | "strict" => return Some("dot"),
+        "graph" | "flowchart" | "sequenceDiagram" | "classDiagram" | "stateDiagram" | "stateDiagram-v2"
+        | "erDiagram" | "gantt" | "journey" | "mindmap" => return Some("mermaid"),
+        _ => {}
+    }
+    structured::detect_json(code).map(|_| "json")
+}
+
+/// The language of the file a comment like `// src/main.rs` or
+/// `<!-- index.html -->` names, when `line` is nothing but such a comment.
+fn file_name_comment(line: &str) -> Option<&'static str> {
+    let text = ["//", "#", "--", "/*", "<!--"]
+        .iter()
+        .find_map(|marker| line.strip_prefix(marker))?
+        .trim_end_matches("*/")
+        .trim_end_matches("-->")
+        .trim();
+    let text = ["File:", "file:", "Filename:", "filename:"]
+        .iter()
+        .find_map(|label| text.strip_prefix(label))
+        .unwrap_or(text)
+        .trim();
+    if text.contains(char::is_whitespace) {
+        return None;
+    }
+    let (_, extension) = text.rsplit_once('.')?;
+    match extension.to_lowercase().as_str() {
+        "rs" => Some("rust"),
+        "py" => Some("python"),
+        "js" | "mjs" | "cjs" | "jsx" => Some("javascript"),
+        "ts" | "tsx" => Some("typescript"),
+        "go" => Some("go"),
+        "java" => Some("java"),
+        "c" | "h" => Some("c"),
+        "cpp" | "cc" | "cxx" | "hpp" => Some("cpp"),
+        "sh" | "bash" => Some("bash"),
+        "sql" => Some("sql"),
+        "html" | "htm" => Some("html"),
+        "css" => Some("css"),
+        "rb" => Some("ruby"),
+        "php" => Some("php"),
+        "json" => Some("json"),
+        _ => None,
+    }
+}
+
+/// Whether `line` has `signal`, as [`Signals`] describes them.
+fn matches(line: &str, signal: &str) -> bool {
+    let (anchored, signal) = match signal.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, signal),
+    };
+    let (to_end, signal) = match signal.strip_suffix('$') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, signal),
+    };
+    let parts: Vec<&str> = signal.split('…').collect();
+    let mut rest = line;
+    for (i, part) in parts.iter().enumerate() {
+        let found = if i == 0 && anchored {
+            rest.starts_with(part).then_some(0)
+        } else if i == parts.len() - 1 && to_end {
+            rest.ends_with(part).then(|| rest.len() - part.len())
+        } else {
+            rest.find(part)
+        };
+        match found {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    !to_end || rest.is_empty()
+}
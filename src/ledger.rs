@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::Usage;
+use crate::error::{Error, Result};
+use crate::incognito;
+use crate::storage;
+
+/// Where both front ends record the tokens each reply used.
+pub fn ledger_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cli_llm").join("usage.jsonl"))
+}
+
+/// The tokens one reply used, as a line of the ledger.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Tells entries apart; a later line with the same id replaces an earlier one.
+    pub id: String,
+    /// When the reply arrived, in seconds since the Unix epoch.
+    pub at: u64,
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl Entry {
+    /// An entry for a reply from `model` that arrived just now, with an id no
+    /// other entry of this or another running instance has.
+    pub fn new(model: &str, usage: &Usage) -> Self {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        Self {
+            id: format!("{}-{}-{}", now.as_nanos(), process::id(), count),
+            at: now.as_secs(),
+            model: model.to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+/// Append `entry` to the ledger at `path`. Nothing is recorded in
+/// [incognito](crate::incognito) mode.
+///
+/// The ledger is append-only: every entry is one line written with a single
+/// append, so the lines of several running instances interleave but never mix.
+pub fn record(path: &Path, entry: &Entry) -> Result<()> {
+    if incognito::is_on() {
+        return Ok(());
+    }
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    if let Some(dir) = path.parent() {
+        storage::create_dir_all(dir).map_err(Error::file(dir))?;
+    }
+    storage::open(path, OpenOptions::new().create(true).append(true))
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(Error::file(path))
+}
+
+/// Record the tokens of a reply from `model` in the ledger, logging a failure
+/// rather than failing the reply over it.
+pub fn note(model: &str, usage: &Usage) {
+    let Some(path) = ledger_path() else {
+        return;
+    };
+    if let Err(e) = record(&path, &Entry::new(model, usage)) {
+        log::warn!("Cannot record token usage: {}", e);
+    }
+}
+
+/// The entries of the ledger at `path`, in the order they were first recorded.
+/// Where several lines share an id the last one wins. A missing ledger has no
+/// entries, and lines that can't be read (say, cut off by a crash) are skipped.
+///
+/// ```
+/// use llm::api::Usage;
+/// use llm::ledger::{self, Entry};
+///
+/// let path = std::env::temp_dir().join(format!("ledger-doc-{}.jsonl", std::process::id()));
+/// let usage = Usage { prompt_tokens: 10, completion_tokens: 5, ..Usage::default() };
+/// let mut first = Entry::new("openai/gpt-4o", &usage);
+/// ledger::record(&path, &first).unwrap();
+/// ledger::record(&path, &Entry::new("anthropic/claude-3.5-sonnet", &usage)).unwrap();
+/// // Recording an entry again corrects it.
+/// first.completion_tokens = 7;
+/// ledger::record(&path, &first).unwrap();
+///
+/// let entries = ledger::read(&path).unwrap();
+/// assert_eq!(entries.len(), 2);
+/// assert_eq!((entries[0].model.as_str(), entries[0].completion_tokens), ("openai/gpt-4o", 7));
+/// let totals = ledger::totals(&entries);
+/// assert_eq!(totals["anthropic/claude-3.5-sonnet"].total_tokens, 15);
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::file(path)(e)),
+    };
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut index = BTreeMap::new();
+    for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let entry: Entry = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("Skipping line {} of {}: {}", number + 1, path.display(), e);
+                continue;
+            }
+        };
+        match index.get(&entry.id) {
+            Some(&at) => entries[at] = entry,
+            None => {
+                index.insert(entry.id.clone(), entries.len());
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Tokens used per model, with the models in name order.
+pub fn totals(entries: &[Entry]) -> BTreeMap<String, Usage> {
+    let mut totals: BTreeMap<String, Usage> = BTreeMap::new();
+    for entry in entries {
+        totals.entry(entry.model.clone()).or_default().add(&Usage {
+            prompt_tokens: entry.prompt_tokens,
+            completion_tokens: entry.completion_tokens,
+            total_tokens: entry.prompt_tokens + entry.completion_tokens,
+            completion_tokens_details: None,
+        });
+    }
+    totals
+}
@@ -0,0 +1,137 @@
+//! Shared code for the chat front ends. The GUI lives in `src/main.rs` and the
+//! terminal client in `src/bin/cli_llm.rs`; both chat through a
+//! [`conversation::Conversation`].
+//!
+//! - [`alternation`]: merging consecutive messages of the same role
+//! - [`annotation`]: notes on settings changed mid-conversation
+//! - [`api`]: request and response types of the chat completions API
+//! - [`attachment`]: images attached to messages
+//! - [`backend`]: guarding against sending to another backend than before
+//! - [`bench`](mod@bench): measuring the latency and throughput of models
+//! - [`bidi`]: right-to-left text
+//! - [`boilerplate`]: hiding boilerplate repeated across replies
+//! - [`checkpoint`]: snapshots taken before history is rewritten
+//! - [`codediff`]: diffs between code blocks
+//! - [`completion`]: completing commands at the terminal prompt
+//! - [`config`]: the config file and connection profiles
+//! - [`conversation`]: the conversation, its history and its requests
+//! - [`credits`]: the credit balance
+//! - [`curl`]: the last request as a `curl` command
+//! - [`digest`]: summaries of saved conversations
+//! - [`doctor`]: checking the setup with `cli_llm doctor`
+//! - [`error`]: the error type
+//! - [`export`]: HTML export
+//! - [`filter`]: filters applied to replies
+//! - [`flatten`]: a conversation as one prompt, for base models
+//! - [`history`]: saved conversations
+//! - [`hyperlink`]: links in terminal output
+//! - [`incognito`]: sessions that write nothing to disk
+//! - [`instance`]: one GUI instance at a time
+//! - [`language`]: guessing the language of unlabeled code blocks
+//! - [`ledger`]: the tokens each reply used, across both front ends
+//! - [`limit`]: length limits on replies
+//! - [`lint`]: checking prompts for likely mistakes
+//! - [`locale`]: the date and locale sent with requests
+//! - [`markdown`]: Markdown in replies: quotes, lists and code blocks
+//! - [`models`]: the models list and context windows
+//! - [`output`]: writing replies to files
+//! - [`paste`]: shortening long pasted blocks and attached files
+//! - [`project`]: standing instructions from a project file
+//! - [`queue`]: the request queue shared by all running instances
+//! - [`rating`]: ratings of replies
+//! - [`recorder`]: the flight recorder of requests and replies
+//! - [`renderer`]: rendering diagrams and other blocks in replies
+//! - [`replace`]: replacing text across a conversation
+//! - [`retry`]: retrying failed requests
+//! - [`seam`]: joining continued replies onto cut-off ones
+//! - [`speech`]: reading replies aloud
+//! - [`storage`]: file handling that stays safe with several instances running
+//! - [`stream`]: streamed replies
+//! - [`structured`]: JSON and table detection
+//! - [`think`]: `<think>` reasoning in replies
+//! - [`timeout`]: request timeouts
+//! - [`tokenizer`]: counting tokens, and how full the context window is
+//! - [`tool`]: the shell tool
+//! - [`undo`]: undoing changes to a conversation
+//! - [`vault`]: encrypting saved conversations
+//! - [`voice`]: voice input
+
+pub mod alternation;
+pub mod annotation;
+pub mod api;
+pub mod attachment;
+pub mod backend;
+pub mod bench;
+pub mod bidi;
+pub mod boilerplate;
+pub mod checkpoint;
+pub mod codediff;
+pub mod completion;
+pub mod config;
+pub mod conversation;
+pub mod credits;
+pub mod curl;
+pub mod digest;
+pub mod doctor;
+pub mod error;
+pub mod export;
+pub mod filter;
+pub mod flatten;
+pub mod history;
+pub mod hyperlink;
+pub mod incognito;
+pub mod instance;
+pub mod language;
+pub mod ledger;
+pub mod limit;
+pub mod lint;
+pub mod locale;
+pub mod markdown;
+pub mod models;
+pub mod output;
+pub mod paste;
+pub mod project;
+pub mod queue;
+pub mod rating;
+pub mod recorder;
+pub mod renderer;
+pub mod replace;
+pub mod retry;
+pub mod seam;
+pub mod speech;
+pub mod storage;
+pub mod stream;
+pub mod structured;
+pub mod think;
+pub mod timeout;
+pub mod tokenizer;
+pub mod tool;
+pub mod undo;
+pub mod vault;
+pub mod voice;
+
+#[cfg(test)]
+mod testutil;
+
+pub use error::{Error, Result};
+
+/// Set up logging for the given verbosity. Default output only shows warnings and errors;
+/// `RUST_LOG` still takes precedence when set.
+///
+/// `binary` is the calling binary's crate name; its logs and this library's follow the
+/// requested level.
+pub fn init_logging(binary: &str, verbosity: u8) {
+    let level = match verbosity {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Warn)
+        .filter_module(env!("CARGO_CRATE_NAME"), level)
+        .filter_module(binary, level)
+        .parse_default_env()
+        .format_timestamp(None)
+        .init();
+}
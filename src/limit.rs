@@ -0,0 +1,255 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// What a [`Limit`] counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// Words outside code blocks; each Chinese or Japanese character counts as a word.
+    Words,
+    /// Characters outside code blocks.
+    Chars,
+    /// Characters on the first line, like a commit subject.
+    FirstLine,
+}
+
+/// A soft length limit for replies, such as "200 words" or "72 subject".
+///
+/// The limit is asked for with an instruction appended to the user message and
+/// checked locally once the reply arrives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub max: usize,
+    pub unit: Unit,
+}
+
+impl FromStr for Limit {
+    type Err = Error;
+
+    /// Parse `<N> words`, `<N> chars` or `<N> subject` (also `<N> first-line`).
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            Error::Config(format!(
+                "Invalid limit '{}': use e.g. \"200 words\", \"500 chars\" or \"72 subject\"",
+                text
+            ))
+        };
+        let text = text.trim();
+        let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let max: usize = text[..split].parse().map_err(|_| invalid())?;
+        let unit = match text[split..].trim().to_lowercase().as_str() {
+            "w" | "word" | "words" => Unit::Words,
+            "c" | "char" | "chars" | "character" | "characters" => Unit::Chars,
+            "subject" | "first-line" | "first line" => Unit::FirstLine,
+            _ => return Err(invalid()),
+        };
+        if max == 0 {
+            return Err(invalid());
+        }
+        Ok(Self { max, unit })
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            Unit::Words => "words",
+            Unit::Chars => "chars",
+            Unit::FirstLine => "subject",
+        };
+        write!(f, "{} {}", self.max, unit)
+    }
+}
+
+impl Limit {
+    /// The instruction appended to user messages.
+    pub fn instruction(&self) -> String {
+        match self.unit {
+            Unit::Words => format!("Keep your answer to at most {} words.", self.max),
+            Unit::Chars => format!("Keep your answer to at most {} characters.", self.max),
+            Unit::FirstLine => format!("Keep the first line of your answer to at most {} characters.", self.max),
+        }
+    }
+
+    /// `message` with the instruction appended.
+    pub fn apply(&self, message: &str) -> String {
+        format!("{}\n\n({})", message.trim_end(), self.instruction())
+    }
+
+    /// `message` without the instruction [`apply`](Self::apply) appended, if it
+    /// has it, so that it isn't asked for twice when the message is sent again.
+    pub fn strip<'a>(&self, message: &'a str) -> &'a str {
+        message
+            .strip_suffix(&format!("\n\n({})", self.instruction()))
+            .unwrap_or(message)
+    }
+
+    /// The size of `reply` in this limit's unit.
+    pub fn measure(&self, reply: &str) -> usize {
+        match self.unit {
+            Unit::Words => count_words(reply),
+            Unit::Chars => prose(reply).iter().map(|line| line.chars().count()).sum(),
+            Unit::FirstLine => first_line(reply).chars().count(),
+        }
+    }
+
+    /// The size of `reply` when it is over the limit.
+    pub fn exceeded_by(&self, reply: &str) -> Option<usize> {
+        Some(self.measure(reply)).filter(|&size| size > self.max)
+    }
+
+    /// The follow-up asking the model to shorten a reply measuring `size`.
+    pub fn tighten_request(&self, size: usize) -> String {
+        let (what, unit) = match self.unit {
+            Unit::Words => ("Your answer", "words"),
+            Unit::Chars => ("Your answer", "characters"),
+            Unit::FirstLine => ("The first line of your answer", "characters"),
+        };
+        format!(
+            "{} has {} {}, over the limit of {}. Rewrite it to fit and reply with the rewritten answer only.",
+            what, size, unit, self.max
+        )
+    }
+}
+
+/// Count the words of `text` outside fenced code blocks.
+///
+/// A word is a run of letters or digits, so punctuation and markdown marks don't
+/// count; `don't`, `co-op` and `3.14` are one word each. Combining accents and
+/// the vowel signs and viramas of Indic scripts belong to the word they are in.
+/// Chinese and Japanese, which are written without spaces, count one word per
+/// character; Thai and Lao, also written without spaces, count one word per run.
+pub fn count_words(text: &str) -> usize {
+    let mut count = 0;
+    for line in prose(text) {
+        for token in line.split_whitespace() {
+            let mut in_word = false;
+            let mut chars = token.chars().peekable();
+            let mut previous = ' ';
+            while let Some(c) = chars.next() {
+                let next = chars.peek().copied().unwrap_or(' ');
+                if is_cjk(c) {
+                    count += 1;
+                    in_word = false;
+                } else if c.is_alphanumeric() {
+                    if !in_word {
+                        count += 1;
+                        in_word = true;
+                    }
+                } else if in_word && is_mark(c) {
+                    // An accent or vowel sign on the letter before it.
+                } else if matches!(c, '.' | ',') && previous.is_ascii_digit() && next.is_ascii_digit() {
+                    // Part of a number such as 3.14 or 1,000.
+                } else if !matches!(c, '\'' | '’' | '-') {
+                    in_word = false;
+                }
+                previous = c;
+            }
+        }
+    }
+    count
+}
+
+/// The lines of `text` outside fenced code blocks, fenced with backticks or
+/// tildes. A block left open runs to the end.
+fn prose(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut fence: Option<&str> = None;
+    for line in text.lines() {
+        let marker = ["```", "~~~"].into_iter().find(|marker| line.trim_start().starts_with(marker));
+        match (fence, marker) {
+            (None, Some(marker)) => fence = Some(marker),
+            (Some(open), Some(marker)) if open == marker => fence = None,
+            (None, None) => lines.push(line),
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// The first non-empty line of `text`, trimmed.
+fn first_line(text: &str) -> &str {
+    text.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or_default()
+}
+
+/// Combining marks and joiners, which are part of the letter before them
+/// without counting as letters themselves.
+fn is_mark(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'   // Combining Diacritical Marks
+        | '\u{0900}'..='\u{0963}' // Devanagari, up to its danda
+        | '\u{0966}'..='\u{0DFF}' // The other Indic scripts
+        | '\u{0E00}'..='\u{0EFF}' // Thai, Lao
+        | '\u{1AB0}'..='\u{1AFF}' // Combining Diacritical Marks Extended
+        | '\u{1DC0}'..='\u{1DFF}' // Combining Diacritical Marks Supplement
+        | '\u{200C}' | '\u{200D}'  // Zero-width non-joiner and joiner
+        | '\u{20D0}'..='\u{20FF}' // Combining Diacritical Marks for Symbols
+        | '\u{FE20}'..='\u{FE2F}') // Combining Half Marks
+}
+
+/// Characters of scripts that don't separate words with spaces.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'   // Hiragana, Katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+        | '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+        | '\u{20000}'..='\u{2FA1F}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_in_latin_scripts() {
+        assert_eq!(count_words("The quick brown fox."), 4);
+        assert_eq!(count_words("Don't split co-op or 3.14, nor 1,000 or l’été."), 9);
+        assert_eq!(count_words("**Bold**, _italic_ and `code` — marks don't count"), 7);
+        assert_eq!(count_words("- one\n- two\n\n1. three"), 4);
+    }
+
+    #[test]
+    fn words_across_unicode() {
+        assert_eq!(count_words("café naïve résumé"), 3);
+        // The same words with their accents as separate combining characters.
+        assert_eq!(count_words("cafe\u{301} nai\u{308}ve re\u{301}sume\u{301}"), 3);
+        assert_eq!(count_words("Привет, как дела?"), 3);
+        assert_eq!(count_words("Γειά σου κόσμε"), 3);
+        assert_eq!(count_words("مرحبا بالعالم ١٢٣"), 3);
+        assert_eq!(count_words("नमस्ते दुनिया"), 2);
+        assert_eq!(count_words("안녕하세요 세계"), 2);
+        assert_eq!(count_words("こんにちは世界"), 7);
+        assert_eq!(count_words("你好，世界"), 4);
+        assert_eq!(count_words("Rust 很好"), 3);
+        assert_eq!(count_words("สวัสดีครับ"), 1);
+        assert_eq!(count_words("👍 🎉"), 0);
+    }
+
+    #[test]
+    fn code_blocks_are_not_counted() {
+        let reply = "Run this:\n```sh\ncargo build --release\n```\nThen ship it.";
+        assert_eq!(count_words(reply), 5);
+        let tildes = "Before\n~~~\nlet x = 1;\n```\nstill code\n~~~\nAfter";
+        assert_eq!(count_words(tildes), 2);
+        let indented = "List:\n  ```\n  code here\n  ```\ndone";
+        assert_eq!(count_words(indented), 2);
+        // A block cut off by the end of the reply runs to the end.
+        assert_eq!(count_words("Start\n```\nnever closed"), 1);
+    }
+
+    #[test]
+    fn characters_skip_code_blocks() {
+        let limit: Limit = "10 chars".parse().unwrap();
+        assert_eq!(limit.measure("héllo\n```\nlong code\n```\n世界"), 7);
+        assert_eq!(limit.exceeded_by("short"), None);
+        assert_eq!(limit.exceeded_by("a bit longer"), Some(12));
+    }
+
+    #[test]
+    fn first_line() {
+        let limit: Limit = "72 subject".parse().unwrap();
+        assert_eq!(limit.measure("\n  Fix the büild  \n\nBody text that is long."), 13);
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+
+/// A likely mistake in a prompt, reported before it is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintIssue {
+    /// A ``` code fence was opened on this (1-based) line but never closed.
+    UnclosedFence { line: usize },
+    /// A `{{...}}` template placeholder was left unfilled.
+    UnresolvedPlaceholder(String),
+    /// The code block opened on this (1-based) line looks cut off, e.g. when
+    /// pasting a file or a log lost its end; `hint` says why.
+    TruncatedAttachment { line: usize, hint: String },
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintIssue::UnclosedFence { line } => {
+                write!(f, "Code fence opened on line {} is never closed", line)
+            }
+            LintIssue::UnresolvedPlaceholder(name) => {
+                write!(f, "Placeholder {{{{{}}}}} was not filled in", name)
+            }
+            LintIssue::TruncatedAttachment { line, hint } => {
+                write!(f, "Code block on line {} looks cut off: {}", line, hint)
+            }
+        }
+    }
+}
+
+/// Check a prompt for unclosed code fences, unfilled `{{...}}` placeholders
+/// and code blocks that look cut off.
+///
+/// ```
+/// use llm::lint::{lint_prompt, LintIssue};
+///
+/// assert_eq!(lint_prompt("Why does this fail?\n```\nfn main() {}\n```"), []);
+/// assert_eq!(
+///     lint_prompt("Review {{file}}:\n```\nfn main() {\n```"),
+///     [
+///         LintIssue::UnresolvedPlaceholder("file".to_string()),
+///         LintIssue::TruncatedAttachment { line: 2, hint: "1 bracket left open".to_string() },
+///     ]
+/// );
+/// ```
+pub fn lint_prompt(text: &str) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+    if let Some(line) = unclosed_fence(text) {
+        issues.push(LintIssue::UnclosedFence { line });
+    }
+    for name in unresolved_placeholders(text) {
+        issues.push(LintIssue::UnresolvedPlaceholder(name));
+    }
+    for (line, block) in code_blocks(text) {
+        if let Some(hint) = truncation_hint(&block) {
+            issues.push(LintIssue::TruncatedAttachment { line, hint });
+        }
+    }
+    issues
+}
+
+/// Lines that tools and terminals put where they left text out.
+const TRUNCATION_MARKERS: &[&str] = &["...", "…", "[...]", "--more--", "(truncated)", "[truncated]", "<truncated>"];
+
+/// The closed code blocks of `text`, with the line each opens on.
+fn code_blocks(text: &str) -> Vec<(usize, Vec<&str>)> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(usize, Vec<&str>)> = None;
+    for (index, line) in text.lines().enumerate() {
+        if line.trim_start().starts_with("```") {
+            match open.take() {
+                Some(block) => blocks.push(block),
+                None => open = Some((index + 1, Vec::new())),
+            }
+        } else if let Some((_, lines)) = &mut open {
+            lines.push(line);
+        }
+    }
+    blocks
+}
+
+/// Why the code block made of `lines` looks cut off, if it does: its last line
+/// is a truncation marker, or it leaves brackets open.
+fn truncation_hint(lines: &[&str]) -> Option<String> {
+    let last = lines.iter().rev().map(|line| line.trim()).find(|line| !line.is_empty())?;
+    let lower = last.to_lowercase();
+    if TRUNCATION_MARKERS.contains(&lower.as_str()) || lower.contains("lines truncated") || lower.contains("more lines") {
+        return Some(format!("it ends with \"{}\"", last));
+    }
+    match open_brackets(lines) {
+        0 => None,
+        1 => Some("1 bracket left open".to_string()),
+        open => Some(format!("{} brackets left open", open)),
+    }
+}
+
+/// How many `(`, `[` and `{` of `lines` are still open at the end, not
+/// counting those in double-quoted strings. Stray closing brackets are
+/// ignored, so that an excerpt starting mid-function isn't reported.
+fn open_brackets(lines: &[&str]) -> usize {
+    let mut open = 0usize;
+    for line in lines {
+        let mut in_string = false;
+        let mut escaped = false;
+        for c in line.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_string => escaped = true,
+                '"' => in_string = !in_string,
+                _ if in_string => {}
+                '(' | '[' | '{' => open += 1,
+                ')' | ']' | '}' => open = open.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+    open
+}
+
+/// The line of the last ``` fence if the fences don't pair up.
+fn unclosed_fence(text: &str) -> Option<usize> {
+    let mut open = None;
+    for (index, line) in text.lines().enumerate() {
+        if line.trim_start().starts_with("```") {
+            open = match open {
+                Some(_) => None,
+                None => Some(index + 1),
+            };
+        }
+    }
+    open
+}
+
+/// Names of `{{...}}` placeholders, each reported once, in order of appearance.
+fn unresolved_placeholders(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let name = after[..end].trim();
+        // Only identifier-like names count; `{{ }}` in code samples is left alone.
+        let looks_like_placeholder = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        if looks_like_placeholder && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+        rest = &after[end + 2..];
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truncated(text: &str) -> Vec<String> {
+        lint_prompt(text)
+            .into_iter()
+            .filter_map(|issue| match issue {
+                LintIssue::TruncatedAttachment { hint, .. } => Some(hint),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unclosed_fence_is_reported_on_its_line() {
+        assert_eq!(lint_prompt("a\n```rust\nfn f() {}\n```\n```\nleft open"), [LintIssue::UnclosedFence { line: 5 }]);
+        assert_eq!(unclosed_fence("```\n```"), None);
+    }
+
+    #[test]
+    fn placeholders_are_reported_once_and_code_braces_are_not() {
+        assert_eq!(unresolved_placeholders("{{name}} and {{ name }} and {{other.key}}"), ["name", "other.key"]);
+        assert_eq!(unresolved_placeholders("let map = {{}}; format!(\"{{ {} }}\", x)"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn blocks_ending_with_a_marker_look_truncated() {
+        assert_eq!(truncated("```\nline 1\nline 2\n...\n```"), ["it ends with \"...\""]);
+        assert_eq!(truncated("```\nerror: boom\n[... 250 more lines]\n\n```"), ["it ends with \"[... 250 more lines]\""]);
+        assert_eq!(truncated("```\nlog\n<truncated>\n```"), ["it ends with \"<truncated>\""]);
+        // An ellipsis inside the block is just text.
+        assert_eq!(truncated("```\nlet rest = ...;\ndone()\n```"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn blocks_with_open_brackets_look_truncated() {
+        assert_eq!(truncated("```json\n{\"items\": [1, 2,\n```"), ["2 brackets left open"]);
+        assert_eq!(truncated("```\nfn main() {\n    println!(\"{\");\n}\n```"), Vec::<String>::new());
+        // An excerpt that starts mid-function closes more than it opens.
+        assert_eq!(truncated("```\n    }\n}\n```"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn only_closed_blocks_are_checked() {
+        assert_eq!(truncated("Outside a block {\n..."), Vec::<String>::new());
+        assert_eq!(lint_prompt("```\nfn main() {"), [LintIssue::UnclosedFence { line: 1 }]);
+    }
+}
@@ -0,0 +1,223 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::ChatMessageRequest;
+use crate::export::civil_date;
+
+/// Telling the model the date, and where the user is, with every request. Set
+/// in the `[locale_context]` section of the config file; off unless enabled.
+/// What isn't set is taken from the system.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct LocaleContext {
+    pub enabled: bool,
+    /// Only send the date, not the timezone, locale and units.
+    pub date_only: bool,
+    /// An IANA timezone such as `Europe/Berlin`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// A language tag such as `de-DE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub units: Option<Units>,
+}
+
+/// Measurement units the user expects.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    /// The units used where `locale` is from: imperial in the United States,
+    /// Liberia and Myanmar, metric everywhere else.
+    pub fn of(locale: &str) -> Self {
+        let region = locale.rsplit('-').next().unwrap_or_default();
+        match region.to_ascii_uppercase().as_str() {
+            "US" | "LR" | "MM" => Self::Imperial,
+            _ => Self::Metric,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Metric => "metric",
+            Self::Imperial => "imperial",
+        }
+    }
+}
+
+/// When and where the user is, as told to the model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Situation {
+    /// Today's date where the user is, as `YYYY-MM-DD`.
+    pub date: String,
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+    pub units: Option<Units>,
+}
+
+impl Situation {
+    /// The context line sent to the model; only the date when `date_only`.
+    ///
+    /// ```
+    /// use llm::locale::{Situation, Units};
+    ///
+    /// let berlin = Situation {
+    ///     date: "2024-06-07".to_string(),
+    ///     timezone: Some("Europe/Berlin".to_string()),
+    ///     locale: Some("de-DE".to_string()),
+    ///     units: Some(Units::of("de-DE")),
+    /// };
+    /// assert_eq!(
+    ///     berlin.line(false),
+    ///     "Current date: 2024-06-07, timezone: Europe/Berlin, locale: de-DE, units: metric"
+    /// );
+    /// assert_eq!(berlin.line(true), "Current date: 2024-06-07");
+    ///
+    /// let chicago = Situation {
+    ///     date: "2024-06-06".to_string(),
+    ///     timezone: Some("America/Chicago".to_string()),
+    ///     locale: Some("en-US".to_string()),
+    ///     units: Some(Units::of("en-US")),
+    /// };
+    /// assert_eq!(
+    ///     chicago.line(false),
+    ///     "Current date: 2024-06-06, timezone: America/Chicago, locale: en-US, units: imperial"
+    /// );
+    ///
+    /// let unknown = Situation { date: "2024-06-07".to_string(), timezone: None, locale: None, units: None };
+    /// assert_eq!(unknown.line(false), "Current date: 2024-06-07");
+    /// ```
+    pub fn line(&self, date_only: bool) -> String {
+        let mut line = format!("Current date: {}", self.date);
+        if date_only {
+            return line;
+        }
+        if let Some(timezone) = &self.timezone {
+            line.push_str(&format!(", timezone: {}", timezone));
+        }
+        if let Some(locale) = &self.locale {
+            line.push_str(&format!(", locale: {}", locale));
+        }
+        if let Some(units) = self.units {
+            line.push_str(&format!(", units: {}", units.name()));
+        }
+        line
+    }
+}
+
+impl LocaleContext {
+    /// Where the user is now, from the settings and the system.
+    pub fn situation(&self) -> Situation {
+        let timezone = self.timezone.clone().or_else(system_timezone);
+        let offset = utc_offset(self.timezone.as_deref());
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let local = secs.saturating_add_signed(offset.unwrap_or(0));
+        let (year, month, day) = civil_date(local);
+        let locale = self.locale.clone().or_else(system_locale);
+        Situation {
+            date: format!("{:04}-{:02}-{:02}", year, month, day),
+            // Without a name, the offset still tells the model the time of day to expect.
+            timezone: timezone.or_else(|| offset.map(format_offset)),
+            units: self.units.or_else(|| locale.as_deref().map(Units::of)),
+            locale,
+        }
+    }
+
+    /// The line as a system message, refreshed every time it is asked for.
+    pub fn message(&self) -> ChatMessageRequest {
+        ChatMessageRequest::new("system", self.situation().line(self.date_only))
+    }
+}
+
+/// A POSIX locale such as `de_DE.UTF-8@euro` as a language tag such as `de-DE`;
+/// `None` for the `C` and `POSIX` locales.
+///
+/// ```
+/// use llm::locale::language_tag;
+///
+/// assert_eq!(language_tag("de_DE.UTF-8").as_deref(), Some("de-DE"));
+/// assert_eq!(language_tag("fr_CA@euro").as_deref(), Some("fr-CA"));
+/// assert_eq!(language_tag("en-GB").as_deref(), Some("en-GB"));
+/// assert_eq!(language_tag("C.UTF-8"), None);
+/// ```
+pub fn language_tag(locale: &str) -> Option<String> {
+    let name = locale.split(['.', '@']).next().unwrap_or_default().trim();
+    if name.is_empty() || name == "C" || name == "POSIX" {
+        return None;
+    }
+    Some(name.replace('_', "-"))
+}
+
+/// The user's locale from `LC_ALL`, `LC_MESSAGES` or `LANG`.
+fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| language_tag(&value))
+}
+
+/// The system's IANA timezone: `TZ`, `/etc/timezone`, or where `/etc/localtime`
+/// links to.
+fn system_timezone() -> Option<String> {
+    if let Ok(tz) = env::var("TZ") {
+        let tz = tz.trim_start_matches(':');
+        if tz.contains('/') || tz == "UTC" {
+            return Some(tz.trim_start_matches("/usr/share/zoneinfo/").to_string());
+        }
+    }
+    if let Ok(name) = fs::read_to_string("/etc/timezone")
+        && !name.trim().is_empty()
+    {
+        return Some(name.trim().to_string());
+    }
+    let target = fs::read_link("/etc/localtime").ok()?;
+    let target = target.to_string_lossy();
+    target.split_once("zoneinfo/").map(|(_, name)| name.to_string())
+}
+
+/// Seconds east of UTC in `timezone`, or the system's timezone, as `date`
+/// reports it; `None` where there is no `date` to ask.
+fn utc_offset(timezone: Option<&str>) -> Option<i64> {
+    if !cfg!(unix) {
+        return None;
+    }
+    let mut command = Command::new("date");
+    command.arg("+%z");
+    if let Some(timezone) = timezone {
+        command.env("TZ", timezone);
+    }
+    let output = command.output().ok().filter(|output| output.status.success())?;
+    parse_offset(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+/// `+0200` or `-0530` in seconds.
+fn parse_offset(text: &str) -> Option<i64> {
+    let (sign, digits) = match text.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// An offset from UTC in seconds as `UTC+02:00`.
+fn format_offset(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    format!("UTC{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
@@ -0,0 +1,187 @@
+use crate::language;
+
+/// The text of a `>` blockquote line, or `None` for other lines.
+///
+/// ```
+/// use llm::markdown::quote_text;
+///
+/// assert_eq!(quote_text("> Quoted"), Some("Quoted"));
+/// assert_eq!(quote_text("  >> nested"), Some("> nested"));
+/// assert_eq!(quote_text("a > b"), None);
+/// ```
+pub fn quote_text(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('>')?;
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+/// The start of a list item: `-`, `*` or `+` for bullets, `1.` or `1)` for
+/// numbered items, followed by a space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Marker<'a> {
+    /// Columns before the marker, with tabs counted as four.
+    pub indent: usize,
+    /// The number of a numbered item, as written (`"3."`); `None` for bullets.
+    pub number: Option<&'a str>,
+    /// The item's text after the marker.
+    pub text: &'a str,
+}
+
+/// The list item `line` starts, if it starts one.
+///
+/// ```
+/// use llm::markdown::marker;
+///
+/// let item = marker("  - nested").unwrap();
+/// assert_eq!((item.indent, item.number, item.text), (2, None, "nested"));
+/// assert_eq!(marker("12. twelfth").unwrap().number, Some("12."));
+/// assert!(marker("**bold** text").is_none());
+/// assert!(marker("-1 degrees").is_none());
+/// ```
+pub fn marker(line: &str) -> Option<Marker<'_>> {
+    let body = line.trim_start();
+    let indent = line[..line.len() - body.len()]
+        .chars()
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum();
+    let digits = body.chars().take_while(char::is_ascii_digit).count();
+    let (number, rest) = match body.chars().next()? {
+        '-' | '*' | '+' => (None, &body[1..]),
+        _ if (1..=9).contains(&digits) && body[digits..].starts_with(['.', ')']) => {
+            (Some(&body[..=digits]), &body[digits + 1..])
+        }
+        _ => return None,
+    };
+    let text = rest.strip_prefix(' ')?;
+    Some(Marker {
+        indent,
+        number,
+        text: text.trim(),
+    })
+}
+
+/// One item of a list, with its place in the nesting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// 0 for top-level items, 1 for items nested under them, and so on.
+    pub level: usize,
+    /// The number of a numbered item, as written (`"3."`); `None` for bullets.
+    pub number: Option<String>,
+    /// The item's paragraphs, each with its lines joined by spaces.
+    pub paragraphs: Vec<String>,
+}
+
+/// The list at the start of `lines`, and how many lines it takes up.
+///
+/// A line that doesn't start an item continues the item before it, unless a
+/// blank line came between and it isn't indented past that item's marker.
+/// Lines starting a heading or a blockquote end the list.
+///
+/// ```
+/// use llm::markdown::list;
+///
+/// let text = ["1. First step,", "   split over lines.", "   - a detail", "2. Second", "", "After the list"];
+/// let (items, used) = list(&text);
+/// assert_eq!(used, 4);
+/// assert_eq!(items[0].paragraphs, ["First step, split over lines."]);
+/// assert_eq!((items[1].level, items[1].number.as_deref()), (1, None));
+/// assert_eq!((items[2].level, items[2].number.as_deref()), (0, Some("2.")));
+/// ```
+pub fn list(lines: &[&str]) -> (Vec<Item>, usize) {
+    let mut items: Vec<Item> = Vec::new();
+    // Marker indents of the items the current one is nested in, and of itself.
+    let mut indents: Vec<usize> = Vec::new();
+    let mut used = 0;
+    let mut after_blank = false;
+    for (i, &line) in lines.iter().enumerate() {
+        if let Some(marker) = marker(line) {
+            while indents.last().is_some_and(|&indent| indent > marker.indent) {
+                indents.pop();
+            }
+            if indents.last().is_none_or(|&indent| indent < marker.indent) {
+                indents.push(marker.indent);
+            }
+            items.push(Item {
+                level: indents.len() - 1,
+                number: marker.number.map(str::to_string),
+                paragraphs: vec![marker.text.to_string()],
+            });
+        } else if line.trim().is_empty() {
+            after_blank = true;
+            continue;
+        } else {
+            let Some(item) = items.last_mut() else {
+                break;
+            };
+            let body = line.trim_start();
+            let indented = line.len() - body.len() > indents.last().copied().unwrap_or(0);
+            let new_block = body.starts_with('#') || body.starts_with('>');
+            if after_blank && indented {
+                item.paragraphs.push(body.trim_end().to_string());
+            } else if !after_blank && !new_block {
+                let paragraph = item.paragraphs.last_mut().expect("items start with a paragraph");
+                paragraph.push(' ');
+                paragraph.push_str(body.trim_end());
+            } else {
+                break;
+            }
+        }
+        after_blank = false;
+        used = i + 1;
+    }
+    (items, used)
+}
+
+/// A fenced code block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// The language named after the opening fence, lowercased. When none is,
+    /// the language [guessed](language::guess) from the code, or empty.
+    pub language: String,
+    /// Whether `language` was guessed rather than named.
+    pub guessed: bool,
+    /// The lines between the fences, each ending in a line break.
+    pub code: String,
+}
+
+/// The fenced code blocks in `text`, in order. A block left open runs to the end.
+///
+/// ```
+/// use llm::markdown::code_blocks;
+///
+/// let blocks = code_blocks("Try this:\n```Rust\nfn main() {}\n```\nor\n```\nls\n```\n\
+///                           ```\n#!/bin/sh\nls\n");
+/// assert_eq!((blocks[0].language.as_str(), blocks[0].code.as_str()), ("rust", "fn main() {}\n"));
+/// assert_eq!((blocks[1].language.as_str(), blocks[1].code.as_str()), ("", "ls\n"));
+/// assert_eq!((blocks[2].language.as_str(), blocks[2].guessed), ("bash", true));
+/// ```
+pub fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut open: Option<CodeBlock> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let fence = trimmed.strip_prefix("```").or_else(|| trimmed.strip_prefix("~~~"));
+        match (fence, &mut open) {
+            (Some(_), Some(_)) => blocks.extend(open.take()),
+            (Some(info), None) => {
+                open = Some(CodeBlock {
+                    language: info.split_whitespace().next().unwrap_or("").to_lowercase(),
+                    code: String::new(),
+                    guessed: false,
+                })
+            }
+            (None, Some(block)) => {
+                block.code.push_str(line);
+                block.code.push('\n');
+            }
+            (None, None) => {}
+        }
+    }
+    blocks.extend(open);
+    for block in blocks.iter_mut().filter(|block| block.language.is_empty()) {
+        if let Some(language) = language::guess(&block.code) {
+            block.language = language.to_string();
+            block.guessed = true;
+        }
+    }
+    blocks
+}
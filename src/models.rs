@@ -0,0 +1,430 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{self, ChatMessageRequest, OpenRouterChatRequest};
+use crate::error::{Error, Result};
+use crate::timeout;
+use crate::{incognito, storage};
+
+/// Model name that picks the best free model currently answering.
+pub const AUTO_FREE: &str = "auto:free";
+
+/// How long a cached models list counts as fresh. An older one is still used,
+/// and refreshed in the background when a model is selected.
+pub const CACHE_FRESH: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Context windows of a few popular models, used until a models list has
+/// been fetched, e.g. offline.
+const KNOWN_CONTEXT: [(&str, u64); 8] = [
+    ("anthropic/claude-3.5-sonnet", 200_000),
+    ("anthropic/claude-3-5-sonnet", 200_000),
+    ("deepseek/deepseek-chat-v3-0324", 163_840),
+    ("google/gemini-pro", 32_768),
+    ("meta-llama/llama-3.1-8b-instruct", 131_072),
+    ("mistralai/mistral-7b-instruct", 32_768),
+    ("openai/gpt-4o", 128_000),
+    ("openai/gpt-4o-mini", 128_000),
+];
+
+/// The models list of this process, kept in sync with the cache file.
+static CACHED: Mutex<Option<Cached>> = Mutex::new(None);
+
+/// Set while a background refresh runs, so selecting models quickly starts one.
+static REFRESHING: AtomicBool = AtomicBool::new(false);
+
+struct Cached {
+    models: Vec<ModelInfo>,
+    fetched: SystemTime,
+}
+
+/// Metadata about one model offered by the endpoint's `/models` list.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModelInfo {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub context_length: Option<u64>,
+    #[serde(default)]
+    pub pricing: Option<Pricing>,
+}
+
+impl ModelInfo {
+    /// Whether the model costs nothing to use (a `:free` variant or zero pricing).
+    pub fn is_free(&self) -> bool {
+        if self.id.ends_with(":free") {
+            return true;
+        }
+        let zero = |price: &str| price.parse::<f64>().is_ok_and(|p| p == 0.0);
+        self.pricing
+            .as_ref()
+            .is_some_and(|p| zero(&p.prompt) && zero(&p.completion))
+    }
+}
+
+/// Per-token prices, as decimal strings in USD.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pricing {
+    #[serde(default)]
+    pub prompt: String,
+    #[serde(default)]
+    pub completion: String,
+}
+
+/// The `/models` response body.
+#[derive(Deserialize)]
+struct ModelsResponse {
+    data: Vec<ModelInfo>,
+}
+
+/// Derive the `/models` URL from the chat completions URL.
+pub fn models_url(chat_url: &str) -> String {
+    match chat_url.strip_suffix("/chat/completions") {
+        Some(base) => format!("{}/models", base),
+        None => format!("{}/models", chat_url.trim_end_matches('/')),
+    }
+}
+
+/// Fetch the list of available models.
+pub async fn fetch_models(
+    client: &reqwest::Client,
+    chat_url: &str,
+    headers: &HeaderMap,
+) -> Result<Vec<ModelInfo>> {
+    let url = models_url(chat_url);
+    log::debug!("GET {}", url);
+    let response = client.get(&url).headers(headers.clone()).send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(Error::from_response(status, &body));
+    }
+    let models: ModelsResponse = serde_json::from_str(&body)?;
+    log::debug!("Fetched {} models", models.data.len());
+    Ok(models.data)
+}
+
+/// Where the models list is cached between runs.
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("cli_llm").join("models.json"))
+}
+
+/// Load the cached models list, if there is one.
+pub fn load_cache() -> Option<Vec<ModelInfo>> {
+    let path = cache_path()?;
+    let data = fs::read(&path).ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(models) => Some(models),
+        Err(e) => {
+            log::warn!("Ignoring unreadable models cache {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Store the models list for the next start, unless in incognito mode, and for
+/// [`metadata`] lookups now.
+pub fn save_cache(models: &[ModelInfo]) {
+    *CACHED.lock().unwrap_or_else(|e| e.into_inner()) = Some(Cached {
+        models: models.to_vec(),
+        fetched: SystemTime::now(),
+    });
+    let Some(path) = cache_path().filter(|_| !incognito::is_on()) else {
+        return;
+    };
+    let data = serde_json::to_vec(models).unwrap_or_default();
+    if let Err(e) = storage::write_atomic(&path, data) {
+        log::warn!("Could not write models cache {}: {}", path.display(), e);
+    }
+}
+
+/// Where a model's [`Metadata`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    /// A models list fetched within [`CACHE_FRESH`].
+    Fresh,
+    /// An older models list.
+    Stale,
+    /// The built-in table of popular models; only the context length is known.
+    Builtin,
+}
+
+/// What is known about a model without asking the endpoint.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub info: ModelInfo,
+    pub freshness: Freshness,
+    /// How long ago the models list was fetched.
+    pub age: Option<Duration>,
+}
+
+impl Metadata {
+    /// Where this came from and how old it is, e.g. "cached 3 h ago".
+    pub fn source(&self) -> String {
+        let Some(age) = self.age else {
+            return "built-in estimate; no models list cached".to_string();
+        };
+        let age = match age.as_secs() {
+            secs @ 0..3600 => format!("{} min", secs / 60),
+            secs @ 3600..86_400 => format!("{} h", secs / 3600),
+            secs => format!("{} d", secs / 86_400),
+        };
+        match self.freshness {
+            Freshness::Stale => format!("cached {} ago, stale", age),
+            _ => format!("cached {} ago", age),
+        }
+    }
+}
+
+/// Run `f` on the cached models list and when it was fetched, reading the
+/// cache file on first use.
+fn with_cached<T>(f: impl FnOnce(Option<&Cached>) -> T) -> T {
+    let mut cached = CACHED.lock().unwrap_or_else(|e| e.into_inner());
+    if cached.is_none() {
+        let fetched = cache_path().and_then(|path| fs::metadata(path).ok()?.modified().ok());
+        *cached = load_cache()
+            .zip(fetched)
+            .map(|(models, fetched)| Cached { models, fetched });
+    }
+    f(cached.as_ref())
+}
+
+/// Cached metadata for `model` (its `:online` variant included), falling back
+/// to the built-in table of popular models.
+pub fn metadata(model: &str) -> Option<Metadata> {
+    let id = model.strip_suffix(":online").unwrap_or(model);
+    let cached = with_cached(|cached| {
+        let cached = cached?;
+        let info = cached.models.iter().find(|info| info.id == id)?.clone();
+        let age = cached.fetched.elapsed().unwrap_or_default();
+        let freshness = if age < CACHE_FRESH { Freshness::Fresh } else { Freshness::Stale };
+        Some(Metadata {
+            info,
+            freshness,
+            age: Some(age),
+        })
+    });
+    cached.or_else(|| {
+        let base = id.split(':').next().unwrap_or(id);
+        let &(_, context) = KNOWN_CONTEXT.iter().find(|(known, _)| *known == base)?;
+        Some(Metadata {
+            info: ModelInfo {
+                id: id.to_string(),
+                name: None,
+                context_length: Some(context),
+                pricing: None,
+            },
+            freshness: Freshness::Builtin,
+            age: None,
+        })
+    })
+}
+
+/// The context window of `model`, in tokens, when known.
+pub fn context_length(model: &str) -> Option<u64> {
+    metadata(model).and_then(|metadata| metadata.info.context_length)
+}
+
+/// Make sure [`metadata`] will know `model`: unless the cached models list is
+/// fresh and has it, fetch a new one on a background thread. Lookups keep
+/// answering from the old list meanwhile.
+pub fn prefetch(chat_url: &str, headers: &HeaderMap, model: &str) {
+    if model == AUTO_FREE || metadata(model).is_some_and(|metadata| metadata.freshness == Freshness::Fresh) {
+        return;
+    }
+    if REFRESHING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    log::debug!("Refreshing the models list for {}", model);
+    let (chat_url, headers) = (chat_url.to_string(), headers.clone());
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build();
+        match runtime {
+            Ok(runtime) => {
+                let client = reqwest::Client::new();
+                let fetch = fetch_models(&client, &chat_url, &headers);
+                match runtime.block_on(timeout::within(timeout::get().request, "no models list", fetch)) {
+                    Ok(models) => save_cache(&models),
+                    Err(e) => log::info!("Could not refresh the models list: {}", e),
+                }
+            }
+            Err(e) => log::warn!("Cannot start async runtime: {}", e),
+        }
+        REFRESHING.store(false, Ordering::SeqCst);
+    });
+}
+
+/// The free models in `models`, in the order `auto:free` tries them.
+///
+/// `preference` lists model ids or id prefixes (e.g. `meta-llama/`), best first;
+/// a model ranks by the first entry that equals or prefixes its id, and models
+/// matching no entry come last. Ties go to the larger context window, then to the
+/// id, so the order never depends on how the endpoint sorted its list.
+pub fn rank_free<'a>(models: &'a [ModelInfo], preference: &[String]) -> Vec<&'a ModelInfo> {
+    let rank = |model: &ModelInfo| {
+        preference
+            .iter()
+            .position(|wanted| model.id.starts_with(wanted.as_str()))
+            .unwrap_or(preference.len())
+    };
+    let mut free: Vec<&ModelInfo> = models.iter().filter(|m| m.is_free()).collect();
+    free.sort_by(|a, b| {
+        rank(a)
+            .cmp(&rank(b))
+            .then(b.context_length.unwrap_or(0).cmp(&a.context_length.unwrap_or(0)))
+            .then(a.id.cmp(&b.id))
+    });
+    free
+}
+
+/// Check that `model` answers by asking it for a single token.
+pub async fn ping(client: &reqwest::Client, chat_url: &str, headers: &HeaderMap, model: &str) -> Result<()> {
+    let mut body = OpenRouterChatRequest::new(model.to_string(), vec![ChatMessageRequest::new("user", "ping")]);
+    body.max_tokens = Some(1);
+    api::fetch_reply(client, chat_url, headers, &body).await.map(|_| ())
+}
+
+/// Resolve `auto:free`: try the free models in [`rank_free`] order and return the
+/// first one that answers a [`ping`].
+pub async fn select_free(
+    client: &reqwest::Client,
+    chat_url: &str,
+    headers: &HeaderMap,
+    models: &[ModelInfo],
+    preference: &[String],
+) -> Result<String> {
+    let candidates = rank_free(models, preference);
+    if candidates.is_empty() {
+        return Err(Error::Config("No free models in the models list".to_string()));
+    }
+    for model in &candidates {
+        match ping(client, chat_url, headers, &model.id).await {
+            Ok(()) => return Ok(model.id.clone()),
+            Err(e) => log::warn!("Free model {} is not answering: {}", model.id, e),
+        }
+    }
+    Err(Error::Config(format!("None of the {} free models answered", candidates.len())))
+}
+
+/// Pick a replacement when `wanted` is not among `models`.
+///
+/// Prefers a free model from the same vendor (the part of the id before `/`), then
+/// any free model, taking the one with the largest context window in each case.
+pub fn pick_fallback<'a>(models: &'a [ModelInfo], wanted: &str) -> Option<&'a ModelInfo> {
+    let vendor = wanted.split('/').next().unwrap_or_default();
+    let largest = |candidates: Vec<&'a ModelInfo>| {
+        candidates
+            .into_iter()
+            .max_by_key(|m| m.context_length.unwrap_or(0))
+    };
+    let free: Vec<&ModelInfo> = models.iter().filter(|m| m.is_free()).collect();
+    let same_vendor = free
+        .iter()
+        .copied()
+        .filter(|m| m.id.split('/').next() == Some(vendor))
+        .collect();
+    largest(same_vendor).or_else(|| largest(free))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(id: &str, context_length: Option<u64>) -> ModelInfo {
+        ModelInfo {
+            id: id.to_string(),
+            name: None,
+            context_length,
+            pricing: None,
+        }
+    }
+
+    fn priced(id: &str, prompt: &str, completion: &str) -> ModelInfo {
+        ModelInfo {
+            pricing: Some(Pricing {
+                prompt: prompt.to_string(),
+                completion: completion.to_string(),
+            }),
+            ..model(id, None)
+        }
+    }
+
+    fn ids(models: Vec<&ModelInfo>) -> Vec<&str> {
+        models.into_iter().map(|model| model.id.as_str()).collect()
+    }
+
+    fn preference(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn free_models() {
+        assert!(model("meta-llama/llama-3-8b:free", None).is_free());
+        assert!(priced("some/model", "0", "0.0").is_free());
+        assert!(!priced("some/model", "0", "0.000002").is_free());
+        assert!(!priced("some/model", "", "").is_free());
+        assert!(!model("openai/gpt-4o", None).is_free());
+        // Only the suffix counts.
+        assert!(!model("vendor/free-model", None).is_free());
+    }
+
+    #[test]
+    fn only_free_models_are_ranked() {
+        let models = [model("openai/gpt-4o", Some(128_000)), model("a/small:free", Some(4_000))];
+        assert_eq!(ids(rank_free(&models, &[])), ["a/small:free"]);
+        assert!(rank_free(&[model("openai/gpt-4o", None)], &[]).is_empty());
+    }
+
+    #[test]
+    fn preference_order_wins() {
+        let models = [
+            model("mistralai/mistral-7b:free", Some(32_000)),
+            model("google/gemma-2-9b:free", Some(8_000)),
+            model("meta-llama/llama-3-8b:free", Some(8_000)),
+            model("qwen/qwen-2-7b:free", Some(128_000)),
+        ];
+        let preference = preference(&["google/gemma-2-9b:free", "meta-llama/"]);
+        assert_eq!(
+            ids(rank_free(&models, &preference)),
+            [
+                "google/gemma-2-9b:free",
+                "meta-llama/llama-3-8b:free",
+                // Matching no entry: the larger context first.
+                "qwen/qwen-2-7b:free",
+                "mistralai/mistral-7b:free",
+            ]
+        );
+    }
+
+    #[test]
+    fn first_matching_entry_ranks() {
+        let models = [model("meta-llama/llama-3-70b:free", None), model("google/gemma-2-9b:free", None)];
+        // The prefix entry comes before the exact id, so the llama model ranks first.
+        let preference = preference(&["meta-llama/", "google/gemma-2-9b:free", "meta-llama/llama-3-70b:free"]);
+        assert_eq!(
+            ids(rank_free(&models, &preference)),
+            ["meta-llama/llama-3-70b:free", "google/gemma-2-9b:free"]
+        );
+    }
+
+    #[test]
+    fn ties_go_to_context_then_id() {
+        let models = [
+            model("b/model:free", None),
+            model("c/model:free", Some(8_000)),
+            model("a/model:free", Some(8_000)),
+            model("d/model:free", Some(32_000)),
+        ];
+        let ranked = ids(rank_free(&models, &preference(&["x/"])));
+        assert_eq!(ranked, ["d/model:free", "a/model:free", "c/model:free", "b/model:free"]);
+        // However the endpoint sorted its list.
+        let reversed: Vec<ModelInfo> = models.iter().rev().cloned().collect();
+        assert_eq!(ids(rank_free(&reversed, &preference(&["x/"]))), ranked);
+    }
+}
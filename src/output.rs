@@ -0,0 +1,118 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::{export, incognito, storage};
+
+/// The file name for `--output-file` `template`, with `{timestamp}` replaced by
+/// the UTC time `secs` since the Unix epoch (`2026-10-16-142530`) and `{model}`
+/// by the model's ID, made safe for file names.
+///
+/// ```
+/// use llm::output::expand_path;
+///
+/// let path = expand_path("answers/{model}-{timestamp}.md", "openai/gpt-4o:online", 1_760_624_730);
+/// assert_eq!(path.to_str(), Some("answers/openai-gpt-4o-online-2025-10-16-142530.md"));
+/// ```
+pub fn expand_path(template: &str, model: &str, secs: u64) -> PathBuf {
+    let (year, month, day) = export::civil_date(secs);
+    let seconds = secs % 86_400;
+    let timestamp = format!(
+        "{:04}-{:02}-{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60
+    );
+    let model: String = model
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '-' })
+        .collect();
+    PathBuf::from(template.replace("{timestamp}", &timestamp).replace("{model}", &model))
+}
+
+/// A file replies are written to instead of the terminal, flushed after every
+/// write so that a streamed reply shows up in it as it arrives.
+#[derive(Debug)]
+pub struct Output {
+    file: File,
+    path: PathBuf,
+    /// Start the next write on a new line: the file appended to doesn't end one.
+    separate: bool,
+    /// Whether what was written last ends a line.
+    at_line_start: bool,
+    /// Where the reply being written starts, and what [`separate`](Self::separate)
+    /// was there.
+    reply_start: (u64, bool),
+}
+
+impl Output {
+    /// Open `path`, emptying it first unless `append`. Appending to a file that
+    /// doesn't end a line starts on a new one.
+    pub fn open(path: &Path, append: bool) -> Result<Self> {
+        incognito::refuse("Writing replies to a file")?;
+        let file = storage::open(path, OpenOptions::new().create(true).write(true).append(append).truncate(!append))
+            .map_err(Error::file(path))?;
+        let separate = append && !ends_line(path);
+        let len = file.metadata().map_err(Error::file(path))?.len();
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            separate,
+            at_line_start: true,
+            reply_start: (len, separate),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `text` and flush it.
+    pub fn write(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        if std::mem::take(&mut self.separate) {
+            self.file.write_all(b"\n").map_err(Error::file(&self.path))?;
+        }
+        self.file.write_all(text.as_bytes()).map_err(Error::file(&self.path))?;
+        self.file.flush().map_err(Error::file(&self.path))?;
+        self.at_line_start = text.ends_with('\n');
+        Ok(())
+    }
+
+    /// End a reply, with a line break unless it ends in one.
+    pub fn end(&mut self) -> Result<()> {
+        if !self.at_line_start {
+            self.write("\n")?;
+        }
+        let position = self.file.stream_position().map_err(Error::file(&self.path))?;
+        self.reply_start = (position, false);
+        Ok(())
+    }
+
+    /// Take back what was written of a reply that failed or was cancelled, so
+    /// the file only holds complete ones.
+    pub fn discard(&mut self) -> Result<()> {
+        let (start, separate) = self.reply_start;
+        self.file.set_len(start).map_err(Error::file(&self.path))?;
+        self.file.seek(SeekFrom::Start(start)).map_err(Error::file(&self.path))?;
+        self.separate = separate;
+        self.at_line_start = true;
+        Ok(())
+    }
+}
+
+/// Whether the file at `path` is empty or ends a line.
+fn ends_line(path: &Path) -> bool {
+    let mut last = [b'\n'];
+    let _ = File::open(path).and_then(|mut file| {
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)
+    });
+    last[0] == b'\n'
+}
@@ -0,0 +1,138 @@
+use eframe::egui;
+use egui::{Color32, Stroke, Visuals};
+
+/// Colours of the chat view: light or dark, each with a high-contrast variant.
+///
+/// The high-contrast variants keep text at 15:1 or more against the bubbles and
+/// secondary text at 7:1 or more (WCAG AAA), with solid outlines instead of faint
+/// grey ones.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub dark: bool,
+    pub high_contrast: bool,
+    /// Fill of the user's bubbles.
+    pub user: Color32,
+    /// Fill of the assistant's bubbles and the typing indicator.
+    pub assistant: Color32,
+    /// Text in bubbles.
+    pub text: Color32,
+    /// Secondary text: hints, notes, queued and excluded messages.
+    pub muted: Color32,
+    /// Outline of bubbles, code blocks and the input area.
+    pub stroke: Stroke,
+    /// Fill of code blocks.
+    pub code: Color32,
+    /// Fill of the input area.
+    pub input: Color32,
+    /// Fill of the Send button.
+    pub accent: Color32,
+}
+
+impl Palette {
+    pub fn new(dark: bool, high_contrast: bool) -> Self {
+        match (dark, high_contrast) {
+            (false, false) => Self {
+                dark,
+                high_contrast,
+                user: Color32::from_rgb(217, 234, 251),
+                assistant: Color32::from_rgb(245, 245, 245),
+                text: Color32::BLACK,
+                muted: Color32::from_gray(150),
+                stroke: Stroke::new(1.0, Color32::from_gray(200)),
+                code: Color32::from_rgb(245, 245, 245),
+                input: Color32::from_rgb(250, 250, 250),
+                accent: Color32::from_rgb(79, 70, 229),
+            },
+            (true, false) => Self {
+                dark,
+                high_contrast,
+                user: Color32::from_rgb(44, 51, 73),
+                assistant: Color32::from_rgb(55, 59, 70),
+                text: Color32::WHITE,
+                muted: Color32::from_gray(150),
+                stroke: Stroke::new(1.0, Color32::from_gray(200)),
+                code: Color32::from_rgb(40, 44, 52),
+                input: Color32::from_rgb(30, 33, 40),
+                accent: Color32::from_rgb(75, 85, 99),
+            },
+            (false, true) => Self {
+                dark,
+                high_contrast,
+                user: Color32::from_rgb(225, 236, 255),
+                assistant: Color32::WHITE,
+                text: Color32::BLACK,
+                muted: Color32::from_gray(80),
+                stroke: Stroke::new(2.0, Color32::BLACK),
+                code: Color32::from_gray(242),
+                input: Color32::WHITE,
+                accent: Color32::from_rgb(40, 30, 160),
+            },
+            (true, true) => Self {
+                dark,
+                high_contrast,
+                user: Color32::from_rgb(0, 30, 70),
+                assistant: Color32::BLACK,
+                text: Color32::WHITE,
+                muted: Color32::from_gray(190),
+                stroke: Stroke::new(2.0, Color32::WHITE),
+                code: Color32::from_gray(20),
+                input: Color32::BLACK,
+                accent: Color32::from_rgb(0, 70, 170),
+            },
+        }
+    }
+
+    /// Background of removed lines in diffs, or of the words that changed in
+    /// them when `strong`.
+    pub fn removed(&self, strong: bool) -> Color32 {
+        match (self.dark, strong) {
+            (false, false) => Color32::from_rgb(255, 235, 233),
+            (false, true) => Color32::from_rgb(255, 192, 188),
+            (true, false) => Color32::from_rgb(72, 36, 40),
+            (true, true) => Color32::from_rgb(130, 45, 50),
+        }
+    }
+
+    /// Background of added lines in diffs, or of the words that changed in
+    /// them when `strong`.
+    pub fn added(&self, strong: bool) -> Color32 {
+        match (self.dark, strong) {
+            (false, false) => Color32::from_rgb(230, 255, 236),
+            (false, true) => Color32::from_rgb(172, 238, 187),
+            (true, false) => Color32::from_rgb(30, 60, 40),
+            (true, true) => Color32::from_rgb(40, 110, 60),
+        }
+    }
+
+    /// egui's visuals for this palette: the stock light or dark theme, with
+    /// plain backgrounds, full-strength text and solid widget outlines when high
+    /// contrast is on.
+    pub fn visuals(&self) -> Visuals {
+        let mut visuals = if self.dark { Visuals::dark() } else { Visuals::light() };
+        if !self.high_contrast {
+            return visuals;
+        }
+        let (background, foreground) = if self.dark {
+            (Color32::BLACK, Color32::WHITE)
+        } else {
+            (Color32::WHITE, Color32::BLACK)
+        };
+        visuals.override_text_color = Some(foreground);
+        visuals.panel_fill = background;
+        visuals.window_fill = background;
+        visuals.extreme_bg_color = background;
+        visuals.window_stroke = Stroke::new(2.0, foreground);
+        let widgets = &mut visuals.widgets;
+        for widget in [
+            &mut widgets.noninteractive,
+            &mut widgets.inactive,
+            &mut widgets.hovered,
+            &mut widgets.active,
+            &mut widgets.open,
+        ] {
+            widget.bg_stroke = Stroke::new(widget.bg_stroke.width.max(1.0), foreground);
+            widget.fg_stroke.color = foreground;
+        }
+        visuals
+    }
+}
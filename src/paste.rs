@@ -0,0 +1,357 @@
+use std::fs;
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::ChatMessageRequest;
+use crate::error::{Error, Result};
+use crate::tokenizer;
+
+/// How pasted blocks too long to send whole are shortened: to their first
+/// [`head`](Self::head) and last [`tail`](Self::tail) lines, since the errors
+/// in a build log or a stack trace are usually at one end or the other.
+///
+/// A block is a fenced code block of a user message, or the whole message when
+/// it has none. Set in the `[paste]` section of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct PasteLimits {
+    /// Estimated tokens a block may have before it is shortened; 0 never shortens.
+    pub max_tokens: usize,
+    /// Lines kept from the start of a shortened block.
+    pub head: usize,
+    /// Lines kept from the end of a shortened block.
+    pub tail: usize,
+}
+
+impl Default for PasteLimits {
+    fn default() -> Self {
+        Self {
+            max_tokens: 4_000,
+            head: 100,
+            tail: 200,
+        }
+    }
+}
+
+/// How one block of a message was shortened.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cut {
+    /// The block, counting from 0.
+    pub block: usize,
+    /// Lines the block has in full.
+    pub lines: usize,
+    pub head: usize,
+    pub tail: usize,
+    /// Chosen by hand for this block rather than by the limits.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+}
+
+impl Cut {
+    /// Lines left out of the block.
+    pub fn omitted(&self) -> usize {
+        self.lines.saturating_sub(self.head.saturating_add(self.tail))
+    }
+
+    /// The line sent in place of the omitted ones.
+    pub fn marker(&self) -> String {
+        format!("[... {} lines omitted ...]", group_digits(self.omitted()))
+    }
+
+    /// What was kept of the block, for tooltips and `/debug context`.
+    pub fn describe(&self) -> String {
+        let kept = match self.omitted() {
+            0 => "sent whole".to_string(),
+            _ => format!("sent the first {} and the last {}", self.head, self.tail),
+        };
+        let pinned = if self.pinned { " (set by hand)" } else { "" };
+        format!("{} lines, {}{}", group_digits(self.lines), kept, pinned)
+    }
+}
+
+/// `n` with thousands separated by commas, as in `48,212`.
+pub fn group_digits(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// The fence a line opens or closes a code block with: its character and
+/// length, and whether anything follows it.
+fn fence(line: &str) -> Option<(char, usize, bool)> {
+    let trimmed = line.trim_start();
+    let mark = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = trimmed.chars().take_while(|&c| c == mark).count();
+    (len >= 3).then(|| (mark, len, !trimmed[len..].trim().is_empty()))
+}
+
+/// The blocks of a message as ranges of its `lines`: the insides of its fenced
+/// code blocks, or all of it when it has none. As in Markdown, a block only
+/// ends at a bare fence of the same character, at least as long as the one
+/// it opened with.
+fn blocks(lines: &[&str]) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(usize, char, usize)> = None;
+    for (i, line) in lines.iter().enumerate() {
+        let Some((mark, len, info)) = fence(line) else {
+            continue;
+        };
+        match open {
+            Some((start, open_mark, open_len)) if mark == open_mark && len >= open_len && !info => {
+                blocks.push(start..i);
+                open = None;
+            }
+            Some(_) => {}
+            None => open = Some((i + 1, mark, len)),
+        }
+    }
+    blocks.extend(open.map(|(start, _, _)| start..lines.len()));
+    if blocks.is_empty() && !lines.is_empty() {
+        blocks.push(0..lines.len());
+    }
+    blocks
+}
+
+/// `original` with its long blocks shortened, and how each was cut. Blocks with a
+/// [`pinned`](Cut::pinned) cut in `pinned` keep its head and tail; others are cut
+/// to `limits` once over [`max_tokens`](PasteLimits::max_tokens). A block no
+/// longer than its head and tail together is sent whole; its cut is only
+/// returned when pinned, to keep it that way.
+///
+/// ```
+/// use llm::paste::{shorten, PasteLimits};
+///
+/// let log: String = (1..=50_000).map(|n| format!("line {}\n", n)).collect();
+/// let limits = PasteLimits { max_tokens: 1_000, head: 2, tail: 1 };
+/// let (sent, cuts) = shorten(&log, &limits, &[]);
+/// assert_eq!(sent, "line 1\nline 2\n[... 49,997 lines omitted ...]\nline 50000\n");
+/// assert_eq!((cuts[0].block, cuts[0].lines, cuts[0].omitted()), (0, 50_000, 49_997));
+///
+/// let message = format!("Why does this fail?\n```\n{}```\n", log);
+/// let (sent, _) = shorten(&message, &limits, &[]);
+/// assert!(sent.starts_with("Why does this fail?\n```\nline 1\nline 2\n[... 49,997"));
+/// assert!(sent.ends_with("line 50000\n```\n"));
+/// ```
+pub fn shorten(original: &str, limits: &PasteLimits, pinned: &[Cut]) -> (String, Vec<Cut>) {
+    let lines: Vec<&str> = original.split_inclusive('\n').collect();
+    let mut cuts = Vec::new();
+    for (block, range) in blocks(&lines).into_iter().enumerate() {
+        let long = limits.max_tokens > 0 && tokenizer::estimate(&lines[range.clone()].concat()) > limits.max_tokens;
+        let cut = match pinned.iter().find(|cut| cut.block == block && cut.pinned) {
+            Some(cut) => Cut {
+                lines: range.len(),
+                ..*cut
+            },
+            None if long => Cut {
+                block,
+                lines: range.len(),
+                head: limits.head,
+                tail: limits.tail,
+                pinned: false,
+            },
+            None => continue,
+        };
+        if cut.omitted() > 0 || cut.pinned {
+            cuts.push((range.start, cut));
+        }
+    }
+    let mut sent = String::with_capacity(original.len());
+    let mut next = 0;
+    for (start, cut) in cuts.iter().filter(|(_, cut)| cut.omitted() > 0) {
+        sent.push_str(&lines[next..start + cut.head].concat());
+        sent.push_str(&cut.marker());
+        sent.push('\n');
+        next = start + cut.lines - cut.tail;
+    }
+    sent.push_str(&lines[next..].concat());
+    (sent, cuts.into_iter().map(|(_, cut)| cut).collect())
+}
+
+/// Shorten the long blocks of a user `message` before it is sent, keeping the
+/// text as typed in [`untruncated`](ChatMessageRequest::untruncated).
+pub fn apply(message: &mut ChatMessageRequest, limits: &PasteLimits) {
+    let original = message.untruncated.take().unwrap_or_else(|| message.content.clone());
+    let (sent, cuts) = shorten(&original, limits, &message.cuts);
+    message.content = sent;
+    message.untruncated = (!cuts.is_empty()).then_some(original);
+    message.cuts = cuts;
+}
+
+/// A text file attached to the next message. It is sent as a fenced block
+/// after the message and shortened like a pasted one, unless its head and tail
+/// were chosen while it was staged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextAttachment {
+    pub name: String,
+    pub text: String,
+    /// Lines kept from the start, when chosen by hand.
+    pub head: Option<usize>,
+    /// Lines kept from the end, when chosen by hand.
+    pub tail: Option<usize>,
+}
+
+impl TextAttachment {
+    pub fn new(name: &str, text: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            text: text.into(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// Read the text file at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).map_err(Error::file(path))?;
+        let text = String::from_utf8(bytes)
+            .map_err(|_| Error::Config(format!("{} is not a UTF-8 text file", path.display())))?;
+        let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into());
+        Ok(Self::new(&name, text))
+    }
+
+    pub fn lines(&self) -> usize {
+        self.text.lines().count()
+    }
+
+    fn is_pinned(&self) -> bool {
+        self.head.is_some() || self.tail.is_some()
+    }
+
+    /// How the attachment will be sent: shortened to `limits` once it is over
+    /// their [`max_tokens`](PasteLimits::max_tokens), to the head and tail
+    /// chosen by hand if there are any, or whole.
+    pub fn cut(&self, limits: &PasteLimits) -> Cut {
+        let lines = self.lines();
+        let long = limits.max_tokens > 0 && tokenizer::estimate(&self.text) > limits.max_tokens;
+        let (head, tail) = match self.is_pinned() || long {
+            true => (self.head.unwrap_or(limits.head), self.tail.unwrap_or(limits.tail)),
+            false => (lines, 0),
+        };
+        Cut {
+            block: 0,
+            lines,
+            head,
+            tail,
+            pinned: self.is_pinned(),
+        }
+    }
+
+    /// The attachment as a fenced block, with a fence longer than any inside it.
+    fn block(&self) -> String {
+        let longest = self.text.lines().filter_map(fence).filter(|(mark, _, _)| *mark == '`');
+        let fence = "`".repeat(longest.map(|(_, len, _)| len + 1).max().unwrap_or(3).max(3));
+        let newline = if self.text.ends_with('\n') { "" } else { "\n" };
+        format!("{}:\n{}\n{}{}{}\n", self.name, fence, self.text, newline, fence)
+    }
+}
+
+/// Send `attachments` after the text of a user `message`, each as a fenced
+/// block, and shorten the long blocks as [`apply`] does. The head and tail
+/// chosen for an attachment are kept as a pinned [`Cut`].
+///
+/// ```
+/// use llm::api::ChatMessageRequest;
+/// use llm::paste::{self, PasteLimits, TextAttachment};
+///
+/// let log: String = (1..=5_000).map(|n| format!("step {}\n", n)).collect();
+/// let mut staged = TextAttachment::new("build.log", log);
+/// staged.tail = Some(2);
+/// let notes = TextAttachment::new("notes.md", "Fails since:\n```\ncargo update\n```\n");
+///
+/// let mut message = ChatMessageRequest::new("user", "Why does the build fail?");
+/// let limits = PasteLimits { max_tokens: 1_000, head: 1, tail: 100 };
+/// paste::attach(&mut message, &[staged, notes], &limits);
+/// assert_eq!(
+///     message.content,
+///     "Why does the build fail?\n\nbuild.log:\n```\nstep 1\n[... 4,997 lines omitted ...]\nstep 4999\nstep 5000\n```\n\n\
+///      notes.md:\n````\nFails since:\n```\ncargo update\n```\n````\n"
+/// );
+/// assert_eq!((message.cuts[0].block, message.cuts[0].pinned), (0, true));
+/// assert!(message.untruncated.unwrap().contains("step 2500\n"));
+/// ```
+pub fn attach(message: &mut ChatMessageRequest, attachments: &[TextAttachment], limits: &PasteLimits) {
+    if !attachments.is_empty() {
+        let mut original = message.untruncated.take().unwrap_or_else(|| message.content.clone());
+        for attachment in attachments {
+            let separator = match original.is_empty() {
+                true => "",
+                false if original.ends_with('\n') => "\n",
+                false => "\n\n",
+            };
+            original.push_str(separator);
+            original.push_str(&attachment.block());
+        }
+        let lines: Vec<&str> = original.split_inclusive('\n').collect();
+        let first = blocks(&lines).len().saturating_sub(attachments.len());
+        for (i, attachment) in attachments.iter().enumerate().filter(|(_, attachment)| attachment.is_pinned()) {
+            let block = first + i;
+            message.cuts.retain(|cut| cut.block != block);
+            message.cuts.push(Cut {
+                block,
+                ..attachment.cut(limits)
+            });
+        }
+        message.content = original;
+    }
+    apply(message, limits);
+}
+
+/// Send block `block` of `message` with `head` and `tail` lines from now on,
+/// keeping the current ones for those not given. Returns the new cut, or `None`
+/// when they cover the block and it is sent whole.
+pub fn recut(
+    message: &mut ChatMessageRequest,
+    limits: &PasteLimits,
+    block: usize,
+    head: Option<usize>,
+    tail: Option<usize>,
+) -> Result<Option<Cut>> {
+    let original = message.untruncated.as_deref().unwrap_or(&message.content);
+    let lines: Vec<&str> = original.split_inclusive('\n').collect();
+    let Some(range) = blocks(&lines).into_iter().nth(block) else {
+        return Err(Error::Config(format!("The message has no block {}.", block + 1)));
+    };
+    let current = message.cuts.iter().find(|cut| cut.block == block);
+    let cut = Cut {
+        block,
+        lines: range.len(),
+        head: head.or(current.map(|cut| cut.head)).unwrap_or(limits.head),
+        tail: tail.or(current.map(|cut| cut.tail)).unwrap_or(limits.tail),
+        pinned: true,
+    };
+    message.cuts.retain(|cut| cut.block != block);
+    message.cuts.push(cut);
+    apply(message, limits);
+    Ok(Some(cut).filter(|cut| cut.omitted() > 0))
+}
+
+/// Lines `line - radius` to `line + radius` of block `block` of `message` as
+/// typed, numbered from 1 within the block, for sending more of a shortened
+/// block on request.
+pub fn excerpt(
+    message: &ChatMessageRequest,
+    block: usize,
+    line: usize,
+    radius: usize,
+) -> Result<(RangeInclusive<usize>, String)> {
+    let original = message.untruncated.as_deref().unwrap_or(&message.content);
+    let lines: Vec<&str> = original.split_inclusive('\n').collect();
+    let Some(range) = blocks(&lines).into_iter().nth(block) else {
+        return Err(Error::Config(format!("The message has no block {}.", block + 1)));
+    };
+    if line == 0 || line > range.len() {
+        return Err(Error::Config(format!("The block has lines 1 to {}.", range.len())));
+    }
+    let first = line.saturating_sub(radius).max(1);
+    let last = line.saturating_add(radius).min(range.len());
+    let text = lines[range.start + first - 1..range.start + last].concat();
+    Ok((first..=last, text))
+}
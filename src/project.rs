@@ -0,0 +1,99 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::api::ChatMessageRequest;
+use crate::error::{Error, Result};
+use crate::{incognito, storage};
+
+/// Name of the project file holding standing instructions for a directory tree.
+pub const FILE_NAME: &str = ".cli_llm.md";
+
+/// Written by `cli_llm init`.
+const TEMPLATE: &str = "\
+# Project instructions
+
+<!-- Everything in this file is sent to the model as an extra system message
+     whenever cli_llm runs in this directory or below it. -->
+
+## About this project
+
+Describe what the project is and who uses it.
+
+## Conventions
+
+- Language, framework and version
+- Code style and naming
+- Things to avoid
+";
+
+/// Standing instructions read from a project file, sent as an extra system
+/// message after the system prompt.
+#[derive(Debug, Clone)]
+pub struct ProjectContext {
+    pub path: PathBuf,
+    pub content: String,
+    modified: Option<SystemTime>,
+}
+
+/// The project file for `dir`: the nearest [`FILE_NAME`] in `dir` or a parent,
+/// up to the root of the repository `dir` is in (or of the file system outside one).
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        let path = dir.join(FILE_NAME);
+        if path.is_file() {
+            return Some(path);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// Create a project file with a template to fill in, refusing to overwrite one.
+pub fn init(dir: &Path) -> Result<PathBuf> {
+    let path = dir.join(FILE_NAME);
+    if path.exists() {
+        return Err(Error::Config(format!("{} already exists", path.display())));
+    }
+    incognito::refuse("Creating a project file")?;
+    storage::write_atomic(&path, TEMPLATE).map_err(Error::file(&path))?;
+    Ok(path)
+}
+
+impl ProjectContext {
+    /// The project file for `dir`, if there is one.
+    pub fn discover(dir: &Path) -> Result<Option<Self>> {
+        find(dir).map(|path| Self::load(&path)).transpose()
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(Error::file(path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            content,
+            modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+        })
+    }
+
+    /// Read the file again if it was modified since it was loaded. Returns
+    /// whether the instructions changed.
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == self.modified {
+            return Ok(false);
+        }
+        let content = fs::read_to_string(&self.path).map_err(Error::file(&self.path))?;
+        self.modified = modified;
+        let changed = content != self.content;
+        self.content = content;
+        Ok(changed)
+    }
+
+    /// The instructions as a system message, or `None` when the file is blank.
+    pub fn message(&self) -> Option<ChatMessageRequest> {
+        let content = self.content.trim();
+        (!content.is_empty()).then(|| ChatMessageRequest::new("system", content))
+    }
+}
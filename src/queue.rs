@@ -0,0 +1,438 @@
+//! The request queue shared by every running instance of both front ends.
+//!
+//! Each request to the chat endpoint takes a [`Ticket`] first. A ticket is a
+//! small file in the queue directory saying which process sent the request,
+//! to which model, from where and when. At most [`slots`] requests run at once;
+//! the others wait their turn, highest priority first and then oldest first.
+//! `cli_llm queue` lists the waiting requests, cancels them, or moves them
+//! to the front.
+//!
+//! There is no daemon: every waiting process reads the directory and works
+//! out its own turn. Two processes may both start in the same instant, so the
+//! limit can briefly be passed by one, but a running request is never stopped.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::{incognito, storage};
+
+/// How many requests the front ends let run at once when the config doesn't
+/// say. The library itself doesn't queue until [`set_slots`] turns it on.
+pub const DEFAULT_SLOTS: usize = 4;
+
+/// How often a waiting request checks whether its turn has come.
+const POLL: Duration = Duration::from_millis(200);
+
+/// Where the owner of a ticket can't be checked for being alive, its ticket
+/// counts as left behind after this long.
+#[cfg(not(unix))]
+const STALE_AFTER: Duration = Duration::from_secs(6 * 60 * 60);
+
+static SLOTS: AtomicUsize = AtomicUsize::new(0);
+static SOURCE: RwLock<String> = RwLock::new(String::new());
+
+/// Let at most `slots` requests of all instances run at once; 0, the default,
+/// turns the queue off.
+pub fn set_slots(slots: usize) {
+    SLOTS.store(slots, Ordering::Relaxed);
+}
+
+/// How many requests may run at once.
+pub fn slots() -> usize {
+    SLOTS.load(Ordering::Relaxed)
+}
+
+/// Say where the requests of this process come from, e.g. `gui` or `bench`,
+/// for `cli_llm queue list`.
+pub fn set_source(source: &str) {
+    if let Ok(mut current) = SOURCE.write() {
+        *current = source.to_string();
+    }
+}
+
+/// Where the tickets are kept.
+pub fn queue_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("cli_llm").join("queue"))
+}
+
+/// Wait for a turn to send a request to `model` in the shared queue.
+///
+/// Requests aren't queued in [incognito](crate::incognito) mode, which writes
+/// nothing to disk, when the queue is off, or when there is no cache directory.
+pub async fn enter(model: &str) -> Result<Ticket> {
+    let slots = slots();
+    match queue_dir() {
+        Some(dir) if slots > 0 && !incognito::is_on() => Queue::new(dir).wait(model, slots).await,
+        _ => Ok(Ticket::unqueued()),
+    }
+}
+
+/// One request in the queue.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// `<process>-<n>`, for `cli_llm queue cancel` and `promote`.
+    pub id: String,
+    pub pid: u32,
+    pub model: String,
+    /// Which front end or command sent the request.
+    pub source: String,
+    /// When the request joined the queue, in milliseconds since the Unix epoch.
+    pub created: u64,
+    /// Higher goes first; raised by `cli_llm queue promote`.
+    #[serde(default)]
+    pub priority: i64,
+    /// When the request left the queue to be sent, in milliseconds since the
+    /// Unix epoch; `None` while it waits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started: Option<u64>,
+}
+
+impl Entry {
+    /// How long ago the request joined the queue.
+    pub fn age(&self) -> Duration {
+        Duration::from_millis(now_millis().saturating_sub(self.created))
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+}
+
+/// Put `entries` in turn order: running requests first, then the waiting ones
+/// by priority and then by age, oldest first.
+pub fn turn_order(entries: &mut [Entry]) {
+    entries.sort_by(|a, b| {
+        let (a_start, b_start) = (a.started.unwrap_or(u64::MAX), b.started.unwrap_or(u64::MAX));
+        a_start
+            .cmp(&b_start)
+            .then(b.priority.cmp(&a.priority))
+            .then(a.created.cmp(&b.created))
+            .then(a.id.cmp(&b.id))
+    });
+}
+
+/// A queue directory.
+pub struct Queue {
+    dir: PathBuf,
+}
+
+impl Queue {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// The requests in the queue, in [`turn_order`]. Tickets left behind by
+    /// processes that have ended are removed.
+    pub fn entries(&self) -> Vec<Entry> {
+        let Ok(files) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut entries = Vec::new();
+        for path in files.flatten().map(|file| file.path()) {
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            // A ticket is written atomically, so one that can't be read is damaged.
+            let entry = fs::read(&path).ok().and_then(|bytes| serde_json::from_slice::<Entry>(&bytes).ok());
+            match entry {
+                Some(entry) if owner_alive(&entry) => entries.push(entry),
+                Some(_) | None if path.exists() => {
+                    log::info!("Removing stale queue ticket {}", path.display());
+                    let _ = fs::remove_file(&path);
+                }
+                _ => {}
+            }
+        }
+        turn_order(&mut entries);
+        entries
+    }
+
+    /// The waiting request `id`, or why it can't be changed.
+    fn waiting(&self, id: &str) -> Result<Entry> {
+        match self.entries().into_iter().find(|entry| entry.id == id) {
+            Some(entry) if entry.is_running() => Err(Error::Config(format!("Request {} is already running", id))),
+            Some(entry) => Ok(entry),
+            None => Err(Error::Config(format!("There is no request {} in the queue", id))),
+        }
+    }
+
+    /// Take the waiting request `id` out of the queue. The process that sent it
+    /// gives up on it with [`Error::Cancelled`].
+    pub fn cancel(&self, id: &str) -> Result<Entry> {
+        let entry = self.waiting(id)?;
+        let path = self.path(id);
+        fs::remove_file(&path).map_err(Error::file(&path))?;
+        Ok(entry)
+    }
+
+    /// Move the waiting request `id` ahead of all the others.
+    pub fn promote(&self, id: &str) -> Result<Entry> {
+        let mut entry = self.waiting(id)?;
+        let top = self.entries().iter().map(|entry| entry.priority).max().unwrap_or(0);
+        entry.priority = top.max(entry.priority) + 1;
+        self.rewrite(&entry)?;
+        Ok(entry)
+    }
+
+    fn write(&self, entry: &Entry) -> Result<()> {
+        let path = self.path(&entry.id);
+        storage::write_atomic(&path, serde_json::to_vec(entry)?).map_err(Error::file(&path))
+    }
+
+    /// Write `entry` over its ticket, unless the ticket was cancelled or
+    /// removed meanwhile; then it stays gone and this fails with
+    /// [`Error::Cancelled`].
+    fn rewrite(&self, entry: &Entry) -> Result<()> {
+        if !self.path(&entry.id).exists() {
+            return Err(Error::Cancelled(entry.id.clone()));
+        }
+        self.write(entry)
+    }
+
+    /// Join the queue with a request to `model` and wait until it is among the
+    /// first `slots` in turn order.
+    pub async fn wait(&self, model: &str, slots: usize) -> Result<Ticket> {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let mut entry = Entry {
+            id: format!("{}-{}", process::id(), COUNT.fetch_add(1, Ordering::Relaxed) + 1),
+            pid: process::id(),
+            model: model.to_string(),
+            source: SOURCE.read().map(|source| source.clone()).unwrap_or_default(),
+            created: now_millis(),
+            priority: 0,
+            started: None,
+        };
+        let joined = Instant::now();
+        self.write(&entry)?;
+        // Dropping the ticket leaves the queue, also while still waiting.
+        let mut ticket = Ticket {
+            path: Some(self.path(&entry.id)),
+            id: Some(entry.id.clone()),
+            queued: Duration::ZERO,
+        };
+        loop {
+            let entries = self.entries();
+            let Some(position) = entries.iter().position(|queued| queued.id == entry.id) else {
+                return Err(Error::Cancelled(entry.id));
+            };
+            if position < slots {
+                // A promotion meanwhile doesn't matter any more once running.
+                entry.started = Some(now_millis());
+                self.rewrite(&entry)?;
+                ticket.queued = joined.elapsed();
+                if !ticket.queued.is_zero() {
+                    log::debug!("Request {} waited {:?} in the queue", entry.id, ticket.queued);
+                }
+                return Ok(ticket);
+            }
+            tokio::time::sleep(POLL).await;
+        }
+    }
+}
+
+/// A turn in the queue, held while the request runs. Dropping it frees the
+/// slot for the next request.
+#[derive(Debug)]
+pub struct Ticket {
+    path: Option<PathBuf>,
+    id: Option<String>,
+    queued: Duration,
+}
+
+impl Ticket {
+    /// A ticket for a request that didn't go through the queue.
+    fn unqueued() -> Self {
+        Self {
+            path: None,
+            id: None,
+            queued: Duration::ZERO,
+        }
+    }
+
+    /// The request's id in the queue, if it was queued.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// How long the request waited for its turn.
+    pub fn queued(&self) -> Duration {
+        self.queued
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Whether the process that wrote `entry` is still running.
+#[cfg(unix)]
+fn owner_alive(entry: &Entry) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(entry.pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process exists; EPERM means it does but
+    // belongs to someone else.
+    // SAFETY: kill has no memory safety requirements, and signal 0 sends
+    // nothing, so no process is affected.
+    let exists = unsafe { libc::kill(pid, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn owner_alive(entry: &Entry) -> bool {
+    entry.pid == process::id() || entry.age() < STALE_AFTER
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::path::Path;
+
+    fn entry(id: &str, created: u64, priority: i64, started: Option<u64>) -> Entry {
+        Entry {
+            id: id.to_string(),
+            pid: process::id(),
+            model: "openai/gpt-4o".to_string(),
+            source: "test".to_string(),
+            created,
+            priority,
+            started,
+        }
+    }
+
+    fn ids(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.id.as_str()).collect()
+    }
+
+    fn put(dir: &Path, entry: &Entry) {
+        Queue::new(dir).write(entry).unwrap();
+    }
+
+    #[test]
+    fn running_first_then_priority_then_age() {
+        let mut entries = vec![
+            entry("old", 1, 0, None),
+            entry("new", 5, 0, None),
+            entry("running", 9, 0, Some(10)),
+            entry("urgent", 7, 2, None),
+            entry("first-running", 8, 0, Some(3)),
+        ];
+        turn_order(&mut entries);
+        assert_eq!(ids(&entries), ["first-running", "running", "urgent", "old", "new"]);
+    }
+
+    #[test]
+    fn promote_moves_a_request_to_the_front() {
+        let dir = TempDir::new("queue", "promote");
+        put(&dir.0, &entry("a", 1, 0, None));
+        put(&dir.0, &entry("b", 2, 3, None));
+        put(&dir.0, &entry("c", 3, 0, None));
+        let queue = Queue::new(&dir.0);
+        assert_eq!(ids(&queue.entries()), ["b", "a", "c"]);
+
+        assert_eq!(queue.promote("c").unwrap().priority, 4);
+        assert_eq!(ids(&queue.entries()), ["c", "b", "a"]);
+        assert!(queue.promote("missing").is_err());
+    }
+
+    #[test]
+    fn only_waiting_requests_are_cancelled() {
+        let dir = TempDir::new("queue", "cancel");
+        put(&dir.0, &entry("running", 1, 0, Some(2)));
+        put(&dir.0, &entry("waiting", 3, 0, None));
+        let queue = Queue::new(&dir.0);
+
+        assert!(queue.cancel("running").unwrap_err().to_string().contains("already running"));
+        assert!(queue.promote("running").is_err());
+        assert_eq!(queue.cancel("waiting").unwrap().id, "waiting");
+        assert_eq!(ids(&queue.entries()), ["running"]);
+        assert!(queue.cancel("waiting").is_err());
+    }
+
+    #[test]
+    fn tickets_of_ended_processes_are_removed() {
+        let dir = TempDir::new("queue", "stale");
+        let mut gone = entry("gone", 1, 0, Some(1));
+        // No process has this id, and on other platforms the ticket is old.
+        gone.pid = 0x7FFF_FFF0;
+        put(&dir.0, &gone);
+        put(&dir.0, &entry("alive", 2, 0, None));
+        fs::write(dir.0.join("damaged.json"), "{").unwrap();
+
+        assert_eq!(ids(&Queue::new(&dir.0).entries()), ["alive"]);
+        assert!(!dir.0.join("gone.json").exists());
+        assert!(!dir.0.join("damaged.json").exists());
+    }
+
+    #[tokio::test]
+    async fn a_request_waits_for_a_free_slot() {
+        let dir = TempDir::new("queue", "slots");
+        let queue = Queue::new(&dir.0);
+        let first = queue.wait("openai/gpt-4o", 1).await.unwrap();
+        assert!(first.queued() < POLL);
+        assert!(queue.entries()[0].is_running());
+
+        let release = async {
+            tokio::time::sleep(3 * POLL).await;
+            let entries = queue.entries();
+            assert_eq!(entries.len(), 2);
+            assert!(!entries[1].is_running());
+            drop(first);
+        };
+        let (second, ()) = tokio::join!(queue.wait("openai/gpt-4o", 1), release);
+        let second = second.unwrap();
+        assert!(second.queued() >= 3 * POLL);
+        assert_eq!(queue.entries()[0].id, second.id().unwrap());
+        drop(second);
+        assert!(queue.entries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_request_gives_up() {
+        let dir = TempDir::new("queue", "cancelled");
+        let queue = Queue::new(&dir.0);
+        let _running = queue.wait("openai/gpt-4o", 1).await.unwrap();
+
+        let cancel = async {
+            tokio::time::sleep(2 * POLL).await;
+            let waiting = queue.entries()[1].id.clone();
+            queue.cancel(&waiting).unwrap();
+        };
+        let (waited, ()) = tokio::join!(queue.wait("openai/gpt-4o", 1), cancel);
+        assert!(matches!(waited, Err(Error::Cancelled(_))));
+        assert_eq!(queue.entries().len(), 1);
+    }
+
+    #[test]
+    fn a_cancelled_ticket_is_not_written_again() {
+        let dir = TempDir::new("queue", "rewrite");
+        let queue = Queue::new(&dir.0);
+        let mut waiting = entry("waiting", 1, 0, None);
+        put(&dir.0, &waiting);
+        queue.cancel("waiting").unwrap();
+
+        waiting.started = Some(2);
+        assert!(matches!(queue.rewrite(&waiting), Err(Error::Cancelled(_))));
+        assert!(queue.entries().is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::ChatMessageRequest;
+use crate::error::{Error, Result};
+use crate::history::SavedMessage;
+
+/// 👍 or 👎 on a reply.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Up,
+    Down,
+}
+
+impl Verdict {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Up => "up",
+            Self::Down => "down",
+        }
+    }
+
+    pub fn emoji(self) -> &'static str {
+        match self {
+            Self::Up => "👍",
+            Self::Down => "👎",
+        }
+    }
+}
+
+impl FromStr for Verdict {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "up" | "good" | "+" | "👍" => Ok(Self::Up),
+            "down" | "bad" | "-" | "👎" => Ok(Self::Down),
+            _ => Err(Error::Config(format!("Unknown rating '{}': use up or down", text))),
+        }
+    }
+}
+
+/// How the user rated a reply, with an optional note on why.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rating {
+    pub verdict: Verdict,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Rating {
+    pub fn new(verdict: Verdict, note: &str) -> Self {
+        Self {
+            verdict,
+            note: Some(note.trim().to_string()).filter(|note| !note.is_empty()),
+        }
+    }
+
+    /// `👎 too long`, for showing under a reply.
+    pub fn label(&self) -> String {
+        match &self.note {
+            Some(note) => format!("{} {}", self.verdict.emoji(), note),
+            None => self.verdict.emoji().to_string(),
+        }
+    }
+}
+
+/// A rated-down reply that was asked for again, kept with the reply that
+/// replaced it as the rejected side of a preference pair.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rejected {
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// What to keep with the reply asked for in place of `reply`: the rejected
+/// replies it replaced itself, and `reply` too when it was rated down.
+pub fn rejected(reply: &ChatMessageRequest) -> Vec<Rejected> {
+    let mut rejected = reply.rejected.clone();
+    if let Some(rating) = reply.rating.as_ref().filter(|rating| rating.verdict == Verdict::Down) {
+        rejected.push(Rejected {
+            content: reply.content.clone(),
+            model: reply.model.clone(),
+            note: rating.note.clone(),
+        });
+    }
+    rejected
+}
+
+/// A message of the context a rated reply was written in.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Turn {
+    pub role: String,
+    pub content: String,
+}
+
+/// One line of `cli_llm export ratings`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Example {
+    /// A reply that replaced a rated-down one, and that one.
+    Pair {
+        conversation: String,
+        model: String,
+        prompt: Vec<Turn>,
+        chosen: String,
+        rejected: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+    },
+    /// A rated reply that nothing replaced; `label` is whether it was rated up.
+    Rated {
+        conversation: String,
+        model: String,
+        prompt: Vec<Turn>,
+        completion: String,
+        label: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+    },
+}
+
+/// The rated replies of a saved conversation as training examples: a pair for
+/// every rated-down reply that was replaced by one not rated down, and the
+/// other rated replies on their own. `model` is the conversation's, for
+/// replies that don't say which model wrote them.
+///
+/// ```
+/// use llm::api::ChatMessageRequest;
+/// use llm::history::SavedMessage;
+/// use llm::rating::{self, Example, Rating, Verdict};
+///
+/// let mut first = ChatMessageRequest::new("assistant", "Rust is a language. It has many features...");
+/// first.rating = Some(Rating::new(Verdict::Down, "too vague"));
+/// let mut second = ChatMessageRequest::new("assistant", "Ownership: every value has one owner.");
+/// second.rejected = rating::rejected(&first);
+/// let messages = [
+///     SavedMessage::from(&ChatMessageRequest::new("user", "What makes Rust safe?")),
+///     SavedMessage::from(&second),
+/// ];
+///
+/// let examples = rating::examples("rust", "openai/gpt-4o", Some("Be brief."), &messages);
+/// assert_eq!(examples.len(), 1);
+/// let Example::Pair { prompt, chosen, rejected, note, .. } = &examples[0] else {
+///     panic!("expected a pair");
+/// };
+/// assert_eq!((prompt[0].role.as_str(), prompt[1].content.as_str()), ("system", "What makes Rust safe?"));
+/// assert_eq!(chosen, "Ownership: every value has one owner.");
+/// assert!(rejected.starts_with("Rust is a language."));
+/// assert_eq!(note.as_deref(), Some("too vague"));
+/// ```
+pub fn examples(conversation: &str, model: &str, system_prompt: Option<&str>, messages: &[SavedMessage]) -> Vec<Example> {
+    let mut prompt: Vec<Turn> = system_prompt
+        .filter(|prompt| !prompt.trim().is_empty())
+        .map(|prompt| Turn {
+            role: "system".to_string(),
+            content: prompt.to_string(),
+        })
+        .into_iter()
+        .collect();
+    let mut examples = Vec::new();
+    for message in messages {
+        let reply_model = message.model.clone().unwrap_or_else(|| model.to_string());
+        let rated_down = message.rating.as_ref().is_some_and(|rating| rating.verdict == Verdict::Down);
+        let paired = !rated_down && !message.rejected.is_empty();
+        if paired {
+            for rejected in &message.rejected {
+                examples.push(Example::Pair {
+                    conversation: conversation.to_string(),
+                    model: reply_model.clone(),
+                    prompt: prompt.clone(),
+                    chosen: message.content.clone(),
+                    rejected: rejected.content.clone(),
+                    note: rejected.note.clone(),
+                });
+            }
+        }
+        // A reply that is the chosen side of pairs needs no example of its own.
+        if let Some(rating) = message.rating.as_ref().filter(|_| !paired) {
+            examples.push(Example::Rated {
+                conversation: conversation.to_string(),
+                model: reply_model,
+                prompt: prompt.clone(),
+                completion: message.content.clone(),
+                label: rating.verdict == Verdict::Up,
+                note: rating.note.clone(),
+            });
+        }
+        if !message.excluded {
+            prompt.push(Turn {
+                role: message.role.clone(),
+                content: message.content.clone(),
+            });
+        }
+    }
+    examples
+}
+
+/// How a model's replies were rated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub replies: usize,
+    pub up: usize,
+    pub down: usize,
+}
+
+impl Tally {
+    /// The share of rated replies rated up, when any were rated.
+    pub fn approval(&self) -> Option<f64> {
+        let rated = self.up + self.down;
+        (rated > 0).then(|| self.up as f64 / rated as f64)
+    }
+}
+
+/// Count the replies of `messages` and their ratings by model into `tallies`.
+/// Rejected replies count as replies rated down.
+pub fn tally(messages: &[SavedMessage], model: &str, tallies: &mut BTreeMap<String, Tally>) {
+    for message in messages.iter().filter(|message| message.role == "assistant") {
+        let tally = tallies.entry(message.model.clone().unwrap_or_else(|| model.to_string())).or_default();
+        tally.replies += 1;
+        match message.rating.as_ref().map(|rating| rating.verdict) {
+            Some(Verdict::Up) => tally.up += 1,
+            Some(Verdict::Down) => tally.down += 1,
+            None => {}
+        }
+        for rejected in &message.rejected {
+            let tally = tallies.entry(rejected.model.clone().unwrap_or_else(|| model.to_string())).or_default();
+            tally.replies += 1;
+            tally.down += 1;
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api;
+use crate::error::{Error, Result};
+use crate::{incognito, storage, vault};
+
+/// A record file is closed once it reaches this size.
+const FILE_LIMIT: u64 = 5 * 1024 * 1024;
+/// Record files kept; with [`FILE_LIMIT`] about 50 MB in all.
+const FILES: usize = 10;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Keep every streamed chunk, not only the reply they make up.
+static CHUNKS: AtomicBool = AtomicBool::new(false);
+/// Tells apart records started in the same millisecond.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+/// Keeps whole records together when several threads write at once.
+static WRITING: Mutex<()> = Mutex::new(());
+
+/// One request to the chat endpoint and what came back, as kept by the flight
+/// recorder: the request body as sent, the response body (or the reassembled
+/// reply of a stream), and the error, if any. Every retry and resumed stream is
+/// a record of its own.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub id: String,
+    /// When the request was sent, in seconds since the Unix epoch.
+    pub at: u64,
+    pub duration_ms: u64,
+    pub url: String,
+    /// Request headers, with the API key redacted.
+    pub headers: String,
+    pub request: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// The response body of a non-streaming request, or of a failed one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// The content of a streamed reply, put back together from its chunks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply: Option<String>,
+    /// How many chunks the streamed reply came in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<u64>,
+    /// When the first and the last chunk arrived, in milliseconds after the
+    /// request was sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_chunk_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_chunk_ms: Option<u64>,
+    /// Every chunk as it arrived, with [`Granularity::Chunk`] only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deltas: Vec<Delta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A streamed chunk and when it arrived, in milliseconds after the request was
+/// sent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub ms: u64,
+    pub text: String,
+}
+
+/// How much of a streamed reply is recorded: `message` keeps the reply put back
+/// together, with the number of chunks and when the first and last arrived;
+/// `chunk` keeps every chunk as well.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    #[default]
+    Message,
+    Chunk,
+}
+
+/// Record requests from now on (`flight_recorder = true` in the config).
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Record streamed replies with `granularity` from now on
+/// (`flight_recorder_granularity` in the config).
+pub fn set_granularity(granularity: Granularity) {
+    CHUNKS.store(granularity == Granularity::Chunk, Ordering::Relaxed);
+}
+
+/// Where the record files are kept.
+pub fn recorder_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cli_llm").join("flight"))
+}
+
+/// A request being recorded. Does nothing unless the recorder is on, nor in
+/// incognito mode. One dropped before it [finished](Self::finish), as when a
+/// streamed reply is stopped, is recorded as stopped.
+pub struct Exchange {
+    record: Option<Record>,
+    started: Instant,
+}
+
+impl Exchange {
+    /// Start recording a request of `body` to `url`.
+    pub fn start(url: &str, headers: &HeaderMap, body: &impl Serialize) -> Self {
+        let record = (ENABLED.load(Ordering::Relaxed) && !incognito::is_on()).then(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            Record {
+                id: format!("{}-{}", now.as_millis(), SEQUENCE.fetch_add(1, Ordering::Relaxed)),
+                at: now.as_secs(),
+                duration_ms: 0,
+                url: url.to_string(),
+                headers: api::redacted_headers(headers),
+                request: serde_json::to_value(body).unwrap_or(Value::Null),
+                status: None,
+                response: None,
+                reply: None,
+                chunks: None,
+                first_chunk_ms: None,
+                last_chunk_ms: None,
+                deltas: Vec::new(),
+                finish_reason: None,
+                error: None,
+            }
+        });
+        Self {
+            record,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn status(&mut self, status: u16) {
+        if let Some(record) = &mut self.record {
+            record.status = Some(status);
+        }
+    }
+
+    /// The response body, kept as JSON when it is JSON and as a string otherwise.
+    pub fn response(&mut self, text: &str) {
+        if let Some(record) = &mut self.record {
+            record.response = Some(serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string())));
+        }
+    }
+
+    /// A piece of streamed content.
+    pub fn delta(&mut self, delta: &str) {
+        if let Some(record) = &mut self.record {
+            let ms = self.started.elapsed().as_millis() as u64;
+            record.reply.get_or_insert_with(String::new).push_str(delta);
+            *record.chunks.get_or_insert(0) += 1;
+            record.first_chunk_ms.get_or_insert(ms);
+            record.last_chunk_ms = Some(ms);
+            if CHUNKS.load(Ordering::Relaxed) {
+                record.deltas.push(Delta { ms, text: delta.to_string() });
+            }
+        }
+    }
+
+    pub fn finish_reason(&mut self, reason: Option<&str>) {
+        if let Some(record) = &mut self.record {
+            record.finish_reason = reason.map(str::to_string);
+        }
+    }
+
+    /// Write the record, noting `error` if the request failed. Problems writing
+    /// it are logged, never passed on to the request.
+    pub fn finish(mut self, error: Option<&Error>) {
+        self.write(error);
+    }
+
+    fn write(&mut self, error: Option<&Error>) {
+        let Some(mut record) = self.record.take() else {
+            return;
+        };
+        record.duration_ms = self.started.elapsed().as_millis() as u64;
+        record.error = error.map(|e| e.to_string());
+        if let Err(e) = append(&record) {
+            log::warn!("Cannot write to the flight recorder: {}", e);
+        }
+    }
+}
+
+impl Drop for Exchange {
+    fn drop(&mut self) {
+        self.write(Some(&Error::Stopped));
+    }
+}
+
+/// Record files with their numbers, oldest first.
+fn files() -> Result<Vec<(u64, PathBuf)>> {
+    let Some(dir) = recorder_dir() else {
+        return Ok(Vec::new());
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::file(&dir)(e)),
+    };
+    let mut files: Vec<(u64, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let number = path.file_name()?.to_str()?.strip_suffix(".jsonl")?.parse().ok()?;
+            Some((number, path))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Append `record` to the newest file, starting a new one when it is full and
+/// dropping the oldest beyond [`FILES`].
+fn append(record: &Record) -> Result<()> {
+    let dir = recorder_dir().ok_or_else(|| Error::Config("No data directory on this system".to_string()))?;
+    let _guard = WRITING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    storage::create_dir_all(&dir).map_err(Error::file(&dir))?;
+    let mut files = files()?;
+    let path = match files.last() {
+        Some((_, last)) if fs::metadata(last).map_or(0, |m| m.len()) < FILE_LIMIT => last.clone(),
+        last => {
+            let path = dir.join(format!("{:06}.jsonl", last.map_or(1, |(number, _)| number + 1)));
+            files.push((0, path.clone()));
+            path
+        }
+    };
+    let mut line = seal_line(serde_json::to_string(record)?)?.into_bytes();
+    line.push(b'\n');
+    storage::open(&path, OpenOptions::new().create(true).append(true))
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(Error::file(&path))?;
+    while files.len() > FILES {
+        let (_, oldest) = files.remove(0);
+        if let Err(e) = fs::remove_file(&oldest) {
+            log::warn!("Cannot remove {}: {}", oldest.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// A record's JSON as a line of a record file: encrypted and base64-encoded
+/// when the [`vault`] is on, so the requests and replies are never kept in the
+/// clear next to encrypted conversations. Fails while the vault is locked.
+fn seal_line(json: String) -> Result<String> {
+    if !vault::is_enabled() {
+        return Ok(json);
+    }
+    vault::seal(json.into_bytes())
+        .map(|sealed| STANDARD.encode(sealed))
+        .map_err(|e| Error::Config(format!("Not recording while saved conversations are locked: {}", e)))
+}
+
+/// The JSON of a line of a record file. Lines written before the vault was
+/// turned on are JSON already.
+fn open_line(line: &str) -> Result<String> {
+    if line.starts_with('{') {
+        return Ok(line.to_string());
+    }
+    let sealed = STANDARD
+        .decode(line)
+        .map_err(|_| Error::Config("Damaged flight recorder line".to_string()))?;
+    String::from_utf8(vault::open(sealed)?).map_err(|_| Error::Config("Damaged flight recorder line".to_string()))
+}
+
+/// The lines of the record file at `path` as JSON. Lines that can't be read,
+/// e.g. one cut short by a crash, are skipped.
+fn read_lines(path: &Path) -> Vec<String> {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| {
+        log::warn!("Cannot read {}: {}", path.display(), e);
+        String::new()
+    });
+    text.lines()
+        .filter_map(|line| {
+            open_line(line)
+                .inspect_err(|e| log::debug!("Skipping a line of {}: {}", path.display(), e))
+                .ok()
+        })
+        .collect()
+}
+
+/// Every record file with its lines as JSON, for [`vault`] to read them before
+/// it changes the key.
+pub(crate) fn plain_files() -> Result<Vec<(PathBuf, Vec<String>)>> {
+    Ok(files()?
+        .into_iter()
+        .map(|(_, path)| {
+            let lines = read_lines(&path);
+            (path, lines)
+        })
+        .collect())
+}
+
+/// Write the record files read by [`plain_files`] again, encrypted with the
+/// vault's current key.
+pub(crate) fn write_sealed(files: Vec<(PathBuf, Vec<String>)>) -> Result<()> {
+    let _guard = WRITING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (path, lines) in files {
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(&seal_line(line)?);
+            text.push('\n');
+        }
+        storage::write_atomic(&path, text).map_err(Error::file(&path))?;
+    }
+    Ok(())
+}
+
+/// Recorded exchanges, newest first, reading one file at a time. Lines that
+/// can't be read, e.g. one cut short by a crash or written under another
+/// passphrase, are skipped.
+pub fn records() -> Result<impl Iterator<Item = Record>> {
+    Ok(files()?.into_iter().rev().flat_map(|(_, path)| {
+        let records: Vec<Record> = read_lines(&path)
+            .iter()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        records.into_iter().rev()
+    }))
+}
+
+/// The most recent exchange.
+pub fn last() -> Result<Option<Record>> {
+    Ok(records()?.next())
+}
+
+/// The exchange recorded as `id`.
+pub fn find(id: &str) -> Result<Record> {
+    records()?
+        .find(|record| record.id == id)
+        .ok_or_else(|| Error::Config(format!("No recorded exchange '{}'", id)))
+}
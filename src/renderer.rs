@@ -0,0 +1,367 @@
+//! Rendering fenced code blocks of replies by their language: Mermaid and
+//! GraphViz diagrams to images, calendar events to `.ics` files, and whatever
+//! else the config maps to a program.
+//!
+//! Renderers that run a program only do so once the user has allowed that
+//! program for that language, which is asked the first time and remembered.
+//! They run with a bare environment, the block as their only input, a scratch
+//! working directory and a time limit. A renderer that isn't installed, isn't allowed or fails
+//! leaves the code block as it is.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::markdown::{self, CodeBlock};
+use crate::{incognito, storage};
+
+/// Longest a renderer may run before it is stopped.
+pub const TIME_LIMIT: Duration = Duration::from_secs(30);
+
+/// A renderer from the config's `[renderers]` table, keyed by fence language:
+/// the program and its arguments, with `{input}` replaced by the path of a
+/// file holding the block and `{output}` by the path to write the result to.
+///
+/// ```toml
+/// [renderers.plantuml]
+/// command = ["plantuml", "-tpng", "-pipe"]
+/// output = "png"
+/// stdout = true
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RendererCommand {
+    pub command: Vec<String>,
+    /// Extension of what the program writes. PNG images show in the GUI;
+    /// other files are saved and linked to.
+    #[serde(default = "default_output")]
+    pub output: String,
+    /// The program reads the block on stdin and writes the result to stdout,
+    /// instead of using `{input}` and `{output}`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stdout: bool,
+}
+
+fn default_output() -> String {
+    "png".to_string()
+}
+
+/// What a renderer does with a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Run a program on it.
+    Run(RendererCommand),
+    /// Save it as it is, as a file with this extension.
+    Save(String),
+}
+
+/// How the blocks of one fence language are rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Renderer {
+    /// The fence language, e.g. `mermaid`.
+    pub language: String,
+    pub action: Action,
+    /// Its program was installed when the renderers were set up.
+    available: bool,
+}
+
+impl Renderer {
+    /// Extension of what it produces.
+    pub fn extension(&self) -> &str {
+        match &self.action {
+            Action::Run(command) => &command.output,
+            Action::Save(extension) => extension,
+        }
+    }
+
+    /// Whether it produces an image to show.
+    pub fn is_image(&self) -> bool {
+        self.extension().eq_ignore_ascii_case("png")
+    }
+
+    /// What the user is asked to allow, e.g. `dot -Tpng {input} -o {output}`;
+    /// none for renderers that run nothing.
+    pub fn command_line(&self) -> Option<String> {
+        match &self.action {
+            Action::Run(command) => Some(command.command.join(" ")),
+            Action::Save(_) => None,
+        }
+    }
+
+    fn new(language: String, action: Action) -> Self {
+        let available = match &action {
+            Action::Run(command) => command.command.first().is_some_and(|program| find_program(program).is_some()),
+            Action::Save(_) => true,
+        };
+        Self {
+            language,
+            action,
+            available,
+        }
+    }
+
+    /// Whether it can run here: its program is installed.
+    pub fn is_available(&self) -> bool {
+        self.available
+    }
+
+    /// Whether the user has allowed it to run. Renderers that run nothing
+    /// need no permission.
+    pub fn is_allowed(&self) -> bool {
+        match self.consent_key() {
+            Some(key) => load_consent().contains(&key),
+            None => true,
+        }
+    }
+
+    /// Remember that the user allowed it to run.
+    pub fn allow(&self) -> Result<()> {
+        let Some(key) = self.consent_key() else {
+            return Ok(());
+        };
+        incognito::refuse("Remembering renderer permissions")?;
+        let path = consent_path().ok_or_else(|| Error::Config("No data directory to remember the permission in".to_string()))?;
+        let mut allowed = load_consent();
+        allowed.insert(key);
+        let json = serde_json::to_vec_pretty(&allowed)?;
+        storage::write_atomic(&path, json).map_err(Error::file(&path))
+    }
+
+    /// The language and the command line, so that changing the command asks again.
+    fn consent_key(&self) -> Option<String> {
+        self.command_line().map(|command| format!("{}: {}", self.language, command))
+    }
+
+    /// Render `code` to `path`, creating its directory. Programs only run once
+    /// the renderer [`is_allowed`](Self::is_allowed).
+    pub fn render(&self, code: &str, path: &Path) -> Result<()> {
+        incognito::refuse("Rendering code blocks")?;
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            storage::create_dir_all(dir).map_err(Error::file(dir))?;
+        }
+        let command = match &self.action {
+            Action::Save(_) => return storage::write_atomic(path, code).map_err(Error::file(path)),
+            Action::Run(command) => command,
+        };
+        if !self.is_allowed() {
+            return Err(Error::Config(format!("The {} renderer hasn't been allowed to run", self.language)));
+        }
+        let Some((program, args)) = command.command.split_first() else {
+            return Err(Error::Config(format!("The {} renderer's command is empty", self.language)));
+        };
+        let scratch = env::temp_dir().join(format!("cli_llm-render-{}-{}", std::process::id(), unique()));
+        storage::create_dir_all(&scratch).map_err(Error::file(&scratch))?;
+        let result = run(program, args, command.stdout, code, &scratch, path);
+        let _ = fs::remove_dir_all(&scratch);
+        result
+    }
+}
+
+/// Run `program` in `scratch` on `code`, leaving its result at `path`.
+fn run(program: &str, args: &[String], stdout: bool, code: &str, scratch: &Path, path: &Path) -> Result<()> {
+    let input = scratch.join("input");
+    storage::write_atomic(&input, code).map_err(Error::file(&input))?;
+    let output = scratch.join(format!(
+        "output.{}",
+        path.extension().and_then(|ext| ext.to_str()).unwrap_or("out")
+    ));
+    let mut command = Command::new(program);
+    command
+        .args(args.iter().map(|arg| {
+            arg.replace("{input}", &input.to_string_lossy())
+                .replace("{output}", &output.to_string_lossy())
+        }))
+        .current_dir(scratch)
+        .env_clear()
+        .stdin(if stdout { Stdio::piped() } else { Stdio::null() })
+        .stdout(if stdout { Stdio::piped() } else { Stdio::null() })
+        .stderr(Stdio::piped());
+    // Programs still need to be found, and some keep caches in the home directory.
+    for name in ["PATH", "HOME", "TMPDIR", "SYSTEMROOT"] {
+        if let Some(value) = env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+    log::info!("Rendering with {}", program);
+    let mut child = command.spawn().map_err(|e| Error::Config(format!("Cannot run {}: {}", program, e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let code = code.to_string();
+        thread::spawn(move || {
+            let _ = stdin.write_all(code.as_bytes());
+        });
+    }
+    let reader = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut bytes);
+            }
+            bytes
+        })
+    };
+    let out = reader(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let err = reader(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() < TIME_LIMIT => thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(Error::Config(format!("{} took longer than {}s and was stopped", program, TIME_LIMIT.as_secs())));
+            }
+            Err(e) => return Err(Error::Config(format!("Cannot run {}: {}", program, e))),
+        }
+    };
+    let out = out.join().unwrap_or_default();
+    let err = err.join().unwrap_or_default();
+    if !status.success() {
+        return Err(Error::Config(format!(
+            "{} failed ({}): {}",
+            program,
+            status,
+            String::from_utf8_lossy(&err).trim()
+        )));
+    }
+    if stdout {
+        return storage::write_atomic(path, out).map_err(Error::file(path));
+    }
+    let rendered = fs::read(&output)
+        .map_err(|_| Error::Config(format!("{} finished without writing {{output}}", program)))?;
+    storage::write_atomic(path, rendered).map_err(Error::file(path))
+}
+
+/// A number telling apart renders started by this process.
+fn unique() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The renderers by fence language: the built-in ones for `mermaid`, `dot`
+/// and `ics`, and those of the config, which take precedence.
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use llm::renderer::{Action, RendererCommand, Renderers};
+///
+/// let configured = BTreeMap::from([(
+///     "plantuml".to_string(),
+///     RendererCommand { command: vec!["plantuml".into(), "-tpng".into(), "-pipe".into()], output: "png".into(), stdout: true },
+/// )]);
+/// let renderers = Renderers::new(&configured);
+/// let reply = "```mermaid\ngraph TD; A-->B\n```\n```ical\nBEGIN:VCALENDAR\n```\n```rust\nfn main() {}\n```\n```plantuml\n@startuml\n```";
+/// let found: Vec<_> = renderers.blocks(reply).iter().map(|(_, renderer)| renderer.language.clone()).collect();
+/// assert_eq!(found, ["mermaid", "ics", "plantuml"]);
+/// assert_eq!(renderers.get("ics").unwrap().action, Action::Save("ics".into()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Renderers {
+    renderers: BTreeMap<String, Renderer>,
+}
+
+impl Renderers {
+    pub fn new(configured: &BTreeMap<String, RendererCommand>) -> Self {
+        let run = |language: &str, command: &[&str]| {
+            let command = RendererCommand {
+                command: command.iter().map(|arg| arg.to_string()).collect(),
+                output: default_output(),
+                stdout: false,
+            };
+            (language.to_string(), Action::Run(command))
+        };
+        let builtin = [
+            run("mermaid", &["mmdc", "-i", "{input}", "-o", "{output}"]),
+            run("dot", &["dot", "-Tpng", "{input}", "-o", "{output}"]),
+            ("ics".to_string(), Action::Save("ics".to_string())),
+        ];
+        let configured = configured
+            .iter()
+            .map(|(language, command)| (language.to_lowercase(), Action::Run(command.clone())));
+        let renderers = builtin
+            .into_iter()
+            .chain(configured)
+            .map(|(language, action)| (language.clone(), Renderer::new(language, action)))
+            .collect();
+        Self { renderers }
+    }
+
+    /// The renderer for blocks in `language`, which is matched without regard to
+    /// case and with its usual aliases (`graphviz`, `ical`, ...).
+    pub fn get(&self, language: &str) -> Option<&Renderer> {
+        let language = language.to_lowercase();
+        let canonical = match language.as_str() {
+            "mmd" => "mermaid",
+            "graphviz" | "gv" => "dot",
+            "ical" | "icalendar" | "vcalendar" => "ics",
+            other => other,
+        };
+        self.renderers.get(&language).or_else(|| self.renderers.get(canonical))
+    }
+
+    /// The code blocks of `text` that have a renderer, with it.
+    pub fn blocks(&self, text: &str) -> Vec<(CodeBlock, &Renderer)> {
+        markdown::code_blocks(text)
+            .into_iter()
+            .filter_map(|block| self.get(&block.language).map(|renderer| (block, renderer)))
+            .collect()
+    }
+}
+
+/// Where rendered blocks are kept: next to the saved conversations.
+pub fn renders_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cli_llm").join("renders"))
+}
+
+/// A file name for block `block` of message `message` (both from 1) of the
+/// conversation `title`, rendered by `renderer`.
+pub fn file_name(title: &str, message: usize, block: usize, renderer: &Renderer) -> String {
+    let stem: String = title
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_') { c } else { '-' })
+        .collect();
+    format!("{}-{}-{}.{}", stem.trim_matches('-'), message, block, renderer.extension())
+}
+
+/// Where the renderers the user allowed are remembered. Kept apart from the
+/// config, so that importing settings can't grant permission to run programs.
+fn consent_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cli_llm").join("allowed_renderers.json"))
+}
+
+fn load_consent() -> BTreeSet<String> {
+    let Some(path) = consent_path() else {
+        return BTreeSet::new();
+    };
+    match fs::read(&path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+            log::warn!("Ignoring {}: {}", path.display(), e);
+            BTreeSet::new()
+        }),
+        Err(_) => BTreeSet::new(),
+    }
+}
+
+/// The full path of `program` if it can be run: as given when it has a
+/// directory, else the first match on `PATH`.
+fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let names: Vec<String> = match cfg!(windows) {
+        true => ["", ".exe", ".cmd", ".bat"].iter().map(|ext| format!("{}{}", program, ext)).collect(),
+        false => vec![program.to_string()],
+    };
+    env::split_paths(&env::var_os("PATH")?).find_map(|dir| {
+        names.iter().map(|name| dir.join(name)).find(|candidate| candidate.is_file())
+    })
+}
@@ -0,0 +1,122 @@
+use crate::api::ChatMessageRequest;
+use crate::paste::{self, PasteLimits};
+
+/// A search and replace over the user's messages, for redoing a conversation
+/// with one term swapped for another.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Replace {
+    pub find: String,
+    pub replace: String,
+    /// Match letters in either case. Only ASCII letters are folded.
+    pub ignore_case: bool,
+}
+
+/// A user message a [`Replace`] changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Index of the message in the history.
+    pub index: usize,
+    /// The message as typed.
+    pub before: String,
+    pub after: String,
+    /// How many times the text was found in it.
+    pub count: usize,
+}
+
+impl Replace {
+    /// `text` with every occurrence replaced, and how many there were.
+    ///
+    /// ```
+    /// use llm::replace::Replace;
+    ///
+    /// let rename = Replace { find: "count".to_string(), replace: "total".to_string(), ignore_case: false };
+    /// assert_eq!(rename.apply("count the Count"), ("total the Count".to_string(), 1));
+    /// let rename = Replace { ignore_case: true, ..rename };
+    /// assert_eq!(rename.apply("count the Count"), ("total the total".to_string(), 2));
+    /// ```
+    pub fn apply(&self, text: &str) -> (String, usize) {
+        let mut replaced = String::with_capacity(text.len());
+        let mut count = 0;
+        let mut rest = text;
+        while let Some(at) = self.position(rest) {
+            replaced.push_str(&rest[..at]);
+            replaced.push_str(&self.replace);
+            rest = &rest[at + self.find.len()..];
+            count += 1;
+        }
+        replaced.push_str(rest);
+        (replaced, count)
+    }
+
+    /// Where `text` first has the text searched for.
+    fn position(&self, text: &str) -> Option<usize> {
+        if self.find.is_empty() {
+            return None;
+        }
+        if !self.ignore_case {
+            return text.find(&self.find);
+        }
+        text.char_indices().map(|(at, _)| at).find(|&at| {
+            text.get(at..at + self.find.len())
+                .is_some_and(|candidate| candidate.eq_ignore_ascii_case(&self.find))
+        })
+    }
+
+    /// The user messages of `messages` the replacement changes, in order,
+    /// matched as typed. Replies are left as they are.
+    ///
+    /// ```
+    /// use llm::api::ChatMessageRequest;
+    /// use llm::replace::Replace;
+    ///
+    /// let messages = [
+    ///     ChatMessageRequest::new("user", "Write fizzbuzz in Python."),
+    ///     ChatMessageRequest::new("assistant", "Here it is in Python: ..."),
+    ///     ChatMessageRequest::new("user", "Now make it print to stderr."),
+    ///     ChatMessageRequest::new("assistant", "Done."),
+    ///     ChatMessageRequest::new("user", "Add type hints, Python 3.12 style."),
+    /// ];
+    /// let rust = Replace { find: "Python".to_string(), replace: "Rust".to_string(), ignore_case: false };
+    /// let changes = rust.changes(&messages);
+    /// assert_eq!(changes.iter().map(|change| change.index).collect::<Vec<_>>(), [0, 4]);
+    /// assert_eq!(changes[1].after, "Add type hints, Rust 3.12 style.");
+    /// ```
+    pub fn changes(&self, messages: &[ChatMessageRequest]) -> Vec<Change> {
+        messages
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| message.role == "user" && !message.display_only)
+            .filter_map(|(index, message)| {
+                let (after, count) = self.apply(message.typed());
+                (count > 0).then(|| Change {
+                    index,
+                    before: message.typed().to_string(),
+                    after,
+                    count,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Change {
+    /// The lines that change, before and after, for previews. The whole message
+    /// is one pair when the replacement adds or removes lines.
+    pub fn lines(&self) -> Vec<(&str, &str)> {
+        let before: Vec<&str> = self.before.lines().collect();
+        let after: Vec<&str> = self.after.lines().collect();
+        if before.len() != after.len() {
+            return vec![(&self.before, &self.after)];
+        }
+        before.into_iter().zip(after).filter(|(before, after)| before != after).collect()
+    }
+
+    /// `message` with the replaced text, its long pasted blocks shortened again.
+    pub fn edit(&self, message: &ChatMessageRequest, limits: &PasteLimits) -> ChatMessageRequest {
+        let mut edited = message.clone();
+        edited.content = self.after.clone();
+        edited.untruncated = None;
+        paste::apply(&mut edited, limits);
+        edited
+    }
+}
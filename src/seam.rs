@@ -0,0 +1,164 @@
+//! Joining a continuation onto the partial reply it continues.
+//!
+//! A reply cut short is continued by sending what arrived back as an
+//! assistant prefill, without its trailing whitespace, which many backends
+//! refuse. Models then often start over a little: they repeat the word that
+//! was cut, restart the last line, or open with whitespace that is already
+//! shown. [`seam`] finds what to leave out so the joined text reads as one reply.
+
+use crate::api::ChatMessageRequest;
+
+/// Most characters of a continuation looked at for a repeat of the partial reply.
+pub const LOOKAHEAD: usize = 200;
+
+/// Repeats at least this long are left out wherever they start; shorter
+/// ones only when they restart a line or a word that was cut.
+const MIN_REPEAT: usize = 12;
+
+/// The assistant prefill continuing `partial`: the text without trailing
+/// whitespace.
+pub fn prefill(partial: &str) -> ChatMessageRequest {
+    ChatMessageRequest::new("assistant", partial.trim_end())
+}
+
+/// How many bytes at the start of `continuation` to leave out when appending
+/// it to `shown`, the partial reply as displayed, whose [`prefill`] the model
+/// continued.
+///
+/// What the continuation repeats of the end of `shown` is left out, and
+/// whitespace at the join appears once. Nothing is ever inserted: a word
+/// split in the middle joins up as it is.
+///
+/// ```
+/// use llm::seam::join;
+///
+/// // Inside a word: the cut word is restarted, or simply completed.
+/// assert_eq!(join("The quick brown fo", " fox jumps"), "The quick brown fox jumps");
+/// assert_eq!(join("The quick brown fo", "x jumps"), "The quick brown fox jumps");
+/// assert_eq!(join("I said no", " no more"), "I said no no more");
+///
+/// // Whitespace already shown isn't doubled, and isn't lost.
+/// assert_eq!(join("The quick ", " brown"), "The quick brown");
+/// assert_eq!(join("The quick ", "brown"), "The quick brown");
+/// assert_eq!(join("First line.\n", "\nSecond line."), "First line.\nSecond line.");
+/// assert_eq!(join("First paragraph.\n", "\n\nSecond."), "First paragraph.\n\nSecond.");
+/// ```
+///
+/// ```
+/// use llm::seam::join;
+///
+/// // After a code fence, whether or not the model repeats it.
+/// let code = "```rust\nfn main() {}\n```";
+/// assert_eq!(join(code, "\n\nThat's all."), "```rust\nfn main() {}\n```\n\nThat's all.");
+/// assert_eq!(join(code, "```\n\nThat's all."), "```rust\nfn main() {}\n```\n\nThat's all.");
+/// assert_eq!(join("```rust\nfn ma", "in() {}\n```"), "```rust\nfn main() {}\n```");
+///
+/// // Across list items: a restarted item is not listed twice.
+/// assert_eq!(join("- one\n- tw", "- two\n- three"), "- one\n- two\n- three");
+/// assert_eq!(join("- one\n- two\n", "- two\n- three"), "- one\n- two\n- three");
+/// assert_eq!(join("- one\n- two\n", "- three"), "- one\n- two\n- three");
+/// assert_eq!(join("1. one\n2.", " two"), "1. one\n2. two");
+/// ```
+pub fn seam(shown: &str, continuation: &str) -> usize {
+    let base = shown.trim_end();
+    let shown_space = &shown[base.len()..];
+    let trimmed = continuation.trim_start();
+    let lead = continuation.len() - trimmed.len();
+    let rest = match repeat(base, trimmed) {
+        Some(len) => lead + len,
+        None => 0,
+    };
+    let after = &continuation[rest..];
+    let space = after.len() - after.trim_start().len();
+    // Keep the whitespace that is shown, plus what the model adds to it.
+    if after[..space].starts_with(shown_space) {
+        rest + shown_space.len()
+    } else {
+        rest + space
+    }
+}
+
+/// `shown` with `continuation` appended as [`seam`] joins them.
+pub fn join(shown: &str, continuation: &str) -> String {
+    format!("{}{}", shown, &continuation[seam(shown, continuation)..])
+}
+
+/// The length in bytes of the longest start of `text` that repeats the end of
+/// `base` and is worth leaving out.
+fn repeat(base: &str, text: &str) -> Option<usize> {
+    let ends: Vec<usize> = text
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .take(LOOKAHEAD)
+        .collect();
+    ends.into_iter().rev().find(|&len| {
+        let repeated = &text[..len];
+        let Some(before) = base.strip_suffix(repeated) else {
+            return false;
+        };
+        let line_start = before.is_empty() || before.ends_with('\n');
+        let word_start = before.is_empty() || before.ends_with(char::is_whitespace);
+        // The model restarted a word that was cut, like "fo" then "fox".
+        let cut_word = repeated.chars().all(is_word) && text[len..].starts_with(is_word);
+        line_start || (word_start && (repeated.chars().count() >= MIN_REPEAT || cut_word))
+    })
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Feeds a streamed continuation through [`seam`]: the first deltas are held
+/// back until there is enough text to tell what repeats the partial reply.
+///
+/// ```
+/// use llm::seam::Seam;
+///
+/// let mut seam = Seam::new("Steps:\n1. Mix\n2. Ba");
+/// let deltas = ["2. Bake", " for an hour", "\n3. Serve"];
+/// let joined: String = deltas.iter().map(|delta| seam.push(delta)).collect::<String>() + &seam.finish();
+/// assert_eq!(joined, "ke for an hour\n3. Serve");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Seam {
+    shown: String,
+    held: String,
+    settled: bool,
+}
+
+impl Seam {
+    /// For a continuation of `shown`, the partial reply as displayed.
+    pub fn new(shown: &str) -> Self {
+        Self {
+            shown: shown.to_string(),
+            held: String::new(),
+            settled: false,
+        }
+    }
+
+    /// The part of `delta` to append, possibly with deltas held back before it.
+    pub fn push(&mut self, delta: &str) -> String {
+        if self.settled {
+            return delta.to_string();
+        }
+        self.held.push_str(delta);
+        if self.held.trim_start().chars().count() <= LOOKAHEAD {
+            return String::new();
+        }
+        self.settle()
+    }
+
+    /// What is still held back, once the continuation has ended.
+    pub fn finish(&mut self) -> String {
+        if self.settled {
+            return String::new();
+        }
+        self.settle()
+    }
+
+    fn settle(&mut self) -> String {
+        self.settled = true;
+        let held = std::mem::take(&mut self.held);
+        held[seam(&self.shown, &held)..].to_string()
+    }
+}
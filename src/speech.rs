@@ -0,0 +1,154 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+/// A system text-to-speech program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    /// macOS `say`.
+    Say,
+    /// `espeak-ng` or `espeak` on Linux and BSD.
+    Espeak(&'static str),
+    /// The .NET speech synthesizer through PowerShell on Windows.
+    PowerShell,
+}
+
+/// Reads text aloud through the platform's speech program, one text at a time.
+///
+/// Playback runs in a child process, so speaking never blocks the caller;
+/// starting a new text or calling [`Speaker::stop`] ends the current one.
+pub struct Speaker {
+    backend: Backend,
+    playing: Option<Child>,
+}
+
+impl Speaker {
+    /// Find a speech program on this system. `None` when there is none, in which
+    /// case callers should turn speech off with a warning.
+    pub fn detect() -> Option<Self> {
+        let backend = if cfg!(target_os = "macos") && on_path("say") {
+            Backend::Say
+        } else if cfg!(windows) && on_path("powershell") {
+            Backend::PowerShell
+        } else if let Some(program) = ["espeak-ng", "espeak"].into_iter().find(|p| on_path(p)) {
+            Backend::Espeak(program)
+        } else {
+            return None;
+        };
+        log::info!("Text-to-speech: {:?}", backend);
+        Some(Self { backend, playing: None })
+    }
+
+    /// Start reading `text` aloud, stopping anything still being read.
+    pub fn speak(&mut self, text: &str) -> io::Result<()> {
+        self.stop();
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+        // The text goes through stdin to stay clear of command-line length limits.
+        let mut command = match self.backend {
+            Backend::Say => Command::new("say"),
+            Backend::Espeak(program) => {
+                let mut command = Command::new(program);
+                command.arg("--stdin");
+                command
+            }
+            Backend::PowerShell => {
+                let mut command = Command::new("powershell");
+                command.args([
+                    "-NoProfile",
+                    "-Command",
+                    "Add-Type -AssemblyName System.Speech; \
+                     (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak([Console]::In.ReadToEnd())",
+                ]);
+                command
+            }
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        // Speech programs may read slowly, so don't let a long text block the caller.
+        if let Some(mut stdin) = child.stdin.take() {
+            let text = text.to_string();
+            thread::spawn(move || stdin.write_all(text.as_bytes()));
+        }
+        self.playing = Some(child);
+        Ok(())
+    }
+
+    /// Whether something is still being read aloud.
+    pub fn is_speaking(&mut self) -> bool {
+        match self.playing.as_mut().map(Child::try_wait) {
+            Some(Ok(None)) => true,
+            Some(_) => {
+                self.playing = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Wait until the current text has been read to the end.
+    pub fn wait(&mut self) {
+        if let Some(mut child) = self.playing.take() {
+            let _ = child.wait();
+        }
+    }
+
+    /// Stop reading aloud.
+    pub fn stop(&mut self) {
+        if let Some(mut child) = self.playing.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Speaker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The parts of a markdown reply worth reading aloud: code blocks are replaced
+/// by a short mention unless `read_code` is set, and formatting marks are dropped.
+pub fn speech_text(reply: &str, read_code: bool) -> String {
+    let mut text = String::new();
+    let mut in_code_block = false;
+    for line in reply.lines() {
+        if line.trim_start().starts_with("```") {
+            if !in_code_block && !read_code {
+                text.push_str("(code block)\n");
+            }
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block && !read_code {
+            continue;
+        }
+        let line = line.trim_start_matches(['#', '>', ' ']);
+        let line = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")).unwrap_or(line);
+        text.extend(line.chars().filter(|c| !matches!(c, '*' | '`' | '_')));
+        text.push('\n');
+    }
+    text
+}
+
+/// Whether `program` can be found in a `PATH` directory.
+fn on_path(program: &str) -> bool {
+    let Some(paths) = env::var_os("PATH") else {
+        return false;
+    };
+    env::split_paths(&paths).any(|dir| {
+        let candidate = dir.join(program);
+        is_file(&candidate) || (cfg!(windows) && is_file(&candidate.with_extension("exe")))
+    })
+}
+
+fn is_file(path: &Path) -> bool {
+    path.metadata().is_ok_and(|meta| meta.is_file())
+}
@@ -0,0 +1,144 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::incognito;
+
+/// Create `dir` and its missing parents. Nothing is created in
+/// [incognito](crate::incognito) mode.
+pub fn create_dir_all(dir: &Path) -> io::Result<()> {
+    incognito::check(dir)?;
+    fs::create_dir_all(dir)
+}
+
+/// Open `path` with `options`, for a file written a piece at a time rather
+/// than all at once with [`write_atomic`]. Nothing is opened in
+/// [incognito](crate::incognito) mode.
+pub fn open(path: &Path, options: &OpenOptions) -> io::Result<File> {
+    incognito::check(path)?;
+    options.open(path)
+}
+
+/// Replace the file at `path` with `contents` so that readers, including other
+/// running instances, see either the old file or the new one and never a partial write.
+///
+/// The data goes to a temporary file in the same directory, which is then renamed
+/// over `path`. Missing parent directories are created. Nothing is written in
+/// [incognito](crate::incognito) mode.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    incognito::check(path)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    let name = path.file_name().map_or_else(|| "file".into(), |name| name.to_string_lossy());
+    // Threads of one process writing at once each need their own temporary file.
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let write = WRITES.fetch_add(1, Ordering::Relaxed);
+    let temp = dir.join(format!(".{}.{}.{}.tmp", name, process::id(), write));
+
+    let result = fs::File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::thread;
+
+    #[test]
+    fn creates_parents_and_replaces() {
+        let dir = TempDir::new("storage", "replace");
+        let path = dir.0.join("nested").join("config.toml");
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(dir.names(), ["nested"]);
+        assert_eq!(fs::read_dir(dir.0.join("nested")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn failed_write_leaves_the_file_and_no_temporary() {
+        let dir = TempDir::new("storage", "failed");
+        // Renaming a file over a directory fails on every platform.
+        let path = dir.0.join("taken");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inside"), "kept").unwrap();
+        assert!(write_atomic(&path, "lost").is_err());
+        assert_eq!(dir.names(), ["taken"]);
+        assert_eq!(fs::read_to_string(path.join("inside")).unwrap(), "kept");
+    }
+
+    #[test]
+    fn readers_never_see_a_partial_write() {
+        let dir = TempDir::new("storage", "concurrent");
+        let path = dir.0.join("settings.json");
+        let versions: Vec<String> = (0..4).map(|n| n.to_string().repeat(64 * 1024)).collect();
+        write_atomic(&path, &versions[0]).unwrap();
+        let writers: Vec<_> = versions
+            .iter()
+            .cloned()
+            .map(|version| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        write_atomic(&path, &version).unwrap();
+                    }
+                })
+            })
+            .collect();
+        while !writers.iter().all(|writer| writer.is_finished()) {
+            let text = fs::read_to_string(&path).unwrap();
+            assert!(versions.contains(&text), "read a partial write of {} bytes", text.len());
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(dir.names(), ["settings.json"]);
+    }
+
+    /// Windows refuses to rename over a file that is open unless it was opened
+    /// for sharing deletes, which the standard library does.
+    #[cfg(windows)]
+    #[test]
+    fn replaces_a_file_open_for_reading() {
+        use std::io::Read;
+
+        let dir = TempDir::new("storage", "open");
+        let path = dir.0.join("settings.json");
+        write_atomic(&path, "old").unwrap();
+        let mut reader = File::open(&path).unwrap();
+        write_atomic(&path, "new").unwrap();
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "old");
+        drop(reader);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(dir.names(), ["settings.json"]);
+    }
+
+    /// The temporary directory on macOS is under `/var`, a link to
+    /// `/private/var`; the temporary file must land next to the target either way.
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn writes_through_the_linked_temporary_directory() {
+        let dir = TempDir::new("storage", "linked");
+        let real = dir.0.canonicalize().unwrap();
+        write_atomic(&dir.0.join("config.toml"), "first").unwrap();
+        write_atomic(&real.join("config.toml"), "second").unwrap();
+        assert_eq!(fs::read_to_string(dir.0.join("config.toml")).unwrap(), "second");
+        assert_eq!(dir.names(), ["config.toml"]);
+    }
+}
@@ -0,0 +1,642 @@
+use std::collections::VecDeque;
+use std::pin::{Pin, pin};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use futures_util::future::{self, Either};
+use futures_util::stream::{self, Stream};
+use futures_util::StreamExt;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::api::{self, Annotation, ChatMessage, Citation, FunctionCall, ToolCall, Usage};
+use crate::error::{Error, Result};
+use crate::recorder::Exchange;
+use crate::timeout;
+
+/// A single server-sent event chunk of a streamed chat completion.
+#[derive(Deserialize, Debug)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    /// Token usage, sent with the final chunk when requested.
+    usage: Option<Usage>,
+    /// The provider OpenRouter routed the request to.
+    #[serde(default)]
+    provider: Option<String>,
+}
+
+/// How a completed stream ended.
+#[derive(Debug, Clone, Default)]
+pub struct StreamEnd {
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+    /// Web sources cited by the reply, gathered from all chunks.
+    pub citations: Vec<Citation>,
+    /// The provider that served the reply.
+    pub provider: Option<String>,
+    /// The tool calls of the reply, put together from their fragments.
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// One choice inside a streamed chunk.
+#[derive(Deserialize, Debug)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: StreamDelta,
+    /// The new text, from the text completions endpoint.
+    #[serde(default)]
+    text: Option<String>,
+    finish_reason: Option<String>,
+}
+
+/// The incremental content carried by a streamed choice.
+#[derive(Deserialize, Debug, Default)]
+struct StreamDelta {
+    content: Option<String>,
+    /// The model's reasoning, from models that show it.
+    #[serde(default)]
+    reasoning: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallFragment>,
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+}
+
+/// One piece of a streamed reply, as [`chat_stream`] yields them.
+#[derive(Debug, Clone)]
+pub enum Delta {
+    /// Text of the reply.
+    Content(String),
+    /// Text of the model's reasoning, from models that show it.
+    Reasoning(String),
+    /// Part of a tool call the model is making.
+    ToolCall(ToolCallFragment),
+    /// The last item of a complete stream: why it ended, the usage, and the
+    /// citations, provider and whole tool calls gathered from all chunks.
+    End(StreamEnd),
+}
+
+/// Part of a tool call. Fragments with the same `index` make up one call: the
+/// first has its `id` and function name, and the arguments arrive in pieces.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ToolCallFragment {
+    #[serde(default)]
+    pub index: usize,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub function: FunctionFragment,
+}
+
+impl ToolCallFragment {
+    /// Add this fragment to the call with its `index` in `calls`, starting the
+    /// call when it is the first.
+    pub fn add_to(&self, calls: &mut Vec<ToolCall>) {
+        if calls.len() <= self.index {
+            calls.resize_with(self.index + 1, || ToolCall::function("", "", ""));
+        }
+        let call = &mut calls[self.index];
+        if let Some(id) = &self.id {
+            call.id.clone_from(id);
+        }
+        let FunctionCall { name, arguments } = &mut call.function;
+        if let Some(fragment) = &self.function.name {
+            name.push_str(fragment);
+        }
+        if let Some(fragment) = &self.function.arguments {
+            arguments.push_str(fragment);
+        }
+    }
+}
+
+/// The function part of a [`ToolCallFragment`].
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FunctionFragment {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: Option<String>,
+}
+
+/// When one streamed chunk arrived, relative to the previous one.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkTiming {
+    /// Byte offset in the message content where the chunk ends.
+    pub end: usize,
+    /// Time since the previous chunk (or since the request, for the first one).
+    pub gap: Duration,
+}
+
+/// Splits a byte stream into the `data:` payloads of server-sent events.
+///
+/// Chunks may end anywhere, even inside a multi-byte UTF-8 character, so bytes are
+/// buffered until a whole line has arrived and only complete lines are decoded.
+/// A line break byte never occurs inside a multi-byte character, so a character
+/// split across chunks is always put back together before decoding.
+#[derive(Default)]
+struct SseDecoder {
+    buf: Vec<u8>,
+}
+
+impl SseDecoder {
+    /// Feed raw bytes and return the data payloads of every complete line.
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(bytes);
+        let mut payloads = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            payloads.extend(payload(&line));
+        }
+        payloads
+    }
+
+    /// The payload of a last line the server sent without a line break.
+    fn finish(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.buf);
+        payload(&line)
+    }
+}
+
+/// The `data:` payload of one event stream line, if it is a data line.
+///
+/// Bytes that are not valid UTF-8 (a broken server, or a body cut off inside a
+/// character) show up as U+FFFD rather than failing the reply, and are logged.
+fn payload(line: &[u8]) -> Option<String> {
+    let line = match std::str::from_utf8(line) {
+        Ok(line) => line.into(),
+        Err(e) => {
+            log::warn!("Invalid UTF-8 in the stream at byte {} of a line; replacing it", e.valid_up_to());
+            String::from_utf8_lossy(line)
+        }
+    };
+    let line = line.trim_end_matches(['\r', '\n']);
+    // Lines starting with ':' are comments (OpenRouter sends keep-alives).
+    line.strip_prefix("data:").map(|data| data.trim_start().to_string())
+}
+
+/// How a reply is coming along, for showing while it streams: its estimated
+/// tokens, characters and the time since the request was sent. The rate is
+/// left out for the first second, when it means little.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// assert_eq!(llm::stream::progress(0, Duration::from_millis(800)), "0 chars · 0.8 s");
+/// assert_eq!(llm::stream::progress(480, Duration::from_secs(4)), "~120 tokens · 480 chars · 4.0 s · 30 tok/s");
+/// ```
+pub fn progress(chars: usize, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    // Four characters a token, as tokenizer::Estimate counts.
+    let tokens = chars.div_ceil(4);
+    let mut line = if tokens > 0 {
+        format!("~{} tokens · {} chars · {:.1} s", tokens, chars, secs)
+    } else {
+        format!("{} chars · {:.1} s", chars, secs)
+    };
+    if tokens > 0 && secs >= 1.0 {
+        line.push_str(&format!(" · {:.0} tok/s", tokens as f64 / secs));
+    }
+    line
+}
+
+/// Stops a streamed reply from elsewhere, such as a thread watching the
+/// keyboard. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct StopSignal(Arc<StopState>);
+
+#[derive(Debug, Default)]
+struct StopState {
+    stopped: AtomicBool,
+    notify: Notify,
+}
+
+impl StopSignal {
+    /// Stop the reply streaming now, or the next one if none is.
+    pub fn stop(&self) {
+        self.0.stopped.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    /// Let replies stream again.
+    pub fn reset(&self) {
+        self.0.stopped.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once [`stop`](Self::stop) was called.
+    async fn stopped(&self) {
+        loop {
+            // Registered before the check, so a stop in between still wakes it.
+            let notified = self.0.notify.notified();
+            if self.is_stopped() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Send a streaming chat request and feed every content delta to `on_delta`.
+///
+/// Returns the finish reason and usage once the server signals completion. Content that
+/// was already passed to `on_delta` stays delivered even when an error is returned,
+/// so the caller can keep the partial answer. A stream that goes quiet for longer
+/// than the idle [`timeout`] fails with [`Error::Timeout`].
+pub async fn stream_chat<B: Serialize>(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    body: &B,
+    on_delta: impl FnMut(&str),
+) -> Result<StreamEnd> {
+    stream_chat_until(client, url, headers, body, &StopSignal::default(), on_delta).await
+}
+
+/// [`stream_chat`], ending early with [`Error::Stopped`] when `stop` is
+/// signalled. What arrived before that stays delivered.
+pub async fn stream_chat_until<B: Serialize>(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    body: &B,
+    stop: &StopSignal,
+    mut on_delta: impl FnMut(&str),
+) -> Result<StreamEnd> {
+    let deltas = chat_stream(client, url, headers, body);
+    tokio::select! {
+        result = forward(deltas, &mut on_delta) => result,
+        () = stop.stopped() => Err(Error::Stopped),
+    }
+}
+
+/// Pass the content of `deltas` on to `on_delta`, until the stream ends.
+async fn forward(deltas: impl Stream<Item = Result<Delta>>, on_delta: &mut impl FnMut(&str)) -> Result<StreamEnd> {
+    let mut deltas = pin!(deltas);
+    while let Some(delta) = deltas.next().await {
+        match delta? {
+            Delta::Content(text) => on_delta(&text),
+            Delta::End(end) => return Ok(end),
+            Delta::Reasoning(_) | Delta::ToolCall(_) => {}
+        }
+    }
+    Err(Error::Interrupted)
+}
+
+/// Send a streaming chat request, and read its reply as a stream of
+/// [`Delta`]s ending with [`Delta::End`].
+///
+/// The response is read as the deltas are asked for, so a slow consumer slows
+/// down the download rather than having it buffered, and dropping the stream
+/// closes the connection. An error ends the stream; deltas before it stay
+/// delivered. The request is sent on the first poll. It fails with
+/// [`Error::Timeout`] when the server doesn't answer, or goes quiet, for longer
+/// than the [`timeout`] settings allow.
+pub fn chat_stream<B: Serialize>(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    body: &B,
+) -> impl Stream<Item = Result<Delta>> + Send + 'static {
+    let mut exchange = Exchange::start(url, headers, body);
+    let send = client.post(url).headers(headers.clone()).json(body).send();
+    let reader = async move {
+        let response = timeout::within(timeout::get().first_byte, "no response", async { Ok(send.await?) }).await;
+        let response = match response {
+            Ok(response) => response,
+            Err(error) => {
+                exchange.finish(Some(&error));
+                return Err(error);
+            }
+        };
+        log::debug!("Stream response status={}", response.status());
+        let status = response.status();
+        exchange.status(status.as_u16());
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            exchange.response(&text);
+            let error = Error::from_response(status, &text);
+            exchange.finish(Some(&error));
+            return Err(error);
+        }
+        let bytes = response.bytes_stream().map(|chunk| chunk.map_err(Error::from));
+        Ok(Reader::new(bytes, Some(exchange)))
+    };
+    stream::once(reader).flat_map(|reader| match reader {
+        Ok(reader) => Either::Left(reader.into_stream()),
+        Err(error) => Either::Right(stream::once(future::ready(Err(error)))),
+    })
+}
+
+/// The [`Delta`]s of a streamed response body, read as they are asked for.
+/// Like [`chat_stream`], but from a body already at hand, such as a recording.
+///
+/// ```
+/// use futures_util::{stream, StreamExt};
+/// use llm::stream::{decode, Delta};
+///
+/// let body = [
+///     "data: {\"choices\":[{\"delta\":{\"reasoning\":\"The user wants the time.\"}}]}\n\n",
+///     "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",",
+///     "\"function\":{\"name\":\"now\",\"arguments\":\"{}\"}}]}}]}\n\n",
+///     "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\ndata: [DONE]\n\n",
+/// ];
+/// let deltas = decode(stream::iter(body.map(Ok)));
+/// let deltas: Vec<_> = tokio::runtime::Runtime::new().unwrap().block_on(deltas.collect());
+/// assert!(matches!(&deltas[0], Ok(Delta::Reasoning(text)) if text == "The user wants the time."));
+/// assert!(matches!(&deltas[1], Ok(Delta::ToolCall(call)) if call.function.name.as_deref() == Some("now")));
+/// let Ok(Delta::End(end)) = &deltas[2] else { panic!("expected the end") };
+/// assert_eq!(end.finish_reason.as_deref(), Some("tool_calls"));
+/// assert_eq!((end.tool_calls[0].id.as_str(), end.tool_calls[0].function.name.as_str()), ("call_1", "now"));
+/// assert_eq!(deltas.len(), 3);
+/// ```
+pub fn decode<S, B>(bytes: S) -> impl Stream<Item = Result<Delta>>
+where
+    S: Stream<Item = Result<B>>,
+    B: AsRef<[u8]>,
+{
+    Reader::new(bytes, None).into_stream()
+}
+
+/// Put a [`chat_stream`] together into the reply the non-streaming
+/// [`api::fetch_reply`] returns for the same request: the first choice, with its
+/// citations and provider, and the usage. A stream that fails or ends early is
+/// an error, even when some content arrived.
+///
+/// ```
+/// use futures_util::stream;
+/// use llm::api;
+/// use llm::stream::{collect_reply, decode};
+///
+/// let body = [
+///     "data: {\"provider\":\"Acme\",\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+///     ": keep-alive\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"lo ",
+///     "wörld\"},\"finish_reason\":\"stop\"}],",
+///     "\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":3,\"total_tokens\":8}}\n\ndata: [DONE]\n",
+/// ];
+/// let reply = collect_reply(decode(stream::iter(body.map(Ok))));
+/// let (streamed, streamed_usage) = tokio::runtime::Runtime::new().unwrap().block_on(reply).unwrap();
+///
+/// let response = r#"{"id": "gen-1", "object": "chat.completion", "created": 0, "provider": "Acme",
+///     "choices": [{"message": {"role": "assistant", "content": "Hello wörld"}, "finish_reason": "stop"}],
+///     "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8}}"#;
+/// let (fetched, fetched_usage) = api::parse_reply(response).unwrap();
+///
+/// assert_eq!(streamed.content, fetched.content);
+/// assert_eq!(streamed.role, fetched.role);
+/// assert_eq!(streamed.provider, fetched.provider);
+/// assert_eq!(streamed_usage.map(|u| u.total_tokens), fetched_usage.map(|u| u.total_tokens));
+/// ```
+pub async fn collect_reply(deltas: impl Stream<Item = Result<Delta>>) -> Result<(ChatMessage, Option<Usage>)> {
+    let mut content = String::new();
+    let end = forward(deltas, &mut |delta| content.push_str(delta)).await?;
+    let annotations = end
+        .citations
+        .into_iter()
+        .map(|citation| Annotation {
+            kind: "url_citation".to_string(),
+            url_citation: Some(citation),
+        })
+        .collect();
+    let message = ChatMessage {
+        role: "assistant".to_string(),
+        content,
+        tool_calls: end.tool_calls,
+        annotations,
+        provider: end.provider,
+    };
+    Ok((message, end.usage))
+}
+
+/// Reads [`Delta`]s from a response body, one network chunk at a time, as they
+/// are asked for; only the deltas of the last chunk are held.
+struct Reader<S> {
+    bytes: Pin<Box<S>>,
+    decoder: SseDecoder,
+    /// Deltas of the last chunk not yet asked for.
+    pending: VecDeque<Delta>,
+    end: StreamEnd,
+    /// The flight recorder's record of the request, until the stream ends.
+    exchange: Option<Exchange>,
+    done: bool,
+}
+
+impl<S, B> Reader<S>
+where
+    S: Stream<Item = Result<B>>,
+    B: AsRef<[u8]>,
+{
+    fn new(bytes: S, exchange: Option<Exchange>) -> Self {
+        Self {
+            bytes: Box::pin(bytes),
+            decoder: SseDecoder::default(),
+            pending: VecDeque::new(),
+            end: StreamEnd::default(),
+            exchange,
+            done: false,
+        }
+    }
+
+    fn into_stream(self) -> impl Stream<Item = Result<Delta>> {
+        stream::unfold(self, |mut reader| async move { reader.next().await.map(|delta| (delta, reader)) })
+    }
+
+    async fn next(&mut self) -> Option<Result<Delta>> {
+        loop {
+            if let Some(delta) = self.pending.pop_front() {
+                return Some(Ok(delta));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(error) = self.read().await {
+                self.done = true;
+                self.pending.clear();
+                if let Some(exchange) = self.exchange.take() {
+                    exchange.finish(Some(&error));
+                }
+                return Some(Err(error));
+            }
+        }
+    }
+
+    /// Read and decode the next chunk of the body, ending the stream after the
+    /// last one.
+    async fn read(&mut self) -> Result<()> {
+        // Anything arriving, keep-alive comments included, shows the stream is alive.
+        let next = timeout::within(timeout::get().idle, "nothing received", async { Ok(self.bytes.next().await) });
+        let Some(chunk) = next.await? else {
+            // A last line without a line break counts if it is complete; a cut-off one
+            // means the connection dropped.
+            if let Some(data) = self.decoder.finish() {
+                match self.handle(&data) {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => {}
+                    Err(e) => log::debug!("Ignoring an incomplete last line: {}", e),
+                }
+            }
+            // Some servers close the body without `[DONE]` but after a finish reason.
+            if self.end.finish_reason.is_none() {
+                return Err(Error::Interrupted);
+            }
+            self.complete();
+            return Ok(());
+        };
+        for data in self.decoder.push(chunk?.as_ref()) {
+            if self.handle(&data)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode one event payload. Returns whether it completed the stream.
+    fn handle(&mut self, data: &str) -> Result<bool> {
+        let pending = &mut self.pending;
+        let exchange = &mut self.exchange;
+        let done = handle_event(data, &mut self.end, &mut |delta| {
+            if let (Delta::Content(text), Some(exchange)) = (&delta, exchange.as_mut()) {
+                exchange.delta(text);
+            }
+            pending.push_back(delta);
+        })?;
+        if done {
+            self.complete();
+        }
+        Ok(done)
+    }
+
+    /// End the stream with [`Delta::End`].
+    fn complete(&mut self) {
+        self.done = true;
+        if let Some(mut exchange) = self.exchange.take() {
+            exchange.finish_reason(self.end.finish_reason.as_deref());
+            exchange.finish(None);
+        }
+        self.pending.push_back(Delta::End(std::mem::take(&mut self.end)));
+    }
+}
+
+/// Apply one event payload to `end`, passing its deltas on to `emit`.
+/// Returns whether it was the final `[DONE]` event.
+fn handle_event(data: &str, end: &mut StreamEnd, emit: &mut impl FnMut(Delta)) -> Result<bool> {
+    if data == "[DONE]" {
+        return Ok(true);
+    }
+    let parsed: StreamChunk = serde_json::from_str(data)?;
+    if parsed.usage.is_some() {
+        end.usage = parsed.usage;
+    }
+    if parsed.provider.is_some() {
+        end.provider = parsed.provider;
+    }
+    for choice in parsed.choices {
+        let delta = choice.delta;
+        if let Some(reasoning) = delta.reasoning.filter(|reasoning| !reasoning.is_empty()) {
+            emit(Delta::Reasoning(reasoning));
+        }
+        if let Some(content) = delta.content.or(choice.text).filter(|content| !content.is_empty()) {
+            emit(Delta::Content(content));
+        }
+        for call in delta.tool_calls {
+            call.add_to(&mut end.tool_calls);
+            emit(Delta::ToolCall(call));
+        }
+        api::collect_citations(&mut end.citations, &delta.annotations);
+        if choice.finish_reason.is_some() {
+            end.finish_reason = choice.finish_reason;
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An event carrying `content`, as OpenRouter sends it.
+    fn event(content: &str) -> String {
+        format!("data: {}\n\n", serde_json::json!({ "choices": [{ "delta": { "content": content } }] }))
+    }
+
+    /// The reply text `decode` puts together from `chunks` of the body.
+    async fn decoded(chunks: Vec<Vec<u8>>) -> String {
+        let deltas: Vec<_> = decode(stream::iter(chunks.into_iter().map(Ok))).collect().await;
+        deltas
+            .into_iter()
+            .filter_map(|delta| match delta.unwrap() {
+                Delta::Content(text) => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Two-, three- and four-byte characters.
+    const TEXT: &str = "héllo €5 🦀 日本";
+
+    #[test]
+    fn split_at_every_byte() {
+        let body = event(TEXT);
+        let bytes = body.as_bytes();
+        let whole = SseDecoder::default().push(bytes);
+        for at in 1..bytes.len() {
+            let mut decoder = SseDecoder::default();
+            let mut payloads = decoder.push(&bytes[..at]);
+            payloads.extend(decoder.push(&bytes[at..]));
+            assert_eq!(payloads, whole, "split at byte {at}");
+        }
+    }
+
+    #[test]
+    fn one_byte_at_a_time() {
+        let body = event(TEXT) + &event("🦀");
+        let mut decoder = SseDecoder::default();
+        let payloads: Vec<_> = body.bytes().flat_map(|byte| decoder.push(&[byte])).collect();
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads[0].contains(TEXT));
+        assert!(payloads[1].contains("🦀"));
+        assert_eq!(decoder.finish(), None);
+    }
+
+    #[test]
+    fn last_line_without_a_break() {
+        let mut decoder = SseDecoder::default();
+        let body = event("€");
+        let body = body.trim_end().as_bytes();
+        let (head, tail) = body.split_at(body.len() - 6);
+        assert!(decoder.push(head).is_empty());
+        assert!(decoder.push(tail).is_empty());
+        assert!(decoder.finish().unwrap().contains('€'));
+    }
+
+    #[test]
+    fn invalid_bytes_become_replacement_characters() {
+        let mut decoder = SseDecoder::default();
+        // A body cut off two bytes into the three-byte "€".
+        let body = b"data: caf\xe2\x82";
+        assert!(decoder.push(body).is_empty());
+        assert_eq!(decoder.finish().as_deref(), Some("caf\u{fffd}"));
+
+        let payloads = decoder.push(b"data: a\xffb\n");
+        assert_eq!(payloads, ["a\u{fffd}b"]);
+    }
+
+    #[tokio::test]
+    async fn decode_reassembles_split_characters() {
+        let body = event(TEXT) + &event(" 🦀") + "data: [DONE]\n\n";
+        let bytes = body.into_bytes();
+        let bytewise = bytes.iter().map(|&byte| vec![byte]).collect();
+        assert_eq!(decoded(bytewise).await, format!("{TEXT} 🦀"));
+        // Every split of the crab's four bytes between two chunks.
+        let crab = bytes.windows(4).position(|w| w == "🦀".as_bytes()).unwrap();
+        for at in crab + 1..crab + 4 {
+            let chunks = vec![bytes[..at].to_vec(), bytes[at..].to_vec()];
+            assert_eq!(decoded(chunks).await, format!("{TEXT} 🦀"), "split at byte {at}");
+        }
+    }
+}
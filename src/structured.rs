@@ -0,0 +1,361 @@
+use std::cmp::Ordering;
+
+use serde_json::Value;
+
+/// Data found in a reply or a code block, as opposed to prose.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Structured {
+    Json(Value),
+    Table(Table),
+}
+
+/// Recognize `text` as JSON or as a CSV/TSV table.
+///
+/// Detection is deliberately strict: only well-formed content is recognized, so
+/// prose that happens to contain brackets or commas is left alone.
+pub fn detect(text: &str) -> Option<Structured> {
+    detect_json(text)
+        .map(Structured::Json)
+        .or_else(|| detect_table(text).map(Structured::Table))
+}
+
+/// `text` parsed as JSON when it is a non-empty object or array and nothing else.
+pub fn detect_json(text: &str) -> Option<Value> {
+    let text = text.trim();
+    let bracketed = (text.starts_with('{') && text.ends_with('}')) || (text.starts_with('[') && text.ends_with(']'));
+    if !bracketed {
+        return None;
+    }
+    let value: Value = serde_json::from_str(text).ok()?;
+    let non_empty = match &value {
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => false,
+    };
+    non_empty.then_some(value)
+}
+
+/// A table with a header row, as read from CSV or TSV.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// `,` for CSV, `\t` for TSV.
+    pub delimiter: char,
+}
+
+impl Table {
+    /// Sort the rows by `column`, comparing numbers as numbers.
+    pub fn sort(&mut self, column: usize, ascending: bool) {
+        self.rows.sort_by(|a, b| {
+            let ordering = compare_cells(&a[column], &b[column]);
+            if ascending { ordering } else { ordering.reverse() }
+        });
+    }
+
+    /// The table as CSV, quoting fields where needed.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for row in std::iter::once(&self.header).chain(&self.rows) {
+            let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+/// `text` parsed as a table when every line splits into the same number of fields.
+///
+/// Tab-separated text needs a header and one row; comma-separated text needs a
+/// header and two rows, a header of distinct non-empty names, and no field ending
+/// like a sentence, since short runs of prose often contain one comma per line.
+pub fn detect_table(text: &str) -> Option<Table> {
+    let lines: Vec<&str> = text.trim().lines().map(|line| line.trim_end_matches('\r')).collect();
+    if lines.iter().any(|line| line.trim().is_empty()) {
+        return None;
+    }
+    let delimiter = if lines.iter().all(|line| line.contains('\t')) { '\t' } else { ',' };
+    let min_lines = if delimiter == '\t' { 2 } else { 3 };
+    if lines.len() < min_lines {
+        return None;
+    }
+
+    let mut records = Vec::with_capacity(lines.len());
+    for line in &lines {
+        records.push(split_record(line, delimiter)?);
+    }
+    let columns = records[0].len();
+    if columns < 2 || records.iter().any(|record| record.len() != columns) {
+        return None;
+    }
+
+    let header = records.remove(0);
+    if delimiter == ',' {
+        let mut names: Vec<&str> = header.iter().map(|name| name.trim()).collect();
+        if names.iter().any(|name| name.is_empty()) {
+            return None;
+        }
+        names.sort_unstable();
+        names.dedup();
+        if names.len() != columns {
+            return None;
+        }
+        let sentence_like = std::iter::once(&header)
+            .chain(&records)
+            .flatten()
+            .any(|field| field.trim_end().ends_with(['.', '!', '?', ':']) && field.trim().parse::<f64>().is_err());
+        if sentence_like {
+            return None;
+        }
+    }
+
+    let trim = |record: Vec<String>| record.into_iter().map(|field| field.trim().to_string()).collect();
+    Some(Table {
+        header: trim(header),
+        rows: records.into_iter().map(trim).collect(),
+        delimiter,
+    })
+}
+
+/// Split one line into fields, honouring double quotes. `None` for a quote left open.
+fn split_record(line: &str, delimiter: char) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                in_quotes = true;
+            }
+            c if c == delimiter && !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+/// A field quoted for CSV when it contains a comma, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Numbers by value, anything else alphabetically ignoring case.
+fn compare_cells(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        _ => a.to_lowercase().cmp(&b.to_lowercase()),
+    }
+}
+
+/// `value` pretty-printed, with ANSI colours for keys, strings, numbers and
+/// literals when `color` is set.
+pub fn pretty_json(value: &Value, color: bool) -> String {
+    let mut out = String::new();
+    write_json(&mut out, value, 0, color);
+    out
+}
+
+const KEY: &str = "\x1b[36m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const LITERAL: &str = "\x1b[35m";
+const RESET: &str = "\x1b[0m";
+
+fn write_json(out: &mut String, value: &Value, depth: usize, color: bool) {
+    let paint = |out: &mut String, code: &str, text: &str| {
+        if color {
+            out.push_str(code);
+            out.push_str(text);
+            out.push_str(RESET);
+        } else {
+            out.push_str(text);
+        }
+    };
+    let indent = |out: &mut String, depth: usize| out.push_str(&"  ".repeat(depth));
+    match value {
+        Value::Null | Value::Bool(_) => paint(out, LITERAL, &value.to_string()),
+        Value::Number(_) => paint(out, NUMBER, &value.to_string()),
+        Value::String(_) => paint(out, STRING, &value.to_string()),
+        Value::Array(items) if items.is_empty() => out.push_str("[]"),
+        Value::Object(map) if map.is_empty() => out.push_str("{}"),
+        Value::Array(items) => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                indent(out, depth + 1);
+                write_json(out, item, depth + 1, color);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            indent(out, depth);
+            out.push(']');
+        }
+        Value::Object(map) => {
+            out.push_str("{\n");
+            for (i, (key, item)) in map.iter().enumerate() {
+                indent(out, depth + 1);
+                paint(out, KEY, &Value::String(key.clone()).to_string());
+                out.push_str(": ");
+                write_json(out, item, depth + 1, color);
+                out.push_str(if i + 1 < map.len() { ",\n" } else { "\n" });
+            }
+            indent(out, depth);
+            out.push('}');
+        }
+    }
+}
+
+/// `reply` with its JSON pretty-printed: the whole reply when it is JSON, and
+/// otherwise every fenced code block that holds JSON. Everything else is kept as is.
+pub fn render_json(reply: &str, color: bool) -> String {
+    if let Some(value) = detect_json(reply) {
+        return pretty_json(&value, color);
+    }
+    let mut out = String::new();
+    let mut code: Option<String> = None;
+    for line in reply.lines() {
+        if line.trim_start().starts_with("```") {
+            match code.take() {
+                Some(block) => {
+                    match detect_json(&block) {
+                        Some(value) => {
+                            out.push_str(&pretty_json(&value, color));
+                            out.push('\n');
+                        }
+                        None => out.push_str(&block),
+                    }
+                    out.push_str(line);
+                    out.push('\n');
+                }
+                None => {
+                    out.push_str(line);
+                    out.push('\n');
+                    code = Some(String::new());
+                }
+            }
+        } else if let Some(block) = &mut code {
+            block.push_str(line);
+            block.push('\n');
+        } else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    // An unclosed block is shown as it came.
+    if let Some(block) = code {
+        out.push_str(&block);
+    }
+    if !reply.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn json_objects_and_arrays() {
+        assert_eq!(detect_json("  {\"a\": 1}\n"), Some(json!({"a": 1})));
+        assert_eq!(detect_json("[1, \"two\", null]"), Some(json!([1, "two", null])));
+        assert!(matches!(detect("[[1, 2], [3, 4]]"), Some(Structured::Json(_))));
+    }
+
+    #[test]
+    fn json_that_is_not_data() {
+        for text in ["{}", "[ ]", "42", "\"text\"", "true", "null", ""] {
+            assert_eq!(detect_json(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn prose_with_brackets_is_not_json() {
+        for text in [
+            "{not json}",
+            "[citation needed]",
+            "{\"a\": 1} is the object you want",
+            "Here it is: {\"a\": 1}",
+            "{\"a\": 1} {\"b\": 2}",
+            "{\"a\": 1,}",
+            "```json\n{\"a\": 1}\n```",
+        ] {
+            assert_eq!(detect(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn csv_tables() {
+        let table = detect_table("name, age\r\nAda, 36\r\n\"Hopper, Grace\", 85\n").unwrap();
+        assert_eq!(table.header, ["name", "age"]);
+        assert_eq!(table.rows, [["Ada", "36"], ["Hopper, Grace", "85"]]);
+        assert_eq!(table.delimiter, ',');
+        assert_eq!(table.to_csv(), "name,age\nAda,36\n\"Hopper, Grace\",85\n");
+    }
+
+    #[test]
+    fn tsv_needs_one_row() {
+        let table = detect_table("key\tvalue\nmode\tdark").unwrap();
+        assert_eq!((table.delimiter, table.rows.len()), ('\t', 1));
+        // Comma-separated text needs two.
+        assert_eq!(detect_table("key,value\nmode,dark"), None);
+    }
+
+    #[test]
+    fn prose_with_commas_is_not_a_table() {
+        for text in [
+            "Well, that depends.\nIf so, go ahead.\nOtherwise, wait.",
+            "So, why not?\nWell, because\nFine, then",
+            "a, b\nc, d, e\nf, g",
+            "a, b\nc, d\n\ne, f",
+            "a, a\nb, c\nd, e",
+            "a, \nb, c\nd, e",
+            "one\ntwo\nthree",
+            "a, \"open\nb, c\nd, e",
+        ] {
+            assert_eq!(detect_table(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn numbers_may_end_with_a_point() {
+        let table = detect_table("item, price\ntea, 3.\ncake, 4.50").unwrap();
+        assert_eq!(table.rows[0], ["tea", "3."]);
+    }
+
+    #[test]
+    fn sorting_compares_numbers_as_numbers() {
+        let mut table = detect_table("name, size\nb, 10\nA, 9\nc, 100").unwrap();
+        table.sort(1, true);
+        let sizes: Vec<&str> = table.rows.iter().map(|row| row[1].as_str()).collect();
+        assert_eq!(sizes, ["9", "10", "100"]);
+        table.sort(0, false);
+        let names: Vec<&str> = table.rows.iter().map(|row| row[0].as_str()).collect();
+        assert_eq!(names, ["c", "b", "A"]);
+    }
+
+    #[test]
+    fn render_json_only_touches_json() {
+        assert_eq!(render_json("Plain {prose}.", false), "Plain {prose}.");
+        assert_eq!(render_json("{\"a\":[1]}", false), "{\n  \"a\": [\n    1\n  ]\n}");
+        let reply = "Here:\n```json\n{\"a\":1}\n```\n```\nnot json\n```\n";
+        assert_eq!(render_json(reply, false), "Here:\n```json\n{\n  \"a\": 1\n}\n```\n```\nnot json\n```\n");
+        // A block the reply never closes is kept as it came.
+        assert_eq!(render_json("```\n{\"a\":1}", false), "```\n{\"a\":1}");
+    }
+}
@@ -0,0 +1,32 @@
+//! Helpers shared by the unit tests.
+
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+/// An empty directory under the system's temporary directory, removed when dropped.
+pub(crate) struct TempDir(pub PathBuf);
+
+impl TempDir {
+    /// A directory for the test `name` of `module`, so tests running at once
+    /// don't share one.
+    pub(crate) fn new(module: &str, name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("cli_llm-{}-{}-{}", module, name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    /// Names of the files in the directory, temporary ones included.
+    pub(crate) fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> =
+            fs::read_dir(&self.0).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into()).collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,144 @@
+//! Reasoning that models put in their content between `<think>` and
+//! `</think>`, kept apart from the answer so it can be hidden.
+//!
+//! A section opens with a `<think>` starting a line outside code blocks, so
+//! replies that mention the tag are left alone. One still open at the end is
+//! reasoning still arriving, or cut off.
+
+/// Opens a reasoning section.
+pub const OPEN: &str = "<think>";
+/// Closes a reasoning section.
+pub const CLOSE: &str = "</think>";
+
+/// A reply split into the model's reasoning and its answer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Thoughts {
+    /// The text of the reasoning sections, separated by blank lines.
+    pub reasoning: String,
+    /// The reply without them.
+    pub answer: String,
+    /// Whether the last section is still open.
+    pub thinking: bool,
+}
+
+impl Thoughts {
+    /// Whether the reply has any reasoning, even if none of its text arrived yet.
+    pub fn has_reasoning(&self) -> bool {
+        self.thinking || !self.reasoning.is_empty()
+    }
+}
+
+/// Split `content` into reasoning and answer.
+///
+/// ```
+/// use llm::think::split;
+///
+/// let thoughts = split("<think>\nThe user greets me.\n</think>\n\nHello!");
+/// assert_eq!((thoughts.reasoning.as_str(), thoughts.answer.as_str()), ("The user greets me.", "Hello!"));
+/// assert!(!thoughts.thinking);
+///
+/// // Still thinking, as while the reply streams in.
+/// let thoughts = split("<think>Let me see");
+/// assert_eq!((thoughts.reasoning.as_str(), thoughts.answer.as_str(), thoughts.thinking), ("Let me see", "", true));
+///
+/// // A tag that is talked about, or in code, isn't reasoning.
+/// for content in ["Wrap it in <think> tags.", "```html\n<think>x</think>\n```\n"] {
+///     let thoughts = split(content);
+///     assert!(!thoughts.has_reasoning());
+///     assert_eq!(thoughts.answer, content);
+/// }
+/// ```
+pub fn split(content: &str) -> Thoughts {
+    let mut thoughts = Thoughts::default();
+    let mut rest = content;
+    while let Some(start) = find_open(rest) {
+        thoughts.answer.push_str(&rest[..start]);
+        let after = &rest[start + OPEN.len()..];
+        let (reasoning, next) = match after.find(CLOSE) {
+            Some(end) => (&after[..end], Some(after[end + CLOSE.len()..].trim_start())),
+            None => (after, None),
+        };
+        let reasoning = reasoning.trim();
+        if !reasoning.is_empty() {
+            if !thoughts.reasoning.is_empty() {
+                thoughts.reasoning.push_str("\n\n");
+            }
+            thoughts.reasoning.push_str(reasoning);
+        }
+        match next {
+            Some(next) => rest = next,
+            None => {
+                thoughts.thinking = true;
+                return thoughts;
+            }
+        }
+    }
+    thoughts.answer.push_str(rest);
+    thoughts
+}
+
+/// Where the first `<think>` that starts a line outside code blocks is.
+fn find_open(text: &str) -> Option<usize> {
+    let mut in_code = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+        } else if !in_code && trimmed.starts_with(OPEN) {
+            return Some(offset + line.len() - trimmed.len());
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Hides the reasoning of a reply as it streams in, giving the answer's text
+/// piece by piece.
+#[derive(Debug, Default)]
+pub struct Hider {
+    content: String,
+    /// How much of the answer was given out.
+    given: usize,
+}
+
+impl Hider {
+    /// The answer's text that `delta` adds. A line that could be the start of
+    /// a `<think>` is held back until it is known not to be.
+    ///
+    /// ```
+    /// use llm::think::Hider;
+    ///
+    /// let mut hider = Hider::default();
+    /// let shown: Vec<String> = ["<thi", "nk>Hm, ", "a sum.</th", "ink>\n\n", "It's ", "4.\n<", "b>"]
+    ///     .iter()
+    ///     .map(|delta| hider.push(delta))
+    ///     .collect();
+    /// assert_eq!(shown, ["", "", "", "", "It's ", "4.\n", "<b>"]);
+    /// assert_eq!(hider.finish(), "");
+    /// ```
+    pub fn push(&mut self, delta: &str) -> String {
+        self.content.push_str(delta);
+        let answer = split(&self.content).answer;
+        let line_start = answer.rfind('\n').map_or(0, |i| i + 1);
+        let last = answer[line_start..].trim_start();
+        let end = match !last.is_empty() && OPEN.starts_with(last) {
+            true => answer.len() - last.len(),
+            false => answer.len(),
+        };
+        self.give(&answer[..end])
+    }
+
+    /// The rest of the answer once the reply is complete, such as a held back
+    /// `<thi` it ended with.
+    pub fn finish(&mut self) -> String {
+        let answer = split(&self.content).answer;
+        self.give(&answer)
+    }
+
+    fn give(&mut self, answer: &str) -> String {
+        let new = answer.get(self.given..).unwrap_or_default().to_string();
+        self.given = self.given.max(answer.len());
+        new
+    }
+}
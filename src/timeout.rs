@@ -0,0 +1,67 @@
+use std::future::Future;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// How long requests to the chat endpoint may take, in seconds; 0 means no limit.
+///
+/// Streams are held open for as long as the model writes, so they aren't given
+/// an overall limit like blocking requests. Instead they fail when the server
+/// goes quiet for [`idle`](Self::idle) seconds; every chunk received,
+/// keep-alives included, starts the wait anew.
+///
+/// Set in the `[timeouts]` section of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Connecting and waiting for the response to start.
+    pub first_byte: u64,
+    /// A whole non-streaming request, until the complete reply has arrived.
+    pub request: u64,
+    /// Silence between two chunks of a stream.
+    pub idle: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            first_byte: 30,
+            request: 300,
+            idle: 60,
+        }
+    }
+}
+
+/// The timeouts every request follows. Requests are made from several places in
+/// both front ends, so this is set once at startup rather than passed to each.
+static TIMEOUTS: RwLock<Timeouts> = RwLock::new(Timeouts {
+    first_byte: 30,
+    request: 300,
+    idle: 60,
+});
+
+/// Use `timeouts` for the following requests.
+pub fn set(timeouts: Timeouts) {
+    if let Ok(mut current) = TIMEOUTS.write() {
+        *current = timeouts;
+    }
+}
+
+/// The timeouts in effect.
+pub fn get() -> Timeouts {
+    TIMEOUTS.read().map_or_else(|_| Timeouts::default(), |timeouts| *timeouts)
+}
+
+/// Run `future`, failing with [`Error::Timeout`] if it takes more than `secs`
+/// seconds (unless `secs` is 0). `what` says what didn't happen in time.
+pub async fn within<T>(secs: u64, what: &str, future: impl Future<Output = Result<T>>) -> Result<T> {
+    if secs == 0 {
+        return future.await;
+    }
+    tokio::time::timeout(Duration::from_secs(secs), future)
+        .await
+        .unwrap_or_else(|_| Err(Error::Timeout(format!("{} within {} s", what, secs))))
+}
@@ -0,0 +1,124 @@
+//! Counting tokens the same way everywhere: trimming long conversations, the
+//! token counter under the GUI input box, the warning before sending a prompt
+//! that nearly fills the model's context window, and shortening long pastes.
+
+use crate::api::ChatMessageRequest;
+
+/// Tokens added by the framing of each message.
+pub const MESSAGE_TOKENS: usize = 4;
+/// Share of the model's context window from which a prompt is getting large.
+pub const WARNING_SHARE: f64 = 0.75;
+/// Share of the model's context window from which a prompt may not fit.
+pub const DANGER_SHARE: f64 = 0.9;
+
+/// Counts the tokens of text the way a model would, or close to it. A
+/// conversation trims and sizes its prompts with its own
+/// [`tokenizer`](crate::conversation::Conversation::tokenizer).
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use llm::conversation::{Client, Conversation};
+/// use llm::tokenizer::Tokenizer;
+///
+/// struct Words;
+///
+/// impl Tokenizer for Words {
+///     fn count(&self, text: &str) -> usize {
+///         text.split_whitespace().count()
+///     }
+/// }
+///
+/// # fn main() -> llm::Result<()> {
+/// let mut chat = Conversation::new(Client::new("http://localhost:8080/v1/chat/completions", "sk-test")?)
+///     .system("Answer briefly.");
+/// assert_eq!(chat.prompt_tokens("What is a borrow checker?"), 4 + 7 + 4 + 4);
+/// chat.tokenizer = Arc::new(Words);
+/// assert_eq!(chat.prompt_tokens("What is a borrow checker?"), 2 + 4 + 5 + 4);
+/// # Ok(())
+/// # }
+/// ```
+pub trait Tokenizer: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+
+    /// Tokens of `message`, framing included.
+    fn message(&self, message: &ChatMessageRequest) -> usize {
+        self.count(&message.content) + MESSAGE_TOKENS
+    }
+}
+
+/// A rough count without knowing the model's tokenizer: four characters per
+/// token, which is close enough for English text with most of them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Estimate;
+
+impl Tokenizer for Estimate {
+    fn count(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+}
+
+/// Tokens of `text` as [`Estimate`] counts them.
+pub fn estimate(text: &str) -> usize {
+    Estimate.count(text)
+}
+
+/// How full a prompt makes the model's context window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pressure {
+    Fine,
+    /// From [`WARNING_SHARE`] of the window.
+    Warning,
+    /// From [`DANGER_SHARE`] of the window.
+    Danger,
+}
+
+impl Pressure {
+    /// The pressure of a prompt of `prompt` tokens on a context window of
+    /// `context` tokens; fine when the window isn't known.
+    ///
+    /// ```
+    /// use llm::tokenizer::Pressure;
+    ///
+    /// assert_eq!(Pressure::of(7_499, 10_000), Pressure::Fine);
+    /// assert_eq!(Pressure::of(7_500, 10_000), Pressure::Warning);
+    /// assert_eq!(Pressure::of(9_000, 10_000), Pressure::Danger);
+    /// assert_eq!(Pressure::of(12_000, 0), Pressure::Fine);
+    /// ```
+    pub fn of(prompt: usize, context: u64) -> Self {
+        if context == 0 {
+            return Self::Fine;
+        }
+        match prompt as f64 / context as f64 {
+            share if share >= DANGER_SHARE => Self::Danger,
+            share if share >= WARNING_SHARE => Self::Warning,
+            _ => Self::Fine,
+        }
+    }
+}
+
+/// What to tell the user before sending a prompt of `prompt` tokens to a
+/// model with a context window of `context` tokens; none while it is fine.
+///
+/// ```
+/// assert_eq!(llm::tokenizer::warning(5_000, 10_000), None);
+/// assert_eq!(
+///     llm::tokenizer::warning(9_500, 10_000).unwrap(),
+///     "The prompt is ~9500 tokens, 95% of the model's 10000-token context window; \
+///      the reply may be cut short or the request refused."
+/// );
+/// ```
+pub fn warning(prompt: usize, context: u64) -> Option<String> {
+    let consequence = match Pressure::of(prompt, context) {
+        Pressure::Fine => return None,
+        Pressure::Warning => "little room is left for the reply",
+        Pressure::Danger => "the reply may be cut short or the request refused",
+    };
+    Some(format!(
+        "The prompt is ~{} tokens, {:.0}% of the model's {}-token context window; {}.",
+        prompt,
+        prompt as f64 / context as f64 * 100.0,
+        context,
+        consequence
+    ))
+}
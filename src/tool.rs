@@ -0,0 +1,296 @@
+//! The `shell` tool: the model can ask to run a command on the user's machine,
+//! and the result goes back to it as a `tool` message.
+//!
+//! Nothing runs on its own. The tool is only offered with `--allow-shell`, and
+//! the terminal client shows every command and asks before running it. A
+//! command runs through `sh -c` (`cmd /C` on Windows) in the current directory,
+//! with a bare environment, so API keys and other secrets in the environment
+//! don't reach it, no input and a time limit. That doesn't stop it from reading
+//! or changing files: what it may do is up to the user who confirms it.
+
+use std::env;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::api::{ChatMessageRequest, ToolCall};
+use crate::error::{Error, Result};
+
+/// The shell tool's name.
+pub const SHELL: &str = "shell";
+
+/// Longest a command may run before it is stopped.
+pub const TIME_LIMIT: Duration = Duration::from_secs(30);
+
+/// How long the output is still read after the command ends, for whatever it
+/// started in the background, before those are stopped too.
+const PIPE_GRACE: Duration = Duration::from_secs(2);
+
+/// Characters of each of a command's outputs sent back to the model; the
+/// middle of longer ones is left out.
+pub const OUTPUT_LIMIT: usize = 8000;
+
+/// The shell tool as offered in a request's `tools`.
+pub fn shell_definition() -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": SHELL,
+            "description": "Run a shell command on the user's machine, in their current directory, and get back its \
+                exit code, stdout and stderr. The user sees every command and may decline it. Commands get no \
+                input, run for at most 30 seconds, and long output is shortened.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string", "description": "The command line to run." }
+                },
+                "required": ["command"]
+            }
+        }
+    })
+}
+
+/// The arguments of a shell tool call.
+#[derive(Deserialize)]
+struct ShellArguments {
+    command: String,
+}
+
+/// The command line a shell tool call asks to run.
+///
+/// ```
+/// use llm::api::ToolCall;
+/// use llm::tool;
+///
+/// let call = ToolCall::function("call_1", "shell", r#"{"command": "ls -la"}"#);
+/// assert_eq!(tool::shell_command(&call).unwrap(), "ls -la");
+/// assert!(tool::shell_command(&ToolCall::function("call_2", "shell", "{}")).is_err());
+/// ```
+pub fn shell_command(call: &ToolCall) -> Result<String> {
+    if call.function.name != SHELL {
+        return Err(Error::Config(format!("There is no tool called '{}'", call.function.name)));
+    }
+    let arguments: ShellArguments = serde_json::from_str(&call.function.arguments)
+        .map_err(|e| Error::Config(format!("The shell tool call has bad arguments: {}", e)))?;
+    Ok(arguments.command)
+}
+
+/// What a tool call came to, sent back to the model as JSON.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ToolResult {
+    /// The command's exit code; none when it didn't run or was stopped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Whether output was left out to keep within [`OUTPUT_LIMIT`].
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Why the command didn't run or finish, such as the user declining it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ToolResult {
+    /// A result for a call that didn't run.
+    pub fn failed(error: impl Into<String>) -> Self {
+        Self {
+            error: Some(error.into()),
+            ..Self::default()
+        }
+    }
+
+    /// The `tool` message answering `call` with this result.
+    ///
+    /// ```
+    /// use llm::api::ToolCall;
+    /// use llm::tool::ToolResult;
+    ///
+    /// let call = ToolCall::function("call_1", "shell", r#"{"command": "rm -rf /"}"#);
+    /// let message = ToolResult::failed("The user declined to run the command.").message(&call);
+    /// assert_eq!(message.role, "tool");
+    /// assert_eq!(message.tool_call_id.as_deref(), Some("call_1"));
+    /// assert_eq!(
+    ///     message.content,
+    ///     r#"{"stdout":"","stderr":"","error":"The user declined to run the command."}"#
+    /// );
+    /// ```
+    pub fn message(&self, call: &ToolCall) -> ChatMessageRequest {
+        let content = serde_json::to_string(self).unwrap_or_default();
+        let mut message = ChatMessageRequest::new("tool", content);
+        message.tool_call_id = Some(call.id.clone());
+        message
+    }
+}
+
+/// Run `command` the way the module describes, and collect what it printed.
+///
+/// Whatever the command leaves running in the background is stopped shortly
+/// after it ends, so that it can't hold the output open.
+///
+/// ```
+/// # #[cfg(unix)] {
+/// use llm::tool::run_shell;
+///
+/// let result = run_shell("sleep 60 & echo started");
+/// assert_eq!(result.stdout, "started\n");
+/// assert_eq!(result.exit_code, Some(0));
+/// # }
+/// ```
+pub fn run_shell(command: &str) -> ToolResult {
+    let mut shell = match cfg!(windows) {
+        true => {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        }
+        false => {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        }
+    };
+    shell
+        .arg(command)
+        .env_clear()
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Its own process group, so that what it starts is stopped along with it.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    for name in ["PATH", "HOME", "TMPDIR", "LANG", "LC_ALL", "TERM", "SYSTEMROOT"] {
+        if let Some(value) = env::var_os(name) {
+            shell.env(name, value);
+        }
+    }
+    log::info!("Running a command the model asked for: {}", command);
+    let mut child = match shell.spawn() {
+        Ok(child) => child,
+        Err(e) => return ToolResult::failed(format!("Cannot run the command: {}", e)),
+    };
+    // Both pipes are read in chunks, so that what was read can be kept when
+    // something the command left running holds them open.
+    let (chunks, received) = mpsc::channel();
+    let reader = |pipe: Option<Box<dyn Read + Send>>, stderr: bool| {
+        let chunks = chunks.clone();
+        thread::spawn(move || {
+            let Some(mut pipe) = pipe else {
+                return;
+            };
+            let mut buf = [0; 8192];
+            while let Ok(n @ 1..) = pipe.read(&mut buf) {
+                if chunks.send((stderr, buf[..n].to_vec())).is_err() {
+                    break;
+                }
+            }
+        });
+    };
+    reader(child.stdout.take().map(|pipe| Box::new(pipe) as _), false);
+    reader(child.stderr.take().map(|pipe| Box::new(pipe) as _), true);
+    drop(chunks);
+    let started = Instant::now();
+    let (exit_code, mut error) = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break (status.code(), None),
+            Ok(None) if started.elapsed() < TIME_LIMIT => thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                kill(&mut child);
+                break (None, Some(format!("Stopped after {}s", TIME_LIMIT.as_secs())));
+            }
+            Err(e) => {
+                kill(&mut child);
+                break (None, Some(format!("Cannot wait for the command: {}", e)));
+            }
+        }
+    };
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let deadline = Instant::now() + PIPE_GRACE;
+    loop {
+        match received.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok((false, chunk)) => out.extend(chunk),
+            Ok((true, chunk)) => err.extend(chunk),
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                kill(&mut child);
+                error.get_or_insert_with(|| "Stopped what the command left running in the background".to_string());
+                break;
+            }
+        }
+    }
+    let (stdout, cut_out) = shorten(&String::from_utf8_lossy(&out), OUTPUT_LIMIT);
+    let (stderr, cut_err) = shorten(&String::from_utf8_lossy(&err), OUTPUT_LIMIT);
+    ToolResult {
+        exit_code,
+        stdout,
+        stderr,
+        truncated: cut_out || cut_err,
+        error,
+    }
+}
+
+/// `command` as shown when asking whether to run it: control characters, such
+/// as a carriage return that would hide what comes before it, and characters
+/// that reorder text are escaped, so that what is shown is what runs.
+///
+/// ```
+/// use llm::tool::shown_command;
+///
+/// assert_eq!(shown_command("ls -l \"my dir\""), "ls -l \"my dir\"");
+/// assert_eq!(shown_command("rm -rf ~\rls"), "rm -rf ~\\rls");
+/// assert_eq!(shown_command("echo \u{1b}[2Kok\nid"), "echo \\u{1b}[2Kok\\nid");
+/// assert_eq!(shown_command("cat \u{202e}txt.sh"), "cat \\u{202e}txt.sh");
+/// ```
+pub fn shown_command(command: &str) -> String {
+    let hidden = |c: char| {
+        c.is_control() || matches!(c, '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+    };
+    command
+        .chars()
+        .map(|c| match hidden(c) {
+            true => c.escape_debug().to_string(),
+            false => c.to_string(),
+        })
+        .collect()
+}
+
+/// Stop `child` and everything in its process group.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(group) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: killpg only sends a signal. The child leads the group, and a
+        // group's id isn't given to a new process while the group has members.
+        unsafe { libc::killpg(group, libc::SIGKILL) };
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// `text` within `limit` characters: its start and end, with a note on what was
+/// left out between them. Also returns whether anything was.
+///
+/// ```
+/// use llm::tool::shorten;
+///
+/// let log: String = (1..=1000).map(|n| format!("line {}\n", n)).collect();
+/// let (short, cut) = shorten(&log, 100);
+/// assert!(cut);
+/// assert!(short.starts_with("line 1\n") && short.ends_with("line 1000\n"));
+/// assert!(short.contains("characters left out"));
+/// assert_eq!(shorten("ok\n", 100), ("ok\n".to_string(), false));
+/// ```
+pub fn shorten(text: &str, limit: usize) -> (String, bool) {
+    let count = text.chars().count();
+    if count <= limit {
+        return (text.to_string(), false);
+    }
+    let head: String = text.chars().take(limit / 2).collect();
+    let tail: String = text.chars().skip(count - limit / 2).collect();
+    let note = format!("\n[… {} characters left out …]\n", count - head.chars().count() - tail.chars().count());
+    (format!("{}{}{}", head, note, tail), true)
+}
@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+use crate::api::ChatMessageRequest;
+use crate::error::{Error, Result};
+
+/// How many operations can be undone in a row; older ones are forgotten.
+pub const LIMIT: usize = 50;
+
+/// What an [`Operation`] did, for telling the user what was undone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Edit,
+    Delete,
+    /// Dropping the messages after a point, to continue from there.
+    Truncate,
+    /// Starting over, possibly with a template's or greeting's messages.
+    Clear,
+}
+
+/// A change to a conversation's messages: `removed` taken out at `at`, `added`
+/// put in their place, and `after` messages following them. Undoing it is the
+/// same change the other way around, so each operation carries its inverse.
+///
+/// Before it is applied, the messages it expects are checked to be where it
+/// left them: an operation on a conversation that changed since, such as a
+/// truncation followed by a new exchange, can't be undone.
+#[derive(Clone)]
+pub struct Operation {
+    pub kind: Kind,
+    at: usize,
+    removed: Vec<ChatMessageRequest>,
+    added: Vec<ChatMessageRequest>,
+    after: usize,
+}
+
+impl Operation {
+    /// Replace the message at `index` with `message`.
+    pub fn edit(messages: &[ChatMessageRequest], index: usize, message: ChatMessageRequest) -> Self {
+        Self::splice(Kind::Edit, messages, index..index + 1, vec![message])
+    }
+
+    pub fn delete(messages: &[ChatMessageRequest], index: usize) -> Self {
+        Self::splice(Kind::Delete, messages, index..index + 1, Vec::new())
+    }
+
+    /// Keep only the first `len` messages.
+    pub fn truncate(messages: &[ChatMessageRequest], len: usize) -> Self {
+        Self::splice(Kind::Truncate, messages, len.min(messages.len())..messages.len(), Vec::new())
+    }
+
+    /// Replace all the messages with `added`.
+    pub fn clear(messages: &[ChatMessageRequest], added: Vec<ChatMessageRequest>) -> Self {
+        Self::splice(Kind::Clear, messages, 0..messages.len(), added)
+    }
+
+    fn splice(
+        kind: Kind,
+        messages: &[ChatMessageRequest],
+        range: std::ops::Range<usize>,
+        added: Vec<ChatMessageRequest>,
+    ) -> Self {
+        Self {
+            kind,
+            at: range.start,
+            removed: messages[range.clone()].to_vec(),
+            added,
+            after: messages.len() - range.end,
+        }
+    }
+
+    /// Whether applying it would leave the messages as they are.
+    fn is_noop(&self) -> bool {
+        self.removed.len() == self.added.len()
+            && self.removed.iter().zip(&self.added).all(|(a, b)| a.role == b.role && a.content == b.content)
+    }
+
+    fn inverse(&self) -> Self {
+        Self {
+            kind: self.kind,
+            at: self.at,
+            removed: self.added.clone(),
+            added: self.removed.clone(),
+            after: self.after,
+        }
+    }
+
+    /// What it did, e.g. "the edit of message 3".
+    pub fn describe(&self) -> String {
+        let first = self.at + 1;
+        match self.kind {
+            Kind::Edit => format!("the edit of message {}", first),
+            Kind::Delete => format!("the deletion of message {}", first),
+            Kind::Truncate if self.removed.len() == 1 => format!("dropping message {}", first),
+            Kind::Truncate => format!("dropping messages {}-{}", first, self.at + self.removed.len()),
+            Kind::Clear => format!("clearing {} messages", self.removed.len()),
+        }
+    }
+
+    /// Why `messages` aren't as the operation expects them, if they aren't.
+    fn conflict(&self, messages: &[ChatMessageRequest]) -> Option<String> {
+        let expected = self.at + self.removed.len() + self.after;
+        if messages.len() != expected {
+            let (count, what) = match messages.len() > expected {
+                true => (messages.len() - expected, "added"),
+                false => (expected - messages.len(), "removed"),
+            };
+            return Some(match count {
+                1 => format!("a message was {} since", what),
+                n => format!("{} messages were {} since", n, what),
+            });
+        }
+        let present = &messages[self.at..self.at + self.removed.len()];
+        let changed = present.iter().zip(&self.removed).position(|(now, then)| {
+            now.role != then.role || now.content != then.content || now.timestamp != then.timestamp
+        });
+        changed.map(|i| format!("message {} was changed since", self.at + i + 1))
+    }
+
+    fn apply(&self, messages: &mut Vec<ChatMessageRequest>) {
+        messages.splice(self.at..self.at + self.removed.len(), self.added.iter().cloned());
+    }
+}
+
+/// The operations of one conversation that can be undone, and those undone
+/// that can be redone. Kept for the session only.
+///
+/// ```
+/// use llm::api::ChatMessageRequest;
+/// use llm::undo::{Operation, UndoStack};
+///
+/// let mut messages: Vec<_> = ["one", "two", "three"].iter().map(|text| ChatMessageRequest::new("user", *text)).collect();
+/// let mut stack = UndoStack::default();
+/// let text = |messages: &[ChatMessageRequest]| messages.iter().map(|msg| msg.content.clone()).collect::<Vec<_>>();
+///
+/// let edit = Operation::edit(&messages, 0, ChatMessageRequest::new("user", "uno"));
+/// stack.record(&mut messages, edit);
+/// let delete = Operation::delete(&messages, 1);
+/// stack.record(&mut messages, delete);
+/// let clear = Operation::clear(&messages, Vec::new());
+/// stack.record(&mut messages, clear);
+/// assert!(messages.is_empty());
+///
+/// assert_eq!(stack.undo(&mut messages).unwrap(), "clearing 2 messages");
+/// assert_eq!(stack.undo(&mut messages).unwrap(), "the deletion of message 2");
+/// assert_eq!(text(&messages), ["uno", "two", "three"]);
+/// stack.redo(&mut messages).unwrap();
+/// assert_eq!(text(&messages), ["uno", "three"]);
+///
+/// // A new message on top: the deletion can no longer be undone.
+/// messages.push(ChatMessageRequest::new("user", "four"));
+/// let error = stack.undo(&mut messages).unwrap_err();
+/// assert!(error.to_string().contains("a message was added since"));
+/// assert_eq!(text(&messages), ["uno", "three", "four"]);
+/// assert!(!stack.can_redo());
+/// ```
+#[derive(Clone, Default)]
+pub struct UndoStack {
+    done: VecDeque<Operation>,
+    undone: Vec<Operation>,
+}
+
+impl UndoStack {
+    /// Apply `operation` to `messages` and remember it. Clears what could be
+    /// redone; does nothing for operations that change nothing.
+    pub fn record(&mut self, messages: &mut Vec<ChatMessageRequest>, operation: Operation) {
+        if operation.is_noop() {
+            return;
+        }
+        operation.apply(messages);
+        self.undone.clear();
+        self.done.push_back(operation);
+        if self.done.len() > LIMIT {
+            self.done.pop_front();
+        }
+    }
+
+    /// Undo the latest operation, returning what it was. An operation that can
+    /// no longer be undone is forgotten, along with what could be redone, with
+    /// an error saying why.
+    pub fn undo(&mut self, messages: &mut Vec<ChatMessageRequest>) -> Result<String> {
+        let operation = self.done.pop_back().ok_or_else(|| Error::Config("Nothing to undo.".to_string()))?;
+        let inverse = operation.inverse();
+        if let Some(conflict) = inverse.conflict(messages) {
+            self.undone.clear();
+            return Err(Error::Config(format!(
+                "Can't undo {}: {}. It was taken off the undo list.",
+                operation.describe(),
+                conflict
+            )));
+        }
+        inverse.apply(messages);
+        let description = operation.describe();
+        self.undone.push(operation);
+        Ok(description)
+    }
+
+    /// Apply the operation undone last again, returning what it was.
+    pub fn redo(&mut self, messages: &mut Vec<ChatMessageRequest>) -> Result<String> {
+        let operation = self.undone.pop().ok_or_else(|| Error::Config("Nothing to redo.".to_string()))?;
+        if let Some(conflict) = operation.conflict(messages) {
+            self.undone.clear();
+            return Err(Error::Config(format!("Can't redo {}: {}.", operation.describe(), conflict)));
+        }
+        operation.apply(messages);
+        let description = operation.describe();
+        self.done.push_back(operation);
+        Ok(description)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// Forget everything, e.g. when another conversation is opened.
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+
+    /// [`clear`](Self::clear), overwriting the messages kept with zeros first.
+    pub fn wipe(&mut self) {
+        for operation in self.done.iter_mut().chain(self.undone.iter_mut()) {
+            for message in operation.removed.iter_mut().chain(operation.added.iter_mut()) {
+                message.wipe();
+            }
+        }
+        self.clear();
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openssl::symm::{self, Cipher};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::{checkpoint, history, incognito, recorder, storage};
+
+/// Environment variable holding the passphrase, read before prompting for it.
+pub const PASSPHRASE_ENV: &str = "CLI_LLM_PASSPHRASE";
+
+/// Start of every encrypted file, followed by the nonce, the tag and the ciphertext.
+const MAGIC: &[u8] = b"cli_llm-vault-1\n";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+/// Encrypted into `vault.json` to tell a wrong passphrase from a right one.
+const CHECK: &[u8] = b"cli_llm vault";
+
+/// scrypt cost parameters: 2^15 rounds with r = 8 take about 32 MiB and a
+/// fraction of a second, once per unlock.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u64 = 8;
+const SCRYPT_P: u64 = 1;
+const SCRYPT_MAX_MEM: u64 = 64 * 1024 * 1024;
+/// Costs accepted from `vault.json`: scrypt needs 128 * r * 2^log_n bytes, so
+/// 2^16 is the most [`SCRYPT_MAX_MEM`] allows.
+const SCRYPT_LOG_N_RANGE: std::ops::RangeInclusive<u8> = 10..=16;
+
+/// Key of the unlocked vault. Saved conversations are read and written from
+/// several places in both front ends, so it is kept for the whole process
+/// rather than passed to each of them.
+static KEY: RwLock<Option<Key>> = RwLock::new(None);
+
+#[derive(Clone)]
+struct Key([u8; KEY_LEN]);
+
+/// Best-effort wipe, so the key doesn't linger in freed memory.
+impl Drop for Key {
+    fn drop(&mut self) {
+        for byte in &mut self.0 {
+            // SAFETY: `byte` is a valid, aligned reference into the key.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+/// `vault.json`: how the key is derived from the passphrase, and a known value
+/// encrypted with it.
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    salt: String,
+    log_n: u8,
+    check: String,
+}
+
+/// What [`enable`] or [`rekey`] did.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    /// Files of conversations and checkpoints written under the new key.
+    pub encrypted: usize,
+}
+
+/// Where the vault settings live, next to (not among) the saved conversations.
+pub fn vault_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cli_llm").join("vault.json"))
+}
+
+/// Whether saved conversations are encrypted.
+pub fn is_enabled() -> bool {
+    vault_path().is_some_and(|path| path.exists())
+}
+
+/// Whether the passphrase has been given in this process.
+pub fn is_unlocked() -> bool {
+    KEY.read().is_ok_and(|key| key.is_some())
+}
+
+/// Whether `data` is an encrypted file.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Unlock the vault with `passphrase`. A wrong passphrase is an error and
+/// leaves every file as it was.
+pub fn unlock(passphrase: &str) -> Result<()> {
+    let vault = read_vault()?;
+    let key = derive(passphrase, &vault)?;
+    let check = STANDARD
+        .decode(&vault.check)
+        .map_err(|_| Error::Config("The vault file is damaged".to_string()))?;
+    if decrypt(&key, &check).is_err() {
+        return Err(Error::Config("Wrong passphrase for the saved conversations".to_string()));
+    }
+    set_key(key);
+    Ok(())
+}
+
+/// `data` encrypted for writing to disk when the vault is on, unchanged
+/// otherwise. Fails while the vault is locked, so nothing is written in the clear.
+pub fn seal(data: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_enabled() {
+        return Ok(data);
+    }
+    let key = KEY.read().map_err(|_| locked())?;
+    let key = key.as_ref().ok_or_else(locked)?;
+    encrypt(key, &data)
+}
+
+/// `data` as read from disk, decrypted when it is encrypted. Files written
+/// before encryption was turned on are passed through.
+pub fn open(data: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_encrypted(&data) {
+        return Ok(data);
+    }
+    let key = KEY.read().map_err(|_| locked())?;
+    let key = key.as_ref().ok_or_else(locked)?;
+    decrypt(key, &data)
+}
+
+/// Turn on encryption with `passphrase`, encrypting the saved conversations and
+/// the flight recorder's records in place.
+///
+/// When the vault already exists, `passphrase` has to unlock it, and any
+/// conversations still in the clear, e.g. after an interrupted migration, are
+/// encrypted.
+pub fn enable(passphrase: &str) -> Result<Summary> {
+    incognito::refuse("Encrypting saved conversations")?;
+    if is_enabled() {
+        unlock(passphrase)?;
+        let mut plain = Vec::new();
+        for path in conversation_files()? {
+            let data = fs::read(&path).map_err(Error::file(&path))?;
+            if !is_encrypted(&data) {
+                plain.push((path, data));
+            }
+        }
+        let encrypted = migrate(plain, None, current_key()?)?;
+        recorder::write_sealed(recorder::plain_files()?)?;
+        return Ok(Summary { encrypted });
+    }
+
+    if passphrase.is_empty() {
+        return Err(Error::Config("The passphrase must not be empty".to_string()));
+    }
+    let plain = read_all()?;
+    let records = recorder::plain_files()?;
+    let (vault, key) = new_vault(passphrase)?;
+    let encrypted = migrate(plain, Some(&vault), key)?;
+    recorder::write_sealed(records)?;
+    Ok(Summary { encrypted })
+}
+
+/// Re-encrypt every saved conversation, and the flight recorder's records,
+/// under `new_passphrase`.
+///
+/// All files are decrypted with the current key before anything is written, so
+/// a damaged file stops the rekey with everything unchanged.
+pub fn rekey(old_passphrase: &str, new_passphrase: &str) -> Result<Summary> {
+    incognito::refuse("Changing the passphrase")?;
+    if !is_enabled() {
+        return Err(Error::Config("Saved conversations are not encrypted".to_string()));
+    }
+    if new_passphrase.is_empty() {
+        return Err(Error::Config("The new passphrase must not be empty".to_string()));
+    }
+    unlock(old_passphrase)?;
+    let plain = read_all()?;
+    let records = recorder::plain_files()?;
+    let (vault, key) = new_vault(new_passphrase)?;
+    let encrypted = migrate(plain, Some(&vault), key)?;
+    recorder::write_sealed(records)?;
+    Ok(Summary { encrypted })
+}
+
+/// The error for reading or writing conversations before unlocking.
+fn locked() -> Error {
+    Error::Config(format!(
+        "Saved conversations are encrypted; unlock them with the passphrase (or set {})",
+        PASSPHRASE_ENV
+    ))
+}
+
+/// A copy of the key of the unlocked vault.
+fn current_key() -> Result<Key> {
+    let key = KEY.read().map_err(|_| locked())?;
+    key.clone().ok_or_else(locked)
+}
+
+fn set_key(key: Key) {
+    if let Ok(mut slot) = KEY.write() {
+        *slot = Some(key);
+    }
+}
+
+/// Settings for a new vault with a fresh salt, and the key they give for `passphrase`.
+fn new_vault(passphrase: &str) -> Result<(VaultFile, Key)> {
+    let mut salt = [0; SALT_LEN];
+    random(&mut salt)?;
+    let mut vault = VaultFile {
+        version: 1,
+        salt: STANDARD.encode(salt),
+        log_n: SCRYPT_LOG_N,
+        check: String::new(),
+    };
+    let key = derive(passphrase, &vault)?;
+    vault.check = STANDARD.encode(encrypt(&key, CHECK)?);
+    Ok((vault, key))
+}
+
+fn read_vault() -> Result<VaultFile> {
+    let path = vault_path().ok_or_else(|| Error::Config("No data directory on this system".to_string()))?;
+    let text = fs::read_to_string(&path).map_err(Error::file(&path))?;
+    let vault: VaultFile = serde_json::from_str(&text)?;
+    if !SCRYPT_LOG_N_RANGE.contains(&vault.log_n) {
+        return Err(Error::Config(format!(
+            "The vault file is damaged: log_n is {}, expected {} to {}",
+            vault.log_n,
+            SCRYPT_LOG_N_RANGE.start(),
+            SCRYPT_LOG_N_RANGE.end()
+        )));
+    }
+    Ok(vault)
+}
+
+fn write_vault(vault: &VaultFile) -> Result<()> {
+    let path = vault_path().ok_or_else(|| Error::Config("No data directory on this system".to_string()))?;
+    storage::write_atomic(&path, serde_json::to_vec_pretty(vault)?).map_err(Error::file(&path))
+}
+
+fn derive(passphrase: &str, vault: &VaultFile) -> Result<Key> {
+    let salt = STANDARD
+        .decode(&vault.salt)
+        .map_err(|_| Error::Config("The vault file is damaged".to_string()))?;
+    let mut key = Key([0; KEY_LEN]);
+    openssl::pkcs5::scrypt(
+        passphrase.as_bytes(),
+        &salt,
+        1 << vault.log_n,
+        SCRYPT_R,
+        SCRYPT_P,
+        SCRYPT_MAX_MEM,
+        &mut key.0,
+    )
+    .map_err(crypto_error)?;
+    Ok(key)
+}
+
+fn encrypt(key: &Key, data: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0; NONCE_LEN];
+    random(&mut nonce)?;
+    let mut tag = [0; TAG_LEN];
+    let ciphertext =
+        symm::encrypt_aead(Cipher::chacha20_poly1305(), &key.0, Some(&nonce), MAGIC, data, &mut tag)
+            .map_err(crypto_error)?;
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + TAG_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&tag);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypt a file written by [`encrypt`]. A wrong key and a damaged file both
+/// fail the authentication check.
+fn decrypt(key: &Key, data: &[u8]) -> Result<Vec<u8>> {
+    let damaged = || Error::Config("Cannot decrypt: wrong passphrase or damaged file".to_string());
+    let rest = data.strip_prefix(MAGIC).ok_or_else(damaged)?;
+    if rest.len() < NONCE_LEN + TAG_LEN {
+        return Err(damaged());
+    }
+    let (nonce, rest) = rest.split_at(NONCE_LEN);
+    let (tag, ciphertext) = rest.split_at(TAG_LEN);
+    symm::decrypt_aead(Cipher::chacha20_poly1305(), &key.0, Some(nonce), MAGIC, ciphertext, tag)
+        .map_err(|_| damaged())
+}
+
+fn random(buf: &mut [u8]) -> Result<()> {
+    openssl::rand::rand_bytes(buf).map_err(crypto_error)
+}
+
+fn crypto_error(error: openssl::error::ErrorStack) -> Error {
+    Error::Config(format!("Encryption failed: {}", error))
+}
+
+/// The files of all saved conversations, archived ones included, and of their
+/// checkpoints.
+fn conversation_files() -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = history::list()?.into_iter().map(|entry| entry.path).collect();
+    files.extend(checkpoint::files()?);
+    Ok(files)
+}
+
+/// Every saved conversation, decrypted with the current key where it is encrypted.
+fn read_all() -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut plain = Vec::new();
+    for path in conversation_files()? {
+        let data = fs::read(&path).map_err(Error::file(&path))?;
+        let data = open(data).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        plain.push((path, data));
+    }
+    Ok(plain)
+}
+
+/// Write each of `plain` back to its file encrypted with `key`, after saving
+/// `vault` when the key is a new one. Returns how many files were written.
+///
+/// Copies of the files, encrypted with `key` too, are kept in a backup
+/// directory until every file reads back as it was, so an interrupted
+/// migration loses nothing and nothing is left behind in the clear.
+fn migrate(plain: Vec<(PathBuf, Vec<u8>)>, vault: Option<&VaultFile>, key: Key) -> Result<usize> {
+    let backup = backup(&plain, &key)?;
+    if let Some(vault) = vault {
+        write_vault(vault)?;
+    }
+    set_key(key);
+    let kept = || match &backup {
+        Some(dir) => format!("; encrypted copies of the files are in {}", dir.display()),
+        None => String::new(),
+    };
+    for (path, data) in &plain {
+        seal(data.clone())
+            .and_then(|sealed| storage::write_atomic(path, sealed).map_err(Error::file(path)))
+            .map_err(|e| Error::Config(format!("{}{}", e, kept())))?;
+    }
+    for (path, data) in &plain {
+        let written = fs::read(path).map_err(Error::file(path))?;
+        if open(written).ok().as_ref() != Some(data) {
+            return Err(Error::Config(format!("{} did not read back as written{}", path.display(), kept())));
+        }
+    }
+    if let Some(dir) = backup {
+        match fs::remove_dir_all(&dir) {
+            // Only succeeds when no other backup is left in `backups`.
+            Ok(()) => _ = dir.parent().map(fs::remove_dir),
+            Err(error) => log::warn!("Could not remove the backup {}: {}", dir.display(), error),
+        }
+    }
+    Ok(plain.len())
+}
+
+/// Write `plain` encrypted with `key` into a new timestamped directory under
+/// `backups`.
+fn backup(plain: &[(PathBuf, Vec<u8>)], key: &Key) -> Result<Option<PathBuf>> {
+    if plain.is_empty() {
+        return Ok(None);
+    }
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let root = dirs::data_dir()
+        .ok_or_else(|| Error::Config("No data directory on this system".to_string()))?
+        .join("cli_llm");
+    let dir = root.join("backups").join(format!("vault-{}", secs));
+    for (path, data) in plain {
+        // Checkpoints of different conversations share file names.
+        let Ok(relative) = path.strip_prefix(&root) else {
+            continue;
+        };
+        let copy = dir.join(relative);
+        storage::write_atomic(&copy, encrypt(key, data)?).map_err(Error::file(&copy))?;
+    }
+    Ok(Some(dir))
+}
@@ -0,0 +1,206 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+
+use crate::config::Transcription;
+use crate::error::{Error, Result};
+use crate::{incognito, storage};
+
+/// Recordings are mono 16-bit PCM at this rate, which speech models expect.
+pub const SAMPLE_RATE: u32 = 16_000;
+
+/// Programs that can record from the default microphone to raw PCM on stdout.
+const RECORDERS: [(&str, &[&str]); 2] = [
+    ("arecord", &["-q", "-f", "S16_LE", "-r", "16000", "-c", "1", "-t", "raw", "-"]),
+    (
+        "sox",
+        &["-q", "-d", "-t", "raw", "-r", "16000", "-c", "1", "-b", "16", "-e", "signed-integer", "-"],
+    ),
+];
+
+/// Records the default microphone through a system program until finished or dropped.
+pub struct Recorder {
+    child: Child,
+    reader: Option<JoinHandle<Vec<u8>>>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Start recording with the first recording program found (`arecord`, then `sox`).
+    pub fn start() -> io::Result<Self> {
+        for (program, args) in RECORDERS {
+            let mut child = match Command::new(program)
+                .args(args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            {
+                Ok(child) => child,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            log::info!("Recording with {}", program);
+            let mut stdout = child.stdout.take().expect("stdout is piped");
+            let reader = thread::spawn(move || {
+                let mut pcm = Vec::new();
+                let _ = stdout.read_to_end(&mut pcm);
+                pcm
+            });
+            return Ok(Self {
+                child,
+                reader: Some(reader),
+                started: Instant::now(),
+            });
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no recording program found (install arecord or sox)",
+        ))
+    }
+
+    /// How long the recording has been running.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Stop recording and return what was recorded as a WAV file.
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        // A program that already exited failed to record, e.g. without a microphone.
+        if let Some(status) = self.child.try_wait()?
+            && !status.success()
+        {
+            let mut message = String::new();
+            if let Some(mut stderr) = self.child.stderr.take() {
+                let _ = stderr.read_to_string(&mut message);
+            }
+            return Err(io::Error::other(format!("recording failed ({}): {}", status, message.trim())));
+        }
+        self.stop();
+        let pcm = self.reader.take().and_then(|reader| reader.join().ok()).unwrap_or_default();
+        if pcm.is_empty() {
+            return Err(io::Error::other("nothing was recorded"));
+        }
+        Ok(wav(&pcm))
+    }
+
+    fn stop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Wrap mono 16-bit PCM samples at [`SAMPLE_RATE`] in a WAV header.
+pub fn wav(pcm: &[u8]) -> Vec<u8> {
+    let data_len = pcm.len() as u32;
+    let mut wav = Vec::with_capacity(44 + pcm.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // bytes per second
+    wav.extend_from_slice(&2u16.to_le_bytes()); // bytes per frame
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.extend_from_slice(pcm);
+    wav
+}
+
+/// The `/audio/transcriptions` response body.
+#[derive(Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+/// Turn a recorded WAV file into text, with the local command when one is
+/// configured and the transcription endpoint otherwise.
+pub async fn transcribe(client: &reqwest::Client, settings: &Transcription, wav: Vec<u8>) -> Result<String> {
+    let text = match (&settings.command, &settings.url) {
+        (Some(command), _) => transcribe_locally(command, wav).await?,
+        (None, Some(url)) => transcribe_remotely(client, url, settings, wav).await?,
+        (None, None) => {
+            return Err(Error::Config(
+                "Voice input needs a `[transcription]` section with a `url` or `command` in config.toml".to_string(),
+            ));
+        }
+    };
+    let text = text.trim().to_string();
+    if text.is_empty() {
+        return Err(Error::Config("No speech was recognized".to_string()));
+    }
+    Ok(text)
+}
+
+/// Send the recording to an OpenAI-compatible transcription endpoint.
+async fn transcribe_remotely(
+    client: &reqwest::Client,
+    url: &str,
+    settings: &Transcription,
+    wav: Vec<u8>,
+) -> Result<String> {
+    let file = Part::bytes(wav).file_name("speech.wav").mime_str("audio/wav")?;
+    let mut form = Form::new()
+        .part("file", file)
+        .text("model", settings.model.clone().unwrap_or_else(|| "whisper-1".to_string()));
+    if let Some(language) = &settings.language {
+        form = form.text("language", language.clone());
+    }
+    let mut request = client.post(url).multipart(form);
+    if let Some(key) = settings.api_key() {
+        request = request.bearer_auth(key);
+    }
+    log::debug!("POST {} transcription", url);
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(Error::from_response(status, &body));
+    }
+    let response: TranscriptionResponse = serde_json::from_str(&body)?;
+    Ok(response.text)
+}
+
+/// Run the configured local program (e.g. whisper.cpp) on the recording, passing
+/// the WAV file's path in place of `{file}`, and read the transcript from its output.
+async fn transcribe_locally(command: &[String], wav: Vec<u8>) -> Result<String> {
+    let Some((program, args)) = command.split_first() else {
+        return Err(Error::Config("The transcription command is empty".to_string()));
+    };
+    incognito::refuse("Transcribing with a local program")?;
+    let path = env::temp_dir().join(format!("cli_llm-voice-{}.wav", std::process::id()));
+    storage::write_atomic(&path, wav).map_err(Error::file(&path))?;
+    let file = path.to_string_lossy();
+    log::debug!("Running {} on {}", program, file);
+    let output = tokio::process::Command::new(program)
+        .args(args.iter().map(|arg| arg.replace("{file}", &file)))
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    let _ = fs::remove_file(&path);
+    let output = output.map_err(|e| Error::Config(format!("Cannot run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(Error::Config(format!(
+            "{} failed ({}): {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
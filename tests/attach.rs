@@ -0,0 +1,38 @@
+//! Files attached in the terminal client are sent after the message, shortened
+//! as staged.
+
+mod common;
+
+use std::fs;
+
+use common::{MockServer, Reply, Sandbox};
+
+#[test]
+fn attached_file_is_sent_with_the_lines_chosen() {
+    let server = MockServer::start(|_| Reply::new("Line 3 is wrong."));
+    let sandbox = Sandbox::new("attach");
+    let log: String = (1..=30_000).map(|n| format!("step {}\n", n)).collect();
+    fs::write(sandbox.root.join("build.log"), log).unwrap();
+    fs::write(sandbox.root.join("short.txt"), "only line\n").unwrap();
+    let script = sandbox.root.join("script.txt");
+    fs::write(
+        &script,
+        "/attach build.log\n/attach short.txt\n/attach missing.txt\n/context edit 1 --head 2 --tail 1\n\
+         /context drop 2\nWhat fails?\nAnd now?\n",
+    )
+    .unwrap();
+
+    let output = sandbox.cli(&server).arg("--repl-script").arg(&script).output().unwrap();
+    let (stdout, stderr) = (String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "the session failed: {}", stderr);
+    assert!(stdout.contains("build.log will be sent as 30,000 lines, sent the first 2 and the last 1"), "{}", stdout);
+    assert!(stderr.contains("missing.txt: "), "{}", stderr);
+
+    assert_eq!(
+        server.prompts(),
+        [
+            "What fails?\n\nbuild.log:\n```\nstep 1\nstep 2\n[... 29,997 lines omitted ...]\nstep 30000\n```\n",
+            "And now?"
+        ]
+    );
+}
@@ -0,0 +1,224 @@
+//! Helpers for running the terminal client end to end: a stand-in for the
+//! chat completions API, and a scratch directory to use as its home.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+/// A reply the mock server gives to a chat request.
+#[derive(Clone)]
+pub struct Reply {
+    pub content: String,
+    /// How long to wait before answering.
+    pub delay: Duration,
+    /// When streaming, the pause between words, which are then sent as chunks
+    /// of their own.
+    pub pace: Duration,
+}
+
+impl Reply {
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            delay: Duration::ZERO,
+            pace: Duration::ZERO,
+        }
+    }
+
+    pub fn after(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn paced(mut self, pace: Duration) -> Self {
+        self.pace = pace;
+        self
+    }
+}
+
+/// An OpenAI-style API on a local port, answering each chat request with what
+/// `reply` gives for the request's JSON body.
+pub struct MockServer {
+    pub url: String,
+    /// The bodies of the chat requests received, in order.
+    pub requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl MockServer {
+    pub fn start(reply: impl Fn(&Value) -> Reply + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind the mock server");
+        let url = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        let reply = Arc::new(reply);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let received = received.clone();
+                let reply = reply.clone();
+                thread::spawn(move || answer(stream, &received, &*reply));
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// The last user message of each chat request received.
+    pub fn prompts(&self) -> Vec<String> {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .iter()
+            .filter_map(|body| {
+                let messages = body["messages"].as_array()?;
+                let last = messages.iter().rev().find(|msg| msg["role"] == "user")?;
+                Some(last["content"].as_str().unwrap_or_default().to_string())
+            })
+            .collect()
+    }
+}
+
+fn answer(stream: TcpStream, received: &Mutex<Vec<Value>>, reply: &(dyn Fn(&Value) -> Reply + Send + Sync)) {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse().unwrap_or(0);
+        }
+    }
+    let mut body = vec![0; length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+    let mut stream = reader.into_inner();
+    if request_line.starts_with("GET") {
+        respond(&mut stream, "application/json", r#"{"data":[]}"#);
+        return;
+    }
+    let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let Reply { content, delay, pace } = reply(&body);
+    received.lock().unwrap().push(body.clone());
+    thread::sleep(delay);
+    let usage = json!({"prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5});
+    if body["stream"] == true && !pace.is_zero() {
+        // No length up front: the words follow one by one until the connection closes.
+        let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n");
+        for word in content.split_inclusive(' ') {
+            let chunk = json!({"choices": [{"delta": {"content": word}}]});
+            if write!(stream, "data: {}\n\n", chunk).and_then(|()| stream.flush()).is_err() {
+                return;
+            }
+            thread::sleep(pace);
+        }
+        let end = json!({"choices": [{"delta": {}, "finish_reason": "stop"}], "usage": usage});
+        let _ = write!(stream, "data: {}\n\ndata: [DONE]\n\n", end);
+    } else if body["stream"] == true {
+        let chunk = json!({"choices": [{"delta": {"content": content}}]});
+        let end = json!({"choices": [{"delta": {}, "finish_reason": "stop"}], "usage": usage});
+        respond(&mut stream, "text/event-stream", &format!("data: {}\n\ndata: {}\n\ndata: [DONE]\n\n", chunk, end));
+    } else {
+        let message = json!({
+            "id": "mock",
+            "object": "chat.completion",
+            "created": 0,
+            "choices": [{"message": {"role": "assistant", "content": content}, "finish_reason": "stop"}],
+            "usage": usage,
+        });
+        respond(&mut stream, "application/json", &message.to_string());
+    }
+}
+
+fn respond(stream: &mut TcpStream, content_type: &str, body: &str) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    );
+}
+
+/// A fresh directory with its own home, config, data, cache and temp
+/// directories, removed when dropped.
+pub struct Sandbox {
+    pub root: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "cli_llm-test-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        for dir in ["home", "config", "data", "cache", "tmp"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        Self { root }
+    }
+
+    /// The terminal client, run in this sandbox against `server`.
+    pub fn cli(&self, server: &MockServer) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_cli_llm"));
+        command
+            .current_dir(&self.root)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.root.join("home"))
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env("XDG_DATA_HOME", self.root.join("data"))
+            .env("XDG_CACHE_HOME", self.root.join("cache"))
+            .env("TMPDIR", self.root.join("tmp"))
+            .env("OPENROUTER_API_KEY", "test-key")
+            .env("OPENROUTER_API_URL", &server.url);
+        command
+    }
+
+    /// Every file under the sandbox's home, config, data, cache and temp
+    /// directories.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for dir in ["home", "config", "data", "cache", "tmp"] {
+            collect(&self.root.join(dir), &mut files);
+        }
+        files
+    }
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match path.is_dir() {
+            true => collect(&path, files),
+            false => files.push(path),
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,39 @@
+//! The terminal client warns before sending a prompt that nearly fills the
+//! model's context window, and without a terminal to ask at sends it anyway.
+
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+
+use common::{MockServer, Reply, Sandbox};
+
+#[test]
+fn warns_before_nearly_filling_the_context_window() {
+    let server = MockServer::start(|_| Reply::new("Noted."));
+    let sandbox = Sandbox::new("context");
+    // google/gemini-pro has a 32,768-token window; each line is ~12,500 tokens.
+    let line = "word ".repeat(10_000);
+    let mut child = sandbox
+        .cli(&server)
+        .args(["--incognito", "--no-stream", "--model", "google/gemini-pro"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "short question").unwrap();
+    writeln!(stdin, "{}", line).unwrap();
+    writeln!(stdin, "{}", line).unwrap();
+    writeln!(stdin, "/quit").unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    let warnings: Vec<&str> = stderr.lines().filter(|line| line.starts_with("Warning: The prompt is")).collect();
+    assert_eq!(warnings.len(), 1, "{}", stderr);
+    assert!(warnings[0].contains("76% of the model's 32768-token context window"), "{}", stderr);
+    assert_eq!(server.prompts().len(), 3);
+}
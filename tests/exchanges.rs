@@ -0,0 +1,49 @@
+//! Exchanges in the terminal client keep their numbers through `/goto`,
+//! `/undo-op` and `/regenerate`, and saved conversations keep them too.
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::Stdio;
+
+use common::{MockServer, Reply, Sandbox};
+
+#[test]
+fn exchange_numbers_are_stable() {
+    let server = MockServer::start(|_| Reply::new("Noted."));
+    let sandbox = Sandbox::new("exchanges");
+    let mut child = sandbox
+        .cli(&server)
+        .args(["--no-stream"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let script = ["One", "Two", "Three", "/goto 1", "/undo-op", "/show 3", "/regenerate", "/show 3", "/save notes", "/quit"];
+    for line in script {
+        writeln!(stdin, "{}", line).unwrap();
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Continuing from exchange 1; dropped 2."), "{}", stdout);
+    assert_eq!(stdout.matches("[3] You: Three").count(), 2, "{}", stdout);
+    assert!(stdout.contains("[3] > Three"), "{}", stdout);
+    assert_eq!(server.prompts()[..4], ["One", "Two", "Three", "Three"]);
+
+    let saved = fs::read_to_string(sandbox.root.join("data/cli_llm/conversations/notes.json")).unwrap();
+    let saved: serde_json::Value = serde_json::from_str(&saved).unwrap();
+    let ids: Vec<u64> = saved["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|msg| msg["role"] == "user")
+        .map(|msg| msg["exchange"].as_u64().unwrap_or(0))
+        .collect();
+    assert_eq!(ids, [1, 2, 3]);
+}
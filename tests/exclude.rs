@@ -0,0 +1,37 @@
+//! `/exclude` in the terminal client leaves an exchange out of the requests
+//! while keeping it in the conversation, and `/include` sends it again.
+
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+
+use common::{MockServer, Reply, Sandbox};
+
+#[test]
+fn excluded_exchanges_are_not_sent() {
+    let server = MockServer::start(|_| Reply::new("Noted."));
+    let sandbox = Sandbox::new("exclude");
+    let mut child = sandbox
+        .cli(&server)
+        .args(["--incognito", "--no-stream"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for line in ["My secret is 1234.", "/exclude 1", "What is my secret?", "/include 1", "And now?", "/quit"] {
+        writeln!(stdin, "{}", line).unwrap();
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let sent = |request: &serde_json::Value| request["messages"].to_string();
+    let requests = server.requests.lock().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert!(!sent(&requests[1]).contains("My secret is 1234."), "{}", sent(&requests[1]));
+    assert!(sent(&requests[1]).contains("What is my secret?"));
+    assert!(sent(&requests[2]).contains("My secret is 1234."), "{}", sent(&requests[2]));
+}
@@ -0,0 +1,76 @@
+//! HTML exports of a fixture conversation with every kind of message match the
+//! golden files next to it. Run with `UPDATE_GOLDEN=1` to rewrite them after an
+//! intended change, and review the diff.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use llm::api::ChatMessageRequest;
+use llm::export::{self, HtmlOptions, Theme, Transcript};
+use llm::history::SavedConversation;
+
+const FIXTURES: &str = "tests/fixtures/export";
+
+/// The fixture conversation's messages, with the greeting the GUI shows ahead
+/// of them, which exports leave out.
+fn messages(saved: &SavedConversation) -> Vec<ChatMessageRequest> {
+    let mut greeting = ChatMessageRequest::new("assistant", "Hello! How can I help?");
+    greeting.display_only = true;
+    std::iter::once(greeting).chain(saved.messages.iter().map(|msg| msg.to_request())).collect()
+}
+
+/// Compare `html` with the golden file `name`, or write it when updating.
+fn check(name: &str, html: &str) {
+    let path = Path::new(FIXTURES).join(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, html).unwrap();
+        return;
+    }
+    let golden = fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    if html != golden {
+        let line = html.lines().zip(golden.lines()).position(|(a, b)| a != b).map_or(0, |n| n + 1);
+        panic!(
+            "{} differs from the export, first at line {}; rerun with UPDATE_GOLDEN=1 if that is intended",
+            path.display(),
+            line
+        );
+    }
+}
+
+#[test]
+fn exports_match_golden_files() {
+    let text = fs::read_to_string(Path::new(FIXTURES).join("conversation.json")).unwrap();
+    let saved: SavedConversation = serde_json::from_str(&text).unwrap();
+    let messages = messages(&saved);
+    let transcript = Transcript {
+        title: "Fixture <all kinds>",
+        model: &saved.model,
+        saved_at: Some(saved.saved_at),
+        system_prompt: saved.system_prompt.as_deref().unwrap_or_default(),
+        messages: &messages,
+    };
+    let cases = [
+        (
+            "light.html",
+            HtmlOptions {
+                theme: Theme::Light,
+                anonymize: true,
+                redact_system: false,
+            },
+        ),
+        (
+            "dark-redacted.html",
+            HtmlOptions {
+                theme: Theme::Dark,
+                anonymize: true,
+                redact_system: true,
+            },
+        ),
+    ];
+    for (name, options) in cases {
+        let html = export::to_html(&transcript, &options);
+        assert_eq!(html, export::to_html(&transcript, &options), "{} is not deterministic", name);
+        check(name, &html);
+    }
+}
@@ -0,0 +1,49 @@
+//! Group chat in the terminal client: every persona answers, under its name.
+
+mod common;
+
+use std::fs;
+
+use common::{MockServer, Reply, Sandbox};
+
+#[test]
+fn every_persona_answers_with_its_name() {
+    let server = MockServer::start(|body| Reply::new(format!("Answer from {}", body["model"].as_str().unwrap_or("?"))));
+    let sandbox = Sandbox::new("group");
+    let config = sandbox.root.join("config/cli_llm/config.toml");
+    fs::create_dir_all(config.parent().unwrap()).unwrap();
+    fs::write(
+        &config,
+        r#"
+[[personas]]
+name = "Optimist"
+model = "model/optimist"
+system_prompt = "Look on the bright side."
+
+[[personas]]
+name = "Skeptic"
+model = "model/skeptic"
+system_prompt = "Look for what can go wrong."
+"#,
+    )
+    .unwrap();
+
+    let output = sandbox
+        .cli(&server)
+        .args(["--incognito", "--no-stream", "--group", "-p", "Should we ship?"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Optimist: Answer from model/optimist"), "{}", stdout);
+    assert!(stdout.contains("Skeptic: Answer from model/skeptic"), "{}", stdout);
+
+    let requests = server.requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0]["messages"][0]["content"], "Look on the bright side.");
+    assert_eq!(requests[1]["messages"][0]["content"], "Look for what can go wrong.");
+    // The second persona sees the first one's reply, under its name.
+    let seen = requests[1]["messages"].as_array().unwrap();
+    assert!(seen.iter().any(|msg| msg["name"] == "Optimist" && msg["content"] == "Answer from model/optimist"));
+}
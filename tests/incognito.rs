@@ -0,0 +1,27 @@
+//! An incognito session leaves nothing behind on disk.
+
+mod common;
+
+use std::fs;
+
+use common::{MockServer, Reply, Sandbox};
+
+#[test]
+fn incognito_session_writes_no_files() {
+    let server = MockServer::start(|_| Reply::new("A reply long enough to be cut short on screen."));
+    let sandbox = Sandbox::new("incognito");
+    let script = sandbox.root.join("script.txt");
+    fs::write(&script, "Hello\n/save notes\nAnd again\n!expect cut short\n").unwrap();
+
+    let output = sandbox
+        .cli(&server)
+        .args(["--incognito", "--max-display-chars", "10", "--repl-script"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success(), "the session failed: {}", stderr);
+    assert_eq!(server.prompts(), ["Hello", "And again"]);
+    assert_eq!(sandbox.files(), Vec::<std::path::PathBuf>::new(), "{}", stderr);
+}
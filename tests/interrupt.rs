@@ -0,0 +1,153 @@
+//! A `--repl-script` run stopped by Ctrl+C keeps every complete reply in its
+//! output file and nothing of an unfinished one, and resuming it from the
+//! manifest it writes runs each remaining prompt once.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{MockServer, Reply, Sandbox};
+
+const PROMPTS: [&str; 6] = ["one", "two", "three", "four", "five", "six"];
+
+/// Answers "answer to <prompt>, word by word", the second prompt slowly enough
+/// to be interrupted halfway.
+fn server() -> MockServer {
+    MockServer::start(|body| {
+        let messages = body["messages"].as_array().unwrap();
+        let prompt = messages.iter().rev().find(|msg| msg["role"] == "user").unwrap()["content"].as_str().unwrap();
+        let reply = Reply::new(format!("answer to {}, word by word", prompt));
+        match prompt {
+            "two" => reply.paced(Duration::from_millis(400)),
+            _ => reply.paced(Duration::from_millis(5)),
+        }
+    })
+}
+
+struct Run {
+    sandbox: Sandbox,
+    script: PathBuf,
+    out: PathBuf,
+}
+
+impl Run {
+    fn new(name: &str) -> Self {
+        let sandbox = Sandbox::new(name);
+        let script = sandbox.root.join("prompts.txt");
+        fs::write(&script, PROMPTS.map(|prompt| format!("{}\n", prompt)).concat()).unwrap();
+        let out = sandbox.root.join("out.txt");
+        Self { sandbox, script, out }
+    }
+
+    fn start(&self, server: &MockServer, args: &[&str]) -> Child {
+        self.sandbox
+            .cli(server)
+            .arg("--repl-script")
+            .arg(&self.script)
+            .arg("--output-file")
+            .arg(&self.out)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+
+    /// Carry on with the command at the top of the resume manifest.
+    fn resume(&self, server: &MockServer) {
+        let manifest = fs::read_to_string(self.sandbox.root.join("prompts.txt.resume")).unwrap();
+        let command = manifest.lines().find_map(|line| line.strip_prefix("#   cli_llm ")).unwrap();
+        let output = self.sandbox.cli(server).args(command.split_whitespace()).output().unwrap();
+        assert!(output.status.success(), "the resumed run failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    fn lines(&self) -> Vec<String> {
+        fs::read_to_string(&self.out).unwrap().lines().map(str::to_string).collect()
+    }
+}
+
+fn interrupt(child: &Child) {
+    let pid = libc::pid_t::try_from(child.id()).unwrap();
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGINT) }, 0);
+}
+
+/// Wait until `done` holds, for at most ten seconds.
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < Duration::from_secs(10), "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn contains(path: &Path, text: &str) -> bool {
+    fs::read_to_string(path).is_ok_and(|content| content.contains(text))
+}
+
+fn expected(prompts: &[&str]) -> Vec<String> {
+    prompts.iter().map(|prompt| format!("answer to {}, word by word", prompt)).collect()
+}
+
+#[test]
+fn reply_in_flight_finishes_within_the_grace_period() {
+    let server = server();
+    let run = Run::new("interrupt-grace");
+    let child = run.start(&server, &[]);
+    wait_for("the second reply to start", || contains(&run.out, "answer to two,"));
+    interrupt(&child);
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(130), "{}", stderr);
+    assert_eq!(run.lines(), expected(&PROMPTS[..2]), "{}", stderr);
+    assert_eq!(server.prompts(), PROMPTS[..2]);
+
+    run.resume(&server);
+    assert_eq!(run.lines(), expected(&PROMPTS));
+    assert_eq!(server.prompts(), PROMPTS);
+}
+
+#[test]
+fn second_interrupt_cancels_the_reply_in_flight() {
+    let server = server();
+    let run = Run::new("interrupt-cancel");
+    let child = run.start(&server, &[]);
+    wait_for("the second reply to start", || contains(&run.out, "answer to two,"));
+    interrupt(&child);
+    thread::sleep(Duration::from_millis(100));
+    interrupt(&child);
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(130), "{}", stderr);
+    // Nothing of the cancelled reply is left, not even a partial line.
+    assert_eq!(fs::read_to_string(&run.out).unwrap(), "answer to one, word by word\n", "{}", stderr);
+
+    run.resume(&server);
+    assert_eq!(run.lines(), expected(&PROMPTS));
+    // Only the cancelled prompt is asked twice.
+    assert_eq!(server.prompts(), ["one", "two", "two", "three", "four", "five", "six"]);
+}
+
+#[test]
+fn zero_grace_cancels_at_the_first_interrupt() {
+    let server = server();
+    let run = Run::new("interrupt-zero");
+    let child = run.start(&server, &["--grace", "0"]);
+    wait_for("the second reply to start", || contains(&run.out, "answer to two,"));
+    interrupt(&child);
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(run.lines(), expected(&PROMPTS[..1]));
+
+    run.resume(&server);
+    assert_eq!(run.lines(), expected(&PROMPTS));
+}